@@ -0,0 +1,17 @@
+// Run-time:
+//   stdout:
+//     Header
+//     ...{3}
+//     Middle
+//     ...{1,2}
+//     Footer
+
+fn main() {
+    println!("Header");
+    for i in 0..3 {
+        println!("Line {}", i);
+    }
+    println!("Middle");
+    println!("Line 3");
+    println!("Footer");
+}
@@ -0,0 +1,12 @@
+// Compiler:
+//   stderr:
+//     re:warning: unused variable: `(?<var>[a-z_]+)`
+//       ...
+//
+// Run-time:
+//   stdout: ${var} was never used
+
+fn main() {
+    let unread = 0;
+    println!("unread was never used");
+}
@@ -0,0 +1,11 @@
+// # Comments can explain what a test is checking.
+// Compiler:
+//   # rustc shouldn't produce any output for this file.
+//   stderr:
+//
+// Run-time:
+//   stdout: Hello world
+
+fn main() {
+    println!("Hello world");
+}
@@ -0,0 +1,10 @@
+// Run-time:
+//   cwd: ..
+//   stdout: rust_lang_tester
+
+use std::env;
+
+fn main() {
+    let cwd = env::current_dir().unwrap();
+    println!("{}", cwd.file_name().unwrap().to_str().unwrap());
+}
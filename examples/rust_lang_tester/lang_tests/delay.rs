@@ -0,0 +1,7 @@
+// Run-time:
+//   delay: 50
+//   stdout: ready
+
+fn main() {
+    println!("ready");
+}
@@ -0,0 +1,12 @@
+// Run-time:
+//   env-clear: true
+//   env-var: LANG_TESTER_A=Hello
+//   stdout: LANG_TESTER_A=Hello
+
+use std::env;
+
+fn main() {
+    for (k, v) in env::vars() {
+        println!("{}={}", k, v);
+    }
+}
@@ -0,0 +1,15 @@
+// Run-time:
+//   env-var:
+//     LANG_TESTER_A=Hello
+//     LANG_TESTER_B=world
+//   stdout: Hello world
+
+use std::env;
+
+fn main() {
+    println!(
+        "{} {}",
+        env::var("LANG_TESTER_A").unwrap(),
+        env::var("LANG_TESTER_B").unwrap()
+    );
+}
@@ -0,0 +1,8 @@
+// Run-time:
+//   status: 1, 6..=8
+
+use std::process;
+
+fn main() {
+    process::exit(7);
+}
@@ -0,0 +1,10 @@
+// ignore-if: target_os = "linux"
+// Compiler:
+//   status: success
+//
+// Run-time:
+//   stdout: Not running on Linux
+
+fn main() {
+    println!("Not running on Linux");
+}
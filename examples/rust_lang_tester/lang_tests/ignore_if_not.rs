@@ -0,0 +1,10 @@
+// ignore-if: not(unix)
+// Compiler:
+//   status: success
+//
+// Run-time:
+//   stdout: Running on Unix
+
+fn main() {
+    println!("Running on Unix");
+}
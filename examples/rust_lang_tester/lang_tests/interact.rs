@@ -0,0 +1,33 @@
+// Run-time:
+//   interact:
+//     expect: >
+//     send: 1 + 2
+//     expect: 3
+//     expect: >
+//     send: 10 + 20
+//     expect: 30
+//   stdout:
+//     ...
+//     > 30
+//     > Bye
+
+use std::io::{self, BufRead, Write};
+
+fn main() {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(line) => line.unwrap(),
+            None => break,
+        };
+        let sum = line
+            .split('+')
+            .map(|x| x.trim().parse::<i64>().unwrap())
+            .sum::<i64>();
+        println!("{}", sum);
+    }
+    println!("Bye");
+}
@@ -0,0 +1,10 @@
+// Run-time:
+//   stdout:
+//     Address: {...} (aligned)
+//     Took {...}ms in {...} passes
+
+fn main() {
+    let x = 0;
+    println!("Address: {:p} (aligned)", &x);
+    println!("Took {}ms in {} passes", x + 3, x + 2);
+}
@@ -0,0 +1,8 @@
+// Run-time:
+//   max-rss: 64
+//   stdout: 1048576
+
+fn main() {
+    let v = vec![1u8; 1024 * 1024];
+    println!("{}", v.iter().map(|&x| usize::from(x)).sum::<usize>());
+}
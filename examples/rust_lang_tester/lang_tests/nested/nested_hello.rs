@@ -0,0 +1,6 @@
+// Run-time:
+//   stdout: Hello from a subdirectory
+
+fn main() {
+    println!("Hello from a subdirectory");
+}
@@ -5,6 +5,6 @@
 //     ...
 //     ...consider adding a `main` function to `examples/rust_lang_tester/lang_tests/no_main.rs`
 //
-//     error: aborting due to previous error
+//     error: aborting due to ...
 //
 //     For more information about this error, try `rustc --explain E0601`.
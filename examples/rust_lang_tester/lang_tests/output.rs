@@ -0,0 +1,14 @@
+// Run-time:
+//   output:
+//     a
+//     b
+//     c
+
+use std::io::{self, Write};
+
+fn main() {
+    println!("a");
+    io::stdout().flush().unwrap();
+    eprintln!("b");
+    println!("c");
+}
@@ -0,0 +1,14 @@
+// Run-time:
+//   extra-args: {testfile} {tempdir}/out {{testdir}
+//   stdout:
+//     ...lang_tests/placeholder_args.rs
+//     .../out
+//     {testdir}
+
+use std::env;
+
+fn main() {
+    for a in env::args().skip(1) {
+        println!("{}", a);
+    }
+}
@@ -0,0 +1,16 @@
+// Run-time:
+//   extra-args:
+//     --name "Hello world"
+//     'it''s'
+//   stdout:
+//     --name
+//     Hello world
+//     its
+
+use std::env;
+
+fn main() {
+    for a in env::args().skip(1) {
+        println!("{}", a);
+    }
+}
@@ -0,0 +1,11 @@
+// Run-time:
+//   stdout:
+//     re: Address: 0x[0-9a-f]+
+//     ...
+//     re: Took [0-9]+ms
+
+fn main() {
+    let x = 0;
+    println!("Address: {:p}", &x);
+    println!("Took {}ms", x + 3);
+}
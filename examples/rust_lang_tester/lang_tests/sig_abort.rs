@@ -0,0 +1,8 @@
+// Run-time:
+//   status: signal(SIGABRT)
+
+use std::process;
+
+fn main() {
+    process::abort();
+}
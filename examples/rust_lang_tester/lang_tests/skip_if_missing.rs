@@ -0,0 +1,7 @@
+// Run-time:
+//   skip-if-missing: lang_tester_no_such_program
+//   stdout: never checked
+
+fn main() {
+    println!("Hello world");
+}
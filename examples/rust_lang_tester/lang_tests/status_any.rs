@@ -0,0 +1,10 @@
+// Run-time:
+//   status: any
+//   stdout: Checking...
+
+use std::process;
+
+fn main() {
+    println!("Checking...");
+    process::exit(3);
+}
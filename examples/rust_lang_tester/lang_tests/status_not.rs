@@ -0,0 +1,8 @@
+// Run-time:
+//   status: not 0, 1
+
+use std::process;
+
+fn main() {
+    process::exit(3);
+}
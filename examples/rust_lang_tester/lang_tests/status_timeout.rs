@@ -0,0 +1,11 @@
+// Run-time:
+//   timeout: 1
+//   status: timeout
+//   stdout: looping
+
+fn main() {
+    println!("looping");
+    loop {
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+}
@@ -0,0 +1,15 @@
+// Run-time:
+//   stdin:
+//     Hello
+//     world
+//   stdout:
+//     HELLO
+//     WORLD
+
+use std::io::{self, Read};
+
+fn main() {
+    let mut s = String::new();
+    io::stdin().read_to_string(&mut s).unwrap();
+    print!("{}", s.to_uppercase());
+}
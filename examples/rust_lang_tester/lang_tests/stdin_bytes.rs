@@ -0,0 +1,11 @@
+// Run-time:
+//   stdin-bytes: hex:ff 00 0a
+//   stdout: [255, 0, 10]
+
+use std::io::{self, Read};
+
+fn main() {
+    let mut buf = Vec::new();
+    io::stdin().read_to_end(&mut buf).unwrap();
+    println!("{:?}", buf);
+}
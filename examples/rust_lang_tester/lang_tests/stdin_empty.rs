@@ -0,0 +1,11 @@
+// Run-time:
+//   stdin:
+//   stdout: 0
+
+use std::io::{self, Read};
+
+fn main() {
+    let mut s = String::new();
+    io::stdin().read_to_string(&mut s).unwrap();
+    println!("{}", s.len());
+}
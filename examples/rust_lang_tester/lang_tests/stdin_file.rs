@@ -0,0 +1,13 @@
+// Run-time:
+//   stdin-file: ../fixtures/stdin_file.txt
+//   stdout:
+//     HELLO
+//     FROM A FILE
+
+use std::io::{self, Read};
+
+fn main() {
+    let mut s = String::new();
+    io::stdin().read_to_string(&mut s).unwrap();
+    print!("{}", s.to_uppercase());
+}
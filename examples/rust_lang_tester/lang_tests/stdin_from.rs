@@ -0,0 +1,11 @@
+// Run-time:
+//   stdin-from: compiler
+//   stdout: Read 0 bytes
+
+use std::io::{self, Read};
+
+fn main() {
+    let mut buf = Vec::new();
+    io::stdin().read_to_end(&mut buf).unwrap();
+    println!("Read {} bytes", buf.len());
+}
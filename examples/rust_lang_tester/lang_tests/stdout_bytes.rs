@@ -0,0 +1,8 @@
+// Run-time:
+//   stdout: Hello
+//   stdout-bytes: 7
+//   stderr-bytes: 0
+
+fn main() {
+    print!("Hello\r\n");
+}
@@ -0,0 +1,19 @@
+// Run-time:
+//   stdout:
+//     starting
+//     ...
+//   stdout-contains:
+//     result: 55
+//     step 3...
+//   stderr-contains: re:took [0-9]+ms
+
+fn main() {
+    println!("starting");
+    let mut total = 0;
+    for i in 1..=10 {
+        println!("step {} of 10", i);
+        total += i;
+    }
+    println!("result: {}", total);
+    eprintln!("took 12ms");
+}
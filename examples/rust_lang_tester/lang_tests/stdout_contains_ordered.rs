@@ -0,0 +1,13 @@
+// Run-time:
+//   stdout-contains-ordered:
+//     Starting...
+//     ...stage 2
+//     Done
+
+fn main() {
+    println!("Starting up");
+    for i in 1..=3 {
+        println!("Running stage {}", i);
+    }
+    println!("Done");
+}
@@ -0,0 +1,11 @@
+// Compiler:
+//   stderr-empty:
+//
+// Run-time:
+//   stdout-empty:
+//   stderr:
+//     warning: nothing to do
+
+fn main() {
+    eprintln!("warning: nothing to do");
+}
@@ -0,0 +1,13 @@
+// Run-time:
+//   stdin:
+//     Hello
+//       world
+//   stdout-equals-stdin: true
+
+use std::io::{self, Read};
+
+fn main() {
+    let mut s = String::new();
+    io::stdin().read_to_string(&mut s).unwrap();
+    print!("{}", s);
+}
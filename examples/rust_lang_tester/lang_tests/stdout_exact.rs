@@ -0,0 +1,14 @@
+// Run-time:
+//   stdout-exact:
+//     Counting...
+//
+//       1
+//       2
+//   stderr-exact:
+
+fn main() {
+    println!("Counting...");
+    println!();
+    println!("  1");
+    println!("  2");
+}
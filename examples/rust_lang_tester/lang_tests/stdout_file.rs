@@ -0,0 +1,8 @@
+// Run-time:
+//   stdout-file: ../fixtures/stdout_file.out
+
+fn main() {
+    for i in 1..=20 {
+        println!("Line {}", i);
+    }
+}
@@ -0,0 +1,18 @@
+// Run-time:
+//   stdout-json:
+//     {
+//       "name": "lang_tester",
+//       "versions": [1, 2.0]
+//     }
+//   stderr-json-subset: {"level": "warn"}
+
+use std::time::SystemTime;
+
+fn main() {
+    let t = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    println!("{{\"versions\":[1,2],\"name\":\"lang_tester\"}}");
+    eprintln!("{{\"time\": {}, \"level\": \"warn\"}}", t);
+}
@@ -0,0 +1,12 @@
+// Run-time:
+//   stdout:
+//     Line 1
+//     ...
+//   stdout-lines: 10
+//   stderr-lines: 0
+
+fn main() {
+    for i in 1..=10 {
+        println!("Line {}", i);
+    }
+}
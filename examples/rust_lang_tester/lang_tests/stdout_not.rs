@@ -0,0 +1,12 @@
+// Run-time:
+//   stdout:
+//     Starting
+//     ...
+//   stdout-not:
+//     ...panic...
+//     re:[Ee]rror.*
+
+fn main() {
+    println!("Starting");
+    println!("All went well");
+}
@@ -0,0 +1,13 @@
+// Run-time:
+//   stdout-raw:
+//     fn main() {
+//         println!("Hello");
+//     ...
+//     }
+
+fn main() {
+    println!("fn main() {{");
+    println!("    println!(\"Hello\");");
+    println!("    // Indented.");
+    println!("}}");
+}
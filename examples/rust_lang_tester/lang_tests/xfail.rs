@@ -0,0 +1,10 @@
+// xfail: the program's output is known to be wrong
+// Compiler:
+//   status: success
+//
+// Run-time:
+//   stdout: 3
+
+fn main() {
+    println!("{}", 1 + 1);
+}
@@ -4,46 +4,34 @@
 //!
 //! See the test files in `lang_tests/` for example.
 
-use std::{path::PathBuf, process::Command};
+use std::process::Command;
 
-use lang_tester::LangTester;
-use tempdir::TempDir;
+use lang_tester::{extract, LangTester};
 
 fn main() {
-    // We use rustc to compile files into a binary: we store those binary files into `tempdir`.
-    // This may not be necessary for other languages.
-    let tempdir = TempDir::new("rust_lang_tester").unwrap();
     LangTester::new()
         .test_dir("examples/rust_lang_tester/lang_tests")
         // Only use files named `*.rs` as test files.
-        .test_file_filter(|p| p.extension().unwrap().to_str().unwrap() == "rs")
+        .test_file_extensions(&["rs"])
         // Extract the first sequence of commented line(s) as the tests.
-        .test_extract(|s| {
-            Some(
-                s.lines()
-                    // Skip non-commented lines at the start of the file.
-                    .skip_while(|l| !l.starts_with("//"))
-                    // Extract consecutive commented lines.
-                    .take_while(|l| l.starts_with("//"))
-                    .map(|l| &l[2..])
-                    .collect::<Vec<_>>()
-                    .join("\n"),
-            )
-        })
+        .test_extract(extract::line_comment_block("//"))
         // We have two test commands:
         //   * `Compiler`: runs rustc.
         //   * `Run-time`: if rustc does not error, and the `Compiler` tests succeed, then the
         //     output binary is run.
-        .test_cmds(move |p| {
+        // We use rustc to compile files into a binary, which we store in a directory unique to the
+        // test. This may not be necessary for other languages.
+        .test_cmds_with_tempdir(|p, tempdir| {
             // Test command 1: Compile `x.rs` into `tempdir/x`.
-            let mut exe = PathBuf::new();
-            exe.push(&tempdir);
-            exe.push(p.file_stem().unwrap());
+            let exe = tempdir.join(p.file_stem().unwrap());
             let mut compiler = Command::new("rustc");
-            compiler.args(&["-o", exe.to_str().unwrap(), p.to_str().unwrap()]);
+            compiler.args(["-o", exe.to_str().unwrap(), p.to_str().unwrap()]);
             // Test command 2: run `tempdir/x`.
             let runtime = Command::new(exe);
-            vec![("Compiler", compiler), ("Run-time", runtime)]
+            vec![
+                ("Compiler".to_owned(), compiler),
+                ("Run-time".to_owned(), runtime),
+            ]
         })
         .run();
 }
@@ -0,0 +1,211 @@
+use std::{
+    collections::hash_map::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+/// The name of the cache file within the cache directory.
+const CACHE_FILE: &str = "lang_tester_cache";
+
+/// A cache of the test data extracted from each test file, keyed by the file's path and
+/// invalidated whenever the file's modification time or size changes. The cache is only ever an
+/// optimisation: a cache file which is missing, unreadable, or corrupt is treated as empty.
+pub(crate) struct Cache {
+    /// The path of the cache file.
+    path: PathBuf,
+    /// The kind of extraction the cached data was produced by: cached data produced by one kind is
+    /// not valid for another.
+    kind: String,
+    entries: HashMap<PathBuf, Entry>,
+}
+
+struct Entry {
+    key: FileKey,
+    /// The `(block name, test data)` pairs extracted from the file.
+    tests: Vec<(String, String)>,
+}
+
+/// The modification time (in nanoseconds since the epoch) and size of a file.
+pub(crate) type FileKey = (u128, u64);
+
+/// Return the `FileKey` of the file at `path`, or `None` if it can't be determined.
+pub(crate) fn file_key(path: &Path) -> Option<FileKey> {
+    let md = fs::metadata(path).ok()?;
+    let mtime = md.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((mtime.as_nanos(), md.len()))
+}
+
+impl Cache {
+    /// Load the cache from `cache_dir`, discarding it if it was not produced by extraction of the
+    /// kind `kind`.
+    pub(crate) fn load(cache_dir: &Path, kind: &str) -> Self {
+        let path = cache_dir.join(CACHE_FILE);
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|s| decode(&s, kind))
+            .unwrap_or_default();
+        Cache {
+            path,
+            kind: kind.to_owned(),
+            entries,
+        }
+    }
+
+    /// Return the test data extracted from the file at `path` if it is cached and the file has not
+    /// changed since.
+    pub(crate) fn get(&self, path: &Path) -> Option<&[(String, String)]> {
+        let entry = self.entries.get(path)?;
+        if file_key(path) == Some(entry.key) {
+            Some(&entry.tests)
+        } else {
+            None
+        }
+    }
+
+    /// Cache the test data `tests` extracted from the file at `path`, which had the key `key` when
+    /// it was read.
+    pub(crate) fn insert(&mut self, path: &Path, key: FileKey, tests: Vec<(String, String)>) {
+        self.entries.insert(path.to_owned(), Entry { key, tests });
+    }
+
+    /// Write the cache to disk, dropping entries for files which no longer exist. Since the cache
+    /// is only an optimisation, failures are silently ignored.
+    pub(crate) fn save(&self) {
+        let mut s = String::new();
+        encode_str(&mut s, &self.kind);
+        let mut paths = self
+            .entries
+            .keys()
+            .filter(|p| p.is_file())
+            .collect::<Vec<_>>();
+        paths.sort();
+        for p in paths {
+            let entry = &self.entries[p];
+            // Paths which aren't valid UTF-8 are simply not cached.
+            let p_str = match p.to_str() {
+                Some(p_str) => p_str,
+                None => continue,
+            };
+            encode_str(&mut s, p_str);
+            encode_str(&mut s, &entry.key.0.to_string());
+            encode_str(&mut s, &entry.key.1.to_string());
+            encode_str(&mut s, &entry.tests.len().to_string());
+            for (name, test_str) in &entry.tests {
+                encode_str(&mut s, name);
+                encode_str(&mut s, test_str);
+            }
+        }
+        // Write to a temporary file and then rename it, so that concurrent test runs never see a
+        // partially written cache.
+        let tmp_path = self
+            .path
+            .with_extension(format!("tmp{}", std::process::id()));
+        let written = self
+            .path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| fs::write(&tmp_path, s))
+            .and_then(|_| fs::rename(&tmp_path, &self.path));
+        if written.is_err() {
+            fs::remove_file(&tmp_path).ok();
+        }
+    }
+}
+
+/// Append `x` to `s` as `<length in bytes>:<x>`.
+fn encode_str(s: &mut String, x: &str) {
+    s.push_str(&x.len().to_string());
+    s.push(':');
+    s.push_str(x);
+}
+
+/// Decode a string encoded by `encode_str` from the start of `s`, returning it and the remainder of
+/// `s`.
+fn decode_str(s: &str) -> Option<(&str, &str)> {
+    let (len, rest) = s.split_at(s.find(':')?);
+    let len = len.parse::<usize>().ok()?;
+    let rest = &rest[1..];
+    if !rest.is_char_boundary(len) {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+/// Decode the contents `s` of a cache file, returning `None` if it is corrupt or was not produced
+/// by extraction of the kind `kind`.
+fn decode(s: &str, kind: &str) -> Option<HashMap<PathBuf, Entry>> {
+    let (s_kind, mut s) = decode_str(s)?;
+    if s_kind != kind {
+        return None;
+    }
+    let mut entries = HashMap::new();
+    while !s.is_empty() {
+        let mut fields = Vec::new();
+        for _ in 0..4 {
+            let (field, rest) = decode_str(s)?;
+            fields.push(field);
+            s = rest;
+        }
+        let key = (fields[1].parse().ok()?, fields[2].parse().ok()?);
+        let mut tests = Vec::new();
+        for _ in 0..fields[3].parse::<usize>().ok()? {
+            let (name, rest) = decode_str(s)?;
+            let (test_str, rest) = decode_str(rest)?;
+            tests.push((name.to_owned(), test_str.to_owned()));
+            s = rest;
+        }
+        entries.insert(PathBuf::from(fields[0]), Entry { key, tests });
+    }
+    Some(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_cache() {
+        let dir = TempDir::new("lang_tester_cache").unwrap();
+        let test_path = dir.path().join("a.rs");
+        fs::write(&test_path, "x").unwrap();
+        let tests = vec![("".to_owned(), "a:\n  status: 1".to_owned())];
+
+        let mut cache = Cache::load(dir.path(), "single");
+        assert!(cache.get(&test_path).is_none());
+        cache.insert(&test_path, file_key(&test_path).unwrap(), tests.clone());
+        cache.save();
+
+        let cache = Cache::load(dir.path(), "single");
+        assert_eq!(cache.get(&test_path), Some(tests.as_slice()));
+        // A cache produced by a different kind of extraction is discarded.
+        assert!(Cache::load(dir.path(), "many").get(&test_path).is_none());
+
+        // Touching the file invalidates its entry.
+        let mtime = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&test_path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        assert!(cache.get(&test_path).is_none());
+
+        // As does changing its size, even if the modification time is unchanged.
+        let mut cache = Cache::load(dir.path(), "single");
+        let (mtime, size) = file_key(&test_path).unwrap();
+        cache.insert(&test_path, (mtime, size), tests.clone());
+        assert_eq!(cache.get(&test_path), Some(tests.as_slice()));
+        cache.insert(&test_path, (mtime, size + 1), tests);
+        assert!(cache.get(&test_path).is_none());
+
+        // A corrupt cache is treated as empty.
+        fs::write(dir.path().join(CACHE_FILE), "6:single1:").unwrap();
+        assert!(Cache::load(dir.path(), "single").get(&test_path).is_none());
+    }
+}
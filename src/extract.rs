@@ -0,0 +1,87 @@
+//! Ready-made functions for extracting test data from test files, suitable for passing to
+//! [`LangTester::test_extract`](../struct.LangTester.html#method.test_extract).
+
+/// Return a function which extracts the first block of consecutive lines starting with `prefix`
+/// (e.g. `//`) in a test file, with `prefix` stripped from each line. Lines before the block which
+/// don't start with `prefix` (e.g. `#![feature(...)]`) are skipped. If the file has no such lines,
+/// the extracted test data is empty (and the test is thus ignored), e.g.:
+///
+/// ```rust,ignore
+/// LangTester::new()
+///     ...
+///     .test_extract(extract::line_comment_block("//"))
+///     ...
+/// ```
+pub fn line_comment_block(prefix: &str) -> impl Fn(&str) -> Option<String> + Send + Sync {
+    let prefix = prefix.to_owned();
+    move |s| {
+        Some(
+            s.lines()
+                // Skip non-commented lines at the start of the file.
+                .skip_while(|l| !l.starts_with(&prefix))
+                // Extract consecutive commented lines.
+                .take_while(|l| l.starts_with(&prefix))
+                .map(|l| &l[prefix.len()..])
+                .collect::<Vec<_>>()
+                .join("\n"),
+        )
+    }
+}
+
+/// Return a function which extracts the contents of the first block comment delimited by `start`
+/// and `end` (e.g. `/*` and `*/`) in a test file, unaltered. Since the test data's indentation is
+/// relative, it can start on the same line as `start`, e.g.:
+///
+/// ```text
+/// /* Compiler:
+///      status: success
+/// */
+/// ```
+///
+/// If the file has no block comment, the extracted test data is empty (and the test is thus
+/// ignored). If the first block comment is not terminated, no test data can be extracted.
+pub fn block_comment(start: &str, end: &str) -> impl Fn(&str) -> Option<String> + Send + Sync {
+    let start = start.to_owned();
+    let end = end.to_owned();
+    move |s| match s.find(&start) {
+        Some(i) => {
+            let s = &s[i + start.len()..];
+            s.find(&end).map(|j| s[..j].to_owned())
+        }
+        None => Some(String::new()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_comment_block() {
+        let f = line_comment_block("//");
+        assert_eq!(
+            f("#![x]\n// a:\n//   b: c\nfn main() {}\n// d:"),
+            Some(" a:\n   b: c".to_owned())
+        );
+        assert_eq!(f("fn main() {}"), Some(String::new()));
+        assert_eq!(
+            line_comment_block("#")("# a:\n#   b: c\nx"),
+            Some(" a:\n   b: c".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_block_comment() {
+        let f = block_comment("/*", "*/");
+        assert_eq!(
+            f("x\n/* a:\n     b: c\n*/\n/* d: */"),
+            Some(" a:\n     b: c\n".to_owned())
+        );
+        assert_eq!(f("fn main() {}"), Some(String::new()));
+        assert_eq!(f("/* a:"), None);
+        assert_eq!(
+            block_comment("(*", "*)")("(*a:\n  b: c*)"),
+            Some("a:\n  b: c".to_owned())
+        );
+    }
+}
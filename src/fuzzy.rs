@@ -1,48 +1,837 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use regex::{Regex, RegexBuilder};
+
 use crate::fatal;
 
-const WILDCARD: &str = "...";
+pub(crate) const WILDCARD: &str = "...";
+/// Within a line, `INTERIOR_WILDCARD` matches zero or more arbitrary characters.
+const INTERIOR_WILDCARD: &str = "{...}";
+/// A line starting with `REGEX_PREFIX` is a regular expression which must match an entire line.
+const REGEX_PREFIX: &str = "re:";
+
+/// Does `s` conform to the fuzzy pattern `pattern`? If `case_insensitive` is `true`, differences in
+/// case are ignored. A line wildcard at the end of `plines` matches all remaining lines; any other
+/// line wildcard is anchored by the (non-wildcard) line which follows it, and matches lazily
+/// without backtracking. Note that `plines` is expected not to start or end with blank lines, and
+/// each line is expected to be `trim`ed.
+pub(crate) fn match_vec(plines: &[&str], s: &str, case_insensitive: bool) -> bool {
+    match_lines(plines, &text_lines(s, false), case_insensitive)
+}
+
+/// As [`match_vec`], except that leading and trailing whitespace on each line of `s` (though not
+/// leading and trailing blank lines) is significant, and so must be matched by `plines` (whose
+/// lines are then not expected to be `trim`ed).
+pub(crate) fn match_vec_raw(plines: &[&str], s: &str, case_insensitive: bool) -> bool {
+    match_lines(plines, &text_lines(s, true), case_insensitive)
+}
+
+/// Split `s` into lines, ignoring leading and trailing blank lines. Unless `raw` is `true`, each
+/// line is also `trim`ed.
+fn text_lines(s: &str, raw: bool) -> Vec<&str> {
+    if !raw {
+        return s.trim().lines().map(|x| x.trim()).collect();
+    }
+    let lines = s.lines().collect::<Vec<_>>();
+    let is_blank = |l: &&str| l.trim().is_empty();
+    let start = lines
+        .iter()
+        .position(|l| !is_blank(l))
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|l| !is_blank(l))
+        .map_or(start, |i| i + 1);
+    lines[start..end].to_vec()
+}
+
+/// Does `slines` conform to the fuzzy pattern `plines`? See [`match_vec`].
+fn match_lines(plines: &[&str], slines: &[&str], case_insensitive: bool) -> bool {
+    let res = compile_lines(plines, case_insensitive);
+    align_lines(plines, &res, slines, case_insensitive).is_some()
+}
 
-/// Does `s` conform to the fuzzy pattern `pattern`? Note that `plines` is expected not to start or
-/// end with blank lines, and each line is expected to be `trim`ed.
-pub(crate) fn match_vec(plines: &[&str], s: &str) -> bool {
+/// If `slines` conforms to the fuzzy pattern `plines` (whose regular expression lines have been
+/// compiled into `res` by [`compile_lines`]), return `Some` containing a `(pattern line index,
+/// text line index)` pair for each non-wildcard line of `plines`, recording which line of
+/// `slines` it matched; otherwise return `None`. See [`match_vec`].
+fn align_lines(
+    plines: &[&str],
+    res: &[Option<Regex>],
+    slines: &[&str],
+    case_insensitive: bool,
+) -> Option<Vec<(usize, usize)>> {
     debug_assert!(plines.is_empty() || !plines[0].is_empty());
     debug_assert!(plines.is_empty() || !plines[plines.len() - 1].is_empty());
-    let slines = s.trim().lines().map(|x| x.trim()).collect::<Vec<_>>();
 
+    let mut aligned = Vec::new();
     let mut pi = 0;
     let mut si = 0;
 
     while pi < plines.len() && si < slines.len() {
-        if plines[pi] == WILDCARD {
+        if let Some(bounds) = wildcard_bounds(plines[pi]) {
+            let (min, max) = bounds
+                .unwrap_or_else(|e| fatal(&format!("Invalid wildcard '{}': {}.", plines[pi], e)));
             pi += 1;
             if pi == plines.len() {
-                return true;
+                let rest = slines.len() - si;
+                return (rest >= min && max.map_or(true, |max| rest <= max)).then_some(aligned);
             }
-            if plines[pi] == WILDCARD {
+            if wildcard_bounds(plines[pi]).is_some() {
                 fatal(&format!(
-                    "Can't have '{}' on two consecutive lines.",
-                    WILDCARD
+                    "Can't have '{}' and '{}' on consecutive lines.",
+                    plines[pi - 1],
+                    plines[pi]
                 ));
             }
-            while si < slines.len() && !match_line(&plines[pi], slines[si]) {
+            // A wildcard matches its minimum number of lines, and then as few more as possible
+            // (i.e. it is lazy): the first subsequent line which matches the next pattern line
+            // ends the wildcard, even if a later line would have let the rest of the pattern
+            // match. If that exceeds the wildcard's maximum, the match fails.
+            let limit = max.map_or(slines.len(), |max| slines.len().min(si + max));
+            si += min;
+            if si > slines.len() {
+                return None;
+            }
+            while si < slines.len()
+                && !match_compiled_line(plines[pi], res[pi].as_ref(), slines[si], case_insensitive)
+            {
+                if si == limit {
+                    return None;
+                }
+                si += 1;
+            }
+        } else if match_compiled_line(plines[pi], res[pi].as_ref(), slines[si], case_insensitive) {
+            aligned.push((pi, si));
+            pi += 1;
+            si += 1;
+        } else {
+            return None;
+        }
+    }
+    ((pi == plines.len() && si == slines.len())
+        || (pi + 1 == plines.len()
+            && si == slines.len()
+            && matches!(wildcard_bounds(plines[pi]), Some(Ok((0, _))))))
+    .then_some(aligned)
+}
+
+/// Matches text against a fuzzy pattern (see [`match_vec`]) a line at a time, as the text is
+/// produced, so that the text itself need never be held in memory. Since line wildcards match
+/// lazily without backtracking, the result is always the same as that of [`match_vec`] (or
+/// [`match_vec_raw`] if `raw` is `true`) on the whole text.
+pub(crate) struct StreamMatcher<'a> {
+    plines: &'a [&'a str],
+    /// The compiled regular expression lines of `plines` (see [`compile_lines`]).
+    res: Vec<Option<Regex>>,
+    raw: bool,
+    case_insensitive: bool,
+    /// The index in `plines` of the next pattern line to be matched.
+    pi: usize,
+    /// If the line wildcard `plines[pi - 1]` is being matched, `(min, max, lines matched so
+    /// far)`.
+    wildcard: Option<(usize, Option<usize>, usize)>,
+    /// The number of lines pushed so far.
+    num_lines: usize,
+    /// Has a non-blank line been pushed yet? Leading blank lines are ignored.
+    started: bool,
+    /// Blank lines which have been pushed but not yet matched: if no non-blank line follows them,
+    /// they are trailing blank lines, which are ignored.
+    blank: Vec<String>,
+    /// If the match has failed, a description of why.
+    failure: Option<String>,
+}
+
+impl<'a> StreamMatcher<'a> {
+    /// Create a matcher for the fuzzy pattern `plines`, which has the same requirements as for
+    /// [`match_vec`] (or [`match_vec_raw`] if `raw` is `true`).
+    pub(crate) fn new(plines: &'a [&'a str], raw: bool, case_insensitive: bool) -> Self {
+        debug_assert!(plines.is_empty() || !plines[0].is_empty());
+        debug_assert!(plines.is_empty() || !plines[plines.len() - 1].is_empty());
+        StreamMatcher {
+            plines,
+            res: compile_lines(plines, case_insensitive),
+            raw,
+            case_insensitive,
+            pi: 0,
+            wildcard: None,
+            num_lines: 0,
+            started: false,
+            blank: Vec::new(),
+            failure: None,
+        }
+    }
+
+    /// Match the next line of text, `line`, which must not contain a newline.
+    pub(crate) fn push_line(&mut self, line: &str) {
+        self.num_lines += 1;
+        if self.failure.is_some() {
+            return;
+        }
+        let line = if self.raw { line } else { line.trim() };
+        if line.trim().is_empty() {
+            if self.started {
+                self.blank.push(line.to_owned());
+            }
+            return;
+        }
+        self.started = true;
+        let blank = std::mem::take(&mut self.blank);
+        for (i, b) in blank.iter().enumerate() {
+            self.match_line(b, self.num_lines - blank.len() + i);
+        }
+        self.match_line(line, self.num_lines);
+    }
+
+    /// Match the line `line`, which is line `line_num` (1-based) of the text.
+    fn match_line(&mut self, line: &str, line_num: usize) {
+        if self.failure.is_some() {
+            return;
+        }
+        loop {
+            if let Some((min, max, ref mut matched)) = self.wildcard {
+                let wildcard = self.plines[self.pi - 1];
+                if self.pi == self.plines.len() {
+                    *matched += 1;
+                    if max.is_some_and(|max| *matched > max) {
+                        self.failure = Some(format!(
+                            "Line {} '{}' is after the lines matched by '{}'",
+                            line_num, line, wildcard
+                        ));
+                    }
+                } else if *matched < min {
+                    *matched += 1;
+                } else if match_compiled_line(
+                    self.plines[self.pi],
+                    self.res[self.pi].as_ref(),
+                    line,
+                    self.case_insensitive,
+                ) {
+                    self.wildcard = None;
+                    self.pi += 1;
+                } else if Some(*matched) == max {
+                    self.failure = Some(format!(
+                        "Line {} '{}' doesn't match pattern '{}' (after {} lines matched by '{}')",
+                        line_num, line, self.plines[self.pi], matched, wildcard
+                    ));
+                } else {
+                    *matched += 1;
+                }
+                return;
+            }
+            let p = match self.plines.get(self.pi) {
+                Some(p) => p,
+                None => {
+                    self.failure = Some(format!(
+                        "Line {} '{}' is after the end of the pattern",
+                        line_num, line
+                    ));
+                    return;
+                }
+            };
+            match wildcard_bounds(p) {
+                Some(bounds) => {
+                    let (min, max) = bounds
+                        .unwrap_or_else(|e| fatal(&format!("Invalid wildcard '{}': {}.", p, e)));
+                    self.pi += 1;
+                    if let Some(next) = self.plines.get(self.pi) {
+                        if wildcard_bounds(next).is_some() {
+                            fatal(&format!(
+                                "Can't have '{}' and '{}' on consecutive lines.",
+                                p, next
+                            ));
+                        }
+                    }
+                    self.wildcard = Some((min, max, 0));
+                }
+                None if match_compiled_line(
+                    p,
+                    self.res[self.pi].as_ref(),
+                    line,
+                    self.case_insensitive,
+                ) =>
+                {
+                    self.pi += 1;
+                    return;
+                }
+                None => {
+                    self.failure = Some(format!(
+                        "Line {} '{}' doesn't match pattern '{}'",
+                        line_num, line, p
+                    ));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Finish matching once all the text has been pushed, returning `None` if it matched, or
+    /// `Some` describing why it didn't.
+    pub(crate) fn finish(self) -> Option<String> {
+        if self.failure.is_some() {
+            return self.failure;
+        }
+        let mut pi = self.pi;
+        match self.wildcard {
+            Some((min, _, matched)) if pi == self.plines.len() => {
+                return (matched < min).then(|| {
+                    format!(
+                        "Output ended after {} lines matched by '{}', which must match at least {}",
+                        matched,
+                        self.plines[pi - 1],
+                        min
+                    )
+                });
+            }
+            Some(_) => (),
+            None if pi == self.plines.len() => return None,
+            None => match wildcard_bounds(self.plines[pi]) {
+                Some(Ok((0, _))) if pi + 1 == self.plines.len() => return None,
+                Some(_) if pi + 1 < self.plines.len() => pi += 1,
+                _ => (),
+            },
+        }
+        Some(format!(
+            "Output ended before a line matched pattern '{}'",
+            self.plines[pi]
+        ))
+    }
+}
+
+/// If `s` conforms to the fuzzy pattern `plines` (see [`match_vec`], or [`match_vec_raw`] if
+/// `raw` is `true`), return `Some` containing the `(name, text)` of each named capture group (e.g.
+/// `(?<id>[0-9]+)`) in the regular expression lines of `plines` which matched; otherwise return
+/// `None`.
+pub(crate) fn match_captures(
+    plines: &[&str],
+    s: &str,
+    raw: bool,
+    case_insensitive: bool,
+) -> Option<Vec<(String, String)>> {
+    let slines = text_lines(s, raw);
+    let res = compile_lines(plines, case_insensitive);
+    let mut captures = Vec::new();
+    for (pi, si) in align_lines(plines, &res, &slines, case_insensitive)? {
+        let re = match res[pi] {
+            Some(ref re) => re,
+            None => continue,
+        };
+        let caps = re.captures(slines[si]).unwrap();
+        for name in re.capture_names().flatten() {
+            if let Some(m) = caps.name(name) {
+                captures.push((name.to_owned(), m.as_str().to_owned()));
+            }
+        }
+    }
+    Some(captures)
+}
+
+/// Return `s` with each reference `${name}` to a capture in `captures` replaced by the capture's
+/// text. References to names which aren't in `captures` are left untouched.
+pub(crate) fn substitute(s: &str, captures: &HashMap<String, String>) -> String {
+    substitute_with(s, |name| captures.get(name).cloned())
+}
+
+/// As [`substitute`], except that if the pattern line `p` is a regular expression line, the text
+/// of each capture is escaped, so that it is matched literally.
+pub(crate) fn substitute_pattern(p: &str, captures: &HashMap<String, String>) -> String {
+    if p.starts_with(REGEX_PREFIX) {
+        substitute_with(p, |name| captures.get(name).map(|text| regex::escape(text)))
+    } else {
+        substitute(p, captures)
+    }
+}
+
+/// Return the pattern line `p` with every `${name}` reference replaced by a placeholder, so that
+/// the line can be checked for validity before the references' values are known.
+pub(crate) fn mask_references(p: &str) -> String {
+    substitute_with(p, |_| Some("x".to_owned()))
+}
+
+/// Return `s` with each `${name}` reference replaced by `f(name)`, unless that is `None`.
+fn substitute_with<F>(s: &str, f: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find("${") {
+        out.push_str(&rest[..i]);
+        let after = &rest[i + 2..];
+        let len = after
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(after.len());
+        let name = &after[..len];
+        let is_ref = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && after[len..].starts_with('}');
+        match if is_ref { f(name) } else { None } {
+            Some(text) => {
+                out.push_str(&text);
+                rest = &after[len + 1..];
+            }
+            None => {
+                out.push_str("${");
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// If `p` is a wildcard line, return `Some` containing the inclusive bounds `(min, max)` on the
+/// number of lines it matches, where `max` is `None` if it is unbounded, or `Err` describing why
+/// the bounds are invalid. Wildcard lines are `...` (any number of lines), `...{n}` (exactly `n` lines),
+/// `...{m,n}` (between `m` and `n` lines), and `...{m,}` (at least `m` lines).
+pub(crate) fn wildcard_bounds(p: &str) -> Option<Result<(usize, Option<usize>), String>> {
+    if p == WILDCARD {
+        return Some(Ok((0, None)));
+    }
+    let bounds = p
+        .strip_prefix(WILDCARD)?
+        .strip_prefix('{')?
+        .strip_suffix('}')?;
+    if bounds.is_empty()
+        || !bounds
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == ',' || c == ' ')
+    {
+        return None;
+    }
+    let parse = |s: &str| {
+        s.trim()
+            .parse::<usize>()
+            .map_err(|_| "bounds must be non-negative integers".to_owned())
+    };
+    Some(match bounds.split_once(',') {
+        None => parse(bounds).map(|n| (n, Some(n))),
+        Some((min, max)) if max.trim().is_empty() => parse(min).map(|min| (min, None)),
+        Some((min, max)) => parse(min).and_then(|min| match parse(max)? {
+            max if max < min => Err("the minimum is greater than the maximum".to_owned()),
+            max => Ok((min, Some(max))),
+        }),
+    })
+}
+
+/// Return the number of lines in `s`, ignoring leading and trailing blank lines (as
+/// [`match_vec`] does).
+pub(crate) fn line_count(s: &str) -> usize {
+    s.trim().lines().count()
+}
+
+/// Return `s` with all ANSI CSI escape sequences (e.g. `\x1b[1;31m`, which sets the colour of
+/// subsequent text on a terminal) removed. An unterminated escape sequence is left untouched.
+pub(crate) fn strip_ansi(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find("\x1b[") {
+        stripped.push_str(&rest[..i]);
+        let seq = &rest[i + 2..];
+        // A sequence is any number of parameter bytes (0x30-0x3F), then any number of
+        // intermediate bytes (0x20-0x2F), then a single final byte (0x40-0x7E).
+        let params = seq
+            .bytes()
+            .take_while(|b| (0x30..=0x3F).contains(b))
+            .count();
+        let inters = seq[params..]
+            .bytes()
+            .take_while(|b| (0x20..=0x2F).contains(b))
+            .count();
+        match seq.as_bytes().get(params + inters) {
+            Some(b) if (0x40..=0x7E).contains(b) => rest = &seq[params + inters + 1..],
+            _ => {
+                stripped.push_str(&rest[i..i + 2]);
+                rest = seq;
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Return `s` with each `\r\n` and each lone `\r` replaced by `\n`, so that text written with any
+/// platform's line endings is split into the same lines.
+pub(crate) fn normalize_newlines(s: &str) -> Cow<'_, str> {
+    if !s.contains('\r') {
+        return Cow::Borrowed(s);
+    }
+    Cow::Owned(s.replace("\r\n", "\n").replace('\r', "\n"))
+}
+
+/// Return the first line of `s` which matches any of the line patterns in `plines`, as a tuple
+/// `(pattern, line)`, or `None` if no line matches. Note that `...` is only meaningful at the start
+/// or end of a pattern, and that each pattern is expected to be `trim`ed.
+pub(crate) fn find_line<'a, 'b>(
+    plines: &[&'a str],
+    s: &'b str,
+    case_insensitive: bool,
+) -> Option<(&'a str, &'b str)> {
+    debug_assert!(plines.iter().all(|p| wildcard_bounds(p).is_none()));
+    let res = compile_lines(plines, case_insensitive);
+    for sline in s.trim().lines().map(|x| x.trim()) {
+        for (p, re) in plines.iter().zip(&res) {
+            if match_compiled_line(p, re.as_ref(), sline, case_insensitive) {
+                return Some((p, sline));
+            }
+        }
+    }
+    None
+}
+
+/// Check that the line patterns `plines` match lines of `s` in order, though not necessarily
+/// adjacent lines (i.e. that they match a subsequence of the lines of `s`). Returns `None` if they
+/// do, or `Some((pattern, line))` for the first pattern which doesn't match any line after `line`,
+/// the line matched by the previous pattern (if there is one). As with [`find_line`], `...` is
+/// only meaningful at the start or end of a pattern, and each pattern is expected to be `trim`ed.
+pub(crate) fn find_unmatched_subsequence<'a, 'b>(
+    plines: &[&'a str],
+    s: &'b str,
+    case_insensitive: bool,
+) -> Option<(&'a str, Option<&'b str>)> {
+    debug_assert!(plines.iter().all(|p| wildcard_bounds(p).is_none()));
+    let mut slines = s.trim().lines().map(|x| x.trim());
+    let mut prev = None;
+    for (p, re) in plines.iter().zip(compile_lines(plines, case_insensitive)) {
+        // Matching each pattern against the earliest line it can is never worse than matching it
+        // against a later one.
+        match slines.find(|sline| match_compiled_line(p, re.as_ref(), sline, case_insensitive)) {
+            Some(sline) => prev = Some(sline),
+            None => return Some((p, prev)),
+        }
+    }
+    None
+}
+
+/// Return the first of the line patterns `plines` which doesn't match any line of `s` (the patterns
+/// can match lines in any order, and several can match the same line), or `None` if they all
+/// match. If `substring` is `true`, a pattern need only match part of a line (e.g. `x` matches
+/// `axb`, as `...x...` would). As with [`find_line`], `...` is only meaningful at the start or end
+/// of a pattern, and each pattern is expected to be `trim`ed.
+pub(crate) fn find_missing_line<'a>(
+    plines: &[&'a str],
+    s: &str,
+    substring: bool,
+    case_insensitive: bool,
+) -> Option<&'a str> {
+    debug_assert!(plines.iter().all(|p| wildcard_bounds(p).is_none()));
+    plines.iter().copied().find(|p| {
+        let p = match substring {
+            true => substring_pattern(p),
+            false => Cow::Borrowed(*p),
+        };
+        let re = compile_line(&p, case_insensitive);
+        !s.trim()
+            .lines()
+            .any(|sline| match_compiled_line(&p, re.as_ref(), sline.trim(), case_insensitive))
+    })
+}
+
+/// Return a line pattern which matches any line containing a match of the line pattern `p`.
+fn substring_pattern(p: &str) -> Cow<'_, str> {
+    if let Some(re) = p.strip_prefix(REGEX_PREFIX) {
+        return Cow::Owned(format!("{}.*(?:{}).*", REGEX_PREFIX, re.trim_start()));
+    }
+    let sww = if p.starts_with(WILDCARD) {
+        ""
+    } else {
+        WILDCARD
+    };
+    let eww = if p.ends_with(WILDCARD) { "" } else { WILDCARD };
+    Cow::Owned(format!("{}{}{}", sww, p, eww))
+}
+
+/// Return the byte offset just after the first line of `s` which matches the line pattern `p`, or
+/// `None` if no line matches. The last line of `s` need not end with a newline, so that e.g. a
+/// prompt can be matched before a command has finished writing its line. Each line is converted
+/// to UTF-8 lossily, so `s` need not be valid UTF-8 (and may end part way through a character). As
+/// with [`find_line`], `p` must not be a wildcard line and is expected to be `trim`ed.
+pub(crate) fn find_line_end(p: &str, s: &[u8], case_insensitive: bool) -> Option<usize> {
+    debug_assert!(wildcard_bounds(p).is_none());
+    let re = compile_line(p, case_insensitive);
+    let mut off = 0;
+    for sline in s.split_inclusive(|&b| b == b'\n') {
+        off += sline.len();
+        let sline = String::from_utf8_lossy(sline);
+        if match_compiled_line(p, re.as_ref(), sline.trim(), case_insensitive) {
+            return Some(off);
+        }
+    }
+    None
+}
+
+/// Split `s` into lines as `exact_mismatch` does: leading/trailing whitespace is trimmed from `s`
+/// and from each line.
+pub(crate) fn exact_lines(s: &str) -> Vec<&str> {
+    let s = s.trim();
+    if s.is_empty() {
+        Vec::new()
+    } else {
+        s.lines().map(|x| x.trim()).collect()
+    }
+}
+
+/// Check that the lines of `s` are exactly `elines`, with no wildcard or regular expression
+/// interpretation (leading and trailing whitespace is ignored, as in [`match_vec`]). Returns
+/// `None` if they are, or `Some` describing the first difference otherwise. Note that `elines` is
+/// expected not to start or end with blank lines, and each line is expected to be `trim`ed.
+pub(crate) fn exact_mismatch(elines: &[&str], s: &str) -> Option<String> {
+    let slines = exact_lines(s);
+    for (i, (e, l)) in elines.iter().zip(slines.iter()).enumerate() {
+        if e != l {
+            // The (1-based, in characters) column of the first difference.
+            let col = e
+                .chars()
+                .zip(l.chars())
+                .take_while(|(ec, lc)| ec == lc)
+                .count()
+                + 1;
+            return Some(format!(
+                "Line {} differs at column {}: expected '{}' but got '{}'",
+                i + 1,
+                col,
+                e,
+                l
+            ));
+        }
+    }
+    if elines.len() > slines.len() {
+        Some(format!(
+            "Line {} is missing: expected '{}'",
+            slines.len() + 1,
+            elines[slines.len()]
+        ))
+    } else if slines.len() > elines.len() {
+        Some(format!(
+            "Line {} is unexpected: got '{}'",
+            elines.len() + 1,
+            slines[elines.len()]
+        ))
+    } else {
+        None
+    }
+}
+
+/// If `p` is a regular expression line, return `Some` containing the result of compiling it
+/// (anchored so that it must match an entire line, and ignoring case if `case_insensitive` is
+/// `true`); otherwise return `None`.
+pub(crate) fn line_regex(p: &str, case_insensitive: bool) -> Option<Result<Regex, regex::Error>> {
+    p.strip_prefix(REGEX_PREFIX).map(|re| {
+        RegexBuilder::new(&format!("^(?:{})$", re.trim_start()))
+            .case_insensitive(case_insensitive)
+            .build()
+    })
+}
+
+/// A line in the diff between a fuzzy pattern and the text it was matched against.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DiffLine<'a> {
+    /// A line of text which was matched by the pattern (including lines matched by a `...`
+    /// wildcard line).
+    Both(&'a str),
+    /// A wildcard line (e.g. `...`) in the pattern.
+    Wildcard(&'a str),
+    /// A pattern line which did not match any line of text.
+    Expected(&'a str),
+    /// A line of text which was not matched by any pattern line.
+    Actual(&'a str),
+}
+
+/// Return a line-by-line diff between the fuzzy pattern `plines` and `s`. The diff is the
+/// alignment with the fewest unmatched lines: when `match_vec(plines, s, case_insensitive)` is
+/// `true`, no line in the diff will be `Expected` or `Actual`. The same assumptions about `plines`
+/// are made as for `match_vec`.
+pub(crate) fn diff<'a>(
+    plines: &[&'a str],
+    s: &'a str,
+    case_insensitive: bool,
+) -> Vec<DiffLine<'a>> {
+    diff_lines(plines, &text_lines(s, false), case_insensitive)
+}
+
+/// As [`diff`], but with whitespace significant in the same way as for [`match_vec_raw`].
+pub(crate) fn diff_raw<'a>(
+    plines: &[&'a str],
+    s: &'a str,
+    case_insensitive: bool,
+) -> Vec<DiffLine<'a>> {
+    diff_lines(plines, &text_lines(s, true), case_insensitive)
+}
+
+/// Return a line-by-line diff between the fuzzy pattern `plines` and `slines`. See [`diff`].
+fn diff_lines<'a>(
+    plines: &[&'a str],
+    slines: &[&'a str],
+    case_insensitive: bool,
+) -> Vec<DiffLine<'a>> {
+    let (plen, slen) = (plines.len(), slines.len());
+    let res = compile_lines(plines, case_insensitive);
+    // `costs[pi][si]` is the minimum number of unmatched lines when aligning `plines[pi..]` with
+    // `slines[si..]`.
+    let mut costs = vec![vec![0; slen + 1]; plen + 1];
+    for pi in (0..=plen).rev() {
+        for si in (0..=slen).rev() {
+            costs[pi][si] = if pi == plen {
+                slen - si
+            } else if plines[pi] == WILDCARD {
+                if si == slen {
+                    costs[pi + 1][si]
+                } else {
+                    costs[pi + 1][si].min(costs[pi][si + 1])
+                }
+            } else if let Some(bounds) = wildcard_bounds(plines[pi]) {
+                // A bounded wildcard which absorbs fewer lines than its minimum counts as a
+                // single unmatched line.
+                let (min, max) = bounds.unwrap_or_else(|e| {
+                    fatal(&format!("Invalid wildcard '{}': {}.", plines[pi], e))
+                });
+                let max = max.map_or(slen - si, |max| max.min(slen - si));
+                (0..=max)
+                    .map(|k| costs[pi + 1][si + k] + usize::from(k < min))
+                    .min()
+                    .unwrap()
+            } else if si == slen {
+                costs[pi + 1][si] + 1
+            } else if match_compiled_line(
+                plines[pi],
+                res[pi].as_ref(),
+                slines[si],
+                case_insensitive,
+            ) {
+                costs[pi + 1][si + 1]
+            } else {
+                (costs[pi + 1][si] + 1).min(costs[pi][si + 1] + 1)
+            };
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut pi = 0;
+    let mut si = 0;
+    while pi < plen || si < slen {
+        if pi == plen {
+            out.push(DiffLine::Actual(slines[si]));
+            si += 1;
+        } else if plines[pi] == WILDCARD {
+            // A wildcard absorbs lines only for as long as that gives a better alignment than
+            // moving on to the next pattern line.
+            out.push(DiffLine::Wildcard(plines[pi]));
+            while si < slen
+                && costs[pi][si + 1] == costs[pi][si]
+                && costs[pi + 1][si] != costs[pi][si]
+            {
+                out.push(DiffLine::Both(slines[si]));
+                si += 1;
+            }
+            pi += 1;
+        } else if let Some(bounds) = wildcard_bounds(plines[pi]) {
+            // Absorb the fewest lines which give the best alignment, preferring not to absorb
+            // fewer than the wildcard's minimum.
+            let (min, max) = bounds
+                .unwrap_or_else(|e| fatal(&format!("Invalid wildcard '{}': {}.", plines[pi], e)));
+            let max = max.map_or(slen - si, |max| max.min(slen - si));
+            let k = (min.min(max + 1)..=max)
+                .chain(0..min.min(max + 1))
+                .find(|&k| costs[pi + 1][si + k] + usize::from(k < min) == costs[pi][si])
+                .unwrap();
+            if k < min {
+                out.push(DiffLine::Expected(plines[pi]));
+            } else {
+                out.push(DiffLine::Wildcard(plines[pi]));
+            }
+            for _ in 0..k {
+                out.push(DiffLine::Both(slines[si]));
                 si += 1;
             }
-        } else if match_line(&plines[pi], slines[si]) {
+            pi += 1;
+        } else if si < slen
+            && costs[pi][si] == costs[pi + 1][si + 1]
+            && match_compiled_line(plines[pi], res[pi].as_ref(), slines[si], case_insensitive)
+        {
+            out.push(DiffLine::Both(slines[si]));
             pi += 1;
             si += 1;
+        } else if si == slen || costs[pi][si] == costs[pi + 1][si] + 1 {
+            out.push(DiffLine::Expected(plines[pi]));
+            pi += 1;
+        } else {
+            out.push(DiffLine::Actual(slines[si]));
+            si += 1;
+        }
+    }
+    out
+}
+
+/// If the pattern line `p` is a regular expression line, compile it (see [`line_regex`]).
+fn compile_line(p: &str, case_insensitive: bool) -> Option<Regex> {
+    line_regex(p, case_insensitive).map(|re| {
+        re.unwrap_or_else(|e| fatal(&format!("Invalid regular expression '{}':\n{}", p, e)))
+    })
+}
+
+/// Compile each line of the pattern `plines` with [`compile_line`], so that a regular expression
+/// line which is matched against many lines of text is compiled only once.
+fn compile_lines(plines: &[&str], case_insensitive: bool) -> Vec<Option<Regex>> {
+    plines
+        .iter()
+        .map(|p| compile_line(p, case_insensitive))
+        .collect()
+}
+
+/// Does the line `s` match the pattern `p`, where `re` is the result of [`compile_line`] on `p`?
+/// If `case_insensitive` is `true`, differences in case are ignored. Note that both strings are
+/// expected to be trimed before being passed to this function.
+fn match_compiled_line(p: &str, re: Option<&Regex>, s: &str, case_insensitive: bool) -> bool {
+    if let Some(re) = re {
+        return re.is_match(s);
+    }
+    if case_insensitive {
+        return match_literal_line(&p.to_lowercase(), &s.to_lowercase());
+    }
+    match_literal_line(p, s)
+}
+
+/// Does the line `s` match the pattern `p`, which contains one or more interior wildcards? `sww`
+/// and `eww` say whether `p` starts and/or ends with an edge wildcard: these take precedence over
+/// interior wildcards (e.g. `...{...}` is an edge wildcard followed by `{...}`).
+fn match_interior_line(mut p: &str, s: &str, sww: bool, eww: bool) -> bool {
+    if sww {
+        p = &p[WILDCARD.len()..];
+    }
+    if eww {
+        p = &p[..p.len() - WILDCARD.len()];
+    }
+    let segs = p.split(INTERIOR_WILDCARD).collect::<Vec<_>>();
+    // Each segment (other than the last, which may need to be anchored to the end of `s`) is
+    // matched as far to the left as possible, leaving as much of `s` as possible for the rest.
+    let mut rest = s;
+    for (i, seg) in segs[..segs.len() - 1].iter().enumerate() {
+        if i == 0 && !sww {
+            if !rest.starts_with(seg) {
+                return false;
+            }
+            rest = &rest[seg.len()..];
         } else {
-            return false;
+            match rest.find(seg) {
+                Some(j) => rest = &rest[j + seg.len()..],
+                None => return false,
+            }
         }
     }
-    (pi == plines.len() && si == slines.len())
-        || (pi + 1 == plines.len() && plines[pi] == WILDCARD && si == slines.len())
+    let last = segs[segs.len() - 1];
+    if eww {
+        rest.contains(last)
+    } else {
+        rest.ends_with(last)
+    }
 }
 
-/// Does the line `s` match the pattern `p`? Note that both strings are expected to be trimed
-/// before being passed to this function.
-fn match_line(p: &str, s: &str) -> bool {
+/// Does the line `s` match the non-regular expression pattern `p`?
+fn match_literal_line(p: &str, s: &str) -> bool {
     let sww = p.starts_with(WILDCARD);
     let eww = p.ends_with(WILDCARD);
+    if p.contains(INTERIOR_WILDCARD) {
+        return match_interior_line(p, s, sww, eww);
+    }
     if sww && eww {
         s.find(&p[WILDCARD.len()..p.len() - WILDCARD.len()])
             .is_some()
@@ -62,7 +851,7 @@ mod tests {
     #[test]
     fn test_match_vec() {
         fn match_vec_helper(p: &str, s: &str) -> bool {
-            match_vec(&p.lines().collect::<Vec<_>>(), s)
+            match_vec(&p.lines().collect::<Vec<_>>(), s, false)
         }
         assert!(match_vec_helper("", ""));
         assert!(match_vec_helper("a", "a"));
@@ -88,4 +877,513 @@ mod tests {
         assert!(match_vec_helper("a\n...b...", "a\nxb"));
         assert!(!match_vec_helper("a\n...b...", "a\nxb\nc"));
     }
+
+    #[test]
+    fn test_match_vec_wildcard_anchoring() {
+        fn match_vec_helper(p: &str, s: &str) -> bool {
+            match_vec(&p.lines().collect::<Vec<_>>(), s, false)
+        }
+        // A trailing wildcard matches everything up to the end of the output, including nothing.
+        assert!(match_vec_helper("a\n...", "a"));
+        assert!(match_vec_helper("a\n...", "a\nb\nc"));
+        assert!(!match_vec_helper("a\n...", "b\na"));
+        assert!(match_vec_helper("a\n...{1,}", "a\nb\nc"));
+        assert!(!match_vec_helper("a\n...{1,}", "a"));
+        // A wildcard elsewhere is anchored by the line following it, which must match.
+        assert!(match_vec_helper("...\nc", "a\nb\nc"));
+        assert!(!match_vec_helper("...\nc", "a\nb\nc\nd"));
+        assert!(match_vec_helper("...\nre:[a-z]\nd", "1\n2\nc\nd"));
+        // The wildcard stops at the first line matching its anchor and never backtracks, even if
+        // stopping at a later line would have let the rest of the pattern match.
+        assert!(match_vec_helper("...\nb\nc", "a\nb\nc"));
+        assert!(!match_vec_helper("...\nb\nc", "b\nx\nb\nc"));
+        assert!(!match_vec_helper("a\n...\nb\n...{1}\nd", "a\nb\nb\nc\nd"));
+        assert!(match_vec_helper("a\n...\nb\n...\nd", "a\nb\nb\nc\nd"));
+        // But a wildcard's minimum is always matched before looking for the anchor.
+        assert!(match_vec_helper("...{2}\nb\nc", "b\nx\nb\nc"));
+        assert!(match_vec_helper("...{1,}\nb\nc", "b\nb\nc"));
+    }
+
+    #[test]
+    fn test_match_vec_whitespace() {
+        // Leading and trailing whitespace on each line, as well as leading and trailing blank
+        // lines, is ignored.
+        assert!(match_vec(&["a", "b"], "a  \nb\t", false));
+        assert!(match_vec(&["a", "b"], "\n  a\n b \n\n", false));
+        assert!(match_vec(&["a", "...", "c"], "a \nb  \nc  ", false));
+        assert!(match_vec(&["re: [a-z]+"], "abc  ", false));
+        // But whitespace within a line, and blank lines between lines, are significant.
+        assert!(!match_vec(&["a b"], "a  b", false));
+        assert!(!match_vec(&["a", "b"], "a\n\nb", false));
+        assert_eq!(exact_mismatch(&["a", "b"], "a  \n  b\t"), None);
+    }
+
+    #[test]
+    fn test_stream_matcher() {
+        fn stream(p: &[&str], s: &str, raw: bool) -> Option<String> {
+            let mut m = StreamMatcher::new(p, raw, false);
+            s.lines().for_each(|l| m.push_line(l));
+            m.finish()
+        }
+        // The result is always the same as when the whole text is matched at once.
+        let cases: &[(&[&str], &str)] = &[
+            (&[], ""),
+            (&[], "a"),
+            (&["a"], "\n a \n\n"),
+            (&["a"], "a\nb"),
+            (&["a", "b"], "a\n\nb"),
+            (&["a", "", "b"], "a\n\nb\n"),
+            (&["..."], ""),
+            (&["a", "..."], "a\nb\nc"),
+            (&["a", "...", "d"], "a\nb\nc\nd"),
+            (&["a", "...", "d"], "a\nb\nc"),
+            (&["a", "...", "c", "...", "e"], "a\nb\nc\nd\ne"),
+            (&["a", "...{2}", "d"], "a\nb\nc\nd"),
+            (&["a", "...{2}", "d"], "a\nb\nd"),
+            (&["a", "...{2}", "d"], "a\nb\nc\nc\nd"),
+            (&["a", "...{1,}"], "a"),
+            (&["a", "...{0,1}"], "a\nb\nc"),
+            (&["a", "...{0,2}"], "a\nb\n\n"),
+            (&["...", "b", "c"], "b\nb\nc"),
+            (&["re:[0-9]+", "...b"], "12\nab"),
+        ];
+        for (p, s) in cases {
+            assert_eq!(
+                stream(p, s, false).is_none(),
+                match_vec(p, s, false),
+                "{:?} {:?}",
+                p,
+                s
+            );
+            assert_eq!(stream(p, s, true).is_none(), match_vec_raw(p, s, false));
+        }
+        assert_eq!(
+            stream(&["a", "b"], "a\nc", false),
+            Some("Line 2 'c' doesn't match pattern 'b'".to_owned())
+        );
+        assert_eq!(
+            stream(&["a"], "a\n\nb", false),
+            Some("Line 2 '' is after the end of the pattern".to_owned())
+        );
+        assert_eq!(
+            stream(&["a", "...", "c"], "a\nb", false),
+            Some("Output ended before a line matched pattern 'c'".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_match_vec_raw() {
+        fn match_vec_raw_helper(p: &str, s: &str) -> bool {
+            match_vec_raw(&p.lines().collect::<Vec<_>>(), s, false)
+        }
+        assert!(match_vec_raw_helper("a\n  b", "a\n  b\n"));
+        assert!(!match_vec_raw_helper("a\n  b", "a\nb"));
+        assert!(!match_vec_raw_helper("a\nb", "a\n  b"));
+        assert!(!match_vec_raw_helper("a", "a "));
+        // Leading and trailing blank lines are still ignored.
+        assert!(match_vec_raw_helper("  a", "\n  \n  a\n\n"));
+        // An unindented `...` is a line wildcard; an indented one ends a line.
+        assert!(match_vec_raw_helper("{\n...\n}", "{\n  a\n    b\n}"));
+        assert!(match_vec_raw_helper("{\n  ...\n}", "{\n  a\n}"));
+        assert!(!match_vec_raw_helper("{\n  ...\n}", "{\n a\n}"));
+        assert!(!match_vec_raw_helper("{\n  ...\n}", "{\n  a\n  b\n}"));
+        assert!(match_vec_raw_helper("...a", "   a"));
+        assert!(match_vec_raw_helper("x{...}y", "x  y"));
+        assert!(match_vec_raw_helper("re:\\s{2}a", "  a"));
+        assert!(!match_vec_raw_helper("re:a", "  a"));
+        assert_eq!(
+            diff_raw(&["a", "  b"], "a\nb", false),
+            vec![
+                DiffLine::Both("a"),
+                DiffLine::Expected("  b"),
+                DiffLine::Actual("b")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_match_interior() {
+        fn match_vec_helper(p: &str, s: &str) -> bool {
+            match_vec(&p.lines().collect::<Vec<_>>(), s, false)
+        }
+        assert!(match_vec_helper("at 0x{...}:", "at 0x7fff1234:"));
+        assert!(match_vec_helper("at 0x{...}:", "at 0x:"));
+        assert!(!match_vec_helper("at 0x{...}:", "at 0x7fff1234"));
+        assert!(!match_vec_helper("at 0x{...}:", "x at 0x1:"));
+        assert!(match_vec_helper("{...}", "anything"));
+        assert!(match_vec_helper("a{...}b{...}c", "a1b2c"));
+        assert!(match_vec_helper("a{...}b{...}c", "abbcc"));
+        assert!(!match_vec_helper("a{...}b{...}c", "acb"));
+        assert!(!match_vec_helper("ab{...}bc", "abc"));
+        assert!(match_vec_helper("ab{...}bc", "abbc"));
+        assert!(match_vec_helper("...a{...}b", "xxa1b"));
+        assert!(!match_vec_helper("...a{...}b", "xxa1bc"));
+        assert!(match_vec_helper("a{...}b...", "a1bcd"));
+        assert!(match_vec_helper("...a{...}b...", "xa1by"));
+        assert!(match_vec_helper("...{...}...", "x"));
+        assert!(match_vec_helper("x\n...\nz{...}", "x\ny\nz1"));
+        assert!(match_vec(&["A{...}B"], "a1b", true));
+    }
+
+    #[test]
+    fn test_match_bounded_wildcard() {
+        fn match_vec_helper(p: &str, s: &str) -> bool {
+            match_vec(&p.lines().collect::<Vec<_>>(), s, false)
+        }
+        assert!(match_vec_helper("a\n...{2}\nd", "a\nb\nc\nd"));
+        assert!(!match_vec_helper("a\n...{2}\nd", "a\nb\nd"));
+        assert!(!match_vec_helper("a\n...{2}\nd", "a\nb\nc\nc\nd"));
+        assert!(match_vec_helper("a\n...{0}\nd", "a\nd"));
+        assert!(!match_vec_helper("a\n...{0}\nd", "a\nb\nd"));
+        assert!(!match_vec_helper("a\n...{1,2}\nd", "a\nd"));
+        assert!(match_vec_helper("a\n...{1,2}\nd", "a\nb\nd"));
+        assert!(match_vec_helper("a\n...{1,2}\nd", "a\nb\nc\nd"));
+        assert!(!match_vec_helper("a\n...{1,2}\nd", "a\nb\nc\nc\nd"));
+        assert!(!match_vec_helper("a\n...{2,}\nd", "a\nb\nd"));
+        assert!(match_vec_helper("a\n...{2,}\nd", "a\nb\nc\nc\nd"));
+        // Trailing and leading wildcards.
+        assert!(match_vec_helper("a\n...{1,2}", "a\nb\nc"));
+        assert!(!match_vec_helper("a\n...{1,2}", "a\nb\nc\nd"));
+        assert!(!match_vec_helper("a\n...{1,2}", "a"));
+        assert!(match_vec_helper("a\n...{0,2}", "a"));
+        assert!(match_vec_helper("...{1}\nb", "a\nb"));
+        assert!(!match_vec_helper("...{1}\nb", "b"));
+        assert!(!match_vec_helper("...{2}", ""));
+        // Wildcards are lazy: the first line after the minimum which matches the next pattern line
+        // ends the wildcard.
+        assert!(!match_vec_helper("...{0,2}\nb\nc", "b\nb\nc"));
+        assert!(match_vec_helper("...{1,2}\nb\nc", "b\nb\nc"));
+        // A line which merely starts with a wildcard line is not itself a wildcard line.
+        assert!(match_vec_helper("...{3}.", "x{3}."));
+        assert!(match_vec_helper("...{...}", "x"));
+    }
+
+    #[test]
+    fn test_wildcard_bounds() {
+        assert_eq!(wildcard_bounds("..."), Some(Ok((0, None))));
+        assert_eq!(wildcard_bounds("...{3}"), Some(Ok((3, Some(3)))));
+        assert_eq!(wildcard_bounds("...{1, 5}"), Some(Ok((1, Some(5)))));
+        assert_eq!(wildcard_bounds("...{2,}"), Some(Ok((2, None))));
+        assert_eq!(wildcard_bounds("...{2,2}"), Some(Ok((2, Some(2)))));
+        assert!(matches!(wildcard_bounds("...{3,1}"), Some(Err(_))));
+        assert!(matches!(wildcard_bounds("...{,1}"), Some(Err(_))));
+        assert!(matches!(wildcard_bounds("...{1,2,3}"), Some(Err(_))));
+        assert_eq!(wildcard_bounds("...{}"), None);
+        assert_eq!(wildcard_bounds("...{...}"), None);
+        assert_eq!(wildcard_bounds("...{x}"), None);
+        assert_eq!(wildcard_bounds("a...{1}"), None);
+    }
+
+    #[test]
+    fn test_match_vec_regex() {
+        fn match_vec_helper(p: &str, s: &str) -> bool {
+            match_vec(&p.lines().collect::<Vec<_>>(), s, false)
+        }
+        assert!(match_vec_helper("re:a+", "aaa"));
+        assert!(!match_vec_helper("re:a+", "aab"));
+        assert!(!match_vec_helper("re:a+", "baa"));
+        assert!(match_vec_helper("re: a|b", "b"));
+        assert!(match_vec_helper(
+            "x\nre:0x[0-9a-f]+\n...\ny",
+            "x\n0x1f\nz\ny"
+        ));
+        assert!(match_vec_helper(
+            "...\nre:[0-9]+ items\n...",
+            "a\n12 items\nb"
+        ));
+        assert!(!match_vec_helper(
+            "...\nre:[0-9]+ items\n...",
+            "a\nno items\nb"
+        ));
+        assert!(match_vec_helper("re:a...", "a..."));
+    }
+
+    #[test]
+    fn test_match_captures() {
+        fn captures_helper(p: &str, s: &str) -> Option<Vec<(String, String)>> {
+            match_captures(&p.lines().collect::<Vec<_>>(), s, false, false)
+        }
+        fn caps(pairs: &[(&str, &str)]) -> Option<Vec<(String, String)>> {
+            Some(
+                pairs
+                    .iter()
+                    .map(|(n, t)| (n.to_string(), t.to_string()))
+                    .collect(),
+            )
+        }
+        assert_eq!(captures_helper("a\n...", "a\nb"), caps(&[]));
+        assert_eq!(
+            captures_helper("...\nre:id (?<id>[0-9]+)\n...", "x\nid 12\nid 34"),
+            caps(&[("id", "12")])
+        );
+        assert_eq!(
+            captures_helper("re:(?<a>x)|(?<b>y)\nre:(?P<c>.*)", "y\n z "),
+            caps(&[("b", "y"), ("c", "z")])
+        );
+        assert_eq!(captures_helper("re:(?<id>[0-9]+)", "x"), None);
+        assert_eq!(
+            match_captures(&["re:(?<s>.*)"], "   a ", true, false),
+            caps(&[("s", "   a ")])
+        );
+    }
+
+    #[test]
+    fn test_substitute() {
+        let captures = vec![("id".to_owned(), "1.2".to_owned())]
+            .into_iter()
+            .collect::<HashMap<_, _>>();
+        assert_eq!(substitute("x ${id} ${id}", &captures), "x 1.2 1.2");
+        assert_eq!(
+            substitute("${other} $id {id}", &captures),
+            "${other} $id {id}"
+        );
+        assert_eq!(substitute_pattern("...${id}", &captures), "...1.2");
+        assert_eq!(substitute_pattern("re:${id}", &captures), "re:1\\.2");
+        assert_eq!(mask_references("re:a${id}b"), "re:axb");
+        assert_eq!(
+            substitute("${${id}} ${1id} ${id ${é} ${", &captures),
+            "${1.2} ${1id} ${id ${é} ${"
+        );
+    }
+
+    #[test]
+    fn test_match_vec_case_insensitive() {
+        fn match_vec_helper(p: &str, s: &str, case_insensitive: bool) -> bool {
+            match_vec(&p.lines().collect::<Vec<_>>(), s, case_insensitive)
+        }
+        assert!(!match_vec_helper("Warning: x", "warning: X", false));
+        assert!(match_vec_helper("Warning: x", "warning: X", true));
+        assert!(match_vec_helper("a\n...\nERROR...", "A\nb\nerror: y", true));
+        assert!(match_vec_helper("...Unused...", "an UNUSED variable", true));
+        assert!(!match_vec_helper(
+            "...Unused...",
+            "an UNUSED variable",
+            false
+        ));
+        assert!(match_vec_helper("re:[a-z]+ \\S+", "ABC DEF", true));
+        assert!(!match_vec_helper("re:[a-z]+ \\S+", "ABC DEF", false));
+        assert!(!match_vec_helper("a\nb", "A\nc", true));
+    }
+
+    #[test]
+    fn test_find_line() {
+        assert_eq!(find_line(&[], "a\nb", false), None);
+        assert_eq!(find_line(&["c"], "a\nb", false), None);
+        assert_eq!(find_line(&["b"], "a\n  b  ", false), Some(("b", "b")));
+        assert_eq!(find_line(&["pan"], "panic!", false), None);
+        assert_eq!(
+            find_line(&["...error...", "pan..."], "x\npanic!\nan error", false),
+            Some(("pan...", "panic!"))
+        );
+        assert_eq!(find_line(&["...PANIC..."], "a panic", false), None);
+        assert_eq!(
+            find_line(&["...PANIC..."], "a panic", true),
+            Some(("...PANIC...", "a panic"))
+        );
+        assert_eq!(
+            find_line(&["re:[0-9]+"], "a\n12", false),
+            Some(("re:[0-9]+", "12"))
+        );
+    }
+
+    #[test]
+    fn test_find_unmatched_subsequence() {
+        let s = "start\n  a 1\nnoise\nb\nc 2\nb\n";
+        assert_eq!(find_unmatched_subsequence(&[], "", false), None);
+        assert_eq!(find_unmatched_subsequence(&["a..."], s, false), None);
+        assert_eq!(
+            find_unmatched_subsequence(&["a...", "b", "...2"], s, false),
+            None
+        );
+        assert_eq!(find_unmatched_subsequence(&["b", "b"], s, false), None);
+        assert_eq!(
+            find_unmatched_subsequence(&["b", "b", "b"], s, false),
+            Some(("b", Some("b")))
+        );
+        assert_eq!(
+            find_unmatched_subsequence(&["c...", "a..."], s, false),
+            Some(("a...", Some("c 2")))
+        );
+        assert_eq!(
+            find_unmatched_subsequence(&["x"], s, false),
+            Some(("x", None))
+        );
+        assert_eq!(
+            find_unmatched_subsequence(&["START", "re:[a-z] [0-9]"], s, true),
+            None
+        );
+    }
+
+    #[test]
+    fn test_find_line_end() {
+        assert_eq!(find_line_end("a", b"", false), None);
+        assert_eq!(find_line_end("b", b"a\n b \nc\n", false), Some(6));
+        assert_eq!(find_line_end(">", b"a\n> ", false), Some(4));
+        assert_eq!(find_line_end("...3", b"1 + 2\n= 3\n", false), Some(10));
+        assert_eq!(find_line_end("A", b"a\n", false), None);
+        assert_eq!(find_line_end("A", b"a\n", true), Some(2));
+        assert_eq!(find_line_end("b", b"\xff\nb\n\xc3", false), Some(4));
+    }
+
+    #[test]
+    fn test_strip_ansi() {
+        assert_eq!(strip_ansi(""), "");
+        assert_eq!(strip_ansi("abc"), "abc");
+        assert_eq!(strip_ansi("\x1b[1;31merror\x1b[0m: x"), "error: x");
+        assert_eq!(strip_ansi("a\x1b[2Kb\x1b[?25lc"), "abc");
+        assert_eq!(strip_ansi("\x1b[mé"), "é");
+        assert_eq!(strip_ansi("a\x1b[1;3"), "a\x1b[1;3");
+        assert_eq!(strip_ansi("a\x1b[1é"), "a\x1b[1é");
+    }
+
+    #[test]
+    fn test_find_missing_line() {
+        let s = "a\n  bc  \nd\n";
+        assert_eq!(find_missing_line(&[], s, false, false), None);
+        assert_eq!(find_missing_line(&["d", "a", "bc"], s, false, false), None);
+        assert_eq!(find_missing_line(&["a", "a"], s, false, false), None);
+        assert_eq!(
+            find_missing_line(&["a", "b", "c"], s, false, false),
+            Some("b")
+        );
+        assert_eq!(
+            find_missing_line(&["...c", "re:[a-d]"], s, false, false),
+            None
+        );
+        assert_eq!(find_missing_line(&["A"], s, false, false), Some("A"));
+        assert_eq!(find_missing_line(&["A"], s, false, true), None);
+
+        assert_eq!(find_missing_line(&["b", "c", "d"], s, true, false), None);
+        assert_eq!(find_missing_line(&["b...", "...c"], s, true, false), None);
+        assert_eq!(find_missing_line(&["cb"], s, true, false), Some("cb"));
+        assert_eq!(find_missing_line(&["b{...}c"], s, true, false), None);
+        assert_eq!(
+            find_missing_line(&["re:c$", "re:  b"], s, true, false),
+            None
+        );
+        assert_eq!(find_missing_line(&["re:e"], s, true, false), Some("re:e"));
+    }
+
+    #[test]
+    fn test_normalize_newlines() {
+        assert_eq!(normalize_newlines(""), "");
+        assert_eq!(normalize_newlines("a\nb"), "a\nb");
+        assert!(matches!(normalize_newlines("a\nb"), Cow::Borrowed(_)));
+        assert_eq!(normalize_newlines("a\r\nb\rc\n\r\nd\r"), "a\nb\nc\n\nd\n");
+        assert_eq!(normalize_newlines("a\r\r\nb"), "a\n\nb");
+
+        // Mixed line endings in the output...
+        let out = normalize_newlines("a\r\nb\rc\nd");
+        assert!(match_vec(&["a", "b", "c", "d"], &out, false));
+        assert!(match_vec_raw(&["a", "b", "c", "d"], &out, false));
+        assert!(!match_vec(&["a", "b", "c", "d"], "a\r\nb\rc\nd", false));
+        // ...and in the expected text.
+        let expected = normalize_newlines("a\r\n...\rd\n");
+        assert!(match_vec(
+            &expected.lines().collect::<Vec<_>>(),
+            &out,
+            false
+        ));
+    }
+
+    #[test]
+    fn test_exact_mismatch() {
+        assert_eq!(exact_mismatch(&[], ""), None);
+        assert_eq!(exact_mismatch(&[], " \n"), None);
+        assert_eq!(exact_mismatch(&["a", "", "b"], "  a\n\nb  \n"), None);
+        assert_eq!(exact_mismatch(&["..."], "..."), None);
+        assert_eq!(
+            exact_mismatch(&["..."], "a"),
+            Some("Line 1 differs at column 1: expected '...' but got 'a'".to_owned())
+        );
+        assert_eq!(
+            exact_mismatch(&["a", "bcd"], "a\nbxd"),
+            Some("Line 2 differs at column 2: expected 'bcd' but got 'bxd'".to_owned())
+        );
+        assert_eq!(
+            exact_mismatch(&["ab"], "abc"),
+            Some("Line 1 differs at column 3: expected 'ab' but got 'abc'".to_owned())
+        );
+        assert_eq!(
+            exact_mismatch(&["a", "b"], "a"),
+            Some("Line 2 is missing: expected 'b'".to_owned())
+        );
+        assert_eq!(
+            exact_mismatch(&["a"], "a\nb"),
+            Some("Line 2 is unexpected: got 'b'".to_owned())
+        );
+        assert_eq!(
+            exact_mismatch(&[], "a"),
+            Some("Line 1 is unexpected: got 'a'".to_owned())
+        );
+        assert_eq!(
+            exact_mismatch(&["A"], "a"),
+            Some("Line 1 differs at column 1: expected 'A' but got 'a'".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        use DiffLine::*;
+        fn diff_helper<'a>(p: &'a str, s: &'a str) -> Vec<DiffLine<'a>> {
+            diff(&p.lines().collect::<Vec<_>>(), s, false)
+        }
+        assert_eq!(diff_helper("", ""), vec![]);
+        assert_eq!(diff_helper("a", "a"), vec![Both("a")]);
+        assert_eq!(diff_helper("a", "b"), vec![Expected("a"), Actual("b")]);
+        assert_eq!(diff_helper("", "a"), vec![Actual("a")]);
+        assert_eq!(diff_helper("a", ""), vec![Expected("a")]);
+        assert_eq!(
+            diff_helper("a\nb\nc", "a\nx\nc"),
+            vec![Both("a"), Expected("b"), Actual("x"), Both("c")]
+        );
+        assert_eq!(
+            diff_helper("a\n...\nd", "a\nb\nc\nd"),
+            vec![Both("a"), Wildcard("..."), Both("b"), Both("c"), Both("d")]
+        );
+        assert_eq!(
+            diff_helper("a\n...\nd", "a\nb\nc"),
+            vec![
+                Both("a"),
+                Wildcard("..."),
+                Both("b"),
+                Both("c"),
+                Expected("d")
+            ]
+        );
+        assert_eq!(
+            diff_helper("a\n...\nc\nd", "a\nb\nc\ne"),
+            vec![
+                Both("a"),
+                Wildcard("..."),
+                Both("b"),
+                Both("c"),
+                Expected("d"),
+                Actual("e")
+            ]
+        );
+        assert_eq!(
+            diff_helper("...b...\nre:[0-9]+", "xbz\n12\nq"),
+            vec![Both("xbz"), Both("12"), Actual("q")]
+        );
+        assert_eq!(
+            diff_helper("a\n...{1,2}\nd", "a\nb\nd"),
+            vec![Both("a"), Wildcard("...{1,2}"), Both("b"), Both("d")]
+        );
+        assert_eq!(
+            diff_helper("a\n...{1,2}\nd", "a\nb\nc\nx\nd"),
+            vec![
+                Both("a"),
+                Wildcard("...{1,2}"),
+                Both("b"),
+                Both("c"),
+                Actual("x"),
+                Both("d")
+            ]
+        );
+        assert_eq!(
+            diff_helper("a\n...{2}\nd", "a\nd"),
+            vec![Both("a"), Expected("...{2}"), Both("d")]
+        );
+    }
 }
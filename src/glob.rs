@@ -0,0 +1,124 @@
+use regex::Regex;
+
+/// Convert the glob `glob` into a regular expression which matches an entire `/`-separated path.
+/// The following are supported:
+///
+///   * `*` matches zero or more characters other than `/`.
+///   * `?` matches a single character other than `/`.
+///   * `**` as a complete path component matches zero or more directories (e.g. `**/*.rs` matches
+///     `a.rs` and `x/y/a.rs`).
+///   * `[abc]`, `[a-z]` match a single character in the set; `[!abc]` one not in the set.
+///   * `{a,b}` matches either `a` or `b` (alternatives can themselves contain globs).
+///
+/// Returns an error message if `glob` is malformed.
+pub(crate) fn glob_regex(glob: &str) -> Result<Regex, String> {
+    let chars = glob.chars().collect::<Vec<_>>();
+    let mut re = String::from("^");
+    // The number of `{`s we're currently nested within.
+    let mut braces = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_start = i == 0 || chars[i - 1] == '/';
+                match chars.get(i + 2) {
+                    Some('/') if at_start => {
+                        re.push_str("(?:[^/]*/)*");
+                        i += 3;
+                        continue;
+                    }
+                    None if at_start => re.push_str(".*"),
+                    _ => {
+                        return Err("'**' can only be used as a complete path component".to_owned())
+                    }
+                }
+                i += 2;
+                continue;
+            }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => {
+                let mut j = i + 1;
+                let negated = chars.get(j) == Some(&'!');
+                if negated {
+                    j += 1;
+                }
+                // As in shells, a `]` straight after the opening `[` (or `[!`) is part of the set.
+                let set_start = j;
+                while j < chars.len() && (chars[j] != ']' || j == set_start) {
+                    j += 1;
+                }
+                if j == chars.len() {
+                    return Err("unterminated '['".to_owned());
+                }
+                re.push_str(if negated { "[^/" } else { "[" });
+                for &c in &chars[set_start..j] {
+                    if c == '-' {
+                        re.push('-');
+                    } else {
+                        re.push_str(&regex::escape(&c.to_string()));
+                    }
+                }
+                re.push(']');
+                i = j;
+            }
+            '{' => {
+                braces += 1;
+                re.push_str("(?:");
+            }
+            ',' if braces > 0 => re.push('|'),
+            '}' if braces > 0 => {
+                braces -= 1;
+                re.push(')');
+            }
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    if braces > 0 {
+        return Err("unterminated '{'".to_owned());
+    }
+    re.push('$');
+    Regex::new(&re).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_glob_regex() {
+        fn m(glob: &str, path: &str) -> bool {
+            glob_regex(glob).unwrap().is_match(path)
+        }
+        assert!(m("a.rs", "a.rs"));
+        assert!(!m("a.rs", "a_rs"));
+        assert!(!m("a.rs", "x/a.rs"));
+        assert!(m("*.rs", "a.rs"));
+        assert!(!m("*.rs", "x/a.rs"));
+        assert!(m("x/*.rs", "x/a.rs"));
+        assert!(m("**/*.rs", "a.rs"));
+        assert!(m("**/*.rs", "x/y/a.rs"));
+        assert!(!m("**/*.rs", "x/y/a.rs.bak"));
+        assert!(m("x/**", "x/y/a.rs"));
+        assert!(!m("x/**", "y/a.rs"));
+        assert!(m("x/**/a.rs", "x/a.rs"));
+        assert!(m("x/**/a.rs", "x/y/z/a.rs"));
+        assert!(m("?.rs", "a.rs"));
+        assert!(!m("?.rs", "ab.rs"));
+        assert!(m("[ab].rs", "b.rs"));
+        assert!(!m("[ab].rs", "c.rs"));
+        assert!(m("[a-c].rs", "b.rs"));
+        assert!(m("[!ab].rs", "c.rs"));
+        assert!(!m("[!ab].rs", "a.rs"));
+        assert!(m("[]].rs", "].rs"));
+        assert!(m("*.{rs,rlib}", "a.rlib"));
+        assert!(m("{x/*,y}.rs", "x/a.rs"));
+        assert!(m("{x/*,y}.rs", "y.rs"));
+        assert!(!m("{x/*,y}.rs", "z.rs"));
+        assert!(m("a,b}", "a,b}"));
+        assert!(glob_regex("[ab").is_err());
+        assert!(glob_regex("{a,b").is_err());
+        assert!(glob_regex("a**").is_err());
+    }
+}
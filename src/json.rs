@@ -0,0 +1,490 @@
+use std::{collections::BTreeMap, fmt};
+
+use crate::output::json_str;
+
+/// The maximum depth to which arrays and objects can be nested, so that deeply nested input can't
+/// overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+/// A JSON value. Numbers are compared by their exact decimal value, so e.g. `1` and `1.0` are
+/// equal, and objects are unordered (if a key is specified more than once, the last value wins).
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Value {
+    Null,
+    Bool(bool),
+    Number(Number),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+/// Displays a `Value` as compact JSON.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{}", json_str(s)),
+            Value::Array(vs) => {
+                write!(f, "[")?;
+                for (i, v) in vs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(kvs) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in kvs.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}:{}", json_str(k), v)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// A JSON number, stored exactly (rather than as e.g. an `f64`, which can't distinguish large
+/// integers) as `digits` × 10^`exp`. `digits` has no leading or trailing zeros, so that equal
+/// numbers have equal representations: zero is stored as an empty `digits`.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Number {
+    negative: bool,
+    digits: String,
+    exp: i64,
+}
+
+/// Displays a `Number` as JavaScript does: in plain form unless it is very large or very small.
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.digits.is_empty() {
+            return write!(f, "0");
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        // The position of the decimal point relative to the start of `digits`.
+        let point = self.digits.len() as i64 + self.exp;
+        if self.exp >= 0 && point <= 21 {
+            write!(f, "{}{}", self.digits, "0".repeat(self.exp as usize))
+        } else if self.exp < 0 && point > 0 {
+            let (int, frac) = self.digits.split_at(point as usize);
+            write!(f, "{}.{}", int, frac)
+        } else if self.exp < 0 && point > -6 {
+            write!(f, "0.{}{}", "0".repeat(-point as usize), self.digits)
+        } else {
+            let (first, rest) = self.digits.split_at(1);
+            let dot = if rest.is_empty() { "" } else { "." };
+            write!(f, "{}{}{}e{}", first, dot, rest, point - 1)
+        }
+    }
+}
+
+/// Parse the JSON text `s`, returning an error message (which includes the line and column of the
+/// error) if it is not valid JSON.
+pub(crate) fn parse(s: &str) -> Result<Value, String> {
+    let mut p = Parser { s, off: 0 };
+    p.skip_ws();
+    let v = p.value(0)?;
+    p.skip_ws();
+    if p.off < s.len() {
+        return Err(p.err("unexpected characters after the JSON value"));
+    }
+    Ok(v)
+}
+
+struct Parser<'a> {
+    s: &'a str,
+    /// The byte offset of the next character to be parsed.
+    off: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.s.as_bytes().get(self.off).cloned()
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.off += 1;
+        }
+    }
+
+    /// Return an error message `msg` for the current position.
+    fn err(&self, msg: &str) -> String {
+        let before = &self.s[..self.off];
+        let line = before.matches('\n').count() + 1;
+        let col = before.chars().rev().take_while(|c| *c != '\n').count() + 1;
+        format!("{} at line {} column {}", msg, line, col)
+    }
+
+    /// If the input continues with `lit`, consume it and return `true`.
+    fn eat(&mut self, lit: &str) -> bool {
+        if self.s[self.off..].starts_with(lit) {
+            self.off += lit.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Parse a value nested within `depth` arrays and objects.
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err(self.err("arrays and objects are nested too deeply"));
+        }
+        match self.peek() {
+            Some(b'n') if self.eat("null") => Ok(Value::Null),
+            Some(b't') if self.eat("true") => Ok(Value::Bool(true)),
+            Some(b'f') if self.eat("false") => Ok(Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[') => {
+                self.off += 1;
+                let mut vs = Vec::new();
+                self.skip_ws();
+                if self.eat("]") {
+                    return Ok(Value::Array(vs));
+                }
+                loop {
+                    self.skip_ws();
+                    vs.push(self.value(depth + 1)?);
+                    self.skip_ws();
+                    if self.eat("]") {
+                        return Ok(Value::Array(vs));
+                    }
+                    if !self.eat(",") {
+                        return Err(self.err("expected ',' or ']'"));
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.off += 1;
+                let mut kvs = BTreeMap::new();
+                self.skip_ws();
+                if self.eat("}") {
+                    return Ok(Value::Object(kvs));
+                }
+                loop {
+                    self.skip_ws();
+                    if self.peek() != Some(b'"') {
+                        return Err(self.err("expected a string key"));
+                    }
+                    let k = self.string()?;
+                    self.skip_ws();
+                    if !self.eat(":") {
+                        return Err(self.err("expected ':'"));
+                    }
+                    self.skip_ws();
+                    kvs.insert(k, self.value(depth + 1)?);
+                    self.skip_ws();
+                    if self.eat("}") {
+                        return Ok(Value::Object(kvs));
+                    }
+                    if !self.eat(",") {
+                        return Err(self.err("expected ',' or '}'"));
+                    }
+                }
+            }
+            _ => Err(self.err("expected a JSON value")),
+        }
+    }
+
+    /// Parse a number, checking that it follows JSON's (stricter than Rust's) syntax.
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.off;
+        let digits = |p: &mut Self| {
+            let start = p.off;
+            while let Some(b'0'..=b'9') = p.peek() {
+                p.off += 1;
+            }
+            &p.s[start..p.off]
+        };
+        let negative = self.eat("-");
+        let int = digits(self);
+        if int.is_empty() || (int.starts_with('0') && int.len() > 1) {
+            self.off = start;
+            return Err(self.err("invalid number"));
+        }
+        let frac = if self.eat(".") { digits(self) } else { "" };
+        if frac.is_empty() && self.s[..self.off].ends_with('.') {
+            self.off = start;
+            return Err(self.err("invalid number"));
+        }
+        let mut exp = 0i64;
+        if self.eat("e") || self.eat("E") {
+            let exp_negative = !self.eat("+") && self.eat("-");
+            let exp_digits = digits(self);
+            if exp_digits.is_empty() {
+                self.off = start;
+                return Err(self.err("invalid number"));
+            }
+            exp = match exp_digits.parse::<i64>() {
+                Ok(e) if exp_negative => -e,
+                Ok(e) => e,
+                Err(_) => {
+                    self.off = start;
+                    return Err(self.err("number's exponent is too large"));
+                }
+            };
+        }
+        // Normalise `int.frac` × 10^`exp` by stripping leading and trailing zeros.
+        let all = format!("{}{}", int, frac);
+        let trimmed = all.trim_start_matches('0').trim_end_matches('0');
+        let trailing = all.trim_end_matches('0').len();
+        let exp = exp
+            .checked_sub(frac.len() as i64)
+            .and_then(|e| e.checked_add((all.len() - trailing) as i64));
+        match exp {
+            Some(exp) => Ok(Value::Number(Number {
+                negative: negative && !trimmed.is_empty(),
+                digits: trimmed.to_owned(),
+                exp: if trimmed.is_empty() { 0 } else { exp },
+            })),
+            None => {
+                self.off = start;
+                Err(self.err("number's exponent is too large"))
+            }
+        }
+    }
+
+    /// Parse a string, the opening `"` of which is the next character.
+    fn string(&mut self) -> Result<String, String> {
+        self.off += 1;
+        let mut out = String::new();
+        loop {
+            let c = match self.s[self.off..].chars().next() {
+                Some(c) => c,
+                None => return Err(self.err("unterminated string")),
+            };
+            match c {
+                '"' => {
+                    self.off += 1;
+                    return Ok(out);
+                }
+                '\\' => {
+                    self.off += 1;
+                    let esc = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.off += 1;
+                            let mut cp = self.hex4()?;
+                            // A surrogate pair encodes a character outside the Basic Multilingual
+                            // Plane.
+                            if (0xD800..0xDC00).contains(&cp) && self.eat("\\u") {
+                                let low = self.hex4()?;
+                                if (0xDC00..0xE000).contains(&low) {
+                                    cp = 0x10000 + ((cp - 0xD800) << 10) + (low - 0xDC00);
+                                }
+                            }
+                            match char::from_u32(cp) {
+                                Some(c) => out.push(c),
+                                None => return Err(self.err("invalid unicode escape")),
+                            }
+                            continue;
+                        }
+                        _ => return Err(self.err("invalid escape")),
+                    };
+                    out.push(esc);
+                    self.off += 1;
+                }
+                c if (c as u32) < 0x20 => return Err(self.err("control character in string")),
+                c => {
+                    out.push(c);
+                    self.off += c.len_utf8();
+                }
+            }
+        }
+    }
+
+    /// Parse 4 hexadecimal digits.
+    fn hex4(&mut self) -> Result<u32, String> {
+        let hex = self.s.get(self.off..self.off + 4).unwrap_or("");
+        if hex.len() != 4 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(self.err("invalid unicode escape"));
+        }
+        self.off += 4;
+        Ok(u32::from_str_radix(hex, 16).unwrap())
+    }
+}
+
+/// Compare the JSON values `expected` and `actual`, returning `None` if they are equal, or `Some`
+/// describing the first difference (and where it is) otherwise. If `subset` is `true`, objects in
+/// `actual` can have keys which the corresponding objects in `expected` don't.
+pub(crate) fn diff(expected: &Value, actual: &Value, subset: bool) -> Option<String> {
+    diff_at("$", expected, actual, subset)
+}
+
+fn diff_at(path: &str, expected: &Value, actual: &Value, subset: bool) -> Option<String> {
+    match (expected, actual) {
+        (Value::Array(evs), Value::Array(avs)) => {
+            for (i, (ev, av)) in evs.iter().zip(avs).enumerate() {
+                if let Some(d) = diff_at(&format!("{}[{}]", path, i), ev, av, subset) {
+                    return Some(d);
+                }
+            }
+            if evs.len() != avs.len() {
+                return Some(format!(
+                    "At {}: expected an array of {} elements but got {}",
+                    path,
+                    evs.len(),
+                    avs.len()
+                ));
+            }
+            None
+        }
+        (Value::Object(ekvs), Value::Object(akvs)) => {
+            for (k, ev) in ekvs {
+                match akvs.get(k) {
+                    Some(av) => {
+                        if let Some(d) = diff_at(&key_path(path, k), ev, av, subset) {
+                            return Some(d);
+                        }
+                    }
+                    None => return Some(format!("At {}: key {} is missing", path, json_str(k))),
+                }
+            }
+            if !subset {
+                if let Some(k) = akvs.keys().find(|k| !ekvs.contains_key(*k)) {
+                    return Some(format!("At {}: unexpected key {}", path, json_str(k)));
+                }
+            }
+            None
+        }
+        _ if expected == actual => None,
+        _ => Some(format!(
+            "At {}: expected {} but got {}",
+            path,
+            describe(expected),
+            describe(actual)
+        )),
+    }
+}
+
+/// The path of the key `k` of the object at `path`.
+fn key_path(path: &str, k: &str) -> String {
+    let is_ident = k.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_ident {
+        format!("{}.{}", path, k)
+    } else {
+        format!("{}[{}]", path, json_str(k))
+    }
+}
+
+/// A short description of `v`: scalars are shown in full, but arrays and objects (which may be
+/// large) only by their kind.
+fn describe(v: &Value) -> String {
+    match v {
+        Value::Array(_) => "an array".to_owned(),
+        Value::Object(_) => "an object".to_owned(),
+        _ => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(" null "), Ok(Value::Null));
+        assert_eq!(parse("true"), Ok(Value::Bool(true)));
+        assert_eq!(parse("-1.5e2"), parse("-150"));
+        assert_eq!(parse("1"), parse("1.0"));
+        assert_eq!(parse("0.5"), parse("5E-1"));
+        assert_eq!(parse("0"), parse("-0.00e3"));
+        assert_eq!(parse("1200"), parse("12e+2"));
+        assert_ne!(parse("9007199254740993"), parse("9007199254740992"));
+        assert_ne!(parse("1"), parse("-1"));
+        assert_eq!(
+            parse(r#""a\"\\\/\n\u00e9\ud83d\ude00""#),
+            Ok(Value::String("a\"\\/\né😀".to_owned()))
+        );
+        assert_eq!(
+            parse("[1, [], {}]"),
+            Ok(Value::Array(vec![
+                parse("1").unwrap(),
+                Value::Array(vec![]),
+                Value::Object(BTreeMap::new())
+            ]))
+        );
+        assert_eq!(
+            parse(r#"{"a": 1, "b": [2]}"#),
+            parse("{\"b\":[2],\n\"a\":1}")
+        );
+        assert_eq!(parse(r#"{"a": 1, "a": 2}"#), parse(r#"{"a": 2}"#));
+        for s in &[
+            "", "nul", "01", "1.", "-", "1e", "+1", "[1,]", "[1 2]", "{\"a\"}", "{a: 1}", "\"a",
+            "\"\\x\"", "\"\t\"", "1 2", "[",
+        ] {
+            assert!(parse(s).is_err(), "{}", s);
+        }
+        assert_eq!(
+            parse("{\n  \"a\": x\n}"),
+            Err("expected a JSON value at line 2 column 8".to_owned())
+        );
+        assert!(parse(&"[".repeat(MAX_DEPTH + 2)).is_err());
+        assert!(parse("1e99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        let s = r#"{"b":[1,2.5,null,true],"a":"x\"y"}"#;
+        let v = parse(s).unwrap();
+        assert_eq!(v.to_string(), r#"{"a":"x\"y","b":[1,2.5,null,true]}"#);
+        assert_eq!(parse(&v.to_string()), Ok(v));
+        let s = "[0, -0, 12e19, 1e21, 1.5e-7, 0.000001, -0.25, 9007199254740993, 1E400]";
+        assert_eq!(
+            parse(s).unwrap().to_string(),
+            "[0,0,120000000000000000000,1e21,1.5e-7,0.000001,-0.25,9007199254740993,1e400]"
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let d = |e: &str, a: &str, subset| diff(&parse(e).unwrap(), &parse(a).unwrap(), subset);
+        assert_eq!(
+            d(r#"{"a": [1, {"b": 2}]}"#, r#"{"a":[1,{"b":2}]}"#, false),
+            None
+        );
+        assert_eq!(
+            d(r#"{"a": [1, {"b": 2}]}"#, r#"{"a": [1, {"b": 3}]}"#, false),
+            Some("At $.a[1].b: expected 2 but got 3".to_owned())
+        );
+        assert_eq!(
+            d(r#"{"a b": 1}"#, r#"{"a b": "1"}"#, false),
+            Some(r#"At $["a b"]: expected 1 but got "1""#.to_owned())
+        );
+        assert_eq!(
+            d("[1, 2]", "[1]", false),
+            Some("At $: expected an array of 2 elements but got 1".to_owned())
+        );
+        assert_eq!(
+            d(r#"{"a": 1}"#, r#"{"a": 1, "b": 2}"#, false),
+            Some(r#"At $: unexpected key "b""#.to_owned())
+        );
+        assert_eq!(d(r#"{"a": 1}"#, r#"{"a": 1, "b": 2}"#, true), None);
+        assert_eq!(
+            d(r#"{"a": 1}"#, r#"{"b": 2}"#, true),
+            Some(r#"At $: key "a" is missing"#.to_owned())
+        );
+        assert_eq!(
+            d(r#"{"a": {}}"#, r#"{"a": []}"#, true),
+            Some("At $.a: expected an object but got an array".to_owned())
+        );
+    }
+}
@@ -8,46 +8,34 @@
 //! [`compiletest_rs`](https://crates.io/crates/compiletest_rs), looks as follows:
 //!
 //! ```rust
-//! use std::{path::PathBuf, process::Command};
+//! use std::process::Command;
 //!
-//! use lang_tester::LangTester;
-//! use tempdir::TempDir;
+//! use lang_tester::{extract, LangTester};
 //!
 //! fn main() {
-//!     // We use rustc to compile files into a binary: we store those binary files into `tempdir`.
-//!     // This may not be necessary for other languages.
-//!     let tempdir = TempDir::new("rust_lang_tester").unwrap();
 //!     LangTester::new()
 //!         .test_dir("examples/rust_lang_tester/lang_tests")
 //!         // Only use files named `*.rs` as test files.
 //!         .test_file_filter(|p| p.extension().unwrap().to_str().unwrap() == "rs")
 //!         // Extract the first sequence of commented line(s) as the tests.
-//!         .test_extract(|s| {
-//!             Some(
-//!                 s.lines()
-//!                     // Skip non-commented lines at the start of the file.
-//!                     .skip_while(|l| !l.starts_with("//"))
-//!                     // Extract consecutive commented lines.
-//!                     .take_while(|l| l.starts_with("//"))
-//!                     .map(|l| &l[2..])
-//!                     .collect::<Vec<_>>()
-//!                     .join("\n"),
-//!             )
-//!         })
+//!         .test_extract(extract::line_comment_block("//"))
 //!         // We have two test commands:
 //!         //   * `Compiler`: runs rustc.
 //!         //   * `Run-time`: if rustc does not error, and the `Compiler` tests succeed, then the
 //!         //     output binary is run.
-//!         .test_cmds(move |p| {
+//!         // We use rustc to compile files into a binary, which we store in a directory unique to
+//!         // the test. This may not be necessary for other languages.
+//!         .test_cmds_with_tempdir(|p, tempdir| {
 //!             // Test command 1: Compile `x.rs` into `tempdir/x`.
-//!             let mut exe = PathBuf::new();
-//!             exe.push(&tempdir);
-//!             exe.push(p.file_stem().unwrap());
+//!             let exe = tempdir.join(p.file_stem().unwrap());
 //!             let mut compiler = Command::new("rustc");
-//!             compiler.args(&["-o", exe.to_str().unwrap(), p.to_str().unwrap()]);
+//!             compiler.args(["-o", exe.to_str().unwrap(), p.to_str().unwrap()]);
 //!             // Test command 2: run `tempdir/x`.
 //!             let runtime = Command::new(exe);
-//!             vec![("Compiler", compiler), ("Run-time", runtime)]
+//!             vec![
+//!                 ("Compiler".to_owned(), compiler),
+//!                 ("Run-time".to_owned(), runtime),
+//!             ]
 //!         })
 //!         .run();
 //! }
@@ -78,39 +66,340 @@
 //!
 //! Test data is specified with a two-level indentation syntax: the outer most level of indentation
 //! defines a test command (multiple command names can be specified, as in the above); the inner
-//! most level of indentation defines alterations to the general command or sub-tests. Each test
-//! command must define at least one sub-test:
-//!
-//!   * `status: <success|failure|signal|<int>>`, where `success` and `failure` map to platform
-//!     specific notions of a command completing successfully or unsuccessfully respectively.
-//!     `signal` checks for termination due to a signal on Unix platforms; on non-Unix platforms, the
-//!     test will be ignored. `<int>` is a signed integer checking for a specific exit code on platforms
-//!     that support it. If not specified, defaults to `success`.
+//! most level of indentation defines alterations to the general command or sub-tests. Indentation
+//! can use tabs or spaces, but a line's indentation must start with that of the lines enclosing it
+//! (so, for example, a tab-indented key cannot have a space-indented value). All of a command's
+//! keys must be indented by the same amount, and each line of a key's value must be indented by at
+//! least as much as the value's first line: anything else is ambiguous, and is reported as an
+//! error. By default, any increase in indentation starts a new level, but
+//! [`LangTester::min_indent`](struct.LangTester.html#method.min_indent) can require a larger
+//! increase (e.g. 2 columns). Lines starting with `#` (or the prefix set with
+//! [`LangTester::comment_prefix`](struct.LangTester.html#method.comment_prefix)) wherever a command
+//! name or key could appear are comments and are ignored; within a key's value, such lines are
+//! part of the value. Each test command must define at least one sub-test:
+//!
+//!   * `status: <success|failure|signal|signal(<sig>)|any|timeout|<int>|<codes>|not <status>>`,
+//!     where `success` and `failure` map to platform specific notions of a command completing
+//!     successfully or unsuccessfully respectively (`error` is accepted as a synonym for
+//!     `failure`). `signal` checks for termination due to a signal on Unix platforms; on non-Unix
+//!     platforms, the test will be ignored. `signal(<sig>)` checks for termination due to a
+//!     specific signal, given as a number (e.g. `signal(6)`) or a name (e.g. `signal(SIGABRT)`).
+//!     `<int>` is a signed integer checking for a specific exit code on platforms that support it.
+//!     `<codes>` is a comma separated list of `<int>`s and/or inclusive ranges `<int>..=<int>`
+//!     within `0..=255` (e.g. `1, 3..=5`), checking that the exit code is any one of those
+//!     specified. `any` accepts any exit status, which is useful when only a command's output is
+//!     of interest; as with `failure`, if the command does not complete successfully, subsequent
+//!     commands are not run.
+//!     `timeout` checks that the command ran for longer than its timeout and was killed, which
+//!     allows e.g. non-termination to be tested; it requires a timeout to be set, with the
+//!     `timeout` key or [`LangTester::test_timeout`](struct.LangTester.html#method.test_timeout),
+//!     and, as with `failure`, subsequent commands are not run. A command which times out fails for
+//!     any other `status`. `not <status>` accepts any exit status which `<status>` does not (e.g.
+//!     `not 0, 1`). Note that a command terminated by a signal has no exit code, so `not <int>` and
+//!     `not <codes>` both accept termination due to a signal. If not specified, defaults to
+//!     `success` (i.e. omitting `status` is not the same as `status: any`). Signals passed to
+//!     [`LangTester::ignore_signals`](struct.LangTester.html#method.ignore_signals) (e.g.
+//!     `SIGPIPE`) are treated as `success` rather than `failure`.
 //!   * `stderr: [<string>]`, `stdout: [<string>]` match `<string>` against a command's `stderr`
 //!     or `stdout`. The special string `...` can be used as a simple wildcard: if a line consists
-//!     solely of `...`, it means "match zero or more lines"; if a line begins with `...`, it means
+//!     solely of `...`, it means "match zero or more lines", and a line consisting solely of
+//!     `...{n}`, `...{m,n}`, or `...{m,}` means "match exactly `n` lines", "match between `m` and
+//!     `n` lines", or "match at least `m` lines" respectively. A line wildcard at the end of a
+//!     pattern matches all remaining lines (e.g. `...` there means "followed by anything, or
+//!     nothing"). A line wildcard anywhere else is anchored by the pattern line which follows it
+//!     (which can not itself be a line wildcard): after matching its minimum number of lines, the
+//!     wildcard stops at the first line which matches the anchor. Such wildcards are lazy and do
+//!     not backtrack, so e.g. the pattern `...`, `b`, `c` does not match the lines `b`, `x`, `b`,
+//!     `c`, because the wildcard stops at the first `b`. If a line begins with `...`, it means
 //!     "match the remainder of the line only"; if a line ends with `...`, it means "match the
-//!     start of the line only". A line may start and end with `...`. Note that `stderr`/`stdout`
-//!     matches ignore leading/trailing whitespace and newlines, but are case sensitive. If not
-//!     specified, defaults to `...` (i.e. match anything). Note that the empty string matches only
+//!     start of the line only". A line may start and end with `...`. Within a line, `{...}` means
+//!     "match zero or more characters" (e.g. `at 0x{...}: error` matches `at 0x7ffe12: error`).
+//!     `...` at the start or end of a line takes precedence, so `...{...}` is a line-start
+//!     wildcard followed by `{...}`. A line beginning with `re:` is a
+//!     [regular expression](https://docs.rs/regex/) which must match the entire line (e.g.
+//!     `re: 0x[0-9a-f]+` matches a line consisting solely of a hexadecimal number); regular
+//!     expression lines can be freely mixed with literal and `...` lines. Note that `stderr`/`stdout`
+//!     matches ignore leading/trailing newlines, and leading/trailing whitespace on each line (so a
+//!     line which differs only in trailing spaces still matches), but are case sensitive (unless
+//!     [`LangTester::case_insensitive`](struct.LangTester.html#method.case_insensitive) is set). If
+//!     not specified, defaults to `...` (i.e. match anything). Note that the empty string matches only
 //!     the empty string so e.g. `stderr:` on its own means that a command's `stderr` muct not
 //!     contain any output.
+//!   * `stderr-empty`, `stdout-empty` (which take no value) check that a command's `stderr` or
+//!     `stdout` is empty, ignoring leading/trailing whitespace and newlines. This is equivalent to
+//!     `stderr:`/`stdout:` on its own, but makes the intent explicit, which matters because
+//!     omitting `stderr`/`stdout` entirely means that the stream is not checked at all.
+//!     `stderr-empty`/`stdout-empty` can not be specified alongside `stderr-contains`,
+//!     `stderr-contains-ordered`, `stderr-lines`, or `stderr-bytes` (and similarly for `stdout`).
+//!   * `stderr-not: [<string>]`, `stdout-not: [<string>]` specify one or more line patterns (one
+//!     per line) which must not match any line of a command's `stderr` or `stdout`. Each pattern
+//!     follows the same rules as a single line of a `stderr`/`stdout` match (e.g. `...panic...`
+//!     matches any line containing `panic`), except that `...` can not be used on a line of its
+//!     own. `stderr-not`/`stdout-not` can be freely combined with `stderr`/`stdout`.
+//!   * `stderr-contains: [<string>]`, `stdout-contains: [<string>]` specify one or more line
+//!     patterns (one per line, following the same rules as for `stderr-not`) each of which must
+//!     match some line of a command's `stderr` or `stdout`, in any order and wherever it appears
+//!     in the output. This is a simpler way of checking that output contains a line than
+//!     surrounding it with `...` lines. If
+//!     [`LangTester::contains_substring`](struct.LangTester.html#method.contains_substring) is
+//!     set, a pattern need only match part of a line. If the match fails, the first pattern which
+//!     could not be matched is reported. `stderr-contains`/`stdout-contains` can be freely
+//!     combined with `stderr`/`stdout`.
+//!   * `stderr-contains-ordered: [<string>]`, `stdout-contains-ordered: [<string>]` specify one or
+//!     more line patterns (one per line, following the same rules as for `stderr-not`) which must
+//!     match lines of a command's `stderr` or `stdout` in the order given, though not necessarily
+//!     adjacent lines: any number of other lines can come before, between, or after them. Unlike
+//!     `stderr`/`stdout`, `...` is not needed between lines, which makes it easy to check e.g. that
+//!     log messages appear in order. If the match fails, the first pattern which could not be
+//!     matched is reported. `stderr-contains-ordered`/`stdout-contains-ordered` can be freely
+//!     combined with `stderr`/`stdout`.
+//!   * `stderr-lines: <int>|<int>..=<int>`, `stdout-lines: <int>|<int>..=<int>` check that a
+//!     command's `stderr` or `stdout` consists of exactly `<int>` lines, or of a number of lines
+//!     in the given inclusive range (e.g. `stdout-lines: 5..=15`). As with `stderr`/`stdout`,
+//!     leading/trailing blank lines are not counted (so empty output has 0 lines).
+//!     `stderr-lines`/`stdout-lines` can be freely combined with the other `stderr`/`stdout`
+//!     keys.
+//!   * `stderr-bytes: <int>|<int>..=<int>`, `stdout-bytes: <int>|<int>..=<int>` check that a
+//!     command wrote exactly `<int>` bytes, or a number of bytes in the given inclusive range, to
+//!     `stderr` or `stdout`. Unlike the other `stderr`/`stdout` keys, the bytes are counted exactly
+//!     as the command wrote them: nothing is trimmed, line endings are not normalised (so `\r\n`
+//!     counts as 2 bytes), and neither
+//!     [`LangTester::strip_ansi`](struct.LangTester.html#method.strip_ansi) nor
+//!     [`LangTester::output_transform`](struct.LangTester.html#method.output_transform) is
+//!     applied. `stderr-bytes`/`stdout-bytes` can be freely combined with the other
+//!     `stderr`/`stdout` keys.
+//!   * `stderr-exact: [<string>]`, `stdout-exact: [<string>]` match `<string>` against a
+//!     command's `stderr` or `stdout` strictly: `...` and `re:` have no special meaning, and the
+//!     output must consist of exactly the lines given, with nothing else before, between, or
+//!     after them. Leading/trailing whitespace and newlines are ignored, but case is always
+//!     significant, and a lone `\r` does not end a line (see
+//!     [`LangTester::normalize_newlines`](struct.LangTester.html#method.normalize_newlines)).
+//!     If the match fails, the first differing line (and the column within it) is reported.
+//!     `stderr-exact`/`stdout-exact` can not be specified alongside `stderr`/`stdout`
+//!     respectively.
+//!   * `stderr-file: <path>`, `stdout-file: <path>` match a command's `stderr` or `stdout` against
+//!     the contents of the file `<path>` (relative to the directory containing the test file),
+//!     using the same rules as `stderr`/`stdout`. This allows large expected outputs to be kept
+//!     separate from the test file. If `<path>` can not be read, the test fails.
+//!   * `stderr-raw: [<string>]`, `stdout-raw: [<string>]` are as `stderr`/`stdout`, except that
+//!     leading and trailing whitespace on each line is significant, which allows e.g. the output
+//!     of pretty printers to be tested. Each line of `<string>` keeps any indentation beyond that
+//!     of its first line, as well as any trailing whitespace, and must match a line of output
+//!     exactly, including its whitespace. Leading/trailing blank lines are still ignored, but a
+//!     lone `\r` does not end a line (see
+//!     [`LangTester::normalize_newlines`](struct.LangTester.html#method.normalize_newlines)).
+//!     Only an unindented `...` (or `...{n}` etc.) is a line wildcard: an indented `...` is
+//!     instead a line ending with `...`, so e.g. `    ...` matches any line which starts with 4
+//!     spaces. `...` at the start of a line matches any leading whitespace (e.g. `...x` matches
+//!     `  x`), and `{...}` matches whitespace as well as other characters. A `re:` line must
+//!     start at the value's indentation, and its regular expression (with leading whitespace
+//!     ignored, as usual) must match the entire line, including its whitespace.
+//!   * `stderr-json: <json>`, `stdout-json: <json>` parse a command's `stderr` or `stdout` as
+//!     JSON and check that it is semantically equal to `<json>` (which can span multiple lines):
+//!     formatting and the order of object keys are ignored, and numbers are compared by value
+//!     (so `1` and `1.0` are equal). `stderr-json-subset: <json>`, `stdout-json-subset: <json>`
+//!     are as `stderr-json`/`stdout-json`, except that objects in the output can also have keys
+//!     which the corresponding objects in `<json>` don't (so e.g. `{"id": 1}` matches
+//!     `{"id": 1, "time": 3}`), which allows fields that vary between runs to be ignored. If
+//!     `<json>` is not valid JSON, the test file fails to parse. If the output is not valid JSON,
+//!     or doesn't match, the first difference and its path within the JSON (e.g.
+//!     `$.items[1].name`) are reported.
+//!
+//!     Only one of `stderr`, `stderr-empty`, `stderr-exact`, `stderr-file`, `stderr-raw`,
+//!     `stderr-json`, and `stderr-json-subset` can be specified (and similarly for `stdout`).
+//!   * `stdout-equals-stdin: <true|false>`, if `true`, checks that a command's `stdout` is the same
+//!     as the data written to its `stdin` (by `stdin`, `stdin-bytes`, `stdin-file`, or
+//!     `stdin-from`, one of which must be specified), which saves duplicating large blocks of text
+//!     when testing programs which pass their input through unchanged. The two are compared as for
+//!     `stdout-exact`, with the same trimming applied to both sides: `...` and `re:` have no
+//!     special meaning, leading/trailing whitespace and newlines are ignored, and the first
+//!     differing line is reported. `stdout-equals-stdin` can not be specified alongside `stdout`,
+//!     `stdout-empty`, `stdout-exact`, `stdout-file`, `stdout-raw`, `stdout-json`, or
+//!     `stdout-json-subset`. If not specified, defaults to `false`.
+//!   * `output-file: <path>` checks that the file `<path>` (relative to the command's working
+//!     directory) exists after the command has run. If followed by a `contents: [<string>]` line
+//!     (indented further than `output-file`), the file's contents must also match `<string>`,
+//!     using the same rules as `stderr`/`stdout`, e.g.:
+//!
+//!     ```text
+//!     output-file: out.txt
+//!       contents:
+//!         Hello
+//!         ...
+//!     ```
+//!
+//!     If the file doesn't exist, or can not be read, the test fails. Note that a file left
+//!     behind by a previous run also satisfies this check, so stale files should be removed (e.g.
+//!     by a `Setup` command or
+//!     [`LangTester::before_cmd`](struct.LangTester.html#method.before_cmd)).
+//!   * `output: [<string>]` matches `<string>` against a command's `stderr` and `stdout` combined,
+//!     interleaved in the order the command wrote to them. The same matching rules as for
+//!     `stderr`/`stdout` apply. `output` can not be specified alongside `stderr`, `stdout`,
+//!     `stderr-empty`, `stdout-empty`, `stderr-exact`, `stdout-exact`, `stderr-file`,
+//!     `stdout-file`, `stderr-not`, `stdout-not`, `stderr-contains`, `stdout-contains`,
+//!     `stderr-contains-ordered`, `stdout-contains-ordered`, `stderr-lines`, `stdout-lines`,
+//!     `stderr-bytes`, `stdout-bytes`, `stderr-raw`, `stdout-raw`, `stderr-json`, `stdout-json`,
+//!     `stderr-json-subset`, `stdout-json-subset`, or `stdout-equals-stdin`.
+//!
+//! If a `stderr`, `stdout`, or `output` test fails, a line-by-line diff between the expected
+//! pattern (`-`) and the actual output (`+`) is shown. The diff is coloured unless `stderr` is not
+//! a terminal or the `NO_COLOR` environment variable is set (see
+//! [`LangTester::reporter_style`](struct.LangTester.html#method.reporter_style)).
 //!
 //! Test commands can alter the general command by specifying zero or more of the following:
 //!
 //!   * `extra-args: <arg 1> [... <arg n>]`, where each space separated argument will be appended,
-//!     in order, to those arguments specified as part of the `test_cmds` function.
+//!     in order, to those arguments specified as part of the `test_cmds` function. Arguments are
+//!     split as by a POSIX shell, so an argument containing spaces can be quoted with `'` or `"`
+//!     (e.g. `--opt "a b"`), and `\` escapes the following character. The value can span multiple
+//!     lines, each of which is split in the same way. The following placeholders in an argument
+//!     are replaced just before the command is run:
+//!
+//!       * `{testfile}`: the absolute path of the test file.
+//!       * `{testdir}`: the absolute path of the directory containing the test file.
+//!       * `{tempdir}`: the test's scratch directory, which only exists if
+//!         [`test_cmds_with_tempdir`](struct.LangTester.html#method.test_cmds_with_tempdir) was
+//!         used (otherwise the test fails).
+//!
+//!     e.g. `extra-args: -o {tempdir}/out.o {testfile}`. `{{` is a literal `{`. Any other
+//!     placeholder (i.e. a `{` followed by lower-case letters or `_` and then a `}`) is an error,
+//!     though a `{` which doesn't start a placeholder (e.g. in `{a,b}`) is left as it is.
+//!   * `cmd: <program> [<arg 1> ... <arg n>]`, runs `<program>` with the given arguments (split
+//!     in the same way as `extra-args`) in place of the command returned by the `test_cmds`
+//!     function, though that command's working directory and environment variables are retained.
+//!     The other keys (e.g. `extra-args`) then apply to the new command as normal. Since this
+//!     allows test files to run arbitrary programs, `cmd` can only be used if
+//!     [`LangTester::allow_inline_cmd`](struct.LangTester.html#method.allow_inline_cmd) is set:
+//!     otherwise the test fails.
+//!   * `cwd: <path>`, runs the command in the directory `<path>`, which, if relative, is relative
+//!     to the directory containing the test file. Note that any relative paths in the command's
+//!     arguments will then be interpreted relative to `<path>`. If `<path>` does not exist, the
+//!     test fails.
+//!   * `env-var: <name>=<value>`, sets the environment variable `<name>` to `<value>` when
+//!     running the command. Multiple environment variables can be set by specifying one
+//!     `<name>=<value>` pair per line.
+//!   * `env-clear: <true|false>`, if `true`, runs the command with an empty environment: the
+//!     command inherits none of the tester's environment variables, nor any set by
+//!     [`LangTester::test_cmds`](struct.LangTester.html#method.test_cmds). Variables set with
+//!     `env-var` are still set. If not specified, defaults to `false`.
+//!
+//!     Variables can also be set for every command with
+//!     [`LangTester::env`](struct.LangTester.html#method.env). In order of increasing precedence,
+//!     a command's environment consists of: the tester's environment; variables set with
+//!     `LangTester::env`; variables set by `LangTester::test_cmds`; and variables set with
+//!     `env-var`. `env-clear` removes all but the last of these.
+//!   * `skip-if-missing: <program 1> [... <program n>]`, skips the command if any of the given
+//!     programs (split in the same way as `extra-args`) can not be found. A program whose name
+//!     contains a `/` is looked for at that path (relative to the command's working directory);
+//!     otherwise it is searched for in the command's `PATH` (taking into account `env-var` and
+//!     `env-clear`), as when the command is run. A skipped command is reported as such rather
+//!     than as a failure, and does not cause the test to fail, though a command which takes its
+//!     `stdin` from a skipped command (see `stdin-from`) is also skipped, and if the `Setup`
+//!     command is skipped, all other commands are too.
+//!   * `timeout: <int>`, where `<int>` is a number of seconds after which the command will be
+//!     killed and the test considered to have failed (unless `status: timeout` is specified). If
+//!     not specified, defaults to the value set by
+//!     [`LangTester::test_timeout`](struct.LangTester.html#method.test_timeout) (which, by default,
+//!     allows commands to run indefinitely).
+//!   * `delay: <int>`, where `<int>` is a number of milliseconds to wait for before the command is
+//!     run (and before each retry, if `retries` is specified). The delay does not count towards
+//!     `timeout` or the command's reported duration. This is a crude way of waiting for e.g. a
+//!     server started by an earlier command to become ready, and makes tests both slower and prone
+//!     to failing on a loaded machine: it should only be used as a last resort, when the command
+//!     can't instead wait for a specific line of output with `interact`'s `expect` steps.
+//!   * `max-rss: <int>`, where `<int>` is a number of megabytes (of 1024 * 1024 bytes): the
+//!     command fails if its peak resident set size (as measured by `wait4` once it has exited)
+//!     exceeds this, with the observed peak reported. On platforms which can't measure a
+//!     command's peak resident set size, a command with `max-rss` always fails.
+//!   * `retries: <int>`, where `<int>` is the number of times the command is rerun if it fails
+//!     (i.e. if any of its sub-tests fail). The command only fails if every attempt fails.
+//!     Commands which pass only after being retried are listed at the end of the test run, so
+//!     that flaky tests can be identified. If not specified, defaults to 0.
+//!   * `stdin: [<string>]`, text to be written to the command's `stdin`. Leading/trailing
+//!     whitespace and newlines are trimmed in the same way as for `stderr`/`stdout`, with a single
+//!     trailing newline then appended. Note that `stdin:` on its own means that the command's
+//!     `stdin` is closed immediately. If none of `stdin`, `stdin-bytes`, `stdin-file`, or
+//!     `stdin-from` is specified, `stdin` is connected to the null device.
+//!   * `stdin-bytes: [<string>]`, raw bytes to be written, unaltered, to the command's `stdin`,
+//!     allowing non-UTF-8 input to be tested. `<string>` is base64 (e.g. `/wA=` for the bytes
+//!     `0xff 0x00`) or, if prefixed with `hex:`, hexadecimal (e.g. `hex:ff00`). Whitespace and
+//!     newlines in `<string>` are ignored.
+//!   * `stdin-file: <path>`, a file whose contents are written, unaltered, to the command's
+//!     `stdin`. If `<path>` is relative, it is relative to the directory containing the test file.
+//!     If `<path>` can not be read, the test fails.
+//!   * `stdin-from: <command>`, writes the stdout captured from `<command>` (which must be run
+//!     before this command) to the command's `stdin`, allowing commands to be chained. If
+//!     `<command>` has an `output` test, its combined stderr and stdout are used instead. Note that
+//!     nothing is captured if [`LangTester::nocapture`](struct.LangTester.html#method.nocapture)
+//!     is set.
+//!   * `interact: [send: <string>|expect: <string>]`, an interactive session with the command
+//!     (e.g. a REPL), performed while it runs: one `send:` or `expect:` step per line. Each
+//!     `send: <string>` step writes `<string>`, followed by a newline, to the command's `stdin`.
+//!     Each `expect: <string>` step waits until a line of the command's `stdout` (after the line
+//!     matched by the previous `expect` step) matches `<string>`, using the same rules as a
+//!     single line of a `stdout` match; the final line need not end with a newline, so prompts can
+//!     be matched (any further output on that line is then treated as a new line). If no line
+//!     matches within 10 seconds, or `stdout` is closed first, the command is killed and the test
+//!     fails. Once every step has been performed, the command's `stdin` is closed. `interact` can
+//!     be freely combined with `stdout` etc., which match against all of the command's output. If
+//!     [`LangTester::nocapture`](struct.LangTester.html#method.nocapture) is set, `expect` steps
+//!     are not checked and all `send` steps are written to `stdin` at once.
+//!
+//!     Only one of `stdin`, `stdin-bytes`, `stdin-file`, `stdin-from`, and `interact` can be
+//!     specified.
+//!
+//! The keys `env-var`, `extra-args`, `skip-if-missing`, `stderr-not`, and `stdout-not` are
+//! additive: they can be specified more than once for a command, each occurrence adding to the
+//! values of the previous ones (e.g. `env-var: X=1` and `env-var: Y=2` on separate lines set both
+//! variables). Every other key (e.g. `status` or `cwd`) can be specified at most once for a
+//! command.
 //!
 //! The above file thus contains 4 meaningful tests, two specified by the user and two implied by
 //! defaults: the `Compiler` should succeed (e.g.  return a `0` exit code when run on Unix), and
 //! its `stderr` output should warn about an unused variable on line 12; and the resulting binary
 //! should succeed produce `Hello world` on `stdout`.
 //!
+//! If `test_cmds` returns a command named `Setup` (e.g. one which generates data files that other
+//! commands depend upon), it is run before all other commands, whatever its position. As with any
+//! other command, it defaults to `status: success`, and its `stderr`/`stdout` are only checked if
+//! a test file specifies a `Setup` test command. If the `Setup` command fails, the remaining
+//! commands are not run, and the test fails, listing the commands which were not run.
+//!
+//! A `re:` line in a command's `stderr`, `stdout`, or `output` pattern can bind values for use by
+//! later commands with named capture groups: e.g. if the `Compiler`'s `stdout` has the line
+//! `re:Generated id (?<id>[0-9]+)` and matches, a later command can then refer to the number that
+//! was captured as `${id}`. References are substituted into the `stderr`, `stdout`, `output`,
+//! `stderr-exact`, `stdout-exact`, `stderr-not`, `stdout-not`, `stderr-contains`,
+//! `stdout-contains`, `stderr-contains-ordered`, `stdout-contains-ordered`, and `stdin` values of
+//! later commands before they are run (e.g. `stdout: Running ${id}`); in a `re:` line, the captured
+//! text is matched literally. Captures are only visible to commands which are run after the command
+//! that bound them, in the same file; a later capture with the same name replaces an earlier one. A
+//! reference to a name which has not been bound is left untouched.
+//!
 //! A file's tests can be ignored entirely if a test command `ignore` is defined:
 //!
 //!   * `ignore: [<string>]`, specifies that this file should be ignored for the reason set out in
 //!     `<string>` (if any). Note that `<string>` is purely for user information and has no effect
 //!     on the running of tests.
+//!   * `ignore-if: <condition>`, specifies that this file should be ignored if `<condition>` holds
+//!     on the platform the tests are being run on. `<condition>` is one of `unix`, `windows`,
+//!     `target_os = "<os>"`, `target_family = "<family>"`, `target_arch = "<arch>"` (where the
+//!     values are as in [`std::env::consts`](https://doc.rust-lang.org/std/env/consts/index.html)),
+//!     or `not(<condition>)`. For example, `ignore-if: not(unix)` ignores a file on non-Unix
+//!     platforms.
+//!
+//! A file's tests can be marked as expected to fail with `xfail: [<string>]`, where `<string>`
+//! (if any) is the reason. The file's tests are run as normal, but the file then passes if any
+//! of its tests fail, and fails if they all pass. The latter is deliberately loud, so that an
+//! `xfail` marker is removed once the underlying problem has been fixed. Files which failed as
+//! expected and which passed unexpectedly are listed separately, under "xfail" and "xpass", in the
+//! summary. `xfail` files are never blessed.
+//!
+//! A file's tests can be given one or more tags with `tags: <tag 1> [... <tag n>]`, where tags
+//! are separated by whitespace (e.g. `tags: slow network`).
+//! [`LangTester::filter_tags`](struct.LangTester.html#method.filter_tags) can then be used to run
+//! only tests with, or without, particular tags: a test which has both an included and an excluded
+//! tag is not run. Tags are also included in JSON and TAP output.
 //!
 //! `lang_tester`'s output is deliberately similar to Rust's normal testing output. Running the
 //! example `rust_lang_tester` in this crate produces the following output:
@@ -146,6 +435,16 @@
 //! test result: ok. 2 passed; 0 failed; 0 ignored; 0 measured; 2 filtered out
 //! ```
 //!
+//! If you want to process test results with other tools, you can instead use
+//! [`LangTester::output_format`](struct.LangTester.html#method.output_format) to select a
+//! machine-readable [`OutputFormat`](enum.OutputFormat.html).
+//!
+//! When a compiler's output legitimately changes, the expected output of many tests may need to be
+//! updated. Running the tests with the `LANG_TESTER_BLESS` environment variable set to `1` (or
+//! using [`LangTester::bless`](struct.LangTester.html#method.bless)) rewrites the expected output
+//! of failing `stderr`, `stdout`, and `output` tests to the actual output (`stderr-raw` and
+//! `stdout-raw` tests are not rewritten).
+//!
 //! Users will often want to integrate such tests into their test suite. An easy way of doing this
 //! is to add a `[[test]]` entry to your `Cargo.toml` along the following lines:
 //!
@@ -157,16 +456,31 @@
 //! ```
 //!
 //! Running `cargo test` will now also run your lang tests.
+//!
+//! Tools which want to check test data without running it (e.g. editor plugins) can use
+//! [`parse`](fn.parse.html), which returns the parsed [`Tests`](struct.Tests.html), or a
+//! [`ParseError`](struct.ParseError.html) for each invalid line of the test data. Tools which
+//! generate test data can convert a [`Status`](enum.Status.html) to and from the textual form of
+//! the `status` key with its `Display` and `FromStr` implementations.
 
 #![allow(clippy::new_without_default)]
 #![allow(clippy::redundant_closure)]
 #![allow(clippy::type_complexity)]
 
+mod cache;
+pub mod extract;
 mod fuzzy;
+mod glob;
+mod json;
+mod output;
 mod parser;
 mod tester;
 
-pub use tester::LangTester;
+pub use output::{OutputFormat, ReporterStyle};
+pub use parser::{parse, parse_with_comment_prefix, parse_with_min_indent, ParseError};
+pub use tester::{
+    CmdResult, IgnoreIf, InteractStep, LangTester, Status, TestCmd, TestResult, Tests,
+};
 
 pub(crate) fn fatal(msg: &str) -> ! {
     eprintln!("\nFatal exception:\n  {}", msg);
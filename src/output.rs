@@ -0,0 +1,588 @@
+use std::{
+    borrow::Cow,
+    env, fs,
+    io::{self, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use termcolor::ColorChoice;
+
+use crate::{fatal, tester::CmdResult};
+
+/// The format in which test results are reported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human readable output, deliberately similar to that of `cargo test`, written to `stderr`.
+    Pretty,
+    /// Newline-delimited JSON written to `stdout`. One object is written per test as it
+    /// completes, followed by a final object summarising the whole run. A test's tags (if it has
+    /// any) are included in its object as a `tags` array.
+    Json,
+    /// [TAP version 13](https://testanything.org/tap-version-13-specification.html) written to
+    /// `stdout`. Failures are described by a YAML block under the corresponding `not ok` line,
+    /// and a test's tags (if it has any) are listed in a YAML block under its line.
+    Tap,
+    /// [JUnit XML](https://llg.cubic.org/docs/junit/) written to the file at `path` once all
+    /// tests have been run. Each test file is a `<testsuite>` and each of its commands a
+    /// `<testcase>`. Human readable output is also written to `stderr`, as for
+    /// [`Pretty`](#variant.Pretty).
+    JUnitXml { path: PathBuf },
+}
+
+impl OutputFormat {
+    /// Does this format write human readable output to `stderr` as tests are run?
+    pub(crate) fn is_pretty(&self) -> bool {
+        matches!(self, OutputFormat::Pretty | OutputFormat::JUnitXml { .. })
+    }
+}
+
+/// The style of the human readable output written to `stderr` by
+/// [`OutputFormat::Pretty`](enum.OutputFormat.html#variant.Pretty) and
+/// [`OutputFormat::JUnitXml`](enum.OutputFormat.html#variant.JUnitXml). Machine readable output
+/// is unaffected. The default style matches `cargo test`, e.g.:
+///
+/// ```rust,ignore
+/// LangTester::new()
+///     ...
+///     .reporter_style(ReporterStyle {
+///         pass: "✓".to_owned(),
+///         fail: "✗".to_owned(),
+///         ..ReporterStyle::default()
+///     })
+///     ...
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReporterStyle {
+    /// What is written after the name of a test which passed. Defaults to `ok`.
+    pub pass: String,
+    /// What is written after the name of a test which failed. Defaults to `FAILED`.
+    pub fail: String,
+    /// If `true`, results and warnings are coloured, as are diffs if `stderr` is a terminal.
+    /// Defaults to `true` unless the `NO_COLOR` environment variable is set.
+    pub colour: bool,
+}
+
+impl Default for ReporterStyle {
+    fn default() -> Self {
+        ReporterStyle {
+            pass: "ok".to_owned(),
+            fail: "FAILED".to_owned(),
+            colour: env::var_os("NO_COLOR").is_none(),
+        }
+    }
+}
+
+impl ReporterStyle {
+    /// How results and warnings written in this style should be coloured.
+    pub(crate) fn colour_choice(&self) -> ColorChoice {
+        match self.colour {
+            true => ColorChoice::Always,
+            false => ColorChoice::Never,
+        }
+    }
+}
+
+/// A JUnit `<testsuite>` element describing a single test file.
+pub(crate) struct JUnitSuite {
+    name: String,
+    tests: usize,
+    failures: usize,
+    skipped: usize,
+    duration: Duration,
+    xml: String,
+}
+
+/// Write whatever is needed before any tests are run. `num_tests` is the number of tests which
+/// will be attempted (i.e. excluding those which have been filtered out).
+pub(crate) fn write_start(format: &OutputFormat, num_tests: usize) {
+    match format {
+        OutputFormat::Pretty | OutputFormat::JUnitXml { .. } => unreachable!(),
+        OutputFormat::Json => (),
+        OutputFormat::Tap => write_line(&format!("TAP version 13\n1..{}", num_tests)),
+    }
+}
+
+/// Write the record for a test which has completed (i.e. it was not ignored). `num_reported` is
+/// the number of tests reported so far, and is incremented by this function. `tags` are the tags
+/// the test has been given.
+pub(crate) fn write_test(
+    format: &OutputFormat,
+    num_reported: &AtomicUsize,
+    test_name: &str,
+    tags: &[String],
+    passed: bool,
+    duration: Duration,
+    cmd_results: &[CmdResult],
+) {
+    match format {
+        OutputFormat::Pretty | OutputFormat::JUnitXml { .. } => unreachable!(),
+        OutputFormat::Json => write_json_test(test_name, tags, passed, duration, cmd_results),
+        OutputFormat::Tap => write_tap_test(num_reported, test_name, tags, passed, cmd_results),
+    }
+}
+
+/// Write the record for an ignored test. `num_reported` is the number of tests reported so far,
+/// and is incremented by this function. `tags` are the tags the test has been given.
+pub(crate) fn write_ignored(
+    format: &OutputFormat,
+    num_reported: &AtomicUsize,
+    test_name: &str,
+    tags: &[String],
+    message: &str,
+) {
+    match format {
+        OutputFormat::Pretty | OutputFormat::JUnitXml { .. } => unreachable!(),
+        OutputFormat::Json => write_json_ignored(test_name, tags, message),
+        OutputFormat::Tap => {
+            let mut line = format!("ok {} - {} # SKIP", tap_num(num_reported), test_name);
+            if !message.is_empty() {
+                line.push(' ');
+                line.push_str(message);
+            }
+            line.push_str(&tap_tags(tags));
+            write_line(&line);
+        }
+    }
+}
+
+/// Write whatever is needed after all tests have been run. `not_run` is the number of tests which
+/// were not run because an earlier test failed.
+pub(crate) fn write_summary(
+    format: &OutputFormat,
+    passed: usize,
+    failed: usize,
+    ignored: usize,
+    filtered: usize,
+    not_run: usize,
+) {
+    match format {
+        OutputFormat::Pretty | OutputFormat::JUnitXml { .. } => unreachable!(),
+        OutputFormat::Json => write_json_summary(passed, failed, ignored, filtered, not_run),
+        OutputFormat::Tap => {
+            if not_run > 0 {
+                write_line(&format!(
+                    "Bail out! Stopped after first failure: {} tests not run",
+                    not_run
+                ));
+            }
+            write_line(&format!(
+                "# {} passed; {} failed; {} ignored; {} filtered out",
+                passed, failed, ignored, filtered
+            ))
+        }
+    }
+}
+
+fn write_json_test(
+    test_name: &str,
+    tags: &[String],
+    passed: bool,
+    duration: Duration,
+    cmd_results: &[CmdResult],
+) {
+    let cmds = cmd_results
+        .iter()
+        .map(|r| {
+            let mut fields = vec![
+                format!("\"name\":{}", json_str(&r.name)),
+                format!("\"result\":{}", json_str(result_str(r.passed))),
+                format!("\"duration\":{}", json_duration(r.duration)),
+            ];
+            if r.retries > 0 {
+                fields.push(format!("\"retries\":{}", r.retries));
+            }
+            if !r.passed {
+                fields.push(format!("\"status\":{}", json_str(&r.status)));
+                for (k, v) in outputs(r) {
+                    fields.push(format!("\"{}\":{}", k, json_str(v)));
+                }
+            }
+            format!("{{{}}}", fields.join(","))
+        })
+        .collect::<Vec<_>>();
+    write_line(&format!(
+        "{{\"type\":\"test\",\"name\":{}{},\"result\":{},\"duration\":{},\"cmds\":[{}]}}",
+        json_str(test_name),
+        json_tags(tags),
+        json_str(result_str(passed)),
+        json_duration(duration),
+        cmds.join(",")
+    ));
+}
+
+/// Write a JSON record for an ignored test.
+fn write_json_ignored(test_name: &str, tags: &[String], message: &str) {
+    write_line(&format!(
+        "{{\"type\":\"test\",\"name\":{}{},\"result\":\"ignored\",\"message\":{}}}",
+        json_str(test_name),
+        json_tags(tags),
+        json_str(message)
+    ));
+}
+
+/// Format `tags` as a `"tags"` field (preceded by a comma) of a JSON test record, or as the empty
+/// string if there are no tags.
+fn json_tags(tags: &[String]) -> String {
+    if tags.is_empty() {
+        return String::new();
+    }
+    format!(",\"tags\":{}", json_list(tags))
+}
+
+/// Format `tags` as a YAML block following a TAP test line, or as the empty string if there are
+/// no tags.
+fn tap_tags(tags: &[String]) -> String {
+    if tags.is_empty() {
+        return String::new();
+    }
+    format!("\n  ---\n  tags: {}\n  ...", json_list(tags))
+}
+
+/// Format `xs` as a JSON array of strings (which is also a valid YAML flow sequence).
+fn json_list(xs: &[String]) -> String {
+    let xs = xs.iter().map(|x| json_str(x)).collect::<Vec<_>>();
+    format!("[{}]", xs.join(","))
+}
+
+/// Write the JSON record summarising the whole run.
+fn write_json_summary(
+    passed: usize,
+    failed: usize,
+    ignored: usize,
+    filtered: usize,
+    not_run: usize,
+) {
+    write_line(&format!(
+        "{{\"type\":\"suite\",\"result\":{},\"passed\":{},\"failed\":{},\"ignored\":{},\
+         \"filtered_out\":{},\"not_run\":{}}}",
+        json_str(result_str(failed == 0)),
+        passed,
+        failed,
+        ignored,
+        filtered,
+        not_run
+    ));
+}
+
+fn write_tap_test(
+    num_reported: &AtomicUsize,
+    test_name: &str,
+    tags: &[String],
+    passed: bool,
+    cmd_results: &[CmdResult],
+) {
+    let mut out = format!(
+        "{} {} - {}",
+        if passed { "ok" } else { "not ok" },
+        tap_num(num_reported),
+        test_name
+    );
+    out.push_str(&tap_tags(tags));
+    for r in cmd_results.iter().filter(|r| !r.passed) {
+        out.push_str("\n  ---");
+        out.push_str(&format!("\n  cmd: {}", yaml_str(&r.name)));
+        out.push_str(&format!("\n  status: {}", yaml_str(&r.status)));
+        for (k, v) in outputs(r) {
+            out.push_str(&format!("\n  {}: {}", k, yaml_str(v)));
+        }
+        out.push_str("\n  ...");
+    }
+    write_line(&out);
+}
+
+/// Return a `<testsuite>` describing the test `test_name`, whose commands' results are
+/// `cmd_results`.
+pub(crate) fn junit_suite(
+    test_name: &str,
+    duration: Duration,
+    cmd_results: &[CmdResult],
+) -> JUnitSuite {
+    let mut xml = String::new();
+    for r in cmd_results {
+        xml.push_str(&format!(
+            "\n    <testcase name={} classname={} time=\"{}\"",
+            xml_attr(&r.name),
+            xml_attr(&format!("lang_tests.{}", test_name)),
+            json_duration(r.duration)
+        ));
+        if r.passed {
+            xml.push_str("/>");
+            continue;
+        }
+        let mut body = format!("status: {}", r.status);
+        for (k, v) in outputs(r) {
+            body.push_str(&format!("\n{}:\n{}", k, v.trim_end()));
+        }
+        xml.push_str(&format!(
+            ">\n      <failure message={}>{}</failure>\n    </testcase>",
+            xml_attr(&r.status),
+            xml_escape(&body)
+        ));
+    }
+    JUnitSuite {
+        name: test_name.to_owned(),
+        tests: cmd_results.len(),
+        failures: cmd_results.iter().filter(|r| !r.passed).count(),
+        skipped: 0,
+        duration,
+        xml,
+    }
+}
+
+/// Return a `<testsuite>` describing the ignored test `test_name`.
+pub(crate) fn junit_ignored(test_name: &str, message: &str) -> JUnitSuite {
+    JUnitSuite {
+        name: test_name.to_owned(),
+        tests: 1,
+        failures: 0,
+        skipped: 1,
+        duration: Duration::from_secs(0),
+        xml: format!(
+            "\n    <testcase name={} classname={} time=\"0.000\">\n      \
+             <skipped message={}/>\n    </testcase>",
+            xml_attr(test_name),
+            xml_attr(&format!("lang_tests.{}", test_name)),
+            xml_attr(message)
+        ),
+    }
+}
+
+/// Write a JUnit XML document containing `suites` to `path`. `duration` is the time taken to run
+/// all tests.
+pub(crate) fn write_junit(path: &Path, suites: &mut [JUnitSuite], duration: Duration) {
+    suites.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuites name=\"lang_tests\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" \
+         time=\"{}\">",
+        suites.iter().map(|s| s.tests).sum::<usize>(),
+        suites.iter().map(|s| s.failures).sum::<usize>(),
+        suites.iter().map(|s| s.skipped).sum::<usize>(),
+        json_duration(duration)
+    );
+    for s in suites.iter() {
+        xml.push_str(&format!(
+            "\n  <testsuite name={} tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{}\">{}\n  \
+             </testsuite>",
+            xml_attr(&format!("lang_tests::{}", s.name)),
+            s.tests,
+            s.failures,
+            s.skipped,
+            json_duration(s.duration),
+            s.xml
+        ));
+    }
+    xml.push_str("\n</testsuites>\n");
+    fs::write(path, xml)
+        .unwrap_or_else(|e| fatal(&format!("Couldn't write {}: {}", path.display(), e)));
+}
+
+/// Return `(name, value)` pairs for the expected and actual output of the command `r`.
+fn outputs(r: &CmdResult) -> Vec<(&'static str, &str)> {
+    if r.combined {
+        vec![
+            ("expected_output", &r.expected_stdout),
+            ("output", &r.stdout),
+        ]
+    } else {
+        vec![
+            ("expected_stderr", &r.expected_stderr),
+            ("stderr", &r.stderr),
+            ("expected_stdout", &r.expected_stdout),
+            ("stdout", &r.stdout),
+        ]
+    }
+}
+
+/// Return the number of the next TAP test to be reported.
+fn tap_num(num_reported: &AtomicUsize) -> usize {
+    num_reported.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+/// Format `s` as a YAML value nested beneath a key indented by 2 spaces. Where possible, multi-line
+/// strings are formatted as a literal block scalar (which is easy for humans to read); otherwise
+/// `s` is formatted as a double-quoted scalar (which, conveniently, has the same escaping rules as
+/// JSON strings).
+fn yaml_str(s: &str) -> String {
+    let s = s.trim_end();
+    if !s.contains('\n')
+        || s.starts_with(char::is_whitespace)
+        || s.chars().any(|c| c != '\n' && c.is_control())
+    {
+        return json_str(s);
+    }
+    let mut out = "|-".to_owned();
+    for l in s.lines() {
+        out.push_str("\n    ");
+        out.push_str(l);
+    }
+    out
+}
+
+fn result_str(passed: bool) -> &'static str {
+    if passed {
+        "ok"
+    } else {
+        "failed"
+    }
+}
+
+/// If showing only the first and last `max` (if `Some`) of `len` lines would omit any, return the
+/// range of the lines to omit.
+pub(crate) fn omitted_lines(len: usize, max: Option<usize>) -> Option<Range<usize>> {
+    match max {
+        Some(max) if len > max.saturating_mul(2) => Some(max..len - max),
+        _ => None,
+    }
+}
+
+/// The line shown in place of `n` omitted lines.
+pub(crate) fn omitted_marker(n: usize) -> String {
+    format!(
+        "... ({} {} omitted) ...",
+        n,
+        if n == 1 { "line" } else { "lines" }
+    )
+}
+
+/// Return `s` with all but its first and last `max` (if `Some`) lines replaced by a marker.
+pub(crate) fn truncate_lines(s: &str, max: Option<usize>) -> Cow<'_, str> {
+    let lines = s.lines().collect::<Vec<_>>();
+    match omitted_lines(lines.len(), max) {
+        Some(omit) => {
+            let marker = omitted_marker(omit.len());
+            let mut kept = lines[..omit.start].to_vec();
+            kept.push(&marker);
+            kept.extend(&lines[omit.end..]);
+            Cow::Owned(kept.join("\n"))
+        }
+        None => Cow::Borrowed(s),
+    }
+}
+
+/// Write `line` to `stdout`, holding the lock so that records from different test threads can't
+/// be interleaved.
+fn write_line(line: &str) {
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    handle.write_all(line.as_bytes()).ok();
+    handle.write_all(b"\n").ok();
+    handle.flush().ok();
+}
+
+fn json_duration(d: Duration) -> String {
+    format!("{:.3}", d.as_secs_f64())
+}
+
+/// Quote and escape `s` as a JSON string.
+pub(crate) fn json_str(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Quote and escape `s` as an XML attribute value.
+fn xml_attr(s: &str) -> String {
+    format!("\"{}\"", xml_escape(s))
+}
+
+/// Escape `s` so that it can be used as XML character data or (quoted) attribute values. Control
+/// characters can not be represented in XML 1.0, even as character references, so they are
+/// replaced by a textual escape such as `\u{1b}`.
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if c.is_control() || c == '\u{fffe}' || c == '\u{ffff}' => {
+                out.push_str(&format!("\\u{{{:x}}}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_str() {
+        assert_eq!(json_str(""), "\"\"");
+        assert_eq!(json_str("a b"), "\"a b\"");
+        assert_eq!(json_str("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(json_str("a\nb\tc\u{1}"), "\"a\\nb\\tc\\u0001\"");
+    }
+
+    #[test]
+    fn test_tags() {
+        assert_eq!(json_tags(&[]), "");
+        let tags = vec!["slow".to_owned(), "a\"b".to_owned()];
+        assert_eq!(json_tags(&tags), ",\"tags\":[\"slow\",\"a\\\"b\"]");
+        assert_eq!(tap_tags(&[]), "");
+        assert_eq!(
+            tap_tags(&tags),
+            "\n  ---\n  tags: [\"slow\",\"a\\\"b\"]\n  ..."
+        );
+    }
+
+    #[test]
+    fn test_truncate_lines() {
+        let s = "a\nb\nc\nd\ne\n";
+        assert_eq!(truncate_lines(s, None), s);
+        assert_eq!(truncate_lines(s, Some(3)), s);
+        assert_eq!(
+            truncate_lines(s, Some(2)),
+            "a\nb\n... (1 line omitted) ...\nd\ne"
+        );
+        assert_eq!(
+            truncate_lines(s, Some(1)),
+            "a\n... (3 lines omitted) ...\ne"
+        );
+        assert_eq!(truncate_lines(s, Some(0)), "... (5 lines omitted) ...");
+        assert_eq!(truncate_lines("", Some(0)), "");
+        assert_eq!(omitted_lines(5, Some(usize::MAX)), None);
+    }
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(xml_escape(""), "");
+        assert_eq!(xml_escape("a\tb\nc"), "a\tb\nc");
+        assert_eq!(
+            xml_escape("<a href=\"x\">&'"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&apos;"
+        );
+        assert_eq!(xml_escape("a\u{1b}[0m\u{0}"), "a\\u{1b}[0m\\u{0}");
+        assert_eq!(xml_attr("a<b"), "\"a&lt;b\"");
+    }
+
+    #[test]
+    fn test_yaml_str() {
+        assert_eq!(yaml_str(""), "\"\"");
+        assert_eq!(yaml_str("a\n  b\n"), "|-\n    a\n      b");
+        assert_eq!(yaml_str("a"), "\"a\"");
+        assert_eq!(yaml_str("  a\nb"), "\"  a\\nb\"");
+        assert_eq!(yaml_str("a\u{1b}[0m"), "\"a\\u001b[0m\"");
+    }
+}
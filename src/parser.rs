@@ -1,152 +1,1520 @@
-use std::collections::hash_map::{Entry, HashMap};
+use std::{
+    collections::hash_map::HashMap, error::Error, fmt, ops::RangeInclusive, path::PathBuf,
+    str::FromStr, time::Duration,
+};
+
+use nix::sys::signal::Signal;
 
 use crate::{
-    fatal,
-    tester::{Status, TestCmd, Tests},
+    fuzzy, json,
+    tester::{IgnoreIf, InteractStep, Status, TestCmd, Tests},
 };
 
-/// Parse test data into a set of `Test`s.
-pub(crate) fn parse_tests(test_str: &str) -> Tests {
+/// An error encountered when parsing test data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    line: usize,
+    msg: String,
+}
+
+impl ParseError {
+    pub(crate) fn new(line: usize, msg: String) -> Self {
+        ParseError { line, msg }
+    }
+
+    /// The (0-based) line of the test data on which the error occurred.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// A human readable description of the error.
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl Error for ParseError {}
+
+/// The default prefix of comment lines in test data.
+pub(crate) const DEFAULT_COMMENT_PREFIX: &str = "#";
+/// The default number of columns by which a command's keys must be indented relative to the
+/// command, and a key's value relative to the key.
+pub(crate) const DEFAULT_MIN_INDENT: usize = 1;
+
+/// Keys which can be specified more than once for a command, each occurrence adding to the values
+/// of the previous ones. Every other key can be specified at most once.
+const ADDITIVE_KEYS: &[&str] = &[
+    "env-var",
+    "extra-args",
+    "skip-if-missing",
+    "stderr-not",
+    "stdout-not",
+];
+
+/// Keys which check a command's stderr or stdout separately, and so can't be specified alongside
+/// `output`.
+const STD_KEYS: &[&str] = &[
+    "stderr",
+    "stdout",
+    "stderr-empty",
+    "stdout-empty",
+    "stderr-exact",
+    "stdout-exact",
+    "stderr-file",
+    "stdout-file",
+    "stderr-not",
+    "stdout-not",
+    "stderr-contains",
+    "stdout-contains",
+    "stderr-contains-ordered",
+    "stdout-contains-ordered",
+    "stderr-lines",
+    "stdout-lines",
+    "stderr-bytes",
+    "stdout-bytes",
+    "stderr-raw",
+    "stdout-raw",
+    "stderr-json",
+    "stdout-json",
+    "stderr-json-subset",
+    "stdout-json-subset",
+    "stdout-equals-stdin",
+];
+
+/// Groups of keys of which a command can specify at most one.
+const EXCLUSIVE_KEYS: &[&[&str]] = &[
+    &[
+        "stderr",
+        "stderr-empty",
+        "stderr-exact",
+        "stderr-file",
+        "stderr-raw",
+        "stderr-json",
+        "stderr-json-subset",
+    ],
+    &[
+        "stdout",
+        "stdout-empty",
+        "stdout-exact",
+        "stdout-file",
+        "stdout-raw",
+        "stdout-json",
+        "stdout-json-subset",
+        "stdout-equals-stdin",
+    ],
+    &[
+        "stdin",
+        "stdin-bytes",
+        "stdin-file",
+        "stdin-from",
+        "interact",
+    ],
+];
+
+/// Pairs of keys which can't both be specified for a command, since an empty stream has no lines to
+/// contain and no lines or bytes to count.
+const CONFLICTING_KEYS: &[(&str, &str)] = &[
+    ("stderr-empty", "stderr-contains"),
+    ("stderr-empty", "stderr-contains-ordered"),
+    ("stderr-empty", "stderr-lines"),
+    ("stderr-empty", "stderr-bytes"),
+    ("stdout-empty", "stdout-contains"),
+    ("stdout-empty", "stdout-contains-ordered"),
+    ("stdout-empty", "stdout-lines"),
+    ("stdout-empty", "stdout-bytes"),
+];
+
+/// Keys whose values keep the whitespace on each line (relative to the indentation of the value's
+/// first line) rather than having it trimmed.
+const RAW_KEYS: &[&str] = &["stderr-raw", "stdout-raw"];
+
+/// Parse test data (i.e. the string returned by the function passed to
+/// [`LangTester::test_extract`](struct.LangTester.html#method.test_extract)) into a set of
+/// [`Tests`](struct.Tests.html), without running them. This allows external tools to check that
+/// test data is valid. If the test data is invalid, as many errors as possible are returned (in the
+/// order they occur in the test data), rather than just the first.
+pub fn parse(test_str: &str) -> Result<Tests<'_>, Vec<ParseError>> {
+    parse_with_comment_prefix(test_str, DEFAULT_COMMENT_PREFIX)
+}
+
+/// As [`parse`](fn.parse.html), but treating lines which start with `comment_prefix` (rather than
+/// the default `#`) as comments, as set by
+/// [`LangTester::comment_prefix`](struct.LangTester.html#method.comment_prefix). If
+/// `comment_prefix` is empty, no lines are treated as comments.
+pub fn parse_with_comment_prefix<'a>(
+    test_str: &'a str,
+    comment_prefix: &str,
+) -> Result<Tests<'a>, Vec<ParseError>> {
+    parse_with_min_indent(test_str, comment_prefix, DEFAULT_MIN_INDENT)
+}
+
+/// As [`parse_with_comment_prefix`](fn.parse_with_comment_prefix.html), but requiring a command's
+/// keys to be indented by at least `min_indent` columns more than the command, and a key's value
+/// by at least `min_indent` columns more than the key, as set by
+/// [`LangTester::min_indent`](struct.LangTester.html#method.min_indent). `min_indent` must be at
+/// least 1.
+pub fn parse_with_min_indent<'a>(
+    test_str: &'a str,
+    comment_prefix: &str,
+    min_indent: usize,
+) -> Result<Tests<'a>, Vec<ParseError>> {
+    debug_assert!(min_indent > 0);
+    let is_comment =
+        |l: &str| !comment_prefix.is_empty() && l.trim_start().starts_with(comment_prefix);
     let lines = test_str.lines().collect::<Vec<_>>();
     let mut tests = HashMap::new();
+    let mut errs = Vec::new();
     let mut line_off = 0;
-    let mut ignore = false;
+    let mut ignore = None;
+    let mut ignore_if = None;
+    let mut xfail = None;
+    let mut tags = Vec::new();
+    // The indentation of, and line of, the first command name (or `ignore` etc.): all others must
+    // have the same indentation.
+    let mut top_indent = None;
     while line_off < lines.len() {
         let indent = indent_level(&lines, line_off);
-        if indent == lines[line_off].len() {
+        if indent == lines[line_off].len() || is_comment(lines[line_off]) {
             line_off += 1;
             continue;
         }
-        let (test_name, val) = key_val(&lines, line_off, indent);
+        let (top_indent, top_line_off) = *top_indent.get_or_insert((indent, line_off));
+        if indent != top_indent {
+            errs.push(ParseError::new(
+                line_off,
+                format!(
+                    "Line {} is indented differently to line {}, but both should be command names.",
+                    line_off, top_line_off
+                ),
+            ));
+            line_off = skip_block(&lines, line_off, indent);
+            continue;
+        }
+        let (test_name, val) = match key_val(&lines, line_off, indent) {
+            Ok(x) => x,
+            Err(e) => {
+                errs.push(e);
+                line_off = skip_block(&lines, line_off, indent);
+                continue;
+            }
+        };
         if test_name == "ignore" {
-            ignore = true;
+            ignore = Some(val.to_owned());
+            line_off += 1;
+            continue;
+        }
+        if test_name == "xfail" {
+            xfail = Some(val.to_owned());
+            line_off += 1;
+            continue;
+        }
+        if test_name == "tags" {
+            if !tags.is_empty() {
+                errs.push(ParseError::new(
+                    line_off,
+                    format!("tags is specified more than once, line {}.", line_off),
+                ));
+            }
+            tags = val.split_whitespace().map(|t| t.to_owned()).collect();
+            if tags.is_empty() {
+                errs.push(ParseError::new(
+                    line_off,
+                    format!("tags has no tags on line {}.", line_off),
+                ));
+            }
+            line_off += 1;
+            continue;
+        }
+        if test_name == "ignore-if" {
+            if ignore_if.is_some() {
+                errs.push(ParseError::new(
+                    line_off,
+                    format!("ignore-if is specified more than once, line {}.", line_off),
+                ));
+            }
+            match parse_ignore_if(val) {
+                Some(c) => ignore_if = Some(c),
+                None => errs.push(ParseError::new(
+                    line_off,
+                    format!(
+                        "Invalid ignore-if condition '{}' on line {}.",
+                        val, line_off
+                    ),
+                )),
+            }
             line_off += 1;
             continue;
         }
         if !val.is_empty() {
-            fatal(&format!(
-                "Test name '{}' can't have a value on line {}.",
-                test_name, line_off
+            errs.push(ParseError::new(
+                line_off,
+                format!(
+                    "Test name '{}' can't have a value on line {}.",
+                    test_name, line_off
+                ),
+            ));
+        }
+        let cmd_name = test_name.to_lowercase();
+        if tests.contains_key(&cmd_name) {
+            // We still parse the command's keys, so that any errors in them are reported.
+            errs.push(ParseError::new(
+                line_off,
+                format!(
+                    "Command name '{}' is specified more than once, line {}.",
+                    test_name, line_off
+                ),
             ));
         }
-        match tests.entry(test_name.to_lowercase()) {
-            Entry::Occupied(_) => fatal(&format!(
-                "Command name '{}' is specified more than once, line {}.",
-                test_name, line_off
-            )),
-            Entry::Vacant(e) => {
+        let cmd_line_off = line_off;
+        line_off += 1;
+        let mut testcmd = TestCmd::default();
+        // The lines on which `output` and `stderr`/`stdout` were specified (if at all): these are
+        // mutually exclusive.
+        let mut output_line_off = None;
+        let mut std_line_off = None;
+        // The indentation of, and line of, the command's first key: all others must have the same
+        // indentation.
+        let mut key_indent = None;
+        while line_off < lines.len() {
+            let sub_indent = indent_level(&lines, line_off);
+            if sub_indent == lines[line_off].len() || is_comment(lines[line_off]) {
                 line_off += 1;
-                let mut testcmd = TestCmd::default();
-                while line_off < lines.len() {
-                    let sub_indent = indent_level(&lines, line_off);
-                    if sub_indent == lines[line_off].len() {
-                        line_off += 1;
+                continue;
+            }
+            if let Err(e) = check_indent(&lines, line_off, cmd_line_off) {
+                errs.push(e);
+                line_off = skip_block(&lines, line_off, sub_indent);
+                continue;
+            }
+            if sub_indent <= indent {
+                break;
+            }
+            let indent_err = match key_indent {
+                _ if sub_indent < indent + min_indent => Some(format!(
+                    "Line {} must be indented by at least {} more than the command on line {}.",
+                    line_off, min_indent, cmd_line_off
+                )),
+                Some((key_indent, key_indent_off)) if sub_indent != key_indent => Some(format!(
+                    "Line {} is indented differently to line {}, but both should be keys.",
+                    line_off, key_indent_off
+                )),
+                _ => None,
+            };
+            if let Some(msg) = indent_err {
+                errs.push(ParseError::new(line_off, msg));
+                line_off = skip_block(&lines, line_off, sub_indent);
+                continue;
+            }
+            key_indent.get_or_insert((sub_indent, line_off));
+            let key_line_off = line_off;
+            let (end_line_off, key, val) =
+                match key_multiline_val(&lines, line_off, sub_indent, min_indent) {
+                    Ok(x) => x,
+                    Err(e) => {
+                        errs.push(e);
+                        line_off = skip_block(&lines, line_off, sub_indent);
                         continue;
                     }
-                    if sub_indent == indent {
-                        break;
+                };
+            line_off = end_line_off;
+            if testcmd.spans.contains_key(key) && !ADDITIVE_KEYS.contains(&key) {
+                errs.push(ParseError::new(
+                    key_line_off,
+                    format!(
+                        "{} is specified more than once, line {}.",
+                        key, key_line_off
+                    ),
+                ));
+                continue;
+            }
+            match parse_cmd_key(&lines, key_line_off, key, val, &mut testcmd) {
+                Ok(()) => {
+                    // The key's span doesn't include any blank lines after its value.
+                    let mut span_end = end_line_off;
+                    while span_end > key_line_off + 1
+                        && indent_level(&lines, span_end - 1) == lines[span_end - 1].len()
+                    {
+                        span_end -= 1;
                     }
-                    let (end_line_off, key, val) = key_multiline_val(&lines, line_off, sub_indent);
-                    line_off = end_line_off;
+                    testcmd.spans.insert(key.to_owned(), key_line_off..span_end);
                     match key {
-                        "extra-args" => {
-                            let val_str = val.join("\n");
-                            testcmd.args.push(val_str);
+                        "output" => output_line_off = Some(key_line_off),
+                        _ if STD_KEYS.contains(&key) => std_line_off = Some(key_line_off),
+                        _ => (),
+                    }
+                }
+                Err(e) => errs.push(e),
+            }
+        }
+        if let (true, Some(span)) = (
+            testcmd.stdout_equals_stdin,
+            testcmd.spans.get("stdout-equals-stdin"),
+        ) {
+            let key_line_off = span.start;
+            if [
+                "stdout",
+                "stdout-empty",
+                "stdout-exact",
+                "stdout-file",
+                "stdout-raw",
+                "stdout-json",
+                "stdout-json-subset",
+            ]
+            .iter()
+            .any(|k| testcmd.spans.contains_key(*k))
+            {
+                errs.push(ParseError::new(
+                    key_line_off,
+                    format!(
+                        "Can't specify stdout-equals-stdin on line {} as well as stdout, \
+                         stdout-empty, stdout-exact, stdout-file, stdout-raw, stdout-json, or \
+                         stdout-json-subset.",
+                        key_line_off
+                    ),
+                ));
+            }
+            if testcmd.stdin.is_none()
+                && testcmd.stdin_file.is_none()
+                && testcmd.stdin_from.is_none()
+            {
+                errs.push(ParseError::new(
+                    key_line_off,
+                    format!(
+                        "stdout-equals-stdin on line {} requires one of stdin, stdin-bytes, \
+                         stdin-file, or stdin-from.",
+                        key_line_off
+                    ),
+                ));
+            }
+        }
+        for &(k1, k2) in CONFLICTING_KEYS {
+            if let (Some(span1), Some(span2)) = (testcmd.spans.get(k1), testcmd.spans.get(k2)) {
+                errs.push(ParseError::new(
+                    span1.start,
+                    format!(
+                        "Can't specify {} on line {} as well as {} on line {}.",
+                        k1, span1.start, k2, span2.start
+                    ),
+                ));
+            }
+        }
+        if let (Some(_), Some(std_line_off)) = (output_line_off, std_line_off) {
+            errs.push(ParseError::new(
+                std_line_off,
+                format!(
+                    "Can't specify stderr or stdout on line {} as well as output.",
+                    std_line_off
+                ),
+            ));
+        }
+        tests.entry(cmd_name).or_insert(testcmd);
+    }
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+    Ok(Tests {
+        ignore,
+        ignore_if,
+        xfail,
+        tags,
+        tests,
+    })
+}
+
+/// Merge the keys of each command in `defaults` (test data, parsed with `comment_prefix` and
+/// `min_indent`, which specifies only commands) into the command of the same name in `tests` (see
+/// `LangTester::default_cmd_assertions`). A key is not merged if the command specifies it, or a
+/// key which can't be specified alongside it. Commands in `defaults` which `tests` doesn't specify
+/// are left for `default_testcmd`, as only once `test_cmds` has been called is it known which
+/// commands will be run. `defaults` must already be known to parse successfully.
+pub(crate) fn apply_defaults<'a>(
+    tests: &mut Tests<'a>,
+    defaults: &'a str,
+    comment_prefix: &str,
+    min_indent: usize,
+) {
+    let dtests = parse_with_min_indent(defaults, comment_prefix, min_indent).unwrap();
+    for (cmd_name, dtestcmd) in &dtests.tests {
+        if let Some(testcmd) = tests.tests.get_mut(cmd_name) {
+            merge_defaults(testcmd, dtestcmd, defaults, min_indent);
+        }
+    }
+}
+
+/// Return the `TestCmd` of a command `cmd_name` which a test's data doesn't specify, with the keys
+/// of the command of the same name in `defaults` (if any) merged into it as `apply_defaults` does.
+pub(crate) fn default_testcmd<'a>(
+    cmd_name: &str,
+    defaults: &'a str,
+    comment_prefix: &str,
+    min_indent: usize,
+) -> TestCmd<'a> {
+    let dtests = parse_with_min_indent(defaults, comment_prefix, min_indent).unwrap();
+    let mut testcmd = TestCmd::default();
+    if let Some(dtestcmd) = dtests.tests.get(cmd_name) {
+        merge_defaults(&mut testcmd, dtestcmd, defaults, min_indent);
+    }
+    testcmd
+}
+
+/// Merge the keys of `dtestcmd` (a command in `defaults`) into `testcmd` (see `apply_defaults`).
+fn merge_defaults<'a>(
+    testcmd: &mut TestCmd<'a>,
+    dtestcmd: &TestCmd,
+    defaults: &'a str,
+    min_indent: usize,
+) {
+    let lines = defaults.lines().collect::<Vec<_>>();
+    let mut dkeys = dtestcmd.spans.iter().collect::<Vec<_>>();
+    dkeys.sort_by_key(|(_, span)| span.start);
+    for (dkey, span) in dkeys {
+        if testcmd
+            .spans
+            .keys()
+            .any(|k| k == dkey || keys_conflict(k, dkey))
+        {
+            continue;
+        }
+        let indent = indent_level(&lines, span.start);
+        let (_, key, val) = key_multiline_val(&lines, span.start, indent, min_indent).unwrap();
+        // The key's span is not recorded, since it doesn't refer to lines of `tests`' test data.
+        parse_cmd_key(&lines, span.start, key, val, testcmd).unwrap();
+    }
+}
+
+/// Can the keys `k1` and `k2` not both be specified for a command?
+fn keys_conflict(k1: &str, k2: &str) -> bool {
+    let std_output = |k1, k2| k1 == "output" && STD_KEYS.contains(&k2);
+    std_output(k1, k2)
+        || std_output(k2, k1)
+        || EXCLUSIVE_KEYS
+            .iter()
+            .any(|group| group.contains(&k1) && group.contains(&k2))
+        || CONFLICTING_KEYS.contains(&(k1, k2))
+        || CONFLICTING_KEYS.contains(&(k2, k1))
+}
+
+/// Parse the key `key` (on line `key_line_off`) of a command, with value `val`, into `testcmd`.
+fn parse_cmd_key<'a>(
+    lines: &[&str],
+    key_line_off: usize,
+    key: &str,
+    val: Vec<&'a str>,
+    testcmd: &mut TestCmd<'a>,
+) -> Result<(), ParseError> {
+    match key {
+        "cmd" => {
+            if val.len() != 1 {
+                return Err(ParseError::new(
+                    key_line_off,
+                    format!("cmd must be a single line on line {}.", key_line_off),
+                ));
+            }
+            let args = split_args(val[0]).ok_or_else(|| {
+                ParseError::new(
+                    key_line_off,
+                    format!(
+                        "Unterminated quote in cmd '{}' on line {}.",
+                        val[0], key_line_off
+                    ),
+                )
+            })?;
+            if args.is_empty() {
+                return Err(ParseError::new(
+                    key_line_off,
+                    format!("cmd is empty on line {}.", key_line_off),
+                ));
+            }
+            testcmd.cmd = Some(args);
+        }
+        "cwd" => {
+            if val.len() != 1 {
+                return Err(ParseError::new(
+                    key_line_off,
+                    format!("cwd must be a single line on line {}.", key_line_off),
+                ));
+            }
+            testcmd.cwd = Some(PathBuf::from(val[0]));
+        }
+        "env-var" => {
+            for var in val {
+                let mut split = var.split('=');
+                match (split.next(), split.next(), split.next()) {
+                    (Some(name), Some(val), None) if !name.trim().is_empty() => {
+                        testcmd
+                            .env
+                            .push((name.trim().to_owned(), val.trim().to_owned()));
+                    }
+                    _ => {
+                        return Err(ParseError::new(
+                            key_line_off,
+                            format!(
+                                "'{}' is not of the form 'NAME=VALUE' on line {}.",
+                                var, key_line_off
+                            ),
+                        ))
+                    }
+                }
+            }
+        }
+        "env-clear" | "stdout-equals-stdin" => {
+            let val_str = val.join("\n");
+            let b = match val_str.as_str() {
+                "true" => true,
+                "false" => false,
+                _ => {
+                    return Err(ParseError::new(
+                        key_line_off,
+                        format!(
+                            "Invalid {} '{}' on line {}: must be 'true' or 'false'.",
+                            key, val_str, key_line_off
+                        ),
+                    ))
+                }
+            };
+            match key {
+                "env-clear" => testcmd.env_clear = b,
+                _ => testcmd.stdout_equals_stdin = b,
+            }
+        }
+        "extra-args" | "skip-if-missing" => {
+            let mut args = Vec::new();
+            for l in val {
+                args.extend(split_args(l).ok_or_else(|| {
+                    ParseError::new(
+                        key_line_off,
+                        format!(
+                            "Unterminated quote in {} '{}' on line {}.",
+                            key, l, key_line_off
+                        ),
+                    )
+                })?);
+            }
+            if key == "extra-args" {
+                for a in &args {
+                    expand_placeholders(a, |_| Ok(String::new())).map_err(|e| {
+                        ParseError::new(
+                            key_line_off,
+                            format!("{} in extra-args on line {}.", e, key_line_off),
+                        )
+                    })?;
+                }
+                testcmd.args.extend(args);
+            } else if args.is_empty() {
+                return Err(ParseError::new(
+                    key_line_off,
+                    format!("skip-if-missing has no programs on line {}.", key_line_off),
+                ));
+            } else {
+                testcmd.skip_if_missing.extend(args);
+            }
+        }
+        "status" => {
+            let val_str = val.join("\n");
+            testcmd.status = parse_status(&val_str).ok_or_else(|| {
+                ParseError::new(
+                    key_line_off,
+                    format!("Unknown status '{}' on line {}", val_str, key_line_off),
+                )
+            })?;
+        }
+        "timeout" => {
+            let val_str = val.join("\n");
+            match val_str.parse::<u64>() {
+                Ok(i) => testcmd.timeout = Some(Duration::from_secs(i)),
+                Err(_) => {
+                    return Err(ParseError::new(
+                        key_line_off,
+                        format!(
+                            "Invalid timeout '{}' on line {}: must be a number of seconds.",
+                            val_str, key_line_off
+                        ),
+                    ))
+                }
+            }
+        }
+        "delay" => {
+            let val_str = val.join("\n");
+            match val_str.parse::<u64>() {
+                Ok(i) => testcmd.delay = Some(Duration::from_millis(i)),
+                Err(_) => {
+                    return Err(ParseError::new(
+                        key_line_off,
+                        format!(
+                            "Invalid delay '{}' on line {}: must be a number of milliseconds.",
+                            val_str, key_line_off
+                        ),
+                    ))
+                }
+            }
+        }
+        "max-rss" => {
+            let val_str = val.join("\n");
+            match val_str.parse::<u64>() {
+                Ok(i) => testcmd.max_rss = Some(i),
+                Err(_) => {
+                    return Err(ParseError::new(
+                        key_line_off,
+                        format!(
+                            "Invalid max-rss '{}' on line {}: must be a number of megabytes.",
+                            val_str, key_line_off
+                        ),
+                    ))
+                }
+            }
+        }
+        "retries" => {
+            let val_str = val.join("\n");
+            match val_str.parse::<usize>() {
+                Ok(i) => testcmd.retries = i,
+                Err(_) => {
+                    return Err(ParseError::new(
+                        key_line_off,
+                        format!(
+                            "Invalid retries '{}' on line {}: must be a number.",
+                            val_str, key_line_off
+                        ),
+                    ))
+                }
+            }
+        }
+        "output" => {
+            check_pattern_lines(lines, key_line_off, &val)?;
+            testcmd.output = Some(val);
+        }
+        "stderr" | "stderr-empty" | "stderr-exact" | "stderr-file" | "stderr-raw"
+        | "stderr-json" | "stderr-json-subset" | "stdout" | "stdout-empty" | "stdout-exact"
+        | "stdout-file" | "stdout-raw" | "stdout-json" | "stdout-json-subset" => {
+            let (stream, pattern, exact, file, json) = if key.starts_with("stderr") {
+                (
+                    "stderr",
+                    &testcmd.stderr,
+                    &testcmd.stderr_exact,
+                    &testcmd.stderr_file,
+                    &testcmd.stderr_json,
+                )
+            } else {
+                (
+                    "stdout",
+                    &testcmd.stdout,
+                    &testcmd.stdout_exact,
+                    &testcmd.stdout_file,
+                    &testcmd.stdout_json,
+                )
+            };
+            if pattern != &[fuzzy::WILDCARD] || exact.is_some() || file.is_some() || json.is_some()
+            {
+                return Err(ParseError::new(
+                    key_line_off,
+                    format!(
+                        "Can't specify {} on line {}: only one of {}, {}-empty, {}-exact, \
+                         {}-file, {}-raw, {}-json, and {}-json-subset can be specified.",
+                        key, key_line_off, stream, stream, stream, stream, stream, stream, stream
+                    ),
+                ));
+            }
+            match key {
+                "stderr" | "stdout" | "stderr-raw" | "stdout-raw" => {
+                    check_pattern_lines(lines, key_line_off, &val)?
+                }
+                "stderr-json" | "stdout-json" | "stderr-json-subset" | "stdout-json-subset" => {
+                    if let Err(e) = json::parse(&val.join("\n")) {
+                        return Err(ParseError::new(
+                            key_line_off,
+                            format!("Invalid JSON in {} on line {}: {}", key, key_line_off, e),
+                        ));
+                    }
+                }
+                "stderr-empty" | "stdout-empty" if !val.is_empty() => {
+                    return Err(ParseError::new(
+                        key_line_off,
+                        format!("{} can't have a value on line {}.", key, key_line_off),
+                    ));
+                }
+                "stderr-file" | "stdout-file" if val.len() != 1 => {
+                    return Err(ParseError::new(
+                        key_line_off,
+                        format!("{} must be a single line on line {}.", key, key_line_off),
+                    ));
+                }
+                _ => (),
+            }
+            match key {
+                "stderr" | "stderr-empty" => testcmd.stderr = val,
+                "stdout" | "stdout-empty" => testcmd.stdout = val,
+                "stderr-raw" => {
+                    testcmd.stderr = val;
+                    testcmd.stderr_raw = true;
+                }
+                "stdout-raw" => {
+                    testcmd.stdout = val;
+                    testcmd.stdout_raw = true;
+                }
+                "stderr-exact" => testcmd.stderr_exact = Some(val),
+                "stdout-exact" => testcmd.stdout_exact = Some(val),
+                "stderr-json" => testcmd.stderr_json = Some(val),
+                "stdout-json" => testcmd.stdout_json = Some(val),
+                "stderr-json-subset" => {
+                    testcmd.stderr_json = Some(val);
+                    testcmd.stderr_json_subset = true;
+                }
+                "stdout-json-subset" => {
+                    testcmd.stdout_json = Some(val);
+                    testcmd.stdout_json_subset = true;
+                }
+                "stderr-file" => testcmd.stderr_file = Some(PathBuf::from(val[0])),
+                _ => testcmd.stdout_file = Some(PathBuf::from(val[0])),
+            }
+        }
+        "stderr-not"
+        | "stdout-not"
+        | "stderr-contains"
+        | "stdout-contains"
+        | "stderr-contains-ordered"
+        | "stdout-contains-ordered" => {
+            check_line_patterns(lines, key_line_off, key, &val)?;
+            let plines = match key {
+                "stderr-not" => &mut testcmd.stderr_not,
+                "stdout-not" => &mut testcmd.stdout_not,
+                "stderr-contains" => &mut testcmd.stderr_contains,
+                "stdout-contains" => &mut testcmd.stdout_contains,
+                "stderr-contains-ordered" => &mut testcmd.stderr_ordered,
+                _ => &mut testcmd.stdout_ordered,
+            };
+            plines.extend(val.into_iter().filter(|l| !l.is_empty()));
+        }
+        "output-file" => {
+            let (path, rest) = match val.split_first() {
+                Some((path, rest)) => (path, rest),
+                None => {
+                    return Err(ParseError::new(
+                        key_line_off,
+                        format!("output-file has no path on line {}.", key_line_off),
+                    ))
+                }
+            };
+            // The path may be followed by a `contents:` pattern which the file must match.
+            let rest = rest
+                .iter()
+                .skip_while(|l| l.is_empty())
+                .copied()
+                .collect::<Vec<_>>();
+            if let Some((first, pattern)) = rest.split_first() {
+                let first = first.strip_prefix("contents:").ok_or_else(|| {
+                    ParseError::new(
+                        key_line_off,
+                        format!(
+                            "Expected 'contents:' after the path of output-file on line {}.",
+                            key_line_off
+                        ),
+                    )
+                })?;
+                let mut contents = vec![first.trim()];
+                contents.extend(pattern);
+                while contents.first() == Some(&"") {
+                    contents.remove(0);
+                }
+                check_pattern_lines(lines, key_line_off, &contents)?;
+                testcmd.output_file_contents = Some(contents);
+            }
+            testcmd.output_file = Some(PathBuf::from(path));
+        }
+        "stderr-lines" | "stdout-lines" | "stderr-bytes" | "stdout-bytes" => {
+            let val_str = val.join("\n");
+            let count = parse_count(&val_str).ok_or_else(|| {
+                ParseError::new(
+                    key_line_off,
+                    format!(
+                        "Invalid {} count '{}' on line {}: must be an integer or an inclusive \
+                         range (e.g. '5..=15').",
+                        if key.ends_with("-lines") {
+                            "line"
+                        } else {
+                            "byte"
+                        },
+                        val_str,
+                        key_line_off
+                    ),
+                )
+            })?;
+            match key {
+                "stderr-lines" => testcmd.stderr_lines = Some(count),
+                "stdout-lines" => testcmd.stdout_lines = Some(count),
+                "stderr-bytes" => testcmd.stderr_bytes = Some(count),
+                _ => testcmd.stdout_bytes = Some(count),
+            }
+        }
+        "stdin" | "stdin-bytes" | "stdin-file" | "stdin-from" | "interact" => {
+            if testcmd.stdin.is_some()
+                || testcmd.stdin_file.is_some()
+                || testcmd.stdin_from.is_some()
+                || !testcmd.interact.is_empty()
+            {
+                return Err(ParseError::new(
+                    key_line_off,
+                    format!(
+                        "Can't specify {} on line {}: only one of stdin, stdin-bytes, stdin-file, \
+                         stdin-from, and interact can be specified.",
+                        key, key_line_off
+                    ),
+                ));
+            }
+            match key {
+                "stdin" => {
+                    let val_str = val.join("\n");
+                    // A non-empty `stdin` always has a single trailing newline.
+                    testcmd.stdin = Some(if val_str.is_empty() {
+                        Vec::new()
+                    } else {
+                        format!("{}\n", val_str).into_bytes()
+                    });
+                }
+                "stdin-bytes" => {
+                    let val_str = val.concat();
+                    let bytes = match val_str.strip_prefix("hex:") {
+                        Some(hex) => decode_hex(hex),
+                        None => decode_base64(&val_str),
+                    };
+                    match bytes {
+                        Some(bytes) => testcmd.stdin = Some(bytes),
+                        None => {
+                            return Err(ParseError::new(
+                                key_line_off,
+                                format!(
+                                    "Invalid stdin-bytes on line {}: must be base64, or hex \
+                                     prefixed with 'hex:'.",
+                                    key_line_off
+                                ),
+                            ))
                         }
-                        "status" => {
-                            let val_str = val.join("\n");
-                            let status = match val_str.to_lowercase().as_str() {
-                                "success" => Status::Success,
-                                "error" => Status::Error,
-                                "signal" => Status::Signal,
-                                x => {
-                                    if let Ok(i) = x.parse::<i32>() {
-                                        Status::Int(i)
-                                    } else {
-                                        fatal(&format!(
-                                            "Unknown status '{}' on line {}",
-                                            val_str, line_off
-                                        ));
-                                    }
+                    }
+                }
+                "interact" => {
+                    for l in val.into_iter().filter(|l| !l.is_empty()) {
+                        let step = match l.split_once(':') {
+                            Some((k, v)) if k.trim() == "send" => InteractStep::Send(v.trim()),
+                            Some((k, v)) if k.trim() == "expect" => {
+                                let p = v.trim();
+                                check_pattern_lines(lines, key_line_off, &[p])?;
+                                if fuzzy::wildcard_bounds(p).is_some() {
+                                    return Err(ParseError::new(
+                                        key_line_off,
+                                        format!(
+                                            "'{}' can't be used as an expect pattern on line {}.",
+                                            p, key_line_off
+                                        ),
+                                    ));
                                 }
-                            };
-                            testcmd.status = status;
-                        }
-                        "stderr" => {
-                            testcmd.stderr = val;
-                        }
-                        "stdout" => {
-                            testcmd.stdout = val;
+                                InteractStep::Expect(p)
+                            }
+                            _ => {
+                                return Err(ParseError::new(
+                                    key_line_off,
+                                    format!(
+                                        "'{}' is not of the form 'send: <string>' or 'expect: \
+                                         <pattern>' on line {}.",
+                                        l, key_line_off
+                                    ),
+                                ))
+                            }
+                        };
+                        testcmd.interact.push(step);
+                    }
+                    if testcmd.interact.is_empty() {
+                        return Err(ParseError::new(
+                            key_line_off,
+                            format!("interact has no steps on line {}.", key_line_off),
+                        ));
+                    }
+                }
+                _ => {
+                    if val.len() != 1 {
+                        return Err(ParseError::new(
+                            key_line_off,
+                            format!("{} must be a single line on line {}.", key, key_line_off),
+                        ));
+                    }
+                    if key == "stdin-file" {
+                        testcmd.stdin_file = Some(PathBuf::from(val[0]));
+                    } else {
+                        testcmd.stdin_from = Some(val[0].to_lowercase());
+                    }
+                }
+            }
+        }
+        _ => {
+            return Err(ParseError::new(
+                key_line_off,
+                format!("Unknown key '{}' on line {}.", key, key_line_off),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Return the offset of the first line after `line_off` which is not blank, is not indented more
+/// than `indent`, and whose indentation is consistent with that of `line_off` (i.e. skip over the
+/// line at `line_off` and any value it has).
+fn skip_block(lines: &[&str], line_off: usize, indent: usize) -> usize {
+    let mut cur_off = line_off + 1;
+    while cur_off < lines.len()
+        && (indent_level(lines, cur_off) == lines[cur_off].len()
+            || indent_level(lines, cur_off) > indent
+            || check_indent(lines, cur_off, line_off).is_err())
+    {
+        cur_off += 1;
+    }
+    cur_off
+}
+
+/// Parse an `ignore-if` condition such as `unix`, `target_os = "linux"`, or `not(windows)`,
+/// returning `None` if `s` is not a valid condition.
+fn parse_ignore_if(s: &str) -> Option<IgnoreIf> {
+    let s = s.trim();
+    if let Some(inner) = s.strip_prefix("not") {
+        let inner = inner.trim_start().strip_prefix('(')?.strip_suffix(')')?;
+        return Some(IgnoreIf::Not(Box::new(parse_ignore_if(inner)?)));
+    }
+    match s {
+        "unix" | "windows" => return Some(IgnoreIf::TargetFamily(s.to_owned())),
+        _ => (),
+    }
+    let i = s.find('=')?;
+    let val = s[i + 1..].trim();
+    let val = val
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(val);
+    if val.is_empty() || val.contains(|c: char| c.is_whitespace() || c == '"') {
+        return None;
+    }
+    let val = val.to_owned();
+    match s[..i].trim() {
+        "target_os" => Some(IgnoreIf::TargetOs(val)),
+        "target_family" => Some(IgnoreIf::TargetFamily(val)),
+        "target_arch" => Some(IgnoreIf::TargetArch(val)),
+        _ => None,
+    }
+}
+
+/// Parse a status such as `success`, `1`, or `1, 3..=5`, returning `None` if `s` is not a valid
+/// status.
+fn parse_status(s: &str) -> Option<Status> {
+    let s = s.to_lowercase();
+    if let Some(inner) = s.strip_prefix("not ") {
+        // `not not x` is just `x`, `not any` can never pass, and `not timeout` is just `any`, so
+        // all are almost certainly mistakes.
+        return match parse_status(inner.trim())? {
+            Status::Not(_) | Status::Any | Status::Timeout => None,
+            status => Some(Status::Not(Box::new(status))),
+        };
+    }
+    match s.as_str() {
+        "success" => Some(Status::Success),
+        // The docs have always called this `failure`, but `error` was accepted first.
+        "failure" | "error" => Some(Status::Error),
+        "signal" => Some(Status::Signal(None)),
+        "any" => Some(Status::Any),
+        "timeout" => Some(Status::Timeout),
+        x if x.starts_with("signal(") && x.ends_with(')') => {
+            parse_signal(&x["signal(".len()..x.len() - 1]).map(|sig| Status::Signal(Some(sig)))
+        }
+        x => {
+            if let Ok(i) = x.parse::<i32>() {
+                return Some(Status::Int(i));
+            }
+            // A comma separated list of exit codes and/or inclusive ranges of exit codes.
+            let mut codes = Vec::new();
+            for c in x.split(',').map(|c| c.trim()) {
+                match c.find("..=") {
+                    Some(i) => {
+                        let start = c[..i].trim().parse::<i32>().ok()?;
+                        let end = c[i + "..=".len()..].trim().parse::<i32>().ok()?;
+                        // Every code in a range is stored, so we only allow ranges of codes that a
+                        // process can actually exit with.
+                        if start < 0 || start > end || end > 255 {
+                            return None;
                         }
-                        _ => fatal(&format!("Unknown key '{}' on line {}.", key, line_off)),
+                        codes.extend(start..=end);
                     }
+                    None => codes.push(c.parse::<i32>().ok()?),
                 }
-                e.insert(testcmd);
             }
+            Some(Status::OneOf(codes))
         }
     }
-    Tests { ignore, tests }
 }
 
-fn indent_level(lines: &[&str], line_off: usize) -> usize {
-    lines[line_off]
+/// Parses the value of a `status` key (e.g. `success` or `not 1..=4`). Returns a `ParseError` on
+/// line 0 if `s` is not a valid status.
+impl FromStr for Status {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_status(s.trim())
+            .ok_or_else(|| ParseError::new(0, format!("Unknown status '{}'", s.trim())))
+    }
+}
+
+/// Parse a count (of lines or bytes) such as `10` or an inclusive range of counts such as `5..=15`.
+fn parse_count(s: &str) -> Option<RangeInclusive<usize>> {
+    match s.find("..=") {
+        Some(i) => {
+            let start = s[..i].trim().parse::<usize>().ok()?;
+            let end = s[i + "..=".len()..].trim().parse::<usize>().ok()?;
+            if start > end {
+                return None;
+            }
+            Some(start..=end)
+        }
+        None => s.trim().parse::<usize>().ok().map(|n| n..=n),
+    }
+}
+
+/// Parse a (case insensitive) signal name such as `SIGABRT` or `abrt`, or a positive signal
+/// number such as `6`, returning the signal's number.
+fn parse_signal(s: &str) -> Option<i32> {
+    let s = s.trim();
+    if let Ok(i) = s.parse::<i32>() {
+        return if i > 0 { Some(i) } else { None };
+    }
+    let name = s.to_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    Signal::from_str(&name).ok().map(|sig| sig as i32)
+}
+
+/// Decode the hexadecimal string `s` (ignoring whitespace), returning `None` if it is not valid
+/// hexadecimal.
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    let digits = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|d| d as u8))
+        .collect::<Option<Vec<_>>>()?;
+    if digits.len() % 2 != 0 {
+        return None;
+    }
+    Some(digits.chunks(2).map(|d| d[0] << 4 | d[1]).collect())
+}
+
+/// Decode the (standard alphabet, optionally padded) base64 string `s` (ignoring whitespace),
+/// returning `None` if it is not valid base64.
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let s = s.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+    let unpadded = s.trim_end_matches('=');
+    if s.len() - unpadded.len() > 2 || (s.len() != unpadded.len() && s.len() % 4 != 0) {
+        return None;
+    }
+    let sextets = unpadded
         .chars()
-        .take_while(|c| c.is_whitespace())
-        .count()
+        .map(|c| match c {
+            'A'..='Z' => Some(c as u32 - 'A' as u32),
+            'a'..='z' => Some(c as u32 - 'a' as u32 + 26),
+            '0'..='9' => Some(c as u32 - '0' as u32 + 52),
+            '+' => Some(62),
+            '/' => Some(63),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()?;
+    if sextets.len() % 4 == 1 {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    for chunk in sextets.chunks(4) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |n, (i, x)| n | x << (18 - 6 * i));
+        bytes.extend(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(bytes)
+}
+
+/// The names of the placeholders (e.g. `{testfile}`) which can be used in `extra-args`.
+pub(crate) const PLACEHOLDERS: &[&str] = &["testfile", "testdir", "tempdir"];
+
+/// Expand the placeholders (e.g. `{testfile}`) in the argument `arg`, replacing each with the
+/// result of calling `f` with its name (e.g. `testfile`). `{{` is a literal `{`, and a `{` which
+/// doesn't start a placeholder (i.e. isn't followed by lower-case letters or `_` and then a `}`)
+/// is left as it is. Returns `Err` if a placeholder isn't one of `PLACEHOLDERS`, or if `f` does.
+pub(crate) fn expand_placeholders<F>(arg: &str, mut f: F) -> Result<String, String>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut expanded = String::new();
+    let mut rest = arg;
+    while let Some(i) = rest.find('{') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(r) = rest.strip_prefix("{{") {
+            expanded.push('{');
+            rest = r;
+            continue;
+        }
+        let name = rest[1..]
+            .find('}')
+            .map(|j| &rest[1..j + 1])
+            .filter(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_lowercase() || c == '_'));
+        match name {
+            Some(name) => {
+                if !PLACEHOLDERS.contains(&name) {
+                    return Err(format!(
+                        "Unknown placeholder '{{{}}}' (expected {{testfile}}, {{testdir}}, or \
+                         {{tempdir}})",
+                        name
+                    ));
+                }
+                expanded.push_str(&f(name)?);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                expanded.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Split `s` into arguments in the manner of a POSIX shell: arguments are separated by whitespace,
+/// unless quoted with `'` or `"`, and `\` escapes the following character (except in `'` quotes,
+/// and in `"` quotes where it only escapes `"` and `\`). Returns `None` if `s` contains an
+/// unterminated quote.
+fn split_args(s: &str) -> Option<Vec<String>> {
+    let mut args = Vec::new();
+    // The argument currently being built, if any. We can't simply check whether it's empty, as
+    // `''` is a valid (empty) argument.
+    let mut arg: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(a) = arg.take() {
+                    args.push(a);
+                }
+            }
+            '\'' => {
+                let a = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '\'' => break,
+                        c => a.push(c),
+                    }
+                }
+            }
+            '"' => {
+                let a = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            c @ ('"' | '\\') => a.push(c),
+                            c => {
+                                a.push('\\');
+                                a.push(c);
+                            }
+                        },
+                        c => a.push(c),
+                    }
+                }
+            }
+            '\\' => {
+                let a = arg.get_or_insert_with(String::new);
+                if let Some(c) = chars.next() {
+                    a.push(c);
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(arg);
+    Some(args)
+}
+
+/// Why a line of a fuzzy pattern is invalid.
+enum InvalidLine {
+    /// The line is a regular expression line which doesn't compile.
+    Regex(String),
+    /// The line is a wildcard line which is malformed or in the wrong place.
+    Wildcard(String),
+}
+
+/// Return the index of the first invalid regular expression line or wildcard line in the fuzzy
+/// pattern `val`, and why it is invalid, or `None` if every line is valid.
+fn invalid_pattern_line(val: &[&str]) -> Option<(usize, InvalidLine)> {
+    for (i, l) in val.iter().enumerate() {
+        // References to captures (e.g. `${id}`) aren't valid regular expression syntax, but are
+        // substituted before the line is used.
+        if let Some(Err(e)) = fuzzy::line_regex(&fuzzy::mask_references(l), false) {
+            return Some((i, InvalidLine::Regex(e.to_string())));
+        }
+        match fuzzy::wildcard_bounds(l) {
+            Some(Err(e)) => return Some((i, InvalidLine::Wildcard(e))),
+            // A line wildcard which isn't at the end of the pattern must be followed by a line
+            // which anchors it.
+            Some(Ok(_)) if i > 0 && fuzzy::wildcard_bounds(val[i - 1]).is_some() => {
+                return Some((
+                    i,
+                    InvalidLine::Wildcard(format!(
+                        "it can't directly follow the wildcard '{}'",
+                        val[i - 1]
+                    )),
+                ))
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Check that every regular expression line and wildcard line in the fuzzy pattern `val` (which
+/// starts on line `line_off`) is valid, returning an error if not.
+fn check_pattern_lines(lines: &[&str], line_off: usize, val: &[&str]) -> Result<(), ParseError> {
+    let (i, invalid) = match invalid_pattern_line(val) {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let l = val[i];
+    // `val` has had blank lines trimmed from its start, so the line the pattern is on isn't
+    // necessarily `line_off + i`: search for it instead.
+    let err_line_off = (line_off + i..lines.len())
+        .find(|&j| lines[j].trim().ends_with(l))
+        .unwrap_or(line_off);
+    let msg = match invalid {
+        InvalidLine::Regex(e) => format!(
+            "Invalid regular expression on line {}:\n{}",
+            err_line_off, e
+        ),
+        InvalidLine::Wildcard(e) => {
+            format!("Invalid wildcard '{}' on line {}: {}.", l, err_line_off, e)
+        }
+    };
+    Err(ParseError::new(err_line_off, msg))
+}
+
+/// Check the `Tests` returned by `LangTester::test_parse` as parsing checks test data: i.e. that
+/// every fuzzy pattern, line pattern, and JSON value is valid. Since such `Tests` have no test data
+/// to refer to, each error is on line 0, and names the command and the field it concerns.
+pub(crate) fn check_tests(tests: &Tests) -> Result<(), Vec<ParseError>> {
+    let mut cmd_names = tests.tests.keys().collect::<Vec<_>>();
+    cmd_names.sort();
+    let errs = cmd_names
+        .into_iter()
+        .filter_map(|cmd_name| {
+            check_testcmd(&tests.tests[cmd_name])
+                .err()
+                .map(|msg| ParseError::new(0, format!("{}: {}", cmd_name, msg)))
+        })
+        .collect::<Vec<_>>();
+    if errs.is_empty() {
+        Ok(())
+    } else {
+        Err(errs)
+    }
+}
+
+/// Check the patterns and JSON values of `testcmd` (see `check_tests`), returning a description
+/// of the first invalid one.
+fn check_testcmd(testcmd: &TestCmd) -> Result<(), String> {
+    let check_pattern = |field: &str, val: &[&str]| match invalid_pattern_line(val) {
+        Some((i, InvalidLine::Regex(e))) => Err(format!(
+            "Invalid regular expression '{}' in {}:\n{}",
+            val[i], field, e
+        )),
+        Some((i, InvalidLine::Wildcard(e))) => Err(format!(
+            "Invalid wildcard '{}' in {}: {}.",
+            val[i], field, e
+        )),
+        None => Ok(()),
+    };
+    check_pattern("stderr", &testcmd.stderr)?;
+    check_pattern("stdout", &testcmd.stdout)?;
+    if let Some(ref output) = testcmd.output {
+        check_pattern("output", output)?;
+    }
+    if let Some(ref contents) = testcmd.output_file_contents {
+        check_pattern("output_file_contents", contents)?;
+    }
+    for &(field, val) in &[
+        ("stderr_not", &testcmd.stderr_not),
+        ("stdout_not", &testcmd.stdout_not),
+        ("stderr_contains", &testcmd.stderr_contains),
+        ("stdout_contains", &testcmd.stdout_contains),
+        ("stderr_ordered", &testcmd.stderr_ordered),
+        ("stdout_ordered", &testcmd.stdout_ordered),
+    ] {
+        check_pattern(field, val)?;
+        if let Some(l) = val.iter().find(|l| fuzzy::wildcard_bounds(l).is_some()) {
+            return Err(format!(
+                "'{}' can't be used on a line of its own in {}.",
+                l, field
+            ));
+        }
+    }
+    for step in &testcmd.interact {
+        if let InteractStep::Expect(p) = *step {
+            check_pattern("interact", &[p])?;
+            if fuzzy::wildcard_bounds(p).is_some() {
+                return Err(format!("'{}' can't be used as an expect pattern.", p));
+            }
+        }
+    }
+    for &(field, val) in &[
+        ("stderr_json", &testcmd.stderr_json),
+        ("stdout_json", &testcmd.stdout_json),
+    ] {
+        if let Some(val) = val {
+            json::parse(&val.join("\n"))
+                .map_err(|e| format!("Invalid JSON in {}: {}", field, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Check that the line patterns in `val` (the value of the key `key`, which starts on line
+/// `line_off`), each of which is matched against single lines, are valid, returning an error if
+/// not.
+fn check_line_patterns(
+    lines: &[&str],
+    line_off: usize,
+    key: &str,
+    val: &[&str],
+) -> Result<(), ParseError> {
+    check_pattern_lines(lines, line_off, val)?;
+    if let Some(l) = val.iter().find(|l| fuzzy::wildcard_bounds(l).is_some()) {
+        return Err(ParseError::new(
+            line_off,
+            format!(
+                "'{}' can't be used on a line of its own in {} on line {}.",
+                l, key, line_off
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Return the length, in bytes, of the indentation of the line at `line_off`.
+fn indent_level(lines: &[&str], line_off: usize) -> usize {
+    leading_len(lines[line_off], |c| c.is_whitespace())
+}
+
+/// Return the length, in bytes, of the longest prefix of `s` whose characters all satisfy `pred`.
+fn leading_len(s: &str, pred: impl Fn(char) -> bool) -> usize {
+    s.char_indices()
+        .find(|&(_, c)| !pred(c))
+        .map_or(s.len(), |(i, _)| i)
+}
+
+/// Check that the indentation of the lines at `line_off` and `other_off` is consistent, i.e. that
+/// the shorter of the two is a prefix of the longer. Since tabs and spaces each count as a single
+/// unit of indentation, mixing them inconsistently would otherwise silently misalign blocks.
+fn check_indent(lines: &[&str], line_off: usize, other_off: usize) -> Result<(), ParseError> {
+    let indent = &lines[line_off][..indent_level(lines, line_off)];
+    let other_indent = &lines[other_off][..indent_level(lines, other_off)];
+    if indent.starts_with(other_indent) || other_indent.starts_with(indent) {
+        Ok(())
+    } else {
+        Err(ParseError::new(
+            line_off,
+            format!(
+                "Inconsistent use of tabs and spaces in indentation on line {} (compared to line \
+                 {}).",
+                line_off, other_off
+            ),
+        ))
+    }
 }
 
 /// Turn a line such as `key: val` into its separate components.
-fn key_val<'a>(lines: &[&'a str], line_off: usize, indent: usize) -> (&'a str, &'a str) {
+fn key_val<'a>(
+    lines: &[&'a str],
+    line_off: usize,
+    indent: usize,
+) -> Result<(&'a str, &'a str), ParseError> {
     let line = lines[line_off];
-    let key_len = line[indent..]
-        .chars()
-        .take_while(|c| !(c.is_whitespace() || c == &':'))
-        .count();
+    let key_len = leading_len(&line[indent..], |c| !(c.is_whitespace() || c == ':'));
     let key = &line[indent..indent + key_len];
     let mut content_start = indent + key_len;
-    content_start += line[content_start..]
-        .chars()
-        .take_while(|c| c.is_whitespace())
-        .count();
-    match line[content_start..].chars().nth(0) {
+    content_start += leading_len(&line[content_start..], |c| c.is_whitespace());
+    match line[content_start..].chars().next() {
         Some(':') => content_start += ':'.len_utf8(),
-        _ => fatal(&format!(
-            "Invalid key terminator at line {}.\n  {}",
-            line_off, line
-        )),
+        _ => {
+            return Err(ParseError::new(
+                line_off,
+                format!("Invalid key terminator at line {}.\n  {}", line_off, line),
+            ))
+        }
     }
-    content_start += line[content_start..]
-        .chars()
-        .take_while(|c| c.is_whitespace())
-        .count();
-    (key, &line[content_start..].trim())
+    content_start += leading_len(&line[content_start..], |c| c.is_whitespace());
+    Ok((key, line[content_start..].trim()))
 }
 
 /// Turn one more lines of the format `key: val` (where `val` may spread over many lines) into its
-/// separate components. Guarantees to trim leading and trailing newlines.
+/// separate components. Lines of `val` after the first must be indented by at least `min_indent`
+/// more than the key, and by no less than the first of them. Guarantees to trim leading and
+/// trailing newlines.
 fn key_multiline_val<'a>(
     lines: &[&'a str],
     mut line_off: usize,
     indent: usize,
-) -> (usize, &'a str, Vec<&'a str>) {
-    let (key, first_line_val) = key_val(lines, line_off, indent);
+    min_indent: usize,
+) -> Result<(usize, &'a str, Vec<&'a str>), ParseError> {
+    let (key, first_line_val) = key_val(lines, line_off, indent)?;
+    let key_line_off = line_off;
     line_off += 1;
     let mut val = vec![first_line_val];
-    if line_off < lines.len() {
-        let sub_indent = indent_level(lines, line_off);
-        while line_off < lines.len() {
-            let cur_indent = indent_level(lines, line_off);
-            if cur_indent == lines[line_off].len() {
-                val.push("");
-                line_off += 1;
-                continue;
-            }
-            if cur_indent <= indent {
-                break;
-            }
-            val.push(&lines[line_off][sub_indent..].trim());
+    // The first non-blank line of the value, and its indentation, against which the indentation of
+    // subsequent lines is checked.
+    let mut first_val = None;
+    while line_off < lines.len() {
+        let cur_indent = indent_level(lines, line_off);
+        if cur_indent == lines[line_off].len() {
+            val.push("");
             line_off += 1;
+            continue;
+        }
+        check_indent(lines, line_off, key_line_off)?;
+        if cur_indent <= indent {
+            break;
         }
+        if cur_indent < indent + min_indent {
+            return Err(ParseError::new(
+                line_off,
+                format!(
+                    "Line {} must be indented by at least {} more than the key on line {}.",
+                    line_off, min_indent, key_line_off
+                ),
+            ));
+        }
+        let (first_val_off, sub_indent) = *first_val.get_or_insert((line_off, cur_indent));
+        check_indent(lines, line_off, first_val_off)?;
+        if cur_indent < sub_indent {
+            return Err(ParseError::new(
+                line_off,
+                format!(
+                    "Line {} is indented less than line {}, the first line of the value of {}.",
+                    line_off, first_val_off, key
+                ),
+            ));
+        }
+        let line = &lines[line_off][sub_indent..];
+        val.push(if RAW_KEYS.contains(&key) {
+            line.trim_end_matches('\r')
+        } else {
+            line.trim()
+        });
+        line_off += 1;
     }
     // Remove trailing empty strings
     while !val.is_empty() && val[val.len() - 1].is_empty() {
@@ -157,27 +1525,935 @@ fn key_multiline_val<'a>(
         val.remove(0);
     }
 
-    (line_off, key, val)
+    Ok((line_off, key, val))
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
 
+    fn parse_tests(test_str: &str) -> Tests<'_> {
+        parse(test_str).unwrap()
+    }
+
+    #[test]
+    fn test_spans() {
+        let tests =
+            parse_tests("a:\n  status: error\n  stderr:\n    x\n\n    y\n\n\nb:\n  stdout:");
+        assert_eq!(tests.tests["a"].spans["status"], 1..2);
+        assert_eq!(tests.tests["a"].spans["stderr"], 2..6);
+        assert_eq!(tests.tests["a"].spans.get("stdout"), None);
+        assert_eq!(tests.tests["b"].spans["stdout"], 9..10);
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let errs =
+            parse("a:\n  status: success\n\nb:\n  stdout:\n    x\n  status: foo").unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert_eq!(errs[0].line(), 6);
+        assert_eq!(errs[0].msg(), "Unknown status 'foo' on line 6");
+        let errs = parse("a:\n  unknown: x").unwrap_err();
+        assert_eq!(errs[0].line(), 1);
+        assert!(parse("a\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let errs = parse(concat!(
+            "ignore-if: nonsense\n",
+            "a:\n",
+            "  status: foo\n",
+            "  bad key\n",
+            "    continued\n",
+            "  timeout: x\n",
+            "  stdout: ok\n",
+            "a:\n",
+            "  cwd:\n",
+            "b: x\n",
+            "  output: y\n",
+            "  stderr: z\n",
+        ))
+        .unwrap_err();
+        assert_eq!(
+            errs.iter().map(|e| e.line()).collect::<Vec<_>>(),
+            vec![0, 2, 3, 5, 7, 8, 9, 11]
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        let tests = parse_tests(
+            "# A comment\na:\n  # Another\n  status: error\n  stderr:\n    # x\n  # y\n\n# z\n\
+             b:\n  stdout: w",
+        );
+        assert_eq!(tests.tests["a"].status, Status::Error);
+        assert_eq!(tests.tests["a"].stderr, vec!["# x"]);
+        assert_eq!(tests.tests["b"].stdout, vec!["w"]);
+        assert!(parse("a:\n  // x\n  status: error").is_err());
+        let tests = parse_with_comment_prefix("a:\n  // x\n  stdout: # y", "//").unwrap();
+        assert_eq!(tests.tests["a"].stdout, vec!["# y"]);
+        assert!(parse_with_comment_prefix("a:\n  # x\n  status: error", "").is_err());
+    }
+
+    #[test]
+    fn test_tab_indent() {
+        let tests =
+            parse_tests("a:\n\tstatus: error\n\tstderr:\n\t\tx\n\n\t\t  y\nb:\n\tstdout: z");
+        assert_eq!(tests.tests["a"].status, Status::Error);
+        assert_eq!(tests.tests["a"].stderr, vec!["x", "", "y"]);
+        assert_eq!(tests.tests["b"].stdout, vec!["z"]);
+
+        // A tab-indented key with a space-indented value.
+        let errs = parse("a:\n\tstderr:\n    x\n\tstatus: error").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![2]);
+        assert!(errs[0]
+            .msg()
+            .starts_with("Inconsistent use of tabs and spaces"));
+        // Value lines which are inconsistent with each other.
+        let errs = parse("a:\n  stderr:\n    x\n  \t y").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![3]);
+        // A key which is inconsistent with its (indented) command.
+        let errs = parse("  a:\n\tstatus: error\n  b:\n    status: error").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_non_ascii() {
+        let tests = parse_tests("Compilé:\n  status: success\n  stdout: café");
+        assert_eq!(tests.tests["compilé"].status, Status::Success);
+        assert_eq!(tests.tests["compilé"].stdout, vec!["café"]);
+        // Indentation using multi-byte whitespace (U+3000 IDEOGRAPHIC SPACE).
+        let tests = parse_tests("a:\n\u{3000}status: error\n\u{3000}stderr:\n\u{3000}\u{3000}x");
+        assert_eq!(tests.tests["a"].status, Status::Error);
+        assert_eq!(tests.tests["a"].stderr, vec!["x"]);
+        let errs = parse("a:\n  stätus: error\n  é").unwrap_err();
+        assert_eq!(
+            errs.iter().map(|e| e.line()).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_indent() {
+        // Keys which are indented less, or more, than the command's first key.
+        let errs = parse("a:\n    status: error\n  stdout: x\nb:\n  status: error").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![2]);
+        assert!(errs[0].msg().contains("both should be keys"));
+        // A more indented key is part of the previous key's value.
+        let errs = parse("a:\n  status: error\n    stdout: x\nb:\n  status: error").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![1]);
+        // Command names which are indented differently.
+        let errs = parse("  a:\n    status: error\n b:\n    status: error").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![2]);
+        // A value line which is indented less than the value's first line.
+        let errs = parse("a:\n  stdout:\n      x\n    y\n  status: error").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![3]);
+        assert!(errs[0].msg().contains("indented less than line 2"));
+        let tests = parse_tests("a:\n  stdout:\n    x\n      y\n  status: error");
+        assert_eq!(tests.tests["a"].stdout, vec!["x", "y"]);
+
+        // Sub-blocks which are under-indented for the minimum indentation.
+        let parse_min = |s| parse_with_min_indent(s, DEFAULT_COMMENT_PREFIX, 2);
+        let errs = parse_min("a:\n status: error\nb:\n  stdout:\n   x").unwrap_err();
+        assert_eq!(
+            errs.iter().map(|e| e.line()).collect::<Vec<_>>(),
+            vec![1, 4]
+        );
+        assert!(errs[0]
+            .msg()
+            .starts_with("Line 1 must be indented by at least 2 more"));
+        assert!(errs[1]
+            .msg()
+            .starts_with("Line 4 must be indented by at least 2 more"));
+        let errs = parse_min("a:\n  stdout: x\n   y").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![2]);
+        // Sub-blocks which are over-indented are fine.
+        let tests =
+            parse_min("a:\n    stdout:\n        x\n          y\nb:\n  status: error").unwrap();
+        assert_eq!(tests.tests["a"].stdout, vec!["x", "y"]);
+        assert_eq!(tests.tests["b"].status, Status::Error);
+    }
+
     #[test]
     fn test_key_multiline() {
-        assert_eq!(key_multiline_val(&["x:", ""], 0, 0), (2, "x", vec![]));
         assert_eq!(
-            key_multiline_val(&["x: y", "  z", "a"], 0, 0),
+            key_multiline_val(&["x:", ""], 0, 0, 1).unwrap(),
+            (2, "x", vec![])
+        );
+        assert_eq!(
+            key_multiline_val(&["x: y", "  z", "a"], 0, 0, 1).unwrap(),
             (2, "x", vec!["y", "z"])
         );
         assert_eq!(
-            key_multiline_val(&["x:", "  z", "a"], 0, 0),
+            key_multiline_val(&["x:", "  z", "a"], 0, 0, 1).unwrap(),
             (2, "x", vec!["z"])
         );
         assert_eq!(
-            key_multiline_val(&["x:", "  z  ", "  a  ", "  ", "b"], 0, 0),
+            key_multiline_val(&["x:", "  z  ", "  a  ", "  ", "b"], 0, 0, 1).unwrap(),
             (4, "x", vec!["z", "a"])
         );
     }
+
+    #[test]
+    fn test_status_failure() {
+        let tests = parse_tests("a:\n  status: failure\n\nb:\n  status: error");
+        assert_eq!(tests.tests["a"].status, Status::Error);
+        assert_eq!(tests.tests["b"].status, Status::Error);
+    }
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(parse_status("success"), Some(Status::Success));
+        assert_eq!(parse_status("Error"), Some(Status::Error));
+        assert_eq!(parse_status("failure"), Some(Status::Error));
+        assert_eq!(
+            parse_status("not failure"),
+            Some(Status::Not(Box::new(Status::Error)))
+        );
+        assert_eq!(parse_status("signal"), Some(Status::Signal(None)));
+        assert_eq!(parse_status("signal(6)"), Some(Status::Signal(Some(6))));
+        assert_eq!(
+            parse_status("Signal( SIGSEGV )"),
+            Some(Status::Signal(Some(Signal::SIGSEGV as i32)))
+        );
+        assert_eq!(
+            parse_status("signal(abrt)"),
+            Some(Status::Signal(Some(Signal::SIGABRT as i32)))
+        );
+        assert_eq!(parse_status("signal(0)"), None);
+        assert_eq!(parse_status("signal(SIGNOPE)"), None);
+        assert_eq!(parse_status("signal()"), None);
+        assert_eq!(
+            parse_status("not signal(kill)"),
+            Some(Status::Not(Box::new(Status::Signal(Some(9)))))
+        );
+        assert_eq!(parse_status("ANY"), Some(Status::Any));
+        assert_eq!(parse_status("-1"), Some(Status::Int(-1)));
+        assert_eq!(parse_status("1, 2,3"), Some(Status::OneOf(vec![1, 2, 3])));
+        assert_eq!(parse_status("1..=4"), Some(Status::OneOf(vec![1, 2, 3, 4])));
+        assert_eq!(
+            parse_status("0, 3 ..= 5"),
+            Some(Status::OneOf(vec![0, 3, 4, 5]))
+        );
+        assert_eq!(parse_status("2..=2"), Some(Status::OneOf(vec![2])));
+        assert_eq!(parse_status("4..=1"), None);
+        assert_eq!(
+            parse_status("0..=255").map(|s| matches!(s, Status::OneOf(ref c) if c.len() == 256)),
+            Some(true)
+        );
+        assert_eq!(parse_status("0..=256"), None);
+        assert_eq!(parse_status("-1..=1"), None);
+        assert_eq!(parse_status("0..=2147483647"), None);
+        assert_eq!(parse_status("1,"), None);
+        assert_eq!(parse_status("1..4"), None);
+        assert_eq!(parse_status("x"), None);
+        assert_eq!(
+            parse_status("Not success"),
+            Some(Status::Not(Box::new(Status::Success)))
+        );
+        assert_eq!(
+            parse_status("not  0"),
+            Some(Status::Not(Box::new(Status::Int(0))))
+        );
+        assert_eq!(
+            parse_status("not 1, 2"),
+            Some(Status::Not(Box::new(Status::OneOf(vec![1, 2]))))
+        );
+        assert_eq!(parse_status("not not 0"), None);
+        assert_eq!(parse_status("not any"), None);
+        assert_eq!(parse_status("Timeout"), Some(Status::Timeout));
+        assert_eq!(parse_status("not timeout"), None);
+        assert_eq!(parse_status("not"), None);
+    }
+
+    #[test]
+    fn test_status_roundtrip() {
+        let statuses = vec![
+            Status::Success,
+            Status::Error,
+            Status::Signal(None),
+            Status::Signal(Some(6)),
+            Status::Int(0),
+            Status::Int(-1),
+            Status::Int(i32::MAX),
+            Status::OneOf(vec![2]),
+            Status::OneOf(vec![1, 2, 3, 4]),
+            Status::OneOf(vec![0, 3, 4, 5]),
+            Status::OneOf(vec![5, 1, 2, 2, -3, -2, 9]),
+            Status::OneOf(vec![i32::MAX - 1, i32::MAX, i32::MIN, i32::MIN + 1]),
+            Status::Any,
+            Status::Timeout,
+            Status::Not(Box::new(Status::Signal(Some(9)))),
+            Status::Not(Box::new(Status::OneOf(vec![1, 2]))),
+        ];
+        for status in statuses {
+            assert_eq!(status.to_string().parse::<Status>(), Ok(status));
+        }
+        assert_eq!(Status::OneOf(vec![2]).to_string(), "2..=2");
+        assert_eq!(Status::OneOf(vec![7, 1, 2, 3, 5]).to_string(), "7,1..=3,5");
+        assert_eq!(Status::OneOf(vec![-1, 0, 1]).to_string(), "-1,0..=1");
+        assert_eq!(
+            Status::OneOf(vec![255, 256, 257]).to_string(),
+            "255,256,257"
+        );
+        assert_eq!(
+            Status::Not(Box::new(Status::Success)).to_string(),
+            "not success"
+        );
+        // Every status parsed from text displays as text which parses back to it.
+        for s in &["Signal(SIGSEGV)", "not 1, 2,3", "0, 3 ..= 5", " any "] {
+            let status = s.parse::<Status>().unwrap();
+            assert_eq!(status.to_string().parse::<Status>(), Ok(status));
+        }
+        let err = "x".parse::<Status>().unwrap_err();
+        assert_eq!((err.line(), err.msg()), (0, "Unknown status 'x'"));
+    }
+
+    #[test]
+    fn test_ignore() {
+        let tests = parse_tests("a:\n  status: success");
+        assert_eq!(tests.ignore, None);
+        assert_eq!(tests.ignore_if, None);
+        assert_eq!(parse_tests("ignore:").ignore, Some("".to_owned()));
+        assert_eq!(
+            parse_tests("ignore: not supported on Windows").ignore,
+            Some("not supported on Windows".to_owned())
+        );
+        assert_eq!(
+            parse_tests("ignore-if: windows").ignore_if,
+            Some(IgnoreIf::TargetFamily("windows".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_xfail() {
+        assert_eq!(parse_tests("a:\n  status: success").xfail, None);
+        let tests = parse_tests("xfail: see #12\na:\n  status: success");
+        assert_eq!(tests.xfail, Some("see #12".to_owned()));
+        assert!(tests.tests.contains_key("a"));
+        assert_eq!(parse_tests("xfail:").xfail, Some("".to_owned()));
+    }
+
+    #[test]
+    fn test_tags() {
+        assert!(parse_tests("a:\n  status: success").tags.is_empty());
+        let tests = parse_tests("tags: slow  network\na:\n  status: success");
+        assert_eq!(tests.tags, vec!["slow".to_owned(), "network".to_owned()]);
+        assert!(tests.tests.contains_key("a"));
+        assert!(parse("tags:").is_err());
+        let errs = parse("tags: slow\ntags: wasm").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_parse_ignore_if() {
+        assert_eq!(
+            parse_ignore_if("unix"),
+            Some(IgnoreIf::TargetFamily("unix".to_owned()))
+        );
+        assert_eq!(
+            parse_ignore_if("target_os = \"linux\""),
+            Some(IgnoreIf::TargetOs("linux".to_owned()))
+        );
+        assert_eq!(
+            parse_ignore_if("target_arch=x86_64"),
+            Some(IgnoreIf::TargetArch("x86_64".to_owned()))
+        );
+        assert_eq!(
+            parse_ignore_if("not( target_family = \"windows\" )"),
+            Some(IgnoreIf::Not(Box::new(IgnoreIf::TargetFamily(
+                "windows".to_owned()
+            ))))
+        );
+        assert_eq!(parse_ignore_if(""), None);
+        assert_eq!(parse_ignore_if("linux"), None);
+        assert_eq!(parse_ignore_if("target_os ="), None);
+        assert_eq!(parse_ignore_if("target_os = \"a b\""), None);
+        assert_eq!(parse_ignore_if("target_env = \"gnu\""), None);
+        assert_eq!(parse_ignore_if("not(unix"), None);
+    }
+
+    #[test]
+    fn test_split_args() {
+        fn strs(v: &[&str]) -> Option<Vec<String>> {
+            Some(v.iter().map(|x| (*x).to_owned()).collect())
+        }
+        assert_eq!(split_args(""), strs(&[]));
+        assert_eq!(split_args("  a  b "), strs(&["a", "b"]));
+        assert_eq!(split_args("--opt \"a b\""), strs(&["--opt", "a b"]));
+        assert_eq!(split_args("'a \\ \"b' c"), strs(&["a \\ \"b", "c"]));
+        assert_eq!(split_args("\"a\\\"b\\c\""), strs(&["a\"b\\c"]));
+        assert_eq!(split_args("a\\ b c"), strs(&["a b", "c"]));
+        assert_eq!(split_args("-D'x=1'\"y\""), strs(&["-Dx=1y"]));
+        assert_eq!(split_args("'' \"\""), strs(&["", ""]));
+        assert_eq!(split_args("'a"), None);
+        assert_eq!(split_args("a \"b"), None);
+    }
+
+    #[test]
+    fn test_extra_args() {
+        let tests = parse_tests("a:\n  extra-args: -O \"x y\"\n    -g\n  extra-args: z");
+        assert_eq!(tests.tests["a"].args, vec!["-O", "x y", "-g", "z"]);
+    }
+
+    #[test]
+    fn test_expand_placeholders() {
+        let f = |name: &str| match name {
+            "tempdir" => Err("no tempdir".to_owned()),
+            _ => Ok(format!("<{}>", name)),
+        };
+        assert_eq!(expand_placeholders("a", f), Ok("a".to_owned()));
+        assert_eq!(
+            expand_placeholders("-o={testdir}/x{testfile}", f),
+            Ok("-o=<testdir>/x<testfile>".to_owned())
+        );
+        assert_eq!(
+            expand_placeholders("{{testfile}} {a,b} {} {", f),
+            Ok("{testfile}} {a,b} {} {".to_owned())
+        );
+        assert_eq!(
+            expand_placeholders("{tempdir}", f),
+            Err("no tempdir".to_owned())
+        );
+        assert!(expand_placeholders("{test_file}", f).is_err());
+
+        let tests = parse_tests("a:\n  extra-args: {testfile} {{x}");
+        assert_eq!(tests.tests["a"].args, vec!["{testfile}", "{{x}"]);
+        let errs = parse("a:\n  extra-args: {testfile}\n    {file}").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn test_skip_if_missing() {
+        let tests = parse_tests(
+            "a:\n  skip-if-missing: x \"y z\"\n    w\n  skip-if-missing: v\nb:\n  status: error",
+        );
+        assert_eq!(tests.tests["a"].skip_if_missing, vec!["x", "y z", "w", "v"]);
+        assert!(tests.tests["b"].skip_if_missing.is_empty());
+        assert!(parse("a:\n  skip-if-missing:").is_err());
+        assert!(parse("a:\n  skip-if-missing: 'x").is_err());
+    }
+
+    #[test]
+    fn test_cmd() {
+        let tests = parse_tests("a:\n  cmd: ./x -O \"a b\"\n\nb:\n  status: success");
+        assert_eq!(
+            tests.tests["a"].cmd,
+            Some(vec!["./x".to_owned(), "-O".to_owned(), "a b".to_owned()])
+        );
+        assert_eq!(tests.tests["b"].cmd, None);
+        assert!(parse("a:\n  cmd:").is_err());
+        assert!(parse("a:\n  cmd: ''x").is_ok());
+        assert!(parse("a:\n  cmd: 'x").is_err());
+        assert!(parse("a:\n  cmd:\n    x\n    y").is_err());
+    }
+
+    #[test]
+    fn test_cwd() {
+        let tests = parse_tests("a:\n  cwd: x/y\n\nb:\n  status: success");
+        assert_eq!(tests.tests["a"].cwd, Some(PathBuf::from("x/y")));
+        assert_eq!(tests.tests["b"].cwd, None);
+    }
+
+    #[test]
+    fn test_env_clear() {
+        let tests = parse_tests("a:\n  env-clear: true\n  env-var: X=1\n\nb:\n  env-clear: false");
+        assert!(tests.tests["a"].env_clear);
+        assert_eq!(tests.tests["a"].env, vec![("X".to_owned(), "1".to_owned())]);
+        assert!(!tests.tests["b"].env_clear);
+        assert!(parse("a:\n  env-clear: yes").is_err());
+    }
+
+    #[test]
+    fn test_env_var() {
+        let tests = parse_tests("a:\n  env-var:\n    X=1\n    Y = 2 \n    Z=");
+        assert_eq!(
+            tests.tests["a"].env,
+            vec![
+                ("X".to_owned(), "1".to_owned()),
+                ("Y".to_owned(), "2".to_owned()),
+                ("Z".to_owned(), "".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeated_keys() {
+        let tests = parse_tests(
+            "a:\n  env-var: X=1\n  extra-args: -O\n  env-var: Y=2\n  stdout-not: x\n  \
+             extra-args: -g\n  stdout-not:\n    y\n    z",
+        );
+        assert_eq!(
+            tests.tests["a"].env,
+            vec![
+                ("X".to_owned(), "1".to_owned()),
+                ("Y".to_owned(), "2".to_owned())
+            ]
+        );
+        assert_eq!(tests.tests["a"].args, vec!["-O", "-g"]);
+        assert_eq!(tests.tests["a"].stdout_not, vec!["x", "y", "z"]);
+        assert!(parse("a:\n  status: success\n  status: failure").is_err());
+        assert!(parse("a:\n  cwd: x\n  cwd: y").is_err());
+        assert!(parse("a:\n  stdout: x\n  stdout: y").is_err());
+        assert!(parse("a:\n  stdin: x\n  stdin: y").is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let tests = parse_tests("a:\n  timeout: 10\n\nb:\n  status: success");
+        assert_eq!(tests.tests["a"].timeout, Some(Duration::from_secs(10)));
+        assert_eq!(tests.tests["b"].timeout, None);
+    }
+
+    #[test]
+    fn test_delay() {
+        let tests = parse_tests("a:\n  delay: 250\n\nb:\n  status: success");
+        assert_eq!(tests.tests["a"].delay, Some(Duration::from_millis(250)));
+        assert_eq!(tests.tests["b"].delay, None);
+        assert!(parse("a:\n  delay: 1s").unwrap_err()[0]
+            .msg()
+            .starts_with("Invalid delay '1s'"));
+    }
+
+    #[test]
+    fn test_max_rss() {
+        let tests = parse_tests("a:\n  max-rss: 64\n\nb:\n  status: success");
+        assert_eq!(tests.tests["a"].max_rss, Some(64));
+        assert_eq!(tests.tests["b"].max_rss, None);
+        assert!(parse("a:\n  max-rss: 1.5").unwrap_err()[0]
+            .msg()
+            .starts_with("Invalid max-rss '1.5'"));
+    }
+
+    #[test]
+    fn test_apply_defaults() {
+        let defaults = "Compiler:\n  status: error\n  stdout: x\n  env-var: A=1\n  stdin: y\n\n\
+                        Run-time:\n  stderr-not: z";
+        let apply = |test_str| {
+            let mut tests = parse(test_str).unwrap();
+            apply_defaults(
+                &mut tests,
+                defaults,
+                DEFAULT_COMMENT_PREFIX,
+                DEFAULT_MIN_INDENT,
+            );
+            tests
+        };
+
+        let tests = apply("Compiler:\n  stderr: w");
+        let c = &tests.tests["compiler"];
+        assert_eq!(c.status, Status::Error);
+        assert_eq!(c.stderr, vec!["w"]);
+        assert_eq!(c.stdout, vec!["x"]);
+        assert_eq!(c.env, vec![("A".to_owned(), "1".to_owned())]);
+        assert_eq!(c.stdin, Some(b"y\n".to_vec()));
+        // Only the test's own keys have spans.
+        assert_eq!(c.spans.keys().collect::<Vec<_>>(), vec!["stderr"]);
+        // Commands the test doesn't specify aren't added, but get the defaults when they're run.
+        assert!(!tests.tests.contains_key("run-time"));
+        let r = default_testcmd(
+            "run-time",
+            defaults,
+            DEFAULT_COMMENT_PREFIX,
+            DEFAULT_MIN_INDENT,
+        );
+        assert_eq!(r.status, Status::Success);
+        assert_eq!(r.stderr_not, vec!["z"]);
+        assert!(r.spans.is_empty());
+        let d = default_testcmd(
+            "other",
+            defaults,
+            DEFAULT_COMMENT_PREFIX,
+            DEFAULT_MIN_INDENT,
+        );
+        assert!(d.stderr_not.is_empty() && d.stdin.is_none());
+
+        // Explicit keys, and keys which can't be specified alongside a default, win.
+        let tests = apply(
+            "Compiler:\n  status: success\n  stdout-exact: v\n  env-var: B=2\n  stdin-file: f\n\n\
+             Run-time:\n  output: o",
+        );
+        let c = &tests.tests["compiler"];
+        assert_eq!(c.status, Status::Success);
+        assert_eq!(c.stdout, vec![fuzzy::WILDCARD]);
+        assert_eq!(c.stdout_exact, Some(vec!["v"]));
+        assert_eq!(c.env, vec![("B".to_owned(), "2".to_owned())]);
+        assert_eq!(c.stdin, None);
+        assert!(tests.tests["run-time"].stderr_not.is_empty());
+    }
+
+    #[test]
+    fn test_retries() {
+        let tests = parse_tests("a:\n  retries: 3\n\nb:\n  status: success");
+        assert_eq!(tests.tests["a"].retries, 3);
+        assert_eq!(tests.tests["b"].retries, 0);
+    }
+
+    #[test]
+    fn test_output() {
+        let tests = parse_tests("a:\n  output:\n    x\n    ...\n\nb:\n  stdout: x");
+        assert_eq!(tests.tests["a"].output, Some(vec!["x", "..."]));
+        assert_eq!(tests.tests["b"].output, None);
+    }
+
+    #[test]
+    fn test_pattern_file() {
+        let tests =
+            parse_tests("a:\n  stdout-file: x/y.out\n  stderr-file: y.err\n\nb:\n  stdout: z");
+        assert_eq!(tests.tests["a"].stdout_file, Some(PathBuf::from("x/y.out")));
+        assert_eq!(tests.tests["a"].stderr_file, Some(PathBuf::from("y.err")));
+        assert_eq!(tests.tests["b"].stdout_file, None);
+        assert!(parse("a:\n  stdout: z\n  stdout-file: x").is_err());
+        assert!(parse("a:\n  stderr-file: x\n  stderr-exact: z").is_err());
+        assert!(parse("a:\n  stdout-file:\n    x\n    y").is_err());
+        assert!(parse("a:\n  output: z\n  stdout-file: x").is_err());
+    }
+
+    #[test]
+    fn test_output_file() {
+        let tests = parse_tests(
+            "a:\n  output-file: x/y\n\nb:\n  output-file: z\n    contents:\n      w\n      ...\n\n\
+             c:\n  output-file:\n    v\n    contents: u",
+        );
+        assert_eq!(tests.tests["a"].output_file, Some(PathBuf::from("x/y")));
+        assert_eq!(tests.tests["a"].output_file_contents, None);
+        assert_eq!(tests.tests["b"].output_file, Some(PathBuf::from("z")));
+        assert_eq!(
+            tests.tests["b"].output_file_contents,
+            Some(vec!["w", "..."])
+        );
+        assert_eq!(tests.tests["c"].output_file, Some(PathBuf::from("v")));
+        assert_eq!(tests.tests["c"].output_file_contents, Some(vec!["u"]));
+        assert!(parse("a:\n  output-file:").is_err());
+        assert!(parse("a:\n  output-file: x\n    y").is_err());
+        assert!(parse("a:\n  output-file: x\n  output-file: y").is_err());
+        assert!(parse("a:\n  output-file: x\n    contents:\n      re:(").is_err());
+    }
+
+    #[test]
+    fn test_not() {
+        let tests = parse_tests(
+            "a:\n  stdout: x\n  stdout-not:\n    ...panic...\n\n    y\n\nb:\n  stderr-not: z",
+        );
+        assert_eq!(tests.tests["a"].stdout, vec!["x"]);
+        assert_eq!(tests.tests["a"].stdout_not, vec!["...panic...", "y"]);
+        assert!(tests.tests["a"].stderr_not.is_empty());
+        assert_eq!(tests.tests["b"].stderr_not, vec!["z"]);
+        assert!(parse("a:\n  stdout-not:\n    x\n    ...\n").is_err());
+        assert!(parse("a:\n  stdout-not:\n    x\n    ...{2}\n").is_err());
+    }
+
+    #[test]
+    fn test_contains() {
+        let tests = parse_tests(
+            "a:\n  stdout: x\n  stdout-contains:\n    ...start\n\n    end\n\nb:\n  \
+             stderr-contains: z",
+        );
+        assert_eq!(tests.tests["a"].stdout, vec!["x"]);
+        assert_eq!(tests.tests["a"].stdout_contains, vec!["...start", "end"]);
+        assert!(tests.tests["a"].stderr_contains.is_empty());
+        assert_eq!(tests.tests["b"].stderr_contains, vec!["z"]);
+        assert!(parse("a:\n  stdout-contains:\n    x\n    ...\n").is_err());
+        assert!(parse("a:\n  stdout-contains: x\n  output: y").is_err());
+        assert!(parse("a:\n  stdout-contains: x\n  stdout-contains: y").is_err());
+    }
+
+    #[test]
+    fn test_empty() {
+        let tests = parse_tests("a:\n  stdout-empty:\n  stderr-not: x\n\nb:\n  stderr-empty:");
+        assert!(tests.tests["a"].stdout.is_empty());
+        assert_eq!(tests.tests["a"].stderr, vec!["..."]);
+        assert_eq!(tests.tests["b"].stdout, vec!["..."]);
+        assert!(tests.tests["b"].stderr.is_empty());
+        assert!(parse("a:\n  stdout-empty: x").is_err());
+        assert!(parse("a:\n  stdout-empty:\n    x").is_err());
+        assert!(parse("a:\n  stdout-empty:\n  stdout: x").is_err());
+        assert!(parse("a:\n  stdout-exact: x\n  stdout-empty:").is_err());
+        assert!(parse("a:\n  stderr-empty:\n  stderr-contains: x").is_err());
+        assert!(parse("a:\n  stderr-contains-ordered: x\n  stderr-empty:").is_err());
+        assert!(parse("a:\n  stdout-empty:\n  output: x").is_err());
+        assert!(parse("a:\n  stdin: x\n  stdout-equals-stdin: true\n  stdout-empty:").is_err());
+        assert_eq!(
+            parse("a:\n  stdout-lines: 0\n  stdout-empty:").unwrap_err(),
+            vec![ParseError::new(
+                2,
+                "Can't specify stdout-empty on line 2 as well as stdout-lines on line 1."
+                    .to_owned()
+            )]
+        );
+        assert!(parse("a:\n  stderr-empty:\n  stderr-bytes: 0").is_err());
+        parse_tests("a:\n  stdout-empty:\n  stderr-contains: x\n  stderr-lines: 1");
+        assert!(keys_conflict("stdout-bytes", "stdout-empty"));
+        assert!(!keys_conflict("stdout-bytes", "stderr-empty"));
+    }
+
+    #[test]
+    fn test_contains_ordered() {
+        let tests = parse_tests(
+            "a:\n  stdout: x\n  stdout-contains-ordered:\n    ...start\n\n    end...\n\nb:\n  \
+             stderr-contains-ordered: z",
+        );
+        assert_eq!(tests.tests["a"].stdout, vec!["x"]);
+        assert_eq!(tests.tests["a"].stdout_ordered, vec!["...start", "end..."]);
+        assert!(tests.tests["a"].stderr_ordered.is_empty());
+        assert_eq!(tests.tests["b"].stderr_ordered, vec!["z"]);
+        assert!(parse("a:\n  stdout-contains-ordered:\n    x\n    ...\n").is_err());
+        assert!(parse("a:\n  stdout-contains-ordered: x\n  output: y").is_err());
+        assert!(parse("a:\n  stdout-contains-ordered: x\n  stdout-contains-ordered: y").is_err());
+    }
+
+    #[test]
+    fn test_line_count() {
+        let tests = parse_tests("a:\n  stdout: x\n  stdout-lines: 3\n\nb:\n  stderr-lines: 1..=4");
+        assert_eq!(tests.tests["a"].stdout_lines, Some(3..=3));
+        assert_eq!(tests.tests["a"].stderr_lines, None);
+        assert_eq!(tests.tests["b"].stderr_lines, Some(1..=4));
+        assert!(parse("a:\n  stdout-lines: 4..=1").is_err());
+        assert!(parse("a:\n  stdout-lines: 1..4").is_err());
+        assert!(parse("a:\n  stdout-lines: x").is_err());
+        assert!(parse("a:\n  output: x\n  stderr-lines: 1").is_err());
+    }
+
+    #[test]
+    fn test_byte_count() {
+        let tests = parse_tests("a:\n  stdout-bytes: 12\n\nb:\n  stderr-bytes: 0..=4");
+        assert_eq!(tests.tests["a"].stdout_bytes, Some(12..=12));
+        assert_eq!(tests.tests["a"].stderr_bytes, None);
+        assert_eq!(tests.tests["b"].stderr_bytes, Some(0..=4));
+        assert!(parse("a:\n  stdout-bytes: -1").is_err());
+        assert!(parse("a:\n  output: x\n  stdout-bytes: 1").is_err());
+    }
+
+    #[test]
+    fn test_bounded_wildcard() {
+        let tests = parse_tests("a:\n  stdout:\n    x\n    ...{1,2}\n    y");
+        assert_eq!(tests.tests["a"].stdout, vec!["x", "...{1,2}", "y"]);
+        let errs = parse("a:\n  stdout:\n    x\n\n    ...{2,1}").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![4]);
+        assert!(errs[0]
+            .msg()
+            .starts_with("Invalid wildcard '...{2,1}' on line 4"));
+        let errs = parse("a:\n  stdout:\n    ...\n    ...{2}\n    x").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![3]);
+        assert!(parse("a:\n  stdout:\n    ...\n\n    ...").is_ok());
+    }
+
+    #[test]
+    fn test_raw() {
+        let tests = parse_tests(
+            "a:\n  stdout-raw:\n    fn f() {\n        x  \n\n    ...\n    }\n\nb:\n  stderr-raw: x",
+        );
+        assert_eq!(
+            tests.tests["a"].stdout,
+            vec!["fn f() {", "    x  ", "", "...", "}"]
+        );
+        assert!(tests.tests["a"].stdout_raw);
+        assert!(!tests.tests["a"].stderr_raw);
+        assert_eq!(tests.tests["b"].stderr, vec!["x"]);
+        assert!(tests.tests["b"].stderr_raw);
+        assert!(parse("a:\n  stdout: x\n  stdout-raw: y").is_err());
+        assert!(parse("a:\n  output: x\n  stderr-raw: y").is_err());
+    }
+
+    #[test]
+    fn test_crlf() {
+        // Test data with `\r\n` line endings (e.g. checked out on Windows) or a mix of line
+        // endings is parsed as if it had `\n` line endings.
+        let tests = parse_tests(
+            "a:\r\n  stdout:\r\n    x\r\n    ...\n    y\r\n\r\nb:\n  stdout-raw:\r\n    z  \r\n",
+        );
+        assert_eq!(tests.tests["a"].stdout, vec!["x", "...", "y"]);
+        assert_eq!(tests.tests["b"].stdout, vec!["z  "]);
+    }
+
+    #[test]
+    fn test_capture_references() {
+        assert!(parse("a:\n  stdout: re:id (?<id>[0-9]+)").is_ok());
+        assert!(parse("a:\n  stdout: re:id ${id}\n  stderr-not: re:${id}").is_ok());
+        assert!(parse("a:\n  stdout: re:(${id}").is_err());
+    }
+
+    #[test]
+    fn test_exact() {
+        let tests = parse_tests("a:\n  stdout-exact:\n    x\n\n    ...\n\nb:\n  stderr-exact:");
+        assert_eq!(tests.tests["a"].stdout_exact, Some(vec!["x", "", "..."]));
+        assert_eq!(tests.tests["a"].stderr_exact, None);
+        assert_eq!(tests.tests["b"].stderr_exact, Some(vec![]));
+        assert_eq!(tests.tests["b"].stdout_exact, None);
+    }
+
+    #[test]
+    fn test_stdin() {
+        let tests = parse_tests("a:\n  stdin:\n    x\n      y\n\nb:\n  stdin:");
+        assert_eq!(tests.tests["a"].stdin, Some(b"x\ny\n".to_vec()));
+        assert_eq!(tests.tests["b"].stdin, Some(Vec::new()));
+        assert_eq!(parse_tests("a:\n  status: success").tests["a"].stdin, None);
+    }
+
+    #[test]
+    fn test_stdin_file() {
+        let tests = parse_tests("a:\n  stdin-file: x/y.txt\n\nb:\n  stdin: z");
+        assert_eq!(tests.tests["a"].stdin_file, Some(PathBuf::from("x/y.txt")));
+        assert_eq!(tests.tests["a"].stdin, None);
+        assert_eq!(tests.tests["b"].stdin_file, None);
+        assert!(parse("a:\n  stdin: z\n  stdin-file: x/y.txt").is_err());
+    }
+
+    #[test]
+    fn test_stdout_equals_stdin() {
+        let tests = parse_tests("a:\n  stdout-equals-stdin: true\n  stdin: x\n\nb:\n  stdout: y");
+        assert!(tests.tests["a"].stdout_equals_stdin);
+        assert_eq!(tests.tests["a"].stdout_exact, None);
+        assert!(!tests.tests["b"].stdout_equals_stdin);
+        parse_tests("a:\n  stdin-file: x\n  stdout-equals-stdin: true");
+        parse_tests("a:\n  stdout-equals-stdin: false\n  stdout: y");
+        assert!(parse("a:\n  stdin: x\n  stdout-equals-stdin: yes").is_err());
+        // There must be something written to stdin to compare stdout with.
+        let errs = parse("a:\n  stdout-equals-stdin: true").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![1]);
+        // stdout can't also be checked in another way.
+        let errs =
+            parse("a:\n  stdout-raw: y\n  stdin: x\n  stdout-equals-stdin: true").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![3]);
+        let errs = parse("a:\n  stdin: x\n  stdout-equals-stdin: true\n  output: y").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![2]);
+        // stdout-not etc. can still be combined with it.
+        parse_tests("a:\n  stdin: x\n  stdout-equals-stdin: true\n  stdout-not: y");
+    }
+
+    #[test]
+    fn test_json() {
+        let tests = parse_tests(
+            "a:\n  stdout-json:\n    {\"x\": [1,\n      2]}\n\nb:\n  stderr-json-subset: {}",
+        );
+        assert_eq!(
+            tests.tests["a"].stdout_json,
+            Some(vec!["{\"x\": [1,", "2]}"])
+        );
+        assert!(!tests.tests["a"].stdout_json_subset);
+        assert_eq!(tests.tests["b"].stderr_json, Some(vec!["{}"]));
+        assert!(tests.tests["b"].stderr_json_subset);
+        assert_eq!(tests.tests["b"].stdout_json, None);
+        let errs = parse("a:\n  stdout-json: {\"x\": }").unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.line()).collect::<Vec<_>>(), vec![1]);
+        assert!(parse("a:\n  stdout-json: 1\n  stdout-exact: 1").is_err());
+        assert!(parse("a:\n  stderr-json: 1\n  stderr-json-subset: 1").is_err());
+        assert!(parse("a:\n  stdout-json: 1\n  output: 1").is_err());
+        assert!(parse("a:\n  stdin: 1\n  stdout-json: 1\n  stdout-equals-stdin: true").is_err());
+    }
+
+    #[test]
+    fn test_stdin_from() {
+        let tests = parse_tests("a:\n  stdin-from: Compiler\n\nb:\n  stdin: z");
+        assert_eq!(tests.tests["a"].stdin_from, Some("compiler".to_owned()));
+        assert_eq!(tests.tests["b"].stdin_from, None);
+        assert!(parse("a:\n  stdin-from: b\n  stdin-file: x").is_err());
+        assert!(parse("a:\n  stdin-from:").is_err());
+    }
+
+    #[test]
+    fn test_stdin_bytes() {
+        let tests =
+            parse_tests("a:\n  stdin-bytes: hex:00ff 10\n\nb:\n  stdin-bytes:\n    /w\n    A=");
+        assert_eq!(tests.tests["a"].stdin, Some(vec![0, 255, 16]));
+        assert_eq!(tests.tests["b"].stdin, Some(vec![255, 0]));
+        assert!(parse("a:\n  stdin-bytes: hex:0").is_err());
+        assert!(parse("a:\n  stdin-bytes: *").is_err());
+        assert!(parse("a:\n  stdin: x\n  stdin-bytes: AA==").is_err());
+    }
+
+    #[test]
+    fn test_interact() {
+        let tests = parse_tests(
+            "a:\n  interact:\n    expect: >\n    send: 1 + 2\n\n    expect: ...3\n    send:\n\n\
+             b:\n  stdin: x",
+        );
+        assert_eq!(
+            tests.tests["a"].interact,
+            vec![
+                InteractStep::Expect(">"),
+                InteractStep::Send("1 + 2"),
+                InteractStep::Expect("...3"),
+                InteractStep::Send(""),
+            ]
+        );
+        assert!(tests.tests["b"].interact.is_empty());
+        assert!(parse("a:\n  interact:").is_err());
+        assert!(parse("a:\n  interact:\n    send: x\n    recv: y").is_err());
+        assert!(parse("a:\n  interact:\n    expect: ...").is_err());
+        assert!(parse("a:\n  interact:\n    expect: re:(").is_err());
+        assert!(parse("a:\n  stdin: x\n  interact:\n    send: y").is_err());
+    }
+
+    #[test]
+    fn test_check_tests() {
+        let check = |testcmd: TestCmd<'static>| {
+            let mut tests = Tests::default();
+            tests.tests.insert("a".to_owned(), testcmd);
+            check_tests(&tests).map_err(|errs| {
+                errs.into_iter()
+                    .map(|e| (e.line(), e.msg().to_owned()))
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(check(TestCmd::default()), Ok(()));
+        let testcmd = TestCmd {
+            stdout: vec!["re:(", "x"],
+            ..TestCmd::default()
+        };
+        let errs = check(testcmd).unwrap_err();
+        assert_eq!(errs.len(), 1);
+        assert!(errs[0]
+            .1
+            .starts_with("a: Invalid regular expression 're:(' in stdout:\n"));
+        let testcmd = TestCmd {
+            stderr: vec!["...", "..."],
+            ..TestCmd::default()
+        };
+        assert!(check(testcmd).is_err());
+        let testcmd = TestCmd {
+            stdout_contains: vec!["..."],
+            ..TestCmd::default()
+        };
+        assert_eq!(
+            check(testcmd),
+            Err(vec![(
+                0,
+                "a: '...' can't be used on a line of its own in stdout_contains.".to_owned()
+            )])
+        );
+        let testcmd = TestCmd {
+            interact: vec![InteractStep::Expect("re:[")],
+            ..TestCmd::default()
+        };
+        assert!(check(testcmd).is_err());
+        let testcmd = TestCmd {
+            stdout_json: Some(vec!["{\"x\":"]),
+            ..TestCmd::default()
+        };
+        assert_eq!(
+            check(testcmd),
+            Err(vec![(
+                0,
+                "a: Invalid JSON in stdout_json: expected a JSON value at line 1 column 6"
+                    .to_owned()
+            )])
+        );
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("0aFf"), Some(vec![10, 255]));
+        assert_eq!(decode_hex("0g"), None);
+        assert_eq!(decode_base64(""), Some(vec![]));
+        assert_eq!(decode_base64("aGVsbG8="), Some(b"hello".to_vec()));
+        assert_eq!(decode_base64("aGVsbG8"), Some(b"hello".to_vec()));
+        assert_eq!(decode_base64("aGVs bG8h"), Some(b"hello!".to_vec()));
+        assert_eq!(decode_base64("aA=="), Some(b"h".to_vec()));
+        assert_eq!(decode_base64("a"), None);
+        assert_eq!(decode_base64("aA="), None);
+        assert_eq!(decode_base64("aGVsbG8-"), None);
+    }
 }
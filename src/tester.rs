@@ -1,36 +1,58 @@
 use std::{
-    collections::{hash_map::HashMap, HashSet},
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{hash_map::HashMap, HashSet, VecDeque},
+    convert::TryFrom,
     env,
-    fs::read_to_string,
-    io::{self, Read, Write},
-    os::unix::process::ExitStatusExt,
+    ffi::OsString,
+    fmt,
+    fs::{self, read_to_string},
+    io::{self, IsTerminal, Read, Write},
+    ops::{Range, RangeInclusive},
+    os::unix::{
+        fs::PermissionsExt,
+        process::{CommandExt, ExitStatusExt},
+    },
     path::{Path, PathBuf},
-    process::{self, Command, ExitStatus},
+    process::{self, Child, ChildStdin, Command, ExitStatus},
     str,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc, Mutex,
     },
-    thread::sleep,
+    thread::{self, sleep},
     time::{Duration, Instant},
 };
 
 use filedescriptor::{
-    poll, pollfd, AsRawSocketDescriptor, FileDescriptor, IntoRawSocketDescriptor, POLLERR, POLLHUP,
-    POLLIN,
+    poll, pollfd, AsRawSocketDescriptor, FileDescriptor, Pipe, POLLERR, POLLHUP, POLLIN,
 };
 use getopts::Options;
-use nix::fcntl::{
-    fcntl,
-    FcntlArg::{F_GETFL, F_SETFL},
-    OFlag,
+use nix::{
+    fcntl::{
+        fcntl,
+        FcntlArg::{F_GETFL, F_SETFL},
+        OFlag,
+    },
+    libc,
+    sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal},
 };
-use num_cpus;
+use regex::Regex;
 use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 use threadpool::ThreadPool;
 use walkdir::WalkDir;
 
-use crate::{fatal, fuzzy, parser::parse_tests};
+use crate::{
+    cache::{self, Cache},
+    fatal,
+    fuzzy::{self, DiffLine},
+    glob, json,
+    output::{self, OutputFormat, ReporterStyle},
+    parser::{
+        apply_defaults, check_tests, default_testcmd, expand_placeholders, parse_with_min_indent,
+        ParseError, DEFAULT_COMMENT_PREFIX, DEFAULT_MIN_INDENT,
+    },
+};
 
 /// The size of the (stack allocated) buffer use to read stderr/stdout from a child process.
 const READBUF: usize = 1024 * 4; // bytes
@@ -42,12 +64,63 @@ const TIMEOUT: u64 = 60; // seconds
 const INITIAL_WAIT_TIMEOUT: u64 = 10000; // nanoseconds
 /// The maximum time we should wait() between checking if a child process has exited.
 const MAX_WAIT_TIMEOUT: u64 = 250_000_000; // nanoseconds
+/// The maximum time to wait for a command's stdout to match an `interact` block's `expect` step.
+const EXPECT_TIMEOUT: u64 = 10; // seconds
+/// When stderr is not a terminal, the minimum time between writing progress lines.
+const PROGRESS_INTERVAL: u64 = 10; // seconds
+/// In watch mode, the time between checking for changed files. We poll rather than use OS file
+/// notifications because polling needs no extra dependencies, behaves the same on every platform
+/// (including network filesystems, where notifications are often unreliable), and only reads file
+/// metadata, so each check is cheap for typically sized test suites.
+const WATCH_INTERVAL: u64 = 250; // milliseconds
+/// The number of lines at the end of a command's stdout which are kept (for failure reports) when
+/// its stdout is streamed.
+const STREAM_TAIL: usize = 20;
+/// The number of bytes in a megabyte, as used by `max-rss`.
+const MB: u64 = 1024 * 1024;
+/// The (lower-cased) name of the command, if any, which is run before all other commands and
+/// which, if it fails, causes the remaining commands not to be run.
+const SETUP_CMD: &str = "setup";
 
 pub struct LangTester<'a> {
-    test_dir: Option<&'a str>,
+    test_dirs: Vec<Cow<'a, Path>>,
+    recursive: bool,
+    /// If `true`, hidden files and editor temporary files (see `is_hidden`) can be tests.
+    include_hidden: bool,
+    report_slowest: usize,
+    /// If `Some`, failure reports show only the first and last this many lines of each command's
+    /// output (and of each diff).
+    max_output_lines: Option<usize>,
+    /// If `Some`, the seed from which the order tests are run in is shuffled.
+    shuffle: Option<u64>,
+    /// If `true`, list the tests, and their commands, rather than running them.
+    list: bool,
+    /// If `true`, report progress while tests are running (see `LangTesterPooler::progress`).
+    progress: bool,
+    /// If `Some`, the directory in which extracted test data is cached.
+    cache_dir: Option<PathBuf>,
+    /// If `true`, a test file from which no test data is extracted is an error.
+    require_test_block: bool,
+    /// If `true`, rerun tests whenever files they might depend on change (see `watch_tests`).
+    watch: bool,
+    /// Paths, in addition to `test_dirs`, which are watched for changes in watch mode.
+    watch_paths: Vec<PathBuf>,
     use_cmdline_args: bool,
     test_file_filter: Option<Box<dyn Fn(&Path) -> bool>>,
+    /// If `Some`, only files whose path relative to `test_dir` matches this (compiled) glob are
+    /// considered tests.
+    test_file_glob: Option<Regex>,
+    filter: Option<Box<dyn Fn(&str) -> bool>>,
     cmdline_filters: Option<Vec<String>>,
+    /// If non-empty, only tests with at least one of these tags are run.
+    include_tags: Vec<String>,
+    /// Tests with any of these tags are not run.
+    exclude_tags: Vec<String>,
+    /// If `Some`, a function called once before any tests are run: if it returns `Err`, no tests
+    /// are run.
+    before_all: Option<Box<dyn FnOnce() -> Result<(), String>>>,
+    /// If `Some`, a function called once after all tests have been run.
+    after_all: Option<Box<dyn FnOnce()>>,
     inner: Arc<LangTesterPooler>,
 }
 
@@ -57,8 +130,202 @@ struct LangTesterPooler {
     test_threads: usize,
     ignored: bool,
     nocapture: bool,
+    /// If `true`, each command's program, arguments, working directory, and environment
+    /// overrides are printed before it is run.
+    verbose: bool,
+    /// If `Some`, a function which returns the value to be printed (in `verbose` mode) for an
+    /// environment variable in place of its real value.
+    redact_env: Option<Box<dyn Fn(&str, &str) -> String + Send + Sync>>,
+    /// If `true`, tests can specify the command line to run with a `cmd` key.
+    allow_inline_cmd: bool,
+    fail_fast: bool,
+    /// If `true`, a test whose test data doesn't parse fails, rather than the whole run being
+    /// aborted.
+    continue_on_parse_error: bool,
+    bless: bool,
+    case_insensitive: bool,
+    /// The prefix of comment lines in test data.
+    comment_prefix: String,
+    /// The minimum number of columns by which keys and values must be indented relative to the
+    /// line enclosing them.
+    min_indent: usize,
+    /// If `Some`, test data (specifying only commands) whose keys are merged into each test's
+    /// commands of the same name, unless the test overrides them.
+    default_cmd_assertions: Option<String>,
+    /// If `Some`, only the (lower-cased) commands named are run: others are skipped.
+    only_cmds: Option<Vec<String>>,
+    /// The numbers of the signals which, if they terminate a command, are treated as success.
+    ignore_signals: Vec<i32>,
+    output_format: OutputFormat,
+    /// The style of human readable output.
+    reporter_style: ReporterStyle,
+    /// The number of tests reported so far. Only used by machine-readable output formats.
+    num_reported: AtomicUsize,
+    /// How long each test which was run took.
+    timings: Mutex<Vec<TestTiming>>,
+    /// The `(test name, command name, retries)` of each command which passed only after being
+    /// retried.
+    flaky: Mutex<Vec<(String, String, usize)>>,
+    /// The names of the tests whose expected output was rewritten by `bless`. The lock is also
+    /// held while rewriting files, so that tests in the same file don't overwrite each other's
+    /// changes.
+    blessed: Mutex<Vec<String>>,
+    /// The `(test name, reason)` of each `xfail` test which failed, as expected.
+    xfailed: Mutex<Vec<(String, String)>>,
+    /// The `(test name, reason)` of each `xfail` test which unexpectedly passed.
+    xpassed: Mutex<Vec<(String, String)>>,
+    /// If `Some`, the progress of the tests being run is reported. Only used by
+    /// `OutputFormat::Pretty`, and never when `nocapture` is set.
+    progress: Option<Mutex<Progress>>,
+    /// The `<testsuite>`s of each test reported so far. Only used by `OutputFormat::JUnitXml`.
+    junit_suites: Mutex<Vec<output::JUnitSuite>>,
+    test_timeout: Option<Duration>,
+    /// Environment variables set for every command (unless `test_cmds` sets them).
+    env: HashMap<String, String>,
+    /// If `Some`, the maximum size, in bytes, of each command's address space.
+    memory_limit: Option<u64>,
+    /// If `Some`, the maximum CPU time each command can use.
+    cpu_limit: Option<Duration>,
     test_extract: Option<Box<dyn Fn(&str) -> Option<String> + Send + Sync>>,
-    test_cmds: Option<Box<dyn Fn(&Path) -> Vec<(&str, Command)> + Send + Sync>>,
+    test_extract_with_path: Option<Box<dyn Fn(&Path, &str) -> Option<String> + Send + Sync>>,
+    test_extract_many: Option<Box<dyn Fn(&str) -> Vec<(String, String)> + Send + Sync>>,
+    /// If `Some`, the function which parses test data in place of the built-in parser.
+    test_parse:
+        Option<Box<dyn for<'b> Fn(&Path, &'b str) -> Result<Tests<'b>, String> + Send + Sync>>,
+    /// The function which returns a test's commands. It is passed the test's scratch directory if
+    /// `test_cmds_tempdir` is `true`.
+    test_cmds: Option<
+        Box<
+            dyn Fn(&Path, &Tests, Option<&Path>) -> Result<Vec<(String, Command)>, String>
+                + Send
+                + Sync,
+        >,
+    >,
+    /// If `true`, each test is given its own scratch directory.
+    test_cmds_tempdir: bool,
+    /// If `true`, tests' scratch directories are not removed after the tests have run.
+    keep_artifacts: bool,
+    /// If `true`, failing tests' scratch directories are not removed after the tests have run.
+    keep_artifacts_on_failure: bool,
+    /// If `true`, ANSI escape sequences are removed from commands' captured stderr and stdout.
+    strip_ansi: bool,
+    output_transform: Option<Box<dyn Fn(&str) -> String + Send + Sync>>,
+    /// If `true`, commands' stdout is checked as it is read, rather than once it has all been
+    /// captured, where possible.
+    stream_output: bool,
+    /// If `true`, `stderr-contains`/`stdout-contains` patterns need only match part of a line.
+    contains_substring: bool,
+    /// If `true`, `\r\n` and `\r` are treated as `\n` in commands' captured output and in pattern
+    /// files, except where output is checked with a raw or exact key.
+    normalize_newlines: bool,
+    on_test_result: Option<Box<dyn Fn(&TestResult) + Send + Sync>>,
+    before_cmd: Option<Box<dyn Fn(&Path, &str) + Send + Sync>>,
+    after_cmd: Option<Box<dyn Fn(&Path, &str) + Send + Sync>>,
+    /// If `Some`, the function which rewrites each command (e.g. to run it under another program)
+    /// before it is run.
+    cmd_wrapper: Option<Box<dyn Fn(&str, &Command) -> Command + Send + Sync>>,
+}
+
+impl LangTesterPooler {
+    /// Forget the results of the previous run, so that tests can be rerun in watch mode.
+    fn reset(&mut self) {
+        self.num_reported = AtomicUsize::new(0);
+        self.timings.get_mut().unwrap().clear();
+        self.flaky.get_mut().unwrap().clear();
+        self.blessed.get_mut().unwrap().clear();
+        self.xfailed.get_mut().unwrap().clear();
+        self.xpassed.get_mut().unwrap().clear();
+        self.junit_suites.get_mut().unwrap().clear();
+    }
+
+    /// Parse the test data `test_str` (extracted from the test file `path`) with the user's parser
+    /// if there is one, or otherwise using the user's comment prefix and minimum indentation,
+    /// merging in the user's default command assertions (if any).
+    fn parse<'b>(&'b self, path: &Path, test_str: &'b str) -> Result<Tests<'b>, Vec<ParseError>> {
+        if let Some(ref test_parse) = self.test_parse {
+            let tests = test_parse(path, test_str).map_err(|msg| vec![ParseError::new(0, msg)])?;
+            check_tests(&tests)?;
+            return Ok(tests);
+        }
+        let mut tests = parse_with_min_indent(test_str, &self.comment_prefix, self.min_indent)?;
+        if let Some(ref defaults) = self.default_cmd_assertions {
+            apply_defaults(&mut tests, defaults, &self.comment_prefix, self.min_indent);
+        }
+        Ok(tests)
+    }
+
+    /// Return the `TestCmd` of the command `cmd_name`, which a test's data doesn't specify, with
+    /// the user's default command assertions (if any) merged in.
+    fn default_testcmd(&self, cmd_name: &str) -> TestCmd<'_> {
+        match self.default_cmd_assertions {
+            Some(ref defaults) => {
+                default_testcmd(cmd_name, defaults, &self.comment_prefix, self.min_indent)
+            }
+            None => TestCmd::default(),
+        }
+    }
+
+    /// Do we need to record the `CmdResult`s of each test?
+    fn record_cmd_results(&self) -> bool {
+        self.output_format != OutputFormat::Pretty || self.on_test_result.is_some()
+    }
+
+    /// If the progress line is displayed, erase it, leaving the cursor at the start of the
+    /// previous line, so that subsequent output (which always starts with a newline) is written
+    /// in its place.
+    fn hide_progress(&self, handle: &mut dyn Write) {
+        if let Some(ref progress) = self.progress {
+            let mut progress = progress.lock().unwrap();
+            if progress.shown {
+                handle.write_all(b"\r\x1b[K\x1b[1A").ok();
+                progress.shown = false;
+            }
+        }
+    }
+
+    /// Display the progress line after other output has been written to `handle`, first
+    /// recording that a test has completed if `test_completed` is `true`. On a terminal, the
+    /// progress line is always displayed (and erased by the next output); otherwise, a progress
+    /// line is written at most every `PROGRESS_INTERVAL` seconds.
+    fn show_progress(&self, handle: &mut dyn Write, test_completed: bool) {
+        if let Some(ref progress) = self.progress {
+            let mut progress = progress.lock().unwrap();
+            if test_completed {
+                progress.completed += 1;
+            }
+            if progress.terminal {
+                if progress.shown {
+                    return;
+                }
+                progress.shown = true;
+            } else if !test_completed
+                || progress.last_written.elapsed() < Duration::from_secs(PROGRESS_INTERVAL)
+            {
+                return;
+            } else {
+                progress.last_written = Instant::now();
+            }
+            handle
+                .write_all(
+                    format!("\nrunning {}/{}", progress.completed, progress.total).as_bytes(),
+                )
+                .ok();
+        }
+    }
+}
+
+/// The progress of the tests being run.
+struct Progress {
+    /// The number of tests to be run.
+    total: usize,
+    /// The number of tests which have completed (including those which were ignored or not run).
+    completed: usize,
+    /// Is stderr a terminal?
+    terminal: bool,
+    /// Is the progress line currently displayed? Only used if `terminal` is `true`.
+    shown: bool,
+    /// When a progress line was last written. Only used if `terminal` is `false`.
+    last_written: Instant,
 }
 
 impl<'a> LangTester<'a> {
@@ -67,25 +334,129 @@ impl<'a> LangTester<'a> {
     /// [`test_cmds`](#method.test_cmds).
     pub fn new() -> Self {
         LangTester {
-            test_dir: None,
+            test_dirs: Vec::new(),
+            recursive: true,
+            include_hidden: false,
+            report_slowest: 0,
+            max_output_lines: None,
+            shuffle: None,
+            list: false,
+            progress: true,
+            cache_dir: None,
+            require_test_block: false,
+            watch: false,
+            watch_paths: Vec::new(),
             test_file_filter: None,
+            test_file_glob: None,
+            filter: None,
+            include_tags: Vec::new(),
+            exclude_tags: Vec::new(),
+            before_all: None,
+            after_all: None,
             use_cmdline_args: true,
             cmdline_filters: None,
             inner: Arc::new(LangTesterPooler {
                 ignored: false,
                 nocapture: false,
-                test_threads: num_cpus::get(),
+                verbose: false,
+                redact_env: None,
+                allow_inline_cmd: false,
+                fail_fast: false,
+                continue_on_parse_error: false,
+                bless: env::var("LANG_TESTER_BLESS").is_ok_and(|v| !v.is_empty() && v != "0"),
+                case_insensitive: false,
+                comment_prefix: DEFAULT_COMMENT_PREFIX.to_owned(),
+                min_indent: DEFAULT_MIN_INDENT,
+                default_cmd_assertions: None,
+                only_cmds: None,
+                ignore_signals: Vec::new(),
+                output_format: OutputFormat::Pretty,
+                reporter_style: ReporterStyle::default(),
+                num_reported: AtomicUsize::new(0),
+                timings: Mutex::new(Vec::new()),
+                flaky: Mutex::new(Vec::new()),
+                blessed: Mutex::new(Vec::new()),
+                xfailed: Mutex::new(Vec::new()),
+                xpassed: Mutex::new(Vec::new()),
+                progress: None,
+                junit_suites: Mutex::new(Vec::new()),
+                test_timeout: None,
+                env: HashMap::new(),
+                memory_limit: None,
+                cpu_limit: None,
+                test_threads: env::var("RUST_TEST_THREADS")
+                    .ok()
+                    .and_then(|s| s.parse::<usize>().ok())
+                    .filter(|&n| n > 0)
+                    .unwrap_or_else(num_cpus::get),
                 test_extract: None,
+                test_extract_with_path: None,
+                test_extract_many: None,
+                test_parse: None,
                 test_cmds: None,
+                test_cmds_tempdir: false,
+                keep_artifacts: false,
+                keep_artifacts_on_failure: false,
+                strip_ansi: false,
+                output_transform: None,
+                stream_output: false,
+                contains_substring: false,
+                normalize_newlines: true,
+                on_test_result: None,
+                before_cmd: None,
+                after_cmd: None,
+                cmd_wrapper: None,
             }),
         }
     }
 
-    /// Specify the directory where test files are contained. Note that this directory will be
-    /// searched recursively (i.e. subdirectories and their contents will also be considered as
-    /// potential test files).
+    /// Specify a directory where test files are contained. Note that, by default, this
+    /// directory will be searched recursively (i.e. subdirectories and their contents will also
+    /// be considered as potential test files): see [`recursive`](#method.recursive). This can be
+    /// called more than once (or see [`test_dirs`](#method.test_dirs)), in which case all the
+    /// directories specified are searched.
     pub fn test_dir(&'a mut self, test_dir: &'a str) -> &'a mut Self {
-        self.test_dir = Some(test_dir);
+        self.test_dirs.push(Cow::Borrowed(Path::new(test_dir)));
+        self
+    }
+
+    /// Specify several directories where test files are contained, in addition to any specified
+    /// with [`test_dir`](#method.test_dir). All directories are searched with the same filters and
+    /// extraction functions. A test's name is its path relative to the directory it was found in
+    /// unless tests in two or more directories would then have the same name, in which case the
+    /// directory is included in the name (e.g. `tests/pass/a` and `tests/fail/a`).
+    pub fn test_dirs<I>(&'a mut self, test_dirs: I) -> &'a mut Self
+    where
+        I: IntoIterator<Item = PathBuf>,
+    {
+        self.test_dirs.extend(test_dirs.into_iter().map(Cow::Owned));
+        self
+    }
+
+    /// If set to `true`, subdirectories of [`test_dir`](#method.test_dir) are searched
+    /// recursively for test files; if set to `false`, only files directly within `test_dir` are
+    /// considered. Tests in subdirectories are named by their path relative to `test_dir` (e.g.
+    /// `a/b/c.rs` is named `a/b/c`), so that tests with the same filename in different
+    /// subdirectories can be distinguished. Symbolic links to directories are not followed. This
+    /// option defaults to `true`.
+    pub fn recursive(&'a mut self, recursive: bool) -> &'a mut Self {
+        self.recursive = recursive;
+        self
+    }
+
+    /// If set to `false`, hidden files and common editor temporary files are never considered to be
+    /// tests, and hidden directories are not searched, so that e.g. an editor's swap file can't be
+    /// mistaken for a test. A file or directory is skipped if its name:
+    ///
+    ///   * starts with `.` (e.g. `.DS_Store`, or Vim's `.x.rs.swp`);
+    ///   * ends with `~` (e.g. Emacs's `x.rs~` backup files);
+    ///   * starts and ends with `#` (e.g. Emacs's `#x.rs#` auto-save files);
+    ///   * ends with `.swp`, `.swo`, or `.swx` (Vim's swap files).
+    ///
+    /// Such files are skipped before [`test_file_filter`](#method.test_file_filter) is called. If
+    /// set to `true`, they are considered in the same way as any other file. Defaults to `false`.
+    pub fn include_hidden(&'a mut self, include_hidden: bool) -> &'a mut Self {
+        self.include_hidden = include_hidden;
         self
     }
 
@@ -107,12 +478,99 @@ impl<'a> LangTester<'a> {
         self
     }
 
+    /// A shorthand for a [`test_file_filter`](#method.test_file_filter) which considers only
+    /// files with one of the filename extensions `exts` to be tests e.g.:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .test_file_extensions(&["rs", "rlib"])
+    ///     ...
+    /// ```
+    ///
+    /// Since this sets the `test_file_filter`, it replaces any previously specified
+    /// `test_file_filter` (and vice versa).
+    pub fn test_file_extensions(&'a mut self, exts: &[&str]) -> &'a mut Self {
+        let exts = exts.iter().map(|e| (*e).to_owned()).collect::<Vec<_>>();
+        self.test_file_filter(move |p| {
+            p.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| exts.iter().any(|x| x == e))
+        })
+    }
+
+    /// If `test_file_glob` is specified, only files whose path relative to the
+    /// [`test_dir`](#method.test_dir) matches the glob `glob` will be considered tests e.g.:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .test_file_glob("**/*.{rs,test}")
+    ///     ...
+    /// ```
+    ///
+    /// `*` and `?` match any characters (respectively a single character) other than `/`; `**/`
+    /// matches zero or more directories; `[...]` and `[!...]` match a single character in (or not
+    /// in) a set; and `{a,b}` matches either `a` or `b`. This can be combined with
+    /// [`test_file_filter`](#method.test_file_filter), in which case files must satisfy both.
+    pub fn test_file_glob(&'a mut self, glob: &str) -> &'a mut Self {
+        self.test_file_glob = Some(
+            glob::glob_regex(glob)
+                .unwrap_or_else(|e| fatal(&format!("Invalid glob '{}': {}.", glob, e))),
+        );
+        self
+    }
+
+    /// If `filter` is specified, only tests whose name (e.g. `a` for a test file `a.rs`) it
+    /// returns `true` for will be run: all other tests are counted as "filtered out". For
+    /// example, to only run tests whose name contains `var`:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .filter(|n| n.contains("var"))
+    ///     ...
+    /// ```
+    ///
+    /// If `filter` is specified, any filters passed on the command-line are ignored.
+    pub fn filter<F>(&'a mut self, filter: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&str) -> bool,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Select tests by the tags they have been given with a `tags:` line in their test data. If
+    /// `include` is non-empty, only tests with at least one of the tags in `include` are run; tests
+    /// with any of the tags in `exclude` are never run, even if they also have a tag in `include`
+    /// (i.e. `exclude` takes precedence). All other tests are counted as "filtered out". For
+    /// example, to run only tests tagged `network`, other than those also tagged `slow`:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .filter_tags(&["network"], &["slow"])
+    ///     ...
+    /// ```
+    ///
+    /// Tags are matched case-sensitively. Selecting tests by tag is independent of selecting them
+    /// by name (with [`filter`](#method.filter) or the command-line): a test is only run if it is
+    /// selected by both.
+    pub fn filter_tags(&'a mut self, include: &[&str], exclude: &[&str]) -> &'a mut Self {
+        self.include_tags = include.iter().map(|t| (*t).to_owned()).collect();
+        self.exclude_tags = exclude.iter().map(|t| (*t).to_owned()).collect();
+        self
+    }
+
     /// Specify a function which can extract the test data for `lang_tester` from a test file. This
     /// function is passed a `&str` and must return a `String`.
     ///
     /// How the test data is extracted from the test file is entirely up to the user, though a
     /// common convention is to store the test data in a comment at the beginning of the test file.
-    /// For example, for Rust code one could use a function along the lines of the following:
+    /// The [`extract`](extract/index.html) module provides functions for common cases (e.g.
+    /// [`extract::line_comment_block("//")`](extract/fn.line_comment_block.html)). For example,
+    /// for Rust code one could use a function along the lines of the following:
     ///
     /// ```rust,ignore
     /// LangTester::new()
@@ -139,6 +597,84 @@ impl<'a> LangTester<'a> {
         self
     }
 
+    /// As [`test_extract`](#method.test_extract), except that the function is also passed the
+    /// `Path` of the test file. This is useful if test files in different languages need to be
+    /// handled differently e.g.:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .test_extract_with_path(|p, s| {
+    ///         let prefix = match p.extension().unwrap().to_str().unwrap() {
+    ///             "py" => "#",
+    ///             _ => "//",
+    ///         };
+    ///         ...
+    ///     })
+    ///     ...
+    /// ```
+    ///
+    /// If both this and [`test_extract`](#method.test_extract) are specified, this function takes
+    /// precedence.
+    pub fn test_extract_with_path<F>(&'a mut self, test_extract_with_path: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&Path, &str) -> Option<String> + Send + Sync,
+    {
+        Arc::get_mut(&mut self.inner)
+            .unwrap()
+            .test_extract_with_path = Some(Box::new(test_extract_with_path));
+        self
+    }
+
+    /// As [`test_extract`](#method.test_extract), except that the function can extract multiple
+    /// independent blocks of test data from a single test file, returning a vector of `(<block
+    /// name>, <test data>)` pairs. Each block is run as a separate test, named
+    /// `<test file>::<block name>`. For example, if test data blocks start with a line such as
+    /// `// test: <block name>`:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .test_extract_many(|s| {
+    ///         let mut blocks = Vec::new();
+    ///         let mut lines = s.lines().peekable();
+    ///         while let Some(l) = lines.next() {
+    ///             if let Some(name) = l.strip_prefix("// test:") {
+    ///                 let mut test_str = Vec::new();
+    ///                 while let Some(l) = lines.next_if(|l| l.starts_with("//   ")) {
+    ///                     test_str.push(&l[2..]);
+    ///                 }
+    ///                 blocks.push((name.trim().to_owned(), test_str.join("\n")));
+    ///             }
+    ///         }
+    ///         blocks
+    ///     })
+    ///     ...
+    /// ```
+    ///
+    /// If specified, this takes precedence over `test_extract` and `test_extract_with_path`. Note
+    /// that a test file from which no blocks are extracted contains no tests.
+    pub fn test_extract_many<F>(&'a mut self, test_extract_many: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&str) -> Vec<(String, String)> + Send + Sync,
+    {
+        Arc::get_mut(&mut self.inner).unwrap().test_extract_many =
+            Some(Box::new(test_extract_many));
+        self
+    }
+
+    /// If set to `true`, every test file must contain test data: a file from which no blocks are
+    /// extracted (see [`test_extract_many`](#method.test_extract_many)), or whose extracted test
+    /// data is empty or specifies no test commands, causes an error listing the offending files
+    /// before any tests are run. This guards against a broken extraction function silently
+    /// disabling tests. If set to `false`, such files are ignored (or, for `test_extract_many`,
+    /// contain no tests). Note that an extraction function returning `None` is always an error.
+    /// Defaults to `false`.
+    pub fn require_test_block(&'a mut self, require_test_block: bool) -> &'a mut Self {
+        self.require_test_block = require_test_block;
+        self
+    }
+
     /// Specify a function which takes a `Path` to a test file and returns a vector containing 1 or
     /// more `(<name>, <[Command](https://doc.rust-lang.org/std/process/struct.Command.html)>)
     /// pairs. The commands will be executed in order on the test file: for each executed command,
@@ -181,561 +717,4426 @@ impl<'a> LangTester<'a> {
     where
         F: 'static + Fn(&Path) -> Vec<(&str, Command)> + Send + Sync,
     {
-        Arc::get_mut(&mut self.inner).unwrap().test_cmds = Some(Box::new(test_cmds));
+        self.test_cmds_fallible(move |p| {
+            Ok(test_cmds(p)
+                .into_iter()
+                .map(|(cmd_name, cmd)| (cmd_name.to_owned(), cmd))
+                .collect())
+        })
+    }
+
+    /// As [`test_cmds`](#method.test_cmds), but the function may fail (e.g. because a tool that a
+    /// command needs is not installed) by returning `Err` with a message suitable for showing to
+    /// the user. A test for which the function fails is reported as having failed with that
+    /// message; other tests are unaffected.
+    pub fn test_cmds_fallible<F>(&'a mut self, test_cmds: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&Path) -> Result<Vec<(String, Command)>, String> + Send + Sync,
+    {
+        let inner = Arc::get_mut(&mut self.inner).unwrap();
+        inner.test_cmds = Some(Box::new(move |p, _, _| test_cmds(p)));
+        inner.test_cmds_tempdir = false;
         self
     }
 
-    /// If set to `true`, this reads arguments from `std::env::args()` and interprets them in the
-    /// same way as normal cargo test files. For example if you have tests "ab" and "cd" but only
-    /// want to run the latter:
+    /// As [`test_cmds`](#method.test_cmds), except that the function is also passed the
+    /// [`Tests`](struct.Tests.html) parsed from the test file's test data. This allows the
+    /// commands to depend on what a test specifies, e.g. to avoid building an executable for a
+    /// test which doesn't run it:
     ///
-    /// ```sh
-    /// $ <test bin> c
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .test_cmds_with_tests(|p, tests| {
+    ///         let mut cmds = vec![("Compiler".to_owned(), compiler_cmd(p))];
+    ///         if tests.tests.contains_key("run-time") {
+    ///             cmds.push(("Run-time".to_owned(), runtime_cmd(p)));
+    ///         }
+    ///         cmds
+    ///     })
+    ///     ...
     /// ```
+    pub fn test_cmds_with_tests<F>(&'a mut self, test_cmds: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&Path, &Tests) -> Vec<(String, Command)> + Send + Sync,
+    {
+        let inner = Arc::get_mut(&mut self.inner).unwrap();
+        inner.test_cmds = Some(Box::new(move |p, tests, _| Ok(test_cmds(p, tests))));
+        inner.test_cmds_tempdir = false;
+        self
+    }
+
+    /// As [`test_cmds`](#method.test_cmds), except that the function is also passed the path of a
+    /// new, empty, directory which is unique to the test. This allows tests which run in parallel
+    /// to write files (e.g. compiled executables) without clashing with each other:
     ///
-    /// As this suggests, a simple substring search is used to decide which tests to run.
-    ///
-    /// You can get help on `lang_tester`'s options:
-    ///
-    /// ```sh
-    /// $ <test bin> --help
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .test_cmds_with_tempdir(|p, tempdir| {
+    ///         let exe = tempdir.join(p.file_stem().unwrap());
+    ///         let mut compiler = Command::new("rustc");
+    ///         compiler.args(&["-o", exe.to_str().unwrap(), p.to_str().unwrap()]);
+    ///         let runtime = Command::new(exe);
+    ///         vec![("Compiler".to_owned(), compiler), ("Run-time".to_owned(), runtime)]
+    ///     })
+    ///     ...
     /// ```
     ///
-    /// This option defaults to `true`.
-    pub fn use_cmdline_args(&'a mut self, use_cmdline_args: bool) -> &'a mut Self {
-        self.use_cmdline_args = use_cmdline_args;
+    /// The directory, and everything in it, is removed once the test has finished (whether it
+    /// passed or failed) unless [`keep_artifacts`](#method.keep_artifacts) or
+    /// [`keep_artifacts_on_failure`](#method.keep_artifacts_on_failure) is set.
+    pub fn test_cmds_with_tempdir<F>(&'a mut self, test_cmds: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&Path, &Path) -> Vec<(String, Command)> + Send + Sync,
+    {
+        let inner = Arc::get_mut(&mut self.inner).unwrap();
+        inner.test_cmds = Some(Box::new(move |p, _, tempdir| {
+            Ok(test_cmds(p, tempdir.unwrap()))
+        }));
+        inner.test_cmds_tempdir = true;
         self
     }
 
-    /// Make sure the user has specified the minimum set of things we need from them.
-    fn validate(&self) {
-        if self.test_dir.is_none() {
-            fatal("test_dir must be specified.");
-        }
-        if self.inner.test_extract.is_none() {
-            fatal("test_extract must be specified.");
-        }
-        if self.inner.test_cmds.is_none() {
-            fatal("test_cmds must be specified.");
-        }
+    /// If set to `true`, the directories created by
+    /// [`test_cmds_with_tempdir`](#method.test_cmds_with_tempdir) are not removed after tests
+    /// have finished, and the directory of each failing test is reported, so that its contents
+    /// can be inspected. Defaults to `false`.
+    pub fn keep_artifacts(&'a mut self, keep_artifacts: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().keep_artifacts = keep_artifacts;
+        self
     }
 
-    /// Enumerate all the test files we need to check, along with the number of files filtered out
-    /// (e.g. if you have tests `a, b, c` and the user does something like `cargo test b`, 2 tests
-    /// (`a` and `c`) will be filtered out.
-    fn test_files(&self) -> (Vec<PathBuf>, usize) {
-        let mut num_filtered = 0;
-        let paths = WalkDir::new(self.test_dir.unwrap())
-            .into_iter()
-            .filter_map(|x| x.ok())
-            .filter(|x| x.file_type().is_file())
-            // Filter out non-test files
-            .filter(|x| match self.test_file_filter.as_ref() {
-                Some(f) => f(x.path()),
-                None => true,
-            })
-            // If the user has named one or more tests on the command-line, run only those,
-            // filtering out the rest (counting them as ignored).
-            .filter(|x| {
-                let x_path = x.path().to_str().unwrap();
-                match self.cmdline_filters.as_ref() {
-                    Some(fs) => {
-                        debug_assert!(self.use_cmdline_args);
-                        for f in fs {
-                            if x_path.contains(f) {
-                                return true;
-                            }
-                        }
-                        num_filtered += 1;
-                        false
-                    }
-                    None => true,
-                }
-            })
-            .map(|x| x.into_path())
-            .collect();
-        (paths, num_filtered)
+    /// If set to `true`, the directories created by
+    /// [`test_cmds_with_tempdir`](#method.test_cmds_with_tempdir) for tests which fail are not
+    /// removed, and their paths are reported alongside the tests' failures, so that their contents
+    /// (e.g. a miscompiled executable) can be inspected. The directories of tests which pass are
+    /// still removed. Defaults to `false`.
+    pub fn keep_artifacts_on_failure(
+        &'a mut self,
+        keep_artifacts_on_failure: bool,
+    ) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner)
+            .unwrap()
+            .keep_artifacts_on_failure = keep_artifacts_on_failure;
+        self
     }
 
-    /// Run all the lang tests.
-    pub fn run(&mut self) {
-        self.validate();
-        if self.use_cmdline_args {
-            let args: Vec<String> = env::args().collect();
-            let matches = Options::new()
-                .optflag("h", "help", "")
-                .optflag("", "ignored", "Run only ignored tests")
-                .optflag(
-                    "",
-                    "nocapture",
-                    "Pass command stderr/stdout through to the terminal",
-                )
-                .optopt(
-                    "",
-                    "test-threads",
-                    "Number of threads used for running tests in parallel",
-                    "n_threads",
-                )
-                .parse(&args[1..])
-                .unwrap_or_else(|_| usage());
-            if matches.opt_present("h") {
-                usage();
-            }
-            if matches.opt_present("ignored") {
-                Arc::get_mut(&mut self.inner).unwrap().ignored = true;
-            }
-            if matches.opt_present("nocapture") {
-                Arc::get_mut(&mut self.inner).unwrap().nocapture = true;
-            }
-            if let Some(s) = matches.opt_str("test-threads") {
-                let test_threads = s.parse::<usize>().unwrap_or_else(|_| usage());
-                if test_threads == 0 {
-                    fatal("Must specify more than 0 threads.");
-                }
-                Arc::get_mut(&mut self.inner).unwrap().test_threads = test_threads;
-            }
-            if !matches.free.is_empty() {
-                self.cmdline_filters = Some(matches.free);
-            }
-        }
-        let (test_files, num_filtered) = self.test_files();
-        eprint!("\nrunning {} tests", test_files.len());
-        let test_files_len = test_files.len();
-        let (failures, num_ignored) = test_file(test_files, Arc::clone(&self.inner));
+    /// If set to `true`, ANSI CSI escape sequences (e.g. those which colour text on a terminal) are
+    /// removed from a command's captured stderr and stdout before they are matched against a
+    /// test's patterns. This allows a program's output to be tested even if it (wrongly) emits
+    /// colour codes when it is not writing to a terminal. The sequences are removed before any
+    /// [`output_transform`](#method.output_transform) is applied, and failure reports show the
+    /// output without them. Defaults to `false`.
+    pub fn strip_ansi(&'a mut self, strip_ansi: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().strip_ansi = strip_ansi;
+        self
+    }
 
-        self.pp_failures(&failures, test_files_len, num_ignored, num_filtered);
+    /// Specify a function which transforms a command's captured stderr and stdout before they are
+    /// matched against a test's `stderr`, `stdout`, and `output` patterns. This is useful for
+    /// normalising output which differs from run to run (e.g. temporary paths or process IDs),
+    /// e.g.:
+    ///
+    /// ```rust,ignore
+    /// let tmp_re = Regex::new("/tmp/[^/]+/").unwrap();
+    /// LangTester::new()
+    ///     ...
+    ///     .output_transform(move |s| tmp_re.replace_all(s, "<TMPDIR>/").into_owned())
+    ///     ...
+    /// ```
+    ///
+    /// The function is called once for each of stderr and stdout (or, for commands with an
+    /// `output` test, once for the combined output) of each command. Failure reports show the
+    /// transformed output, since that is what was matched.
+    pub fn output_transform<F>(&'a mut self, output_transform: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&str) -> String + Send + Sync,
+    {
+        Arc::get_mut(&mut self.inner).unwrap().output_transform = Some(Box::new(output_transform));
+        self
+    }
 
-        if !failures.is_empty() {
-            process::exit(1);
-        }
+    /// If set to `true`, a command's stdout is checked as the command writes it, rather than
+    /// being captured in full and then checked, so that commands which write very large amounts
+    /// of output can be tested without running out of memory. Only the last few lines of stdout
+    /// are kept, for failure reports: a failure to match `stdout` (or `stdout-raw`) is reported
+    /// as the first line which didn't match, rather than as a diff. Since line wildcards match
+    /// lazily, without backtracking, the result of matching is the same as when stdout is
+    /// captured in full.
+    ///
+    /// Only `stdout`, `stdout-file`, `stdout-raw`, `stdout-not`, `stdout-lines`, and
+    /// `stdout-bytes` can be checked as stdout is written. A command's stdout is still captured in
+    /// full if it has an `output`, `stdout-exact`, `stdout-equals-stdin`, `stdout-json`,
+    /// `stdout-json-subset`, `stdout-contains`, `stdout-contains-ordered`, or `interact` test; if
+    /// its `stdout` pattern binds captures; if another command takes its stdout as `stdin-from`;
+    /// or if [`output_transform`](#method.output_transform) or [`bless`](#method.bless) is set.
+    /// Defaults to `false`.
+    pub fn stream_output(&'a mut self, stream_output: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().stream_output = stream_output;
+        self
     }
 
-    /// Pretty print any failures to `stderr`.
-    fn pp_failures(
-        &self,
-        failures: &[(String, TestFailure)],
-        test_files_len: usize,
-        num_ignored: usize,
-        num_filtered: usize,
-    ) {
-        if !failures.is_empty() {
-            eprintln!("\n\nfailures:");
-            for (test_fname, test) in failures {
+    /// If set to `true`, each `stderr-contains`/`stdout-contains` pattern need only match part of
+    /// a line of output, rather than a whole line (e.g. `error` matches any line containing
+    /// `error`, as `...error...` would otherwise be needed to). A `re:` line's regular expression
+    /// then need only match part of a line too. Defaults to `false`.
+    pub fn contains_substring(&'a mut self, contains_substring: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().contains_substring = contains_substring;
+        self
+    }
+
+    /// If set to `true`, each `\r\n` and each lone `\r` in a command's captured stderr and stdout
+    /// (and in `stderr-file` and `stdout-file` pattern files) is treated as `\n` before matching,
+    /// so that the same tests pass whichever line endings a program writes. Line endings are
+    /// normalised after any [`output_transform`](#method.output_transform) is applied. Output
+    /// checked by a raw or exact key (`stderr-raw`, `stdout-raw`, `stderr-exact`,
+    /// `stdout-exact`, or `stdout-equals-stdin`) is matched as the command wrote it, as is stdout
+    /// passed to another command with `stdin-from`. Defaults to `true`.
+    pub fn normalize_newlines(&'a mut self, normalize_newlines: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().normalize_newlines = normalize_newlines;
+        self
+    }
+
+    /// Specify a function which is called with the [`TestResult`](struct.TestResult.html) of each
+    /// test after it has been run (ignored tests are not run, and so are not passed to the
+    /// function). This allows results to be reported in custom ways (e.g. to a telemetry system)
+    /// in addition to, rather than instead of, the normal output, e.g.:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .on_test_result(|r| {
+    ///         for cmd in &r.cmds {
+    ///             record(&r.name, &cmd.name, cmd.passed, cmd.duration);
+    ///         }
+    ///     })
+    ///     ...
+    /// ```
+    ///
+    /// Since tests are run in parallel, the function may be called from multiple threads at once.
+    pub fn on_test_result<F>(&'a mut self, on_test_result: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&TestResult) + Send + Sync,
+    {
+        Arc::get_mut(&mut self.inner).unwrap().on_test_result = Some(Box::new(on_test_result));
+        self
+    }
+
+    /// Specify a function which is called with the path of a test file and the (lower-cased) name
+    /// of a command immediately before each time that command is run. Together with
+    /// [`after_cmd`](#method.after_cmd), this allows per-command fixtures (e.g. scratch
+    /// directories) to be set up and torn down, e.g.:
+    ///
+    /// ```rust,ignore
+    /// fn scratch_dir(p: &Path, cmd_name: &str) -> PathBuf { ... }
+    ///
+    /// LangTester::new()
+    ///     ...
+    ///     .before_cmd(|p, cmd_name| fs::create_dir_all(scratch_dir(p, cmd_name)).unwrap())
+    ///     .after_cmd(|p, cmd_name| fs::remove_dir_all(scratch_dir(p, cmd_name)).unwrap())
+    ///     ...
+    /// ```
+    ///
+    /// A command which is retried (see the `retries` key) is surrounded by calls to `before_cmd`
+    /// and `after_cmd` on each attempt. Since tests are run in parallel, the function may be
+    /// called from multiple threads at once.
+    pub fn before_cmd<F>(&'a mut self, before_cmd: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&Path, &str) + Send + Sync,
+    {
+        Arc::get_mut(&mut self.inner).unwrap().before_cmd = Some(Box::new(before_cmd));
+        self
+    }
+
+    /// Specify a function which is called with the path of a test file and the (lower-cased) name
+    /// of a command immediately after each time that command has been run. The function is called
+    /// whether the command passed or failed (including if it timed out), so it can reliably clean
+    /// up anything created by [`before_cmd`](#method.before_cmd). Commands which are not run (e.g.
+    /// because an earlier command failed) are not passed to either function.
+    pub fn after_cmd<F>(&'a mut self, after_cmd: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&Path, &str) + Send + Sync,
+    {
+        Arc::get_mut(&mut self.inner).unwrap().after_cmd = Some(Box::new(after_cmd));
+        self
+    }
+
+    /// Specify a function which is called with the (lower-cased) name of a command (e.g.
+    /// `run-time`) and the command itself, and returns the command to run in its place. This
+    /// allows commands to be run under another program (e.g. an emulator such as `qemu-arm`, or a
+    /// memory checker such as `valgrind`) without changing [`test_cmds`](#method.test_cmds), e.g.:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .cmd_wrapper(|cmd_name, cmd| {
+    ///         let mut wrapped = match cmd_name {
+    ///             "run-time" => {
+    ///                 let mut wrapped = Command::new("valgrind");
+    ///                 wrapped.arg("--error-exitcode=1").arg(cmd.get_program());
+    ///                 wrapped
+    ///             }
+    ///             _ => Command::new(cmd.get_program()),
+    ///         };
+    ///         wrapped.args(cmd.get_args());
+    ///         wrapped
+    ///     })
+    ///     ...
+    /// ```
+    ///
+    /// Since a `Command` can't be cloned, a command which is not to be wrapped must be rebuilt
+    /// from its program and arguments, as above.
+    ///
+    /// The function is called once for each command which is run (and not, for example, for
+    /// commands which are skipped), after the test's `extra-args` have been appended and their
+    /// placeholders expanded, and after `cwd`, `env-var`, and `env-clear` have been applied, so
+    /// the command it is passed is exactly the one which would otherwise have been run. The
+    /// returned command keeps the original command's working directory and environment, except
+    /// where the function sets its own, and is subject to the same resource limits (see
+    /// [`resource_limits`](#method.resource_limits)). Note that checks on the command's exit
+    /// status and output then apply to the wrapper program. Since tests are run in parallel, the
+    /// function may be called from multiple threads at once.
+    pub fn cmd_wrapper<F>(&'a mut self, cmd_wrapper: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&str, &Command) -> Command + Send + Sync,
+    {
+        Arc::get_mut(&mut self.inner).unwrap().cmd_wrapper = Some(Box::new(cmd_wrapper));
+        self
+    }
+
+    /// Specify a function which is called once, before any tests are run, to do expensive setup
+    /// shared by all tests (e.g. building a library which tests link against). If it returns
+    /// `Err`, no tests are run, the error is reported, and the process exits with a non-zero exit
+    /// code (after calling [`after_all`](#method.after_all), so that anything partially set up
+    /// can be cleaned up). It is not called when tests are only being listed.
+    pub fn before_all<F>(&'a mut self, before_all: F) -> &'a mut Self
+    where
+        F: 'static + FnOnce() -> Result<(), String>,
+    {
+        self.before_all = Some(Box::new(before_all));
+        self
+    }
+
+    /// Specify a function which is called once, after all tests have been run (whether they passed
+    /// or failed), to clean up anything created by [`before_all`](#method.before_all). It is also
+    /// called if `before_all` returns `Err`. In watch mode, it is called when watching stops. Note
+    /// that it is not called if the run is aborted by a fatal error (e.g. invalid test data).
+    pub fn after_all<F>(&'a mut self, after_all: F) -> &'a mut Self
+    where
+        F: 'static + FnOnce(),
+    {
+        self.after_all = Some(Box::new(after_all));
+        self
+    }
+
+    /// If specified, any command which runs for longer than `test_timeout` will be killed and the
+    /// test it is part of considered to have failed. Individual tests can override this with the
+    /// `timeout` key. By default, commands are allowed to run indefinitely.
+    pub fn test_timeout(&'a mut self, test_timeout: Duration) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().test_timeout = Some(test_timeout);
+        self
+    }
+
+    /// Set the environment variable `name` to `value` for every command run (including `Setup`
+    /// commands), e.g. `env("LANG", "C")` to make locale-dependent output predictable. Calling
+    /// this again with the same `name` replaces its value. In order of increasing precedence, a
+    /// command's environment is made up of: the tester's environment; variables set with this
+    /// function; variables set on the command returned by [`test_cmds`](#method.test_cmds); and
+    /// variables set with the `env-var` key. The `env-clear` key removes all but the last of
+    /// these.
+    pub fn env(&'a mut self, name: &str, value: &str) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner)
+            .unwrap()
+            .env
+            .insert(name.to_owned(), value.to_owned());
+        self
+    }
+
+    /// If specified, limit the resources each command can use, so that a runaway command can't
+    /// exhaust the machine: `memory_limit` is the maximum size, in bytes, of a command's address
+    /// space (`RLIMIT_AS`), and `cpu_limit` the maximum CPU time it can use (`RLIMIT_CPU`, rounded
+    /// up to a whole number of seconds). A command which exceeds `memory_limit` typically has its
+    /// allocations fail; one which exceeds `cpu_limit` is killed, and reported as such. Unlike
+    /// [`test_timeout`](#method.test_timeout), which limits the wall-clock time a command runs
+    /// for, `cpu_limit` applies to each process separately, and is enforced by the operating system
+    /// even on processes the command starts. Limits can be lowered, but not raised above the limits
+    /// `lang_tester` itself runs with. Limits are only supported on Unix. By default, commands'
+    /// resources are not limited.
+    pub fn resource_limits(
+        &'a mut self,
+        memory_limit: Option<u64>,
+        cpu_limit: Option<Duration>,
+    ) -> &'a mut Self {
+        let inner = Arc::get_mut(&mut self.inner).unwrap();
+        inner.memory_limit = memory_limit;
+        inner.cpu_limit = cpu_limit;
+        self
+    }
+
+    /// Specify the maximum number of tests which can be run concurrently. A value of 1 means that
+    /// tests are run sequentially. If not specified, defaults to the value of the
+    /// `RUST_TEST_THREADS` environment variable if it is set, or the number of logical CPUs
+    /// otherwise. Note that the `--test-threads` command-line option (see
+    /// [`use_cmdline_args`](#method.use_cmdline_args)) overrides this value.
+    pub fn test_threads(&'a mut self, test_threads: usize) -> &'a mut Self {
+        if test_threads == 0 {
+            fatal("Must specify more than 0 threads.");
+        }
+        Arc::get_mut(&mut self.inner).unwrap().test_threads = test_threads;
+        self
+    }
+
+    /// If set to `true`, commands' stderr and stdout are passed through to the terminal rather than
+    /// being captured. Since their output is not captured, `stderr`, `stdout`, and `output` tests
+    /// are skipped (with a warning), though `status` tests are still checked. Note that the
+    /// `--nocapture` command-line option (see [`use_cmdline_args`](#method.use_cmdline_args))
+    /// also enables this. Defaults to `false`.
+    pub fn nocapture(&'a mut self, nocapture: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().nocapture = nocapture;
+        self
+    }
+
+    /// If set to `true`, each command's program, arguments, working directory, and environment
+    /// overrides are printed to stderr before it is run, after `extra-args` have been split and
+    /// placeholders (e.g. `{testfile}`) expanded. This helps debug tests which behave unexpectedly
+    /// because their command line is not what was intended. Environment variables are printed
+    /// with their values unaltered unless [`redact_env`](#method.redact_env) is set. Note that
+    /// the `--verbose` command-line option (see [`use_cmdline_args`](#method.use_cmdline_args))
+    /// also enables this. Defaults to `false`.
+    pub fn verbose(&'a mut self, verbose: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().verbose = verbose;
+        self
+    }
+
+    /// Specify a function which, given the name and value of one of a command's environment
+    /// variables, returns the value to print in its place in [`verbose`](#method.verbose) mode,
+    /// so that e.g. secrets do not end up in logs:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .redact_env(|k, v| match k.ends_with("_TOKEN") {
+    ///         true => "<redacted>".to_owned(),
+    ///         false => v.to_owned(),
+    ///     })
+    ///     ...
+    /// ```
+    ///
+    /// The values the command is run with are unaffected.
+    pub fn redact_env<F>(&'a mut self, redact_env: F) -> &'a mut Self
+    where
+        F: 'static + Fn(&str, &str) -> String + Send + Sync,
+    {
+        Arc::get_mut(&mut self.inner).unwrap().redact_env = Some(Box::new(redact_env));
+        self
+    }
+
+    /// If set to `true`, tests can use the `cmd` key to replace a command returned by
+    /// [`test_cmds`](#method.test_cmds) with a command line of their own. Since this allows test
+    /// files to run arbitrary programs, it must be explicitly enabled: if it is not, a test which
+    /// uses `cmd` fails. Defaults to `false`.
+    pub fn allow_inline_cmd(&'a mut self, allow_inline_cmd: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().allow_inline_cmd = allow_inline_cmd;
+        self
+    }
+
+    /// If set to `true`, no new tests are started once a test has failed (though tests which are
+    /// already running are allowed to finish). The tests which were not run are reported as such.
+    /// Defaults to `false`.
+    pub fn fail_fast(&'a mut self, fail_fast: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().fail_fast = fail_fast;
+        self
+    }
+
+    /// If set to `true`, a test whose test data can't be parsed fails (with the parse errors as
+    /// the reason), and the remaining tests are run as normal. If set to `false`, the parse errors
+    /// are reported and the whole run is aborted, without any results being reported. Either way,
+    /// the process exits with a non-zero exit code. Defaults to `false`.
+    pub fn continue_on_parse_error(&'a mut self, continue_on_parse_error: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner)
+            .unwrap()
+            .continue_on_parse_error = continue_on_parse_error;
+        self
+    }
+
+    /// If set to `true`, a command whose `stderr`, `stdout`, or `output` (or `stderr-file` or
+    /// `stdout-file`) test fails has the expected output rewritten to its actual output, rather
+    /// than failing (though only if all its other sub-tests pass). Inline expected output is
+    /// rewritten in the test file itself, preserving the surrounding formatting and indentation;
+    /// `stderr-file`/`stdout-file` files are overwritten. The tests which were "blessed" in this
+    /// way are listed at the end of the test run. Since the actual output is written literally,
+    /// blessed output should be checked (e.g. with `git diff`), and wildcards reintroduced where
+    /// output is expected to vary. This requires that each line of a test file's test data (as
+    /// returned by the function passed to [`test_extract`](#method.test_extract)) is the end of
+    /// a line of the test file, with the lines in the same order and without gaps (as is the case
+    /// when test data is stored in a block of comments). Defaults to `true` if the
+    /// `LANG_TESTER_BLESS` environment variable is set to a value other than `0`, and `false`
+    /// otherwise.
+    pub fn bless(&'a mut self, bless: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().bless = bless;
+        self
+    }
+
+    /// If set to `true`, `stderr`, `stdout`, and `output` tests ignore differences in case
+    /// (including in regular expression lines) when matching. Defaults to `false`.
+    pub fn case_insensitive(&'a mut self, case_insensitive: bool) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Set the prefix which marks a line of test data as a comment (e.g. `//` for languages in
+    /// which `#` is significant). Comment lines are ignored wherever a command name or key could
+    /// appear. An empty prefix means that no lines are treated as comments. Defaults to `#`.
+    pub fn comment_prefix(&'a mut self, comment_prefix: &str) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().comment_prefix = comment_prefix.to_owned();
+        self
+    }
+
+    /// Specify default assertions for commands, as test data which specifies only commands, e.g.:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .default_cmd_assertions("Compiler:\n  status: success\n  stderr-not: warning: ...")
+    ///     ...
+    /// ```
+    ///
+    /// Each key of a command in `defaults` is merged into the command of the same name (ignoring
+    /// case) in each test, as if the test had specified it, unless the test specifies the key
+    /// itself, or a key which can't be specified alongside it (e.g. `stdout-exact` overrides a
+    /// default `stdout`, and `output` overrides all default `stderr` and `stdout` keys). Keys which
+    /// can be specified more than once (e.g. `env-var`) are overridden rather than added to.
+    /// Defaults are merged into every command that is run, including those for which a test has
+    /// no test data (though such commands are not passed to
+    /// [`test_cmds_with_tests`](#method.test_cmds_with_tests)). `defaults` is
+    /// parsed (using the [`comment_prefix`](#method.comment_prefix) and
+    /// [`min_indent`](#method.min_indent)) when the tests are run: if it can't be parsed, or
+    /// specifies anything other than commands, lang_tester exits with an error.
+    pub fn default_cmd_assertions(&'a mut self, defaults: &str) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner)
+            .unwrap()
+            .default_cmd_assertions = Some(defaults.to_owned());
+        self
+    }
+
+    /// Specify a function which parses the test data extracted from a test file (see
+    /// [`test_extract`](#method.test_extract)) in place of the built-in parser, allowing tests to
+    /// be specified in a different format (e.g. YAML or TOML). The function is passed the path of
+    /// the test file and its test data, and returns the [`Tests`](struct.Tests.html) it specifies
+    /// (with commands keyed by their lower-cased names), or `Err` with a message suitable for
+    /// showing to the user if the test data is malformed, e.g.:
+    ///
+    /// ```rust,ignore
+    /// LangTester::new()
+    ///     ...
+    ///     .test_parse(|p, test_str| {
+    ///         let spec = my_spec::parse(test_str)
+    ///             .map_err(|e| format!("{}: {}", p.display(), e))?;
+    ///         let mut tests = Tests::default();
+    ///         for cmd in spec.cmds {
+    ///             let mut testcmd = TestCmd::default();
+    ///             testcmd.status = cmd.status;
+    ///             testcmd.stdout = cmd.stdout.lines().collect();
+    ///             tests.tests.insert(cmd.name.to_lowercase(), testcmd);
+    ///         }
+    ///         Ok(tests)
+    ///     })
+    ///     ...
+    /// ```
+    ///
+    /// Malformed test data is treated in the same way as a parse error from the built-in parser
+    /// (see [`continue_on_parse_error`](#method.continue_on_parse_error)), as are invalid regular
+    /// expression lines, wildcard lines, and JSON values in the returned `Tests`.
+    /// [`comment_prefix`](#method.comment_prefix) and [`min_indent`](#method.min_indent) have no
+    /// effect, and [`default_cmd_assertions`](#method.default_cmd_assertions) can't be used.
+    /// [`bless`](#method.bless) only rewrites expected output whose location in the test data is
+    /// recorded in [`TestCmd::spans`](struct.TestCmd.html#structfield.spans).
+    pub fn test_parse<F>(&'a mut self, test_parse: F) -> &'a mut Self
+    where
+        F: 'static + for<'b> Fn(&Path, &'b str) -> Result<Tests<'b>, String> + Send + Sync,
+    {
+        Arc::get_mut(&mut self.inner).unwrap().test_parse = Some(Box::new(test_parse));
+        self
+    }
+
+    /// Require a command's keys to be indented by at least `min_indent` columns (where a tab counts
+    /// as one column) more than the command name, and a key's value by at least `min_indent`
+    /// columns more than the key. For example, with `min_indent(2)`, a key indented by a single
+    /// space relative to its command is an error rather than being silently accepted, which helps
+    /// catch test data whose indentation was mangled when it was extracted. Defaults to 1.
+    pub fn min_indent(&'a mut self, min_indent: usize) -> &'a mut Self {
+        if min_indent == 0 {
+            fatal("Must specify a minimum indentation of at least 1.");
+        }
+        Arc::get_mut(&mut self.inner).unwrap().min_indent = min_indent;
+        self
+    }
+
+    /// Only run the commands (as returned by the function passed to
+    /// [`test_cmds`](#method.test_cmds)) whose names are in `only_cmds` (ignoring case), skipping
+    /// all others. For example, `only_cmds(&["Compiler"])` runs only the compiler step of each
+    /// test. Skipped commands are reported as such rather than as failures. A `Setup` command is
+    /// always run, since other commands may depend on it; a command which takes its stdin from a
+    /// skipped command (see `stdin-from`) is also skipped. Defaults to running all commands.
+    pub fn only_cmds(&'a mut self, only_cmds: &[&str]) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().only_cmds =
+            Some(only_cmds.iter().map(|x| x.to_lowercase()).collect());
+        self
+    }
+
+    /// Treat a command which is terminated by any of the signals `ignore_signals` (given as signal
+    /// numbers, e.g. `libc::SIGPIPE`) as having completed successfully, so that e.g. a program
+    /// which is killed by `SIGPIPE` when the consumer of its output exits early passes `status:
+    /// success` (and fails `status: error`). Explicit checks for signals (e.g. `status:
+    /// signal(SIGPIPE)`) are unaffected. This has no effect on platforms without signals. Defaults
+    /// to no signals.
+    pub fn ignore_signals(&'a mut self, ignore_signals: &[i32]) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().ignore_signals = ignore_signals.to_vec();
+        self
+    }
+
+    /// If set to a value greater than 0, the total time taken to run all tests, and the
+    /// `report_slowest` slowest tests (along with how long each of their commands took to run), are
+    /// reported after all tests have been run. This information is only reported by output
+    /// formats which write human readable output (i.e. not
+    /// [`OutputFormat::Json`](enum.OutputFormat.html#variant.Json) or
+    /// [`OutputFormat::Tap`](enum.OutputFormat.html#variant.Tap)). Defaults to 0.
+    pub fn report_slowest(&'a mut self, report_slowest: usize) -> &'a mut Self {
+        self.report_slowest = report_slowest;
+        self
+    }
+
+    /// Limit the output shown in failure reports to the first and last `max_output_lines` lines of
+    /// each command's stderr and stdout (and of each diff between a pattern and the output),
+    /// with a `... (n lines omitted) ...` line in place of the rest. This keeps failure reports
+    /// readable when a command writes huge amounts of unexpected output. Only human readable
+    /// output is truncated: the JSON, TAP, and JUnit XML [`OutputFormat`](enum.OutputFormat.html)s
+    /// (the last of which is written to a file), and
+    /// [`on_test_result`](#method.on_test_result), are always given a command's full output.
+    /// Defaults to showing output in full.
+    pub fn max_output_lines(&'a mut self, max_output_lines: usize) -> &'a mut Self {
+        self.max_output_lines = Some(max_output_lines);
+        self
+    }
+
+    /// If `Some(seed)`, tests are started in an order shuffled deterministically from `seed`,
+    /// which can help uncover tests which (unintentionally) depend on other tests having been run
+    /// first. The seed is reported after all tests have been run, so that a failing order can be
+    /// reproduced. If `None`, tests are started in the order of their paths. Defaults to `None`.
+    pub fn shuffle(&'a mut self, shuffle: Option<u64>) -> &'a mut Self {
+        self.shuffle = shuffle;
+        self
+    }
+
+    /// If set to `true`, [`run`](#method.run) lists each test, and the names of the commands it
+    /// specifies, on `stdout` rather than running it. No commands are executed, but every test is
+    /// parsed, so this is a quick way of checking a test suite for errors: any errors are reported
+    /// on `stderr` and cause the process to exit with a non-zero exit code. Note that the `--list`
+    /// command-line option (see [`use_cmdline_args`](#method.use_cmdline_args)) also enables
+    /// this. Defaults to `false`.
+    pub fn list(&'a mut self, list: bool) -> &'a mut Self {
+        self.list = list;
+        self
+    }
+
+    /// If set, the test data extracted from each test file (see
+    /// [`test_extract`](#method.test_extract) and friends) is cached in the directory `cache_dir`
+    /// (which is created if it doesn't exist). On subsequent runs, test files whose modification
+    /// time and size are unchanged are neither read nor passed to the extraction function (though
+    /// their commands are still run as normal). Since the extraction function itself can't be
+    /// compared from run to run, you must delete `cache_dir` if you change it. Defaults to no
+    /// cache.
+    pub fn cache_dir(&'a mut self, cache_dir: PathBuf) -> &'a mut Self {
+        self.cache_dir = Some(cache_dir);
+        self
+    }
+
+    /// If set to `true`, after the tests have been run they are rerun whenever files in the test
+    /// directories (or in any paths specified with [`watch_path`](#method.watch_path)) change,
+    /// until the user presses Ctrl-C. If only test files have changed, only those tests are rerun;
+    /// otherwise every test is rerun. Changes are detected by periodically checking files'
+    /// modification times and sizes, and tests are only rerun once files have stopped changing, so
+    /// that an editor saving several files at once causes only a single rerun. If stderr is a
+    /// terminal, the screen is cleared before each rerun. Pressing Ctrl-C while tests are running
+    /// stops any further tests from being started. When the user presses Ctrl-C, the process exits
+    /// with a failure status if the most recent run had failures. Defaults to `false`.
+    pub fn watch(&'a mut self, watch: bool) -> &'a mut Self {
+        self.watch = watch;
+        self
+    }
+
+    /// Specify a file or directory which, in [`watch`](#method.watch) mode, is watched for changes
+    /// in addition to the test directories: a change to any file within it causes every test to be
+    /// rerun. This is typically used for the source code of the compiler or VM being tested. This
+    /// can be called more than once.
+    pub fn watch_path(&'a mut self, watch_path: PathBuf) -> &'a mut Self {
+        self.watch_paths.push(watch_path);
+        self
+    }
+
+    /// If set to `true`, the number of tests which have completed is reported while tests are
+    /// running. If stderr is a terminal, a single progress line is continually updated; otherwise
+    /// a progress line is written every few seconds. Progress is only reported by
+    /// [`OutputFormat::Pretty`](enum.OutputFormat.html#variant.Pretty), and never when
+    /// [`nocapture`](#method.nocapture) is set. Defaults to `true`.
+    pub fn progress(&'a mut self, progress: bool) -> &'a mut Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Specify the style of the human readable output written to `stderr`: what is written when a
+    /// test passes or fails, and whether output is coloured. Machine readable output formats (see
+    /// [`output_format`](#method.output_format)) are unaffected. Defaults to
+    /// [`ReporterStyle::default`](struct.ReporterStyle.html), which matches `cargo test` and
+    /// respects the `NO_COLOR` environment variable.
+    pub fn reporter_style(&'a mut self, reporter_style: ReporterStyle) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().reporter_style = reporter_style;
+        self
+    }
+
+    /// Specify the format in which test results are reported. Defaults to
+    /// [`OutputFormat::Pretty`](enum.OutputFormat.html#variant.Pretty).
+    pub fn output_format(&'a mut self, output_format: OutputFormat) -> &'a mut Self {
+        Arc::get_mut(&mut self.inner).unwrap().output_format = output_format;
+        self
+    }
+
+    /// If set to `true`, this reads arguments from `std::env::args()` and interprets them in the
+    /// same way as normal cargo test files. For example if you have tests "ab" and "cd" but only
+    /// want to run the latter:
+    ///
+    /// ```sh
+    /// $ <test bin> c
+    /// ```
+    ///
+    /// As this suggests, a simple substring search is used to decide which tests to run.
+    ///
+    /// You can get help on `lang_tester`'s options:
+    ///
+    /// ```sh
+    /// $ <test bin> --help
+    /// ```
+    ///
+    /// This option defaults to `true`.
+    pub fn use_cmdline_args(&'a mut self, use_cmdline_args: bool) -> &'a mut Self {
+        self.use_cmdline_args = use_cmdline_args;
+        self
+    }
+
+    /// Make sure the user has specified the minimum set of things we need from them.
+    fn validate(&self) {
+        if self.test_dirs.is_empty() {
+            fatal("test_dir or test_dirs must be specified.");
+        }
+        if self.inner.test_extract.is_none()
+            && self.inner.test_extract_with_path.is_none()
+            && self.inner.test_extract_many.is_none()
+        {
+            fatal("test_extract, test_extract_with_path, or test_extract_many must be specified.");
+        }
+        if self.inner.test_cmds.is_none() {
+            fatal(
+                "test_cmds, test_cmds_fallible, test_cmds_with_tests, or test_cmds_with_tempdir \
+                 must be specified.",
+            );
+        }
+        if let Some(ref defaults) = self.inner.default_cmd_assertions {
+            if self.inner.test_parse.is_some() {
+                fatal("default_cmd_assertions can't be used with test_parse.");
+            }
+            match parse_with_min_indent(defaults, &self.inner.comment_prefix, self.inner.min_indent)
+            {
+                Ok(tests)
+                    if tests.ignore.is_none()
+                        && tests.ignore_if.is_none()
+                        && tests.xfail.is_none()
+                        && tests.tags.is_empty() => {}
+                Ok(_) => fatal("default_cmd_assertions can only specify commands."),
+                Err(errs) => {
+                    let msgs = errs.iter().map(|e| e.msg()).collect::<Vec<_>>();
+                    fatal(&format!(
+                        "Couldn't parse default_cmd_assertions:\n  {}",
+                        msgs.join("\n  ")
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Enumerate all the test files we need to check, along with the number of files filtered out
+    /// (e.g. if you have tests `a, b, c` and the user does something like `cargo test b`, 2 tests
+    /// (`a` and `c`) will be filtered out. Each test file is returned as a pair `(test name, path)`.
+    fn test_files(&self) -> (Vec<(String, PathBuf)>, usize) {
+        let mut num_filtered = 0;
+        // The `(test name, path, test directory)` of each test file.
+        let mut files = Vec::new();
+        for test_dir in &self.test_dirs {
+            let mut walker = WalkDir::new(test_dir);
+            if !self.recursive {
+                walker = walker.max_depth(1);
+            }
+            let dir_files = walker
+                .into_iter()
+                // The test directory itself is never skipped, even if its name looks hidden.
+                .filter_entry(|x| self.include_hidden || x.depth() == 0 || !is_hidden(x.path()))
+                .filter_map(|x| x.ok())
+                .filter(|x| x.file_type().is_file())
+                // Filter out non-test files
+                .filter(|x| match self.test_file_filter.as_ref() {
+                    Some(f) => f(x.path()),
+                    None => true,
+                })
+                .filter(|x| match self.test_file_glob.as_ref() {
+                    Some(re) => {
+                        let rel_path = x.path().strip_prefix(test_dir).unwrap();
+                        let components = rel_path
+                            .components()
+                            .map(|c| c.as_os_str().to_string_lossy())
+                            .collect::<Vec<_>>();
+                        re.is_match(&components.join("/"))
+                    }
+                    None => true,
+                })
+                .map(|x| (test_name(test_dir, x.path()), x.into_path(), test_dir));
+            files.extend(dir_files);
+        }
+        // Tests in different directories with the same name are disambiguated by including their
+        // directory in their name.
+        let mut name_counts = HashMap::new();
+        for (name, _, _) in &files {
+            *name_counts.entry(name.clone()).or_insert(0) += 1;
+        }
+        let mut paths = files
+            .into_iter()
+            .map(|(name, p, test_dir)| {
+                if name_counts[&name] > 1 {
+                    (test_dir.join(&name).to_str().unwrap().to_owned(), p)
+                } else {
+                    (name, p)
+                }
+            })
+            // If the user has specified a filter, or named one or more tests on the command-line,
+            // run only those, filtering out the rest (counting them as ignored).
+            .filter(|(name, p)| {
+                if let Some(f) = self.filter.as_ref() {
+                    if f(name) {
+                        return true;
+                    }
+                    num_filtered += 1;
+                    return false;
+                }
+                let x_path = p.to_str().unwrap();
+                match self.cmdline_filters.as_ref() {
+                    Some(fs) => {
+                        debug_assert!(self.use_cmdline_args);
+                        for f in fs {
+                            if x_path.contains(f) {
+                                return true;
+                            }
+                        }
+                        num_filtered += 1;
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .collect::<Vec<_>>();
+        // The order `WalkDir` returns files in is platform dependent.
+        paths.sort_by(|(_, p1), (_, p2)| p1.cmp(p2));
+        (paths, num_filtered)
+    }
+
+    /// Read each test file in `test_files` and extract its test data, returning a vector of `(test
+    /// name, path, test data)` tuples (one per test: if `test_extract_many` was specified, a test
+    /// file may contain any number of tests).
+    fn extract_tests(&self, test_files: Vec<(String, PathBuf)>) -> Vec<(String, PathBuf, String)> {
+        let many = self.inner.test_extract_many.is_some();
+        let mut cache = self
+            .cache_dir
+            .as_ref()
+            .map(|d| Cache::load(d, if many { "many" } else { "single" }));
+        let mut tests = Vec::new();
+        // The names of the test files without test data, if `require_test_block` is set.
+        let mut missing = Vec::new();
+        for (test_fname, p) in test_files {
+            // Each file is extracted into `(block name, test data)` pairs: if `test_extract_many`
+            // wasn't specified, there is exactly one pair, with an empty block name.
+            let blocks = match cache.as_ref().and_then(|c| c.get(&p)) {
+                Some(blocks) => blocks.to_vec(),
+                None => {
+                    // The key is taken before the file is read, so that if the file is changed
+                    // while being read, it will be extracted again next time.
+                    let key = cache::file_key(&p);
+                    let all_str = read_to_string(p.as_path())
+                        .unwrap_or_else(|_| fatal(&format!("Couldn't read {}", test_fname)));
+                    let blocks = match self.inner.test_extract_many {
+                        Some(ref f) => f(&all_str),
+                        None => {
+                            let test_str = match self.inner.test_extract_with_path.as_ref() {
+                                Some(f) => f(p.as_path(), &all_str),
+                                None => self.inner.test_extract.as_ref().unwrap()(&all_str),
+                            }
+                            .unwrap_or_else(|| {
+                                fatal(&format!("Couldn't extract test string from {}", test_fname))
+                            });
+                            vec![(String::new(), test_str)]
+                        }
+                    };
+                    if let (Some(cache), Some(key)) = (cache.as_mut(), key) {
+                        cache.insert(&p, key, blocks.clone());
+                    }
+                    blocks
+                }
+            };
+            if self.require_test_block
+                && (blocks.is_empty()
+                    || blocks
+                        .iter()
+                        .any(|(_, test_str)| self.is_empty_test(&p, test_str)))
+            {
+                missing.push(test_fname.clone());
+            }
+            for (block_name, test_str) in blocks {
+                let name = if many {
+                    format!("{}::{}", test_fname, block_name)
+                } else {
+                    test_fname.clone()
+                };
+                tests.push((name, p.clone(), test_str));
+            }
+        }
+        if let Some(cache) = cache {
+            cache.save();
+        }
+        if !missing.is_empty() {
+            fatal(&format!(
+                "No test data found in (see LangTester::require_test_block):\n    {}",
+                missing.join("\n    ")
+            ));
+        }
+        tests
+    }
+
+    /// Does the test data `test_str` (extracted from the test file `path`) specify nothing (i.e.
+    /// it is blank, or consists only of comments)? Test data which doesn't parse isn't considered
+    /// empty, since its errors are reported when the test is run.
+    fn is_empty_test(&self, path: &Path, test_str: &str) -> bool {
+        match self.inner.parse(path, test_str) {
+            Ok(tests) => {
+                tests.tests.is_empty()
+                    && tests.ignore.is_none()
+                    && tests.ignore_if.is_none()
+                    && tests.xfail.is_none()
+                    && tests.tags.is_empty()
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Remove from `test_files` the tests not selected by
+    /// [`filter_tags`](#method.filter_tags), returning the number removed. Test data which doesn't
+    /// parse is kept, so that its errors are reported when the test is run.
+    fn retain_tagged(&self, test_files: &mut Vec<(String, PathBuf, String)>) -> usize {
+        if self.include_tags.is_empty() && self.exclude_tags.is_empty() {
+            return 0;
+        }
+        let len = test_files.len();
+        test_files.retain(|(_, p, test_str)| match self.inner.parse(p, test_str) {
+            Ok(tests) => {
+                (self.include_tags.is_empty()
+                    || tests.tags.iter().any(|t| self.include_tags.contains(t)))
+                    && !tests.tags.iter().any(|t| self.exclude_tags.contains(t))
+            }
+            Err(_) => true,
+        });
+        len - test_files.len()
+    }
+
+    /// Run all the lang tests.
+    pub fn run(&mut self) {
+        self.validate();
+        if self.use_cmdline_args {
+            let args: Vec<String> = env::args().collect();
+            let matches = Options::new()
+                .optflag("h", "help", "")
+                .optflag("", "ignored", "Run only ignored tests")
+                .optflag(
+                    "",
+                    "list",
+                    "List tests and their commands without running them",
+                )
+                .optflag(
+                    "",
+                    "nocapture",
+                    "Pass command stderr/stdout through to the terminal",
+                )
+                .optflag("", "verbose", "Print each command line before running it")
+                .optopt(
+                    "",
+                    "shuffle-seed",
+                    "Run tests in an order shuffled from the given seed",
+                    "seed",
+                )
+                .optopt(
+                    "",
+                    "test-threads",
+                    "Number of threads used for running tests in parallel",
+                    "n_threads",
+                )
+                .parse(&args[1..])
+                .unwrap_or_else(|_| usage());
+            if matches.opt_present("h") {
+                usage();
+            }
+            if matches.opt_present("ignored") {
+                Arc::get_mut(&mut self.inner).unwrap().ignored = true;
+            }
+            if matches.opt_present("list") {
+                self.list = true;
+            }
+            if matches.opt_present("nocapture") {
+                Arc::get_mut(&mut self.inner).unwrap().nocapture = true;
+            }
+            if matches.opt_present("verbose") {
+                Arc::get_mut(&mut self.inner).unwrap().verbose = true;
+            }
+            if let Some(s) = matches.opt_str("shuffle-seed") {
+                self.shuffle = Some(s.parse::<u64>().unwrap_or_else(|_| usage()));
+            }
+            if let Some(s) = matches.opt_str("test-threads") {
+                let test_threads = s.parse::<usize>().unwrap_or_else(|_| usage());
+                if test_threads == 0 {
+                    fatal("Must specify more than 0 threads.");
+                }
+                Arc::get_mut(&mut self.inner).unwrap().test_threads = test_threads;
+            }
+            if !matches.free.is_empty() {
+                self.cmdline_filters = Some(matches.free);
+            }
+        }
+        let (mut test_files, mut num_filtered) = self.test_files();
+        if let Some(seed) = self.shuffle {
+            shuffle(&mut test_files, seed);
+        }
+        let mut test_files = self.extract_tests(test_files);
+        num_filtered += self.retain_tagged(&mut test_files);
+        if self.list {
+            self.list_tests(&test_files);
+            return;
+        }
+        if let Some(before_all) = self.before_all.take() {
+            if let Err(msg) = before_all() {
+                if let Some(after_all) = self.after_all.take() {
+                    after_all();
+                }
+                fatal(&format!("before_all failed: {}", msg));
+            }
+        }
+        let passed = if self.watch {
+            install_interrupt_handler();
+            let passed = self.run_test_files(test_files, num_filtered);
+            self.watch_tests(passed)
+        } else {
+            self.run_test_files(test_files, num_filtered)
+        };
+        if let Some(after_all) = self.after_all.take() {
+            after_all();
+        }
+        if !passed {
+            process::exit(1);
+        }
+    }
+
+    /// Run the tests in `test_files` and report the results, returning `true` if they all passed.
+    fn run_test_files(
+        &mut self,
+        test_files: Vec<(String, PathBuf, String)>,
+        num_filtered: usize,
+    ) -> bool {
+        if self.inner.output_format.is_pretty() {
+            eprint!("\nrunning {} tests", test_files.len());
+            if self.progress && !self.inner.nocapture {
+                Arc::get_mut(&mut self.inner).unwrap().progress = Some(Mutex::new(Progress {
+                    total: test_files.len(),
+                    completed: 0,
+                    terminal: io::stderr().is_terminal(),
+                    shown: false,
+                    last_written: Instant::now(),
+                }));
+            }
+        } else {
+            output::write_start(&self.inner.output_format, test_files.len());
+        }
+        let test_files_len = test_files.len();
+        let run_start = Instant::now();
+        let (failures, num_ignored, num_not_run) = test_file(test_files, Arc::clone(&self.inner));
+        let run_duration = run_start.elapsed();
+        self.inner.hide_progress(&mut io::stderr().lock());
+
+        if let OutputFormat::JUnitXml { ref path } = self.inner.output_format {
+            output::write_junit(
+                path,
+                &mut self.inner.junit_suites.lock().unwrap(),
+                run_duration,
+            );
+        }
+        match self.inner.output_format {
+            OutputFormat::Pretty | OutputFormat::JUnitXml { .. } => {
+                self.pp_failures(
+                    &failures,
+                    test_files_len,
+                    num_ignored,
+                    num_filtered,
+                    num_not_run,
+                );
+                if self.report_slowest > 0 {
+                    self.pp_slowest(run_duration);
+                }
+            }
+            _ => output::write_summary(
+                &self.inner.output_format,
+                test_files_len - failures.len() - num_ignored - num_not_run,
+                failures.len(),
+                num_ignored,
+                num_filtered,
+                num_not_run,
+            ),
+        }
+
+        failures.is_empty()
+    }
+
+    /// After the tests have been run once, watch the test directories (and any paths specified
+    /// with `watch_path`) for changes, rerunning the affected tests after each change, until the
+    /// user interrupts us. `passed` is whether the initial run passed. Returns whether the most
+    /// recent run passed.
+    fn watch_tests(&mut self, mut passed: bool) -> bool {
+        let mut snapshot = self.watch_snapshot();
+        let mut test_paths = self
+            .test_files()
+            .0
+            .into_iter()
+            .map(|(_, p)| p)
+            .collect::<Vec<_>>();
+        loop {
+            sleep(Duration::from_millis(WATCH_INTERVAL));
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                return passed;
+            }
+            let mut new_snapshot = self.watch_snapshot();
+            if new_snapshot == snapshot {
+                continue;
+            }
+            // Editors often save a file in several steps, so we wait until files have stopped
+            // changing before rerunning anything.
+            loop {
+                sleep(Duration::from_millis(WATCH_INTERVAL));
+                if INTERRUPTED.load(Ordering::SeqCst) {
+                    return passed;
+                }
+                let settled = self.watch_snapshot();
+                if settled == new_snapshot {
+                    break;
+                }
+                new_snapshot = settled;
+            }
+            let changed = new_snapshot
+                .iter()
+                .filter(|(p, key)| snapshot.get(*p) != Some(key))
+                .map(|(p, _)| p.clone())
+                .collect::<HashSet<_>>();
+            let deleted = snapshot
+                .keys()
+                .filter(|p| !new_snapshot.contains_key(*p))
+                .cloned()
+                .collect::<Vec<_>>();
+            snapshot = new_snapshot;
+
+            // If only test files have changed, only those tests are rerun; if anything else has
+            // changed (e.g. an `stdout-file`, or a source file specified with `watch_path`), we
+            // can't know which tests are affected, so all of them are rerun. Deleting a test file
+            // doesn't affect any other test.
+            let (mut test_files, mut num_filtered) = self.test_files();
+            let all = changed
+                .iter()
+                .any(|p| !test_files.iter().any(|(_, test_p)| test_p == p))
+                || deleted.iter().any(|p| !test_paths.contains(p));
+            test_paths = test_files.iter().map(|(_, p)| p.clone()).collect();
+            if !all {
+                test_files.retain(|(_, p)| changed.contains(p));
+            }
+            if test_files.is_empty() {
+                continue;
+            }
+            if let Some(seed) = self.shuffle {
+                shuffle(&mut test_files, seed);
+            }
+            let mut test_files = self.extract_tests(test_files);
+            num_filtered += self.retain_tagged(&mut test_files);
+            if test_files.is_empty() {
+                continue;
+            }
+            if io::stderr().is_terminal() {
+                // Clear the screen and move the cursor to its top-left.
+                eprint!("\x1b[2J\x1b[H");
+            }
+            Arc::get_mut(&mut self.inner).unwrap().reset();
+            passed = self.run_test_files(test_files, num_filtered);
+        }
+    }
+
+    /// Return the `FileKey` of every file under the test directories and the paths specified with
+    /// `watch_path`, other than those in `cache_dir` (which we write to ourselves).
+    fn watch_snapshot(&self) -> HashMap<PathBuf, cache::FileKey> {
+        let mut snapshot = HashMap::new();
+        let paths = self
+            .test_dirs
+            .iter()
+            .map(|p| p.as_ref())
+            .chain(self.watch_paths.iter().map(|p| p.as_path()));
+        for path in paths {
+            let files = WalkDir::new(path)
+                .into_iter()
+                .filter_entry(|x| self.include_hidden || x.depth() == 0 || !is_hidden(x.path()))
+                .filter_map(|x| x.ok())
+                .filter(|x| x.file_type().is_file())
+                .filter(|x| match self.cache_dir {
+                    Some(ref cache_dir) => !x.path().starts_with(cache_dir),
+                    None => true,
+                });
+            for x in files {
+                if let Some(key) = cache::file_key(x.path()) {
+                    snapshot.insert(x.into_path(), key);
+                }
+            }
+        }
+        snapshot
+    }
+
+    /// Print each test in `test_files`, and the names of the commands it specifies (in the order
+    /// they would be run), to `stdout`. Any errors in the tests are printed to `stderr`, after
+    /// which the process exits with a non-zero exit code.
+    fn list_tests(&self, test_files: &[(String, PathBuf, String)]) {
+        let mut num_errors = 0;
+        for (test_fname, path, test_str) in test_files {
+            let tests = match self.inner.parse(path, test_str) {
+                Ok(tests) => tests,
+                Err(errs) => {
+                    for e in &errs {
+                        eprintln!("lang_tests::{}: {}", test_fname, e);
+                    }
+                    num_errors += errs.len();
+                    continue;
+                }
+            };
+            let tempdir = if self.inner.test_cmds_tempdir {
+                Some(create_tempdir())
+            } else {
+                None
+            };
+            let cmd_pairs =
+                self.inner.test_cmds.as_ref().unwrap()(path.as_path(), &tests, tempdir.as_deref());
+            if let Some(tempdir) = tempdir {
+                fs::remove_dir_all(tempdir).ok();
+            }
+            let mut cmd_names = match cmd_pairs {
+                Ok(cmd_pairs) => cmd_pairs
+                    .into_iter()
+                    .map(|(cmd_name, _)| cmd_name.to_lowercase())
+                    .collect::<Vec<_>>(),
+                Err(msg) => {
+                    eprintln!("lang_tests::{}: {}", test_fname, msg);
+                    num_errors += 1;
+                    continue;
+                }
+            };
+            cmd_names.sort_by_key(|cmd_name| cmd_name != SETUP_CMD);
+            let mut unknown = tests
+                .tests
+                .keys()
+                .filter(|k| !cmd_names.contains(k))
+                .map(|k| k.as_str())
+                .collect::<Vec<_>>();
+            if !unknown.is_empty() {
+                unknown.sort_unstable();
+                eprintln!(
+                    "lang_tests::{}: command name(s) '{}' in tests are not found in the actual \
+                     commands.",
+                    test_fname,
+                    unknown.join(", ")
+                );
+                num_errors += 1;
+                continue;
+            }
+            cmd_names.retain(|cmd_name| tests.tests.contains_key(cmd_name));
+            println!("lang_tests::{}: {}", test_fname, cmd_names.join(", "));
+        }
+        println!(
+            "\n{} {}",
+            test_files.len(),
+            if test_files.len() == 1 {
+                "test"
+            } else {
+                "tests"
+            }
+        );
+        if num_errors > 0 {
+            eprintln!(
+                "\n{} {} found",
+                num_errors,
+                if num_errors == 1 { "error" } else { "errors" }
+            );
+            process::exit(1);
+        }
+    }
+
+    /// Pretty print the total time taken to run all tests (`run_duration`), and the slowest tests,
+    /// to `stderr`.
+    fn pp_slowest(&self, run_duration: Duration) {
+        let mut timings = self.inner.timings.lock().unwrap();
+        timings.sort_by_key(|t| Reverse(t.duration));
+        eprintln!("total time: {}; slowest tests:", duration_str(run_duration));
+        for t in timings.iter().take(self.report_slowest) {
+            let cmds = t
+                .cmds
+                .iter()
+                .map(|(cmd_name, d)| format!("{} {}", cmd_name, duration_str(*d)))
+                .collect::<Vec<_>>();
+            eprintln!(
+                "    lang_tests::{} {} ({})",
+                t.name,
+                duration_str(t.duration),
+                cmds.join(", ")
+            );
+        }
+        eprintln!();
+    }
+
+    /// Pretty print any failures to `stderr`.
+    fn pp_failures(
+        &self,
+        failures: &[(String, TestFailure)],
+        test_files_len: usize,
+        num_ignored: usize,
+        num_filtered: usize,
+        num_not_run: usize,
+    ) {
+        if !failures.is_empty() {
+            eprintln!("\n\nfailures:");
+            for (test_fname, test) in failures {
                 if let Some(ref status) = test.status {
                     eprintln!("\n---- lang_tests::{} status ----\n{}", test_fname, status);
                 }
-                if let Some(ref stderr) = test.stderr {
-                    eprintln!(
-                        "\n---- lang_tests::{} stderr ----\n{}\n",
-                        test_fname, stderr
-                    );
+                let streams = [
+                    (
+                        "stderr",
+                        &test.stderr,
+                        &test.stderr_pattern,
+                        &test.stderr_forbidden,
+                        &test.stderr_missing,
+                        &test.stderr_unordered,
+                        &test.stderr_mismatch,
+                        &test.stderr_line_count,
+                        &test.stderr_byte_count,
+                    ),
+                    (
+                        "stdout",
+                        &test.stdout,
+                        &test.stdout_pattern,
+                        &test.stdout_forbidden,
+                        &test.stdout_missing,
+                        &test.stdout_unordered,
+                        &test.stdout_mismatch,
+                        &test.stdout_line_count,
+                        &test.stdout_byte_count,
+                    ),
+                    (
+                        "output",
+                        &test.output,
+                        &test.output_pattern,
+                        &None,
+                        &None,
+                        &None,
+                        &None,
+                        &None,
+                        &None,
+                    ),
+                ];
+                for (
+                    name,
+                    actual,
+                    pattern,
+                    forbidden,
+                    missing,
+                    unordered,
+                    mismatch,
+                    line_count,
+                    byte_count,
+                ) in streams
+                {
+                    if let Some(forbidden) = forbidden {
+                        eprintln!(
+                            "\n---- lang_tests::{} {}-not ----\n{}",
+                            test_fname, name, forbidden
+                        );
+                    }
+                    if let Some(missing) = missing {
+                        eprintln!(
+                            "\n---- lang_tests::{} {}-contains ----\n{}",
+                            test_fname, name, missing
+                        );
+                    }
+                    if let Some(unordered) = unordered {
+                        eprintln!(
+                            "\n---- lang_tests::{} {}-contains-ordered ----\n{}",
+                            test_fname, name, unordered
+                        );
+                    }
+                    if let Some((key, mismatch)) = mismatch {
+                        eprintln!(
+                            "\n---- lang_tests::{} {} ----\n{}",
+                            test_fname, key, mismatch
+                        );
+                    }
+                    if let Some(line_count) = line_count {
+                        eprintln!(
+                            "\n---- lang_tests::{} {}-lines ----\n{}",
+                            test_fname, name, line_count
+                        );
+                    }
+                    if let Some(byte_count) = byte_count {
+                        eprintln!(
+                            "\n---- lang_tests::{} {}-bytes ----\n{}",
+                            test_fname, name, byte_count
+                        );
+                    }
+                    let actual = match actual {
+                        Some(actual) => actual,
+                        None => continue,
+                    };
+                    match pattern {
+                        Some(pattern) => {
+                            eprintln!(
+                                "\n---- lang_tests::{} {} (-expected +actual) ----",
+                                test_fname, name
+                            );
+                            let raw = match name {
+                                "stderr" => test.stderr_raw,
+                                "stdout" => test.stdout_raw,
+                                _ => false,
+                            };
+                            write_diff(
+                                pattern,
+                                actual,
+                                raw,
+                                self.inner.case_insensitive,
+                                self.max_output_lines,
+                                self.inner.reporter_style.colour,
+                            );
+                            eprintln!();
+                        }
+                        None => eprintln!(
+                            "\n---- lang_tests::{} {} ----\n{}\n",
+                            test_fname,
+                            name,
+                            output::truncate_lines(actual, self.max_output_lines)
+                        ),
+                    }
+                }
+                if let Some(ref error) = test.output_file_error {
+                    eprintln!(
+                        "\n---- lang_tests::{} output-file ----\n{}",
+                        test_fname, error
+                    );
+                }
+                if let Some((ref path, ref pattern, ref contents)) = test.output_file_mismatch {
+                    eprintln!(
+                        "\n---- lang_tests::{} output-file {} (-expected +actual) ----",
+                        test_fname,
+                        path.display()
+                    );
+                    write_diff(
+                        pattern,
+                        contents,
+                        false,
+                        self.inner.case_insensitive,
+                        self.max_output_lines,
+                        self.inner.reporter_style.colour,
+                    );
+                    eprintln!();
+                }
+                if let Some(ref artifacts) = test.artifacts {
+                    eprintln!(
+                        "\n---- lang_tests::{} artifacts ----\n{}",
+                        test_fname,
+                        artifacts.display()
+                    );
+                }
+            }
+            eprintln!("\nfailures:");
+            for (test_fname, _) in failures {
+                eprint!("    lang_tests::{}", test_fname);
+            }
+        }
+
+        let mut flaky = self.inner.flaky.lock().unwrap();
+        if !flaky.is_empty() {
+            flaky.sort();
+            eprint!("\n\nflaky (passed after being retried):");
+            for (test_fname, cmd_name, retries) in flaky.iter() {
+                eprint!(
+                    "\n    lang_tests::{} {}: {} {}",
+                    test_fname,
+                    cmd_name,
+                    retries,
+                    if *retries == 1 { "retry" } else { "retries" }
+                );
+            }
+        }
+
+        let mut blessed = self.inner.blessed.lock().unwrap();
+        if !blessed.is_empty() {
+            blessed.sort();
+            eprint!("\n\nblessed (expected output updated):");
+            for test_fname in blessed.iter() {
+                eprint!("\n    lang_tests::{}", test_fname);
+            }
+        }
+
+        let mut xfailed = self.inner.xfailed.lock().unwrap();
+        if !xfailed.is_empty() {
+            xfailed.sort();
+            eprint!("\n\nxfail (failed as expected):");
+            for (test_fname, reason) in xfailed.iter() {
+                eprint!("\n    lang_tests::{}", test_fname);
+                if !reason.is_empty() {
+                    eprint!(" ({})", reason);
+                }
+            }
+        }
+
+        let mut xpassed = self.inner.xpassed.lock().unwrap();
+        if !xpassed.is_empty() {
+            xpassed.sort();
+            eprint!("\n\nxpass (marked xfail but passed):");
+            for (test_fname, reason) in xpassed.iter() {
+                eprint!("\n    lang_tests::{}", test_fname);
+                if !reason.is_empty() {
+                    eprint!(" ({})", reason);
+                }
+            }
+        }
+
+        eprint!("\n\ntest result: ");
+        if failures.is_empty() {
+            write_with_colour("ok", Color::Green, &self.inner.reporter_style);
+        } else {
+            write_with_colour("FAILED", Color::Red, &self.inner.reporter_style);
+        }
+        eprintln!(
+            ". {} passed; {} failed; {} ignored; 0 measured; {} filtered out\n",
+            test_files_len - failures.len() - num_ignored - num_not_run,
+            failures.len(),
+            num_ignored,
+            num_filtered
+        );
+        if let Some(seed) = self.shuffle {
+            eprintln!("tests were shuffled with seed {}\n", seed);
+        }
+        if num_not_run > 0 {
+            if INTERRUPTED.load(Ordering::SeqCst) {
+                eprintln!("interrupted: {} tests not run\n", num_not_run);
+            } else {
+                eprintln!(
+                    "stopped after first failure (fail fast): {} tests not run\n",
+                    num_not_run
+                );
+            }
+        }
+    }
+}
+
+/// The status of an executed command. A `Status` can be converted to (with `Display`) and from
+/// (with `FromStr`) the textual form accepted by the `status` key, and any `Status` which can be
+/// parsed from test data is displayed as text which parses back to the same `Status`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Status {
+    /// The command exited successfully (by whatever definition of "successful" the running
+    /// platform uses).
+    Success,
+    /// The command did not execute successfully (by whatever definition of "not successful" the
+    /// running platform uses).
+    Error,
+    /// The command terminated due to a signal: if `Some`, it must have been the given signal
+    /// number. This option may not be available on all platforms.
+    Signal(Option<i32>),
+    /// The command exited with a precise exit code. This option may not be available on all
+    /// platforms.
+    Int(i32),
+    /// The command exited with any one of a set of exit codes. This option may not be available
+    /// on all platforms.
+    OneOf(Vec<i32>),
+    /// The command exited in any way at all (though it must not have timed out).
+    Any,
+    /// The command ran for longer than its timeout and was killed. This requires the command to
+    /// have a timeout (set with the `timeout` key or `LangTester::test_timeout`).
+    Timeout,
+    /// The command exited in any way which does not match the inner status (e.g.
+    /// `Not(Box::new(Status::Int(0)))` passes for any exit code other than 0, and also if the
+    /// command terminated due to a signal, since it then has no exit code). A command which timed
+    /// out still fails.
+    Not(Box<Status>),
+}
+
+impl Status {
+    /// Does the exit status `status` match this status? Termination by any of the signals in
+    /// `ignore_signals` is treated as success.
+    fn matches(&self, status: ExitStatus, ignore_signals: &[i32]) -> bool {
+        match self {
+            Status::Success => succeeded(status, ignore_signals),
+            Status::Error => !succeeded(status, ignore_signals),
+            Status::Signal(None) => status.signal().is_some(),
+            Status::Signal(Some(sig)) => status.signal() == Some(*sig),
+            Status::Int(i) => status.code() == Some(*i),
+            Status::OneOf(codes) => status.code().is_some_and(|c| codes.contains(&c)),
+            Status::Any => true,
+            // A command which timed out is checked separately, since it has no meaningful exit
+            // status.
+            Status::Timeout => false,
+            Status::Not(s) => !s.matches(status, ignore_signals),
+        }
+    }
+
+    /// Does this status check for termination due to a signal?
+    fn mentions_signal(&self) -> bool {
+        match self {
+            Status::Signal(_) => true,
+            Status::Not(s) => s.mentions_signal(),
+            _ => false,
+        }
+    }
+}
+
+/// Did a command which exited with `status` complete successfully? Termination by any of the
+/// signals in `ignore_signals` counts as success.
+fn succeeded(status: ExitStatus, ignore_signals: &[i32]) -> bool {
+    status.success()
+        || status
+            .signal()
+            .is_some_and(|sig| ignore_signals.contains(&sig))
+}
+
+/// A condition under which a test file should be ignored. Conditions are checked against the
+/// platform the tests are being run on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IgnoreIf {
+    /// The operating system (e.g. `linux`) is the given value.
+    TargetOs(String),
+    /// The operating system family (e.g. `unix`) is the given value.
+    TargetFamily(String),
+    /// The CPU architecture (e.g. `x86_64`) is the given value.
+    TargetArch(String),
+    /// The inner condition does not hold.
+    Not(Box<IgnoreIf>),
+}
+
+impl IgnoreIf {
+    /// Does this condition hold on the current platform?
+    pub fn holds(&self) -> bool {
+        match self {
+            IgnoreIf::TargetOs(os) => os == env::consts::OS,
+            IgnoreIf::TargetFamily(family) => family == env::consts::FAMILY,
+            IgnoreIf::TargetArch(arch) => arch == env::consts::ARCH,
+            IgnoreIf::Not(c) => !c.holds(),
+        }
+    }
+}
+
+impl fmt::Display for IgnoreIf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IgnoreIf::TargetOs(os) => write!(f, "target_os = \"{}\"", os),
+            IgnoreIf::TargetFamily(family) => write!(f, "target_family = \"{}\"", family),
+            IgnoreIf::TargetArch(arch) => write!(f, "target_arch = \"{}\"", arch),
+            IgnoreIf::Not(c) => write!(f, "not({})", c),
+        }
+    }
+}
+
+/// Displays a `Status` as the `status` key would specify it (e.g. `signal(6)` or `1..=4,7`).
+/// Signals are shown as numbers, so the text is the same on every platform. Statuses which test
+/// data can't specify (e.g. `OneOf` with no codes, `OneOf` with a single code outside `0..=255`, or
+/// `Not(Any)`) are displayed as text which does not parse.
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Success => write!(f, "success"),
+            Status::Error => write!(f, "error"),
+            Status::Signal(None) => write!(f, "signal"),
+            Status::Signal(Some(sig)) => write!(f, "signal({})", sig),
+            Status::Int(i) => write!(f, "{}", i),
+            // A single code on its own would parse as an `Int`.
+            Status::OneOf(codes) if codes.len() == 1 => write!(f, "{}..={}", codes[0], codes[0]),
+            Status::OneOf(codes) => {
+                // Runs of consecutive codes are displayed as ranges, preserving the codes' order.
+                // The `status` key only accepts ranges within `0..=255`, so codes outside that are
+                // displayed individually.
+                let mut i = 0;
+                while i < codes.len() {
+                    let mut j = i;
+                    while j + 1 < codes.len()
+                        && (0..255).contains(&codes[j])
+                        && codes[j] + 1 == codes[j + 1]
+                    {
+                        j += 1;
+                    }
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    if j > i {
+                        write!(f, "{}..={}", codes[i], codes[j])?;
+                    } else {
+                        write!(f, "{}", codes[i])?;
+                    }
+                    i = j + 1;
+                }
+                Ok(())
+            }
+            Status::Any => write!(f, "any"),
+            Status::Timeout => write!(f, "timeout"),
+            Status::Not(s) => write!(f, "not {}", s),
+        }
+    }
+}
+
+/// A step of an `interact` block.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InteractStep<'a> {
+    /// Write this line, followed by a newline, to the command's stdin.
+    Send(&'a str),
+    /// Wait for a line of the command's stdout, after that matched by the previous `Expect`, to
+    /// match this line pattern.
+    Expect(&'a str),
+}
+
+/// A user `TestCmd`: the expectations for a single command of a test.
+#[derive(Clone, Debug)]
+pub struct TestCmd<'a> {
+    /// The status the command is expected to exit with.
+    pub status: Status,
+    /// The fuzzy pattern the command's stderr must match.
+    pub stderr: Vec<&'a str>,
+    /// The fuzzy pattern the command's stdout must match.
+    pub stdout: Vec<&'a str>,
+    /// If `Some`, the lines the command's stderr must consist of exactly, with no wildcard or
+    /// regular expression interpretation. `stderr` is then ignored.
+    pub stderr_exact: Option<Vec<&'a str>>,
+    /// If `Some`, the lines the command's stdout must consist of exactly, with no wildcard or
+    /// regular expression interpretation. `stdout` is then ignored.
+    pub stdout_exact: Option<Vec<&'a str>>,
+    /// If `true`, the command's stdout must consist exactly of the data written to its stdin (with
+    /// the same rules as `stdout_exact`). `stdout` is then ignored.
+    pub stdout_equals_stdin: bool,
+    /// If `Some`, the JSON the command's stderr must be semantically equal to (i.e. ignoring
+    /// formatting and the order of object keys). `stderr` is then ignored.
+    pub stderr_json: Option<Vec<&'a str>>,
+    /// If `Some`, the JSON the command's stdout must be semantically equal to (i.e. ignoring
+    /// formatting and the order of object keys). `stdout` is then ignored.
+    pub stdout_json: Option<Vec<&'a str>>,
+    /// If `true`, objects in the command's stderr can have keys which those in `stderr_json`
+    /// don't.
+    pub stderr_json_subset: bool,
+    /// If `true`, objects in the command's stdout can have keys which those in `stdout_json`
+    /// don't.
+    pub stdout_json_subset: bool,
+    /// If `true`, leading and trailing whitespace on each line of the command's stderr is
+    /// significant when it is matched against `stderr`.
+    pub stderr_raw: bool,
+    /// If `true`, leading and trailing whitespace on each line of the command's stdout is
+    /// significant when it is matched against `stdout`.
+    pub stdout_raw: bool,
+    /// If `Some`, a file containing the pattern the command's stderr must match (with the same
+    /// rules as `stderr`). Relative paths are relative to the directory containing the test file.
+    pub stderr_file: Option<PathBuf>,
+    /// If `Some`, a file containing the pattern the command's stdout must match (with the same
+    /// rules as `stdout`). Relative paths are relative to the directory containing the test file.
+    pub stdout_file: Option<PathBuf>,
+    /// If `Some`, a file which must exist after the command has run. Relative paths are relative
+    /// to the command's working directory.
+    pub output_file: Option<PathBuf>,
+    /// If `Some`, the fuzzy pattern the contents of `output_file` must match.
+    pub output_file_contents: Option<Vec<&'a str>>,
+    /// Line patterns which must not match any line of the command's stderr.
+    pub stderr_not: Vec<&'a str>,
+    /// Line patterns which must not match any line of the command's stdout.
+    pub stdout_not: Vec<&'a str>,
+    /// Line patterns each of which must match some line of the command's stderr, in any order.
+    pub stderr_contains: Vec<&'a str>,
+    /// Line patterns each of which must match some line of the command's stdout, in any order.
+    pub stdout_contains: Vec<&'a str>,
+    /// Line patterns which must match lines of the command's stderr in order, though other lines
+    /// may come before, between, or after them.
+    pub stderr_ordered: Vec<&'a str>,
+    /// Line patterns which must match lines of the command's stdout in order, though other lines
+    /// may come before, between, or after them.
+    pub stdout_ordered: Vec<&'a str>,
+    /// If `Some`, the number of lines of the command's stderr (after leading and trailing blank
+    /// lines are trimmed, as for `stderr`) must be in this range.
+    pub stderr_lines: Option<RangeInclusive<usize>>,
+    /// If `Some`, the number of lines of the command's stdout (after leading and trailing blank
+    /// lines are trimmed, as for `stdout`) must be in this range.
+    pub stdout_lines: Option<RangeInclusive<usize>>,
+    /// If `Some`, the number of bytes the command wrote to stderr (exactly as they were written,
+    /// with no trimming or normalisation) must be in this range.
+    pub stderr_bytes: Option<RangeInclusive<usize>>,
+    /// If `Some`, the number of bytes the command wrote to stdout (exactly as they were written,
+    /// with no trimming or normalisation) must be in this range.
+    pub stdout_bytes: Option<RangeInclusive<usize>>,
+    /// If `Some`, the pattern the command's combined stderr and stdout (interleaved in the order
+    /// they were written) should match. `stderr` and `stdout` are then ignored.
+    pub output: Option<Vec<&'a str>>,
+    /// The bytes to be written to the command's stdin (from either `stdin` or `stdin-bytes`). If
+    /// `None` (and `stdin_file` is `None`), the command's stdin is connected to the null device.
+    pub stdin: Option<Vec<u8>>,
+    /// If `Some`, a file whose contents are to be written to the command's stdin. Relative paths
+    /// are relative to the directory containing the test file.
+    pub stdin_file: Option<PathBuf>,
+    /// If `Some`, the (lower-cased) name of an earlier command whose captured stdout is to be
+    /// written to the command's stdin.
+    pub stdin_from: Option<String>,
+    /// The steps of an interactive session with the command, performed in order while it runs. If
+    /// non-empty, the command's stdin is closed once every step has been performed.
+    pub interact: Vec<InteractStep<'a>>,
+    /// If `Some`, the program (and its arguments) to run instead of the command returned by
+    /// `test_cmds`.
+    pub cmd: Option<Vec<String>>,
+    /// A list of custom command line arguments which should be passed when
+    /// executing the test command. Placeholders (e.g. `{testfile}`) are expanded just before the
+    /// command is run.
+    pub args: Vec<String>,
+    /// Programs which the command needs: if any of them can't be found (as a path if it contains
+    /// a `/`, or on `PATH` otherwise), the command is skipped.
+    pub skip_if_missing: Vec<String>,
+    /// If `Some`, the working directory the command should be run in. Relative paths are relative
+    /// to the directory containing the test file.
+    pub cwd: Option<PathBuf>,
+    /// If `Some`, the maximum time the command is allowed to run for before it is killed.
+    pub timeout: Option<Duration>,
+    /// If `Some`, the time to sleep for before the command is run.
+    pub delay: Option<Duration>,
+    /// If `Some`, the maximum peak resident set size, in megabytes, the command may reach.
+    pub max_rss: Option<u64>,
+    /// The number of times the command is rerun if it fails.
+    pub retries: usize,
+    /// The lines of the test data (0-based, with an exclusive end) occupied by each key (e.g.
+    /// `stdout`) specified for the command, including the key's value.
+    pub spans: HashMap<String, Range<usize>>,
+    /// If `true`, the command is run with an empty environment (other than the variables in
+    /// `env`).
+    pub env_clear: bool,
+    /// A list of environment variables `(name, value)` which should be set when executing the
+    /// test command.
+    pub env: Vec<(String, String)>,
+}
+
+/// The expectations of a command that the test data doesn't mention: it must succeed, and its
+/// output can be anything.
+impl Default for TestCmd<'_> {
+    fn default() -> Self {
+        Self {
+            status: Status::Success,
+            stderr: vec!["..."],
+            stdout: vec!["..."],
+            stderr_exact: None,
+            stderr_raw: false,
+            stdout_raw: false,
+            stdout_exact: None,
+            stdout_equals_stdin: false,
+            stderr_json: None,
+            stdout_json: None,
+            stderr_json_subset: false,
+            stdout_json_subset: false,
+            stderr_file: None,
+            stdout_file: None,
+            output_file: None,
+            output_file_contents: None,
+            stderr_not: Vec::new(),
+            stdout_not: Vec::new(),
+            stderr_contains: Vec::new(),
+            stdout_contains: Vec::new(),
+            stderr_ordered: Vec::new(),
+            stdout_ordered: Vec::new(),
+            stderr_lines: None,
+            stdout_lines: None,
+            stderr_bytes: None,
+            stdout_bytes: None,
+            output: None,
+            stdin: None,
+            stdin_file: None,
+            stdin_from: None,
+            interact: Vec::new(),
+            cmd: None,
+            args: Vec::new(),
+            skip_if_missing: Vec::new(),
+            cwd: None,
+            timeout: None,
+            delay: None,
+            max_rss: None,
+            retries: 0,
+            spans: HashMap::new(),
+            env_clear: false,
+            env: Vec::new(),
+        }
+    }
+}
+
+/// A collection of tests, as parsed from a test file's test data.
+#[derive(Clone, Debug, Default)]
+pub struct Tests<'a> {
+    /// If `Some`, the file should be ignored, with the (possibly empty) reason given.
+    pub ignore: Option<String>,
+    /// If `Some`, the file should be ignored if the condition holds.
+    pub ignore_if: Option<IgnoreIf>,
+    /// If `Some`, the file's tests are expected to fail, for the (possibly empty) reason given.
+    pub xfail: Option<String>,
+    /// The tags the file's tests have been given (in the order they were specified), which
+    /// [`LangTester::filter_tags`](struct.LangTester.html#method.filter_tags) selects tests by.
+    pub tags: Vec<String>,
+    /// The commands of the test, keyed by their (lower-cased) names. Commands that the test data
+    /// doesn't mention are not included.
+    pub tests: HashMap<String, TestCmd<'a>>,
+}
+
+/// The result of running a single test, as passed to the function specified with
+/// [`LangTester::on_test_result`](struct.LangTester.html#method.on_test_result).
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct TestResult {
+    /// The name of the test.
+    pub name: String,
+    /// Did the test pass?
+    pub passed: bool,
+    /// How long the test took to run.
+    pub duration: Duration,
+    /// The results of each of the test's commands, in the order they were run (or, if the setup
+    /// command failed, would have been run).
+    pub cmds: Vec<CmdResult>,
+}
+
+/// The result of running a single command of a test. If the command was retried, this is the
+/// result of its final run.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct CmdResult {
+    /// The (lower-cased) name of the command.
+    pub name: String,
+    /// Did the command pass all its sub-tests?
+    pub passed: bool,
+    /// How long the command took to run, from spawn to exit.
+    pub duration: Duration,
+    /// The number of times the command was retried.
+    pub retries: usize,
+    /// A description of the command's exit status.
+    pub status: String,
+    /// The command's exit status, or `None` if the command was not run.
+    pub exit_status: Option<ExitStatus>,
+    /// The pattern the command's stderr was expected to match.
+    pub expected_stderr: String,
+    /// The command's captured stderr, after
+    /// [`LangTester::strip_ansi`](struct.LangTester.html#method.strip_ansi) and
+    /// [`LangTester::output_transform`](struct.LangTester.html#method.output_transform) (if any)
+    /// have been applied: this is what was matched against `expected_stderr`.
+    pub stderr: String,
+    /// The pattern the command's stdout was expected to match.
+    pub expected_stdout: String,
+    /// The command's captured stdout, after `strip_ansi` and `output_transform` (if any) have been
+    /// applied: this is what was matched against `expected_stdout`.
+    pub stdout: String,
+    raw_stderr: Vec<u8>,
+    raw_stdout: Vec<u8>,
+    /// If `true`, the command had an `output` test: `expected_stdout`, `stdout`, and
+    /// [`stdout_bytes`](#method.stdout_bytes) then refer to the combined stderr and stdout, and
+    /// `expected_stderr`, `stderr`, and [`stderr_bytes`](#method.stderr_bytes) are empty.
+    pub combined: bool,
+}
+
+impl CmdResult {
+    /// The bytes the command wrote to stderr, exactly as they were captured.
+    pub fn stderr_bytes(&self) -> &[u8] {
+        &self.raw_stderr
+    }
+
+    /// The bytes the command wrote to stdout, exactly as they were captured. If the command's
+    /// stdout was streamed (see
+    /// [`LangTester::stream_output`](struct.LangTester.html#method.stream_output)), it isn't kept,
+    /// so this is empty, and `stdout` is only its last few lines.
+    pub fn stdout_bytes(&self) -> &[u8] {
+        &self.raw_stdout
+    }
+}
+
+/// How long a test took to run.
+struct TestTiming {
+    name: String,
+    duration: Duration,
+    /// The `(name, duration)` of each command run, from spawn to exit.
+    cmds: Vec<(String, Duration)>,
+}
+
+/// If one or more parts of a `TestCmd` fail, the parts that fail are set to `Some(...)` in an
+/// instance of this struct.
+#[derive(Debug, Default, PartialEq)]
+struct TestFailure {
+    status: Option<String>,
+    stderr: Option<String>,
+    stdout: Option<String>,
+    /// The combined stderr and stdout of a command with an `output` test.
+    output: Option<String>,
+    /// If the `stderr`, `stdout`, or `output` sub-tests failed, the patterns they were matched
+    /// against, so that the user can be shown a diff.
+    stderr_pattern: Option<Vec<String>>,
+    stdout_pattern: Option<Vec<String>>,
+    output_pattern: Option<Vec<String>>,
+    /// Were `stderr_pattern`/`stdout_pattern` matched with whitespace significant (i.e. were they
+    /// `stderr-raw`/`stdout-raw` patterns)?
+    stderr_raw: bool,
+    stdout_raw: bool,
+    /// If the `stderr-not` or `stdout-not` sub-tests failed, a description of the line which
+    /// matched a forbidden pattern.
+    stderr_forbidden: Option<String>,
+    stdout_forbidden: Option<String>,
+    /// If the `stderr-contains` or `stdout-contains` sub-tests failed, a description of the first
+    /// line pattern which wasn't matched.
+    stderr_missing: Option<String>,
+    stdout_missing: Option<String>,
+    /// If the `stderr-contains-ordered` or `stdout-contains-ordered` sub-tests failed, a
+    /// description of the first line pattern which wasn't matched.
+    stderr_unordered: Option<String>,
+    stdout_unordered: Option<String>,
+    /// If the `stderr-exact`, `stdout-exact`, `stdout-equals-stdin`, or `-json` sub-tests failed,
+    /// the key of the sub-test and a description of the first difference.
+    stderr_mismatch: Option<(&'static str, String)>,
+    stdout_mismatch: Option<(&'static str, String)>,
+    /// If the `stderr-lines` or `stdout-lines` sub-tests failed, a description of the difference.
+    stderr_line_count: Option<String>,
+    stdout_line_count: Option<String>,
+    /// If the `stderr-bytes` or `stdout-bytes` sub-tests failed, a description of the difference.
+    stderr_byte_count: Option<String>,
+    stdout_byte_count: Option<String>,
+    /// If the `output-file` sub-test failed because the file couldn't be read, a description of
+    /// why.
+    output_file_error: Option<String>,
+    /// If the `output-file` sub-test failed because the file's contents didn't match, the path of
+    /// the file, the pattern it was matched against, and its actual contents.
+    output_file_mismatch: Option<(PathBuf, Vec<String>, String)>,
+    /// If `Some`, the test's scratch directory, which has been kept so that its contents can be
+    /// inspected.
+    artifacts: Option<PathBuf>,
+}
+
+fn write_with_colour(s: &str, colour: Color, style: &ReporterStyle) {
+    let mut stderr = StandardStream::stderr(style.colour_choice());
+    stderr.set_color(ColorSpec::new().set_fg(Some(colour))).ok();
+    io::stderr().write_all(s.as_bytes()).ok();
+    stderr.reset().ok();
+}
+
+/// Write a line-by-line diff between the fuzzy pattern `pattern` and `actual` (with whitespace
+/// significant if `raw` is `true`) to `stderr`. The diff is coloured if `colour` is `true` and
+/// `stderr` is a terminal. If `max_lines` is `Some`, only the first and last `max_lines` lines of
+/// the diff are written.
+fn write_diff(
+    pattern: &[String],
+    actual: &str,
+    raw: bool,
+    case_insensitive: bool,
+    max_lines: Option<usize>,
+    colour: bool,
+) {
+    let colour = if colour && io::stderr().is_terminal() {
+        ColorChoice::Always
+    } else {
+        ColorChoice::Never
+    };
+    let pattern = pattern.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+    let stderr = StandardStream::stderr(colour);
+    let mut handle = stderr.lock();
+    let diff = if raw {
+        fuzzy::diff_raw(&pattern, actual, case_insensitive)
+    } else {
+        fuzzy::diff(&pattern, actual, case_insensitive)
+    };
+    let omit = output::omitted_lines(diff.len(), max_lines);
+    for (i, l) in diff.into_iter().enumerate() {
+        if let Some(ref omit) = omit {
+            if i == omit.start {
+                handle
+                    .write_all(format!("{}\n", output::omitted_marker(omit.len())).as_bytes())
+                    .ok();
+            }
+            if omit.contains(&i) {
+                continue;
+            }
+        }
+        let (prefix, line, fg) = match l {
+            DiffLine::Both(s) => (' ', s, None),
+            DiffLine::Wildcard(s) => (' ', s, None),
+            DiffLine::Expected(s) => ('-', s, Some(Color::Red)),
+            DiffLine::Actual(s) => ('+', s, Some(Color::Green)),
+        };
+        handle.set_color(ColorSpec::new().set_fg(fg)).ok();
+        handle
+            .write_all(format!("{} {}", prefix, line).as_bytes())
+            .ok();
+        handle.reset().ok();
+        handle.write_all(b"\n").ok();
+    }
+}
+
+/// Print the program, arguments, working directory, and environment overrides of `test_name`'s
+/// command `cmd_name` (`cmd`, whose environment was cleared first if `env_clear` is `true`).
+fn write_verbose_cmd(
+    inner: &LangTesterPooler,
+    test_name: &str,
+    cmd_name: &str,
+    cmd: &Command,
+    env_clear: bool,
+) {
+    let mut s = format!(
+        "\nlang_tests::{} {}:\n  program: {:?}\n  args: {:?}\n",
+        test_name,
+        cmd_name,
+        cmd.get_program(),
+        cmd.get_args().collect::<Vec<_>>()
+    );
+    if let Some(dir) = cmd.get_current_dir() {
+        s.push_str(&format!("  cwd: {:?}\n", dir));
+    }
+    if env_clear {
+        s.push_str("  env: (cleared)\n");
+    }
+    for (k, v) in cmd.get_envs() {
+        let k = k.to_string_lossy();
+        match v {
+            Some(v) => {
+                let v = v.to_string_lossy();
+                let v = match inner.redact_env {
+                    Some(ref redact_env) => Cow::Owned(redact_env(&k, &v)),
+                    None => v,
+                };
+                s.push_str(&format!("  env: {}={:?}\n", k, v));
+            }
+            None => s.push_str(&format!("  env: {} (removed)\n", k)),
+        }
+    }
+    let mut handle = io::stderr().lock();
+    inner.hide_progress(&mut handle);
+    handle.write_all(s.as_bytes()).ok();
+    inner.show_progress(&mut handle, false);
+}
+
+/// Warn the user that the `stderr`/`stdout`/`output` tests of `test_name`'s command `cmd_name`
+/// have been skipped because the command's output was not captured.
+fn write_nocapture_warning(test_name: &str, cmd_name: &str, style: &ReporterStyle) {
+    let stderr = StandardStream::stderr(style.colour_choice());
+    let mut handle = stderr.lock();
+    handle
+        .set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))
+        .ok();
+    handle.write_all(b"\nwarning").ok();
+    handle.reset().ok();
+    handle
+        .write_all(
+            format!(
+                ": lang_tests::{} {}: not checking stderr/stdout as output is not being captured\n",
+                test_name, cmd_name
+            )
+            .as_bytes(),
+        )
+        .ok();
+}
+
+fn write_ignored(test_name: &str, tags: &[String], message: &str, inner: Arc<LangTesterPooler>) {
+    if let OutputFormat::JUnitXml { .. } = inner.output_format {
+        inner
+            .junit_suites
+            .lock()
+            .unwrap()
+            .push(output::junit_ignored(test_name, message));
+    }
+    if !inner.output_format.is_pretty() {
+        output::write_ignored(
+            &inner.output_format,
+            &inner.num_reported,
+            test_name,
+            tags,
+            message,
+        );
+        return;
+    }
+    // Grab a lock on stderr so that we can avoid the possibility of lines blurring
+    // together in confusing ways.
+    let stderr = StandardStream::stderr(inner.reporter_style.colour_choice());
+    let mut handle = stderr.lock();
+    if inner.test_threads > 1 {
+        inner.hide_progress(&mut handle);
+        handle
+            .write_all(format!("\ntest lang_tests::{} ... ", test_name).as_bytes())
+            .ok();
+    }
+    handle
+        .set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))
+        .ok();
+    handle.write_all(b"ignored").ok();
+    handle.reset().ok();
+    if !message.is_empty() {
+        handle.write_all(format!(" ({})", message).as_bytes()).ok();
+    }
+    inner.show_progress(&mut handle, true);
+}
+
+fn to_owned_lines(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|x| (*x).to_owned()).collect()
+}
+
+/// The name of the test contained in the test file `path`: its path relative to `test_dir`, minus
+/// any extension.
+fn test_name(test_dir: &Path, path: &Path) -> String {
+    path.strip_prefix(test_dir)
+        .unwrap_or(path)
+        .with_extension("")
+        .to_str()
+        .unwrap()
+        .to_owned()
+}
+
+/// Set when the user presses Ctrl-C in watch mode: no further tests are then started.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_interrupt(_: nix::libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Catch Ctrl-C (`SIGINT`), so that in watch mode we can stop cleanly rather than being killed
+/// part way through a run. Commands being run still receive the signal, since they are in the same
+/// process group as us.
+fn install_interrupt_handler() {
+    let action = SigAction::new(
+        SigHandler::Handler(on_interrupt),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    // `on_interrupt` only performs an atomic store, which is async-signal-safe.
+    unsafe { sigaction(Signal::SIGINT, &action) }
+        .unwrap_or_else(|_| fatal("Couldn't install SIGINT handler."));
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "Usage: [--ignored] [--list] [--nocapture] [--verbose] [--shuffle-seed=<seed>] \
+         [--test-threads=<n>] [<filter1>] [... <filtern>]"
+    );
+    process::exit(1);
+}
+
+/// Shuffle `v` deterministically from `seed`, using a Fisher-Yates shuffle driven by the
+/// SplitMix64 generator (which is more than random enough for ordering tests).
+fn shuffle<T>(v: &mut [T], seed: u64) {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    for i in (1..v.len()).rev() {
+        let j = (next() % (i as u64 + 1)) as usize;
+        v.swap(i, j);
+    }
+}
+
+/// Is `path` a hidden file or directory, or an editor temporary file (see
+/// [`LangTester::include_hidden`])?
+fn is_hidden(path: &Path) -> bool {
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy(),
+        None => return false,
+    };
+    name.starts_with('.')
+        || name.ends_with('~')
+        || (name.len() > 1 && name.starts_with('#') && name.ends_with('#'))
+        || [".swp", ".swo", ".swx"]
+            .iter()
+            .any(|ext| name.ends_with(ext))
+}
+
+/// Check for the case where the user has a test called `X` but `test_cmds` doesn't have a command
+/// with a matching name. This is almost certainly a bug, in the sense that the test can never,
+/// ever fire, so an error message is returned and the test fails.
+fn check_names<'a>(
+    cmd_pairs: &[(String, Command)],
+    tests: &HashMap<String, TestCmd<'a>>,
+) -> Result<(), String> {
+    let cmd_names = cmd_pairs.iter().map(|x| &x.0).collect::<HashSet<_>>();
+    let test_names = tests.keys().collect::<HashSet<_>>();
+    let mut diff = test_names
+        .difference(&cmd_names)
+        .map(|x| x.as_str())
+        .collect::<Vec<_>>();
+    if !diff.is_empty() {
+        diff.sort_unstable();
+        return Err(format!(
+            "Command name(s) '{}' in tests are not found in the actual commands.",
+            diff.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Create a new, empty, uniquely named directory within the system's temporary directory.
+fn create_tempdir() -> PathBuf {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+    loop {
+        let dir = env::temp_dir().join(format!(
+            "lang_tester-{}-{}",
+            process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        match fs::create_dir(&dir) {
+            Ok(()) => return dir,
+            // Left behind by an earlier process which had the same process ID.
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => (),
+            Err(e) => fatal(&format!(
+                "Couldn't create directory '{}': {}",
+                dir.display(),
+                e
+            )),
+        }
+    }
+}
+
+/// Replace the command in each pair of `cmd_pairs` whose test specifies `cmd` with one which runs
+/// that command line instead, keeping the original command's working directory and environment.
+/// Returns an error if a test specifies `cmd` but `allow_inline_cmd` is `false`.
+fn inline_cmds<'a>(
+    cmd_pairs: Vec<(String, Command)>,
+    tests: &HashMap<String, TestCmd<'a>>,
+    allow_inline_cmd: bool,
+) -> Result<Vec<(String, Command)>, String> {
+    cmd_pairs
+        .into_iter()
+        .map(|(cmd_name, cmd)| {
+            let args = match tests.get(&cmd_name).and_then(|t| t.cmd.as_ref()) {
+                Some(args) => args,
+                None => return Ok((cmd_name, cmd)),
+            };
+            if !allow_inline_cmd {
+                return Err(format!(
+                    "{}: cmd can only be used if LangTester::allow_inline_cmd is set.",
+                    cmd_name
+                ));
+            }
+            let mut inline_cmd = Command::new(&args[0]);
+            inline_cmd.args(&args[1..]);
+            if let Some(dir) = cmd.get_current_dir() {
+                inline_cmd.current_dir(dir);
+            }
+            for (k, v) in cmd.get_envs() {
+                match v {
+                    Some(v) => inline_cmd.env(k, v),
+                    None => inline_cmd.env_remove(k),
+                };
+            }
+            Ok((cmd_name, inline_cmd))
+        })
+        .collect()
+}
+
+/// Give `wrapped` (the command returned by the user's `cmd_wrapper` for `cmd`) `cmd`'s working
+/// directory and environment, cleared first if `env_clear` is `true`, except where `wrapped` sets
+/// its own.
+fn keep_cmd_env(cmd: &Command, wrapped: &mut Command, env_clear: bool) {
+    if wrapped.get_current_dir().is_none() {
+        if let Some(dir) = cmd.get_current_dir() {
+            wrapped.current_dir(dir);
+        }
+    }
+    let wrapped_envs = wrapped
+        .get_envs()
+        .map(|(k, v)| (k.to_owned(), v.map(|v| v.to_owned())))
+        .collect::<Vec<_>>();
+    if env_clear {
+        wrapped.env_clear();
+    }
+    let envs = cmd.get_envs().chain(
+        wrapped_envs
+            .iter()
+            .map(|(k, v)| (k.as_os_str(), v.as_deref())),
+    );
+    for (k, v) in envs {
+        match v {
+            Some(v) => wrapped.env(k, v),
+            None => wrapped.env_remove(k),
+        };
+    }
+}
+
+/// Check that every `stdin-from` in `tests` names a command which is run before the command it is
+/// specified for, returning an error if not.
+fn check_stdin_from<'a>(
+    cmd_pairs: &[(String, Command)],
+    tests: &HashMap<String, TestCmd<'a>>,
+) -> Result<(), String> {
+    for (i, (cmd_name, _)) in cmd_pairs.iter().enumerate() {
+        if let Some(from) = tests.get(cmd_name).and_then(|t| t.stdin_from.as_ref()) {
+            if !cmd_pairs[..i].iter().any(|(n, _)| n == from) {
+                return Err(format!(
+                    "Command '{}' takes its stdin from '{}', which is not an earlier command.",
+                    cmd_name, from
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run every test in `test_files` (a vector of `(test name, path, test data)` tuples), returning a
+/// tuple `(failures, num_ignored, num_not_run)`, where `num_not_run` is the number of tests not
+/// run because an earlier test failed and `fail_fast` is set.
+fn test_file(
+    test_files: Vec<(String, PathBuf, String)>,
+    inner: Arc<LangTesterPooler>,
+) -> (Vec<(String, TestFailure)>, usize, usize) {
+    let failures = Arc::new(Mutex::new(Vec::new()));
+    let num_ignored = Arc::new(AtomicUsize::new(0));
+    let num_not_run = Arc::new(AtomicUsize::new(0));
+    let pool = ThreadPool::new(inner.test_threads);
+    for (test_fname, p, test_str) in test_files {
+        let num_ignored = num_ignored.clone();
+        let num_not_run = num_not_run.clone();
+        let failures = failures.clone();
+        let inner = inner.clone();
+        pool.execute(move || {
+            if (inner.fail_fast && !failures.lock().unwrap().is_empty())
+                || INTERRUPTED.load(Ordering::SeqCst)
+            {
+                num_not_run.fetch_add(1, Ordering::Relaxed);
+                inner.show_progress(&mut io::stderr().lock(), true);
+                return;
+            }
+            if inner.test_threads == 1 && inner.output_format.is_pretty() {
+                let mut handle = io::stderr().lock();
+                inner.hide_progress(&mut handle);
+                write!(handle, "\ntest lang_test::{} ... ", test_fname).ok();
+            }
+
+            if test_str.is_empty() {
+                write_ignored(test_fname.as_str(), &[], "test string is empty", inner);
+                num_ignored.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            let (tests, parse_error) = match inner.parse(&p, &test_str) {
+                Ok(tests) => (tests, None),
+                Err(errs) => {
+                    let msgs = errs.iter().map(|e| e.msg()).collect::<Vec<_>>();
+                    if !inner.continue_on_parse_error {
+                        fatal(&msgs.join("\n  "))
+                    }
+                    let msg = format!("Couldn't parse test data:\n  {}", msgs.join("\n  "));
+                    (Tests::default(), Some(msg))
+                }
+            };
+            let ignore = match (&tests.ignore, &tests.ignore_if) {
+                (Some(reason), _) => Some(reason.clone()),
+                (None, Some(c)) if c.holds() => Some(format!("ignore-if: {}", c)),
+                (None, _) => None,
+            };
+            // A test which doesn't parse always fails, even if only ignored tests are being run.
+            if parse_error.is_none() && inner.ignored != ignore.is_some() {
+                write_ignored(
+                    test_fname.as_str(),
+                    &tests.tags,
+                    ignore.as_deref().unwrap_or(""),
+                    inner,
+                );
+                num_ignored.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+
+            let tempdir = if inner.test_cmds_tempdir {
+                Some(create_tempdir())
+            } else {
+                None
+            };
+            let ignored = run_tests(
+                Arc::clone(&inner),
+                tests,
+                test_fname,
+                p,
+                &test_str,
+                parse_error,
+                tempdir.as_deref(),
+                Arc::clone(&failures),
+            );
+            if let Some(tempdir) = tempdir {
+                // If the test failed and its directory is to be kept, `run_tests` will have
+                // recorded the directory in the test's failure.
+                let kept = inner.keep_artifacts
+                    || failures
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .any(|(_, f)| f.artifacts.as_ref() == Some(&tempdir));
+                if !kept {
+                    fs::remove_dir_all(tempdir).ok();
+                }
+            }
+            if ignored {
+                num_ignored.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    }
+    pool.join();
+    let failures = Mutex::into_inner(Arc::try_unwrap(failures).unwrap()).unwrap();
+
+    (
+        failures,
+        Arc::try_unwrap(num_ignored).unwrap().into_inner(),
+        Arc::try_unwrap(num_not_run).unwrap().into_inner(),
+    )
+}
+
+/// Run the tests for `path` (whose test data is `test_str`). If `parse_error` is `Some`, the test
+/// data couldn't be parsed, so no commands are run and the test fails with it as its status.
+#[allow(clippy::too_many_arguments)]
+fn run_tests<'a>(
+    inner: Arc<LangTesterPooler>,
+    tests: Tests<'a>,
+    test_fname: String,
+    path: PathBuf,
+    test_str: &str,
+    parse_error: Option<String>,
+    tempdir: Option<&Path>,
+    failures: Arc<Mutex<Vec<(String, TestFailure)>>>,
+) -> bool {
+    if !cfg!(unix) && tests.tests.values().any(|t| t.status.mentions_signal()) {
+        write_ignored(
+            test_fname.as_str(),
+            &tests.tags,
+            "signal termination not supported on this platform",
+            inner,
+        );
+        return true;
+    }
+
+    let mut failure = TestFailure::default();
+    let cmd_pairs = match parse_error {
+        Some(msg) => Err(msg),
+        None => inner.test_cmds.as_ref().unwrap()(path.as_path(), &tests, tempdir),
+    };
+    let xfail = tests.xfail;
+    let tags = tests.tags;
+    let mut tests = tests.tests;
+    let cmd_pairs = cmd_pairs.and_then(|cmd_pairs| {
+        let cmd_pairs = cmd_pairs
+            .into_iter()
+            .map(|(test_name, cmd)| (test_name.to_lowercase(), cmd))
+            .collect::<Vec<_>>();
+        check_names(&cmd_pairs, &tests)?;
+        inline_cmds(cmd_pairs, &tests, inner.allow_inline_cmd)
+    });
+    let mut cmd_pairs = match cmd_pairs {
+        Ok(cmd_pairs) => cmd_pairs,
+        Err(msg) => {
+            // None of the test's commands can be run, so the test fails with `msg` as its status.
+            failure.status = Some(msg);
+            Vec::new()
+        }
+    };
+    // The setup command always runs first (the sort is stable, so the other commands' order is
+    // preserved).
+    cmd_pairs.sort_by_key(|(cmd_name, _)| cmd_name != SETUP_CMD);
+    // Commands which the test data doesn't specify are still subject to the default assertions.
+    for (cmd_name, _) in &cmd_pairs {
+        if !tests.contains_key(cmd_name) {
+            tests.insert(cmd_name.clone(), inner.default_testcmd(cmd_name));
+        }
+    }
+    if let Err(msg) = check_stdin_from(&cmd_pairs, &tests) {
+        // As with an error from `inline_cmds`, none of the test's commands are run.
+        failure.status = Some(msg);
+        cmd_pairs.clear();
+    }
+    let mut skipped = Vec::new();
+    if let Some(ref only_cmds) = inner.only_cmds {
+        cmd_pairs.retain(|(cmd_name, _)| {
+            let from_skipped = tests
+                .get(cmd_name)
+                .and_then(|t| t.stdin_from.as_ref())
+                .is_some_and(|from| skipped.contains(from));
+            if cmd_name == SETUP_CMD || (only_cmds.contains(cmd_name) && !from_skipped) {
+                return true;
+            }
+            skipped.push(cmd_name.clone());
+            false
+        });
+    }
+
+    let test_start = Instant::now();
+    let mut cmd_results = Vec::new();
+    let mut setup_failed = false;
+    let mut cmd_timings = Vec::new();
+    let mut bless_edits = Vec::new();
+    // The captured stdout of each command run so far, for use by `stdin-from`.
+    let mut cmd_stdouts = HashMap::new();
+    // The text of each named capture group bound by the commands run so far, for substitution
+    // into the patterns of later commands.
+    let mut captures = HashMap::new();
+    let mut substituted = Vec::new();
+    let mut cmd_iter = cmd_pairs.into_iter();
+    for (cmd_name, mut cmd) in cmd_iter.by_ref() {
+        let test = &tests[&cmd_name];
+        // Variables set by `test_cmds` take precedence over those set with `LangTester::env`.
+        for (k, v) in &inner.env {
+            if !cmd.get_envs().any(|(cmd_k, _)| cmd_k == k.as_str()) {
+                cmd.env(k, v);
+            }
+        }
+        // A command which needs a missing program is skipped, as are the commands which depend on
+        // it: those which take its stdout as their stdin, and, if it is the setup command, all
+        // others.
+        let depends_on_skipped = skipped.iter().any(|s| s == SETUP_CMD)
+            || test
+                .stdin_from
+                .as_ref()
+                .is_some_and(|from| skipped.contains(from));
+        if depends_on_skipped
+            || test
+                .skip_if_missing
+                .iter()
+                .any(|p| !program_exists(&cmd, test, &path, p))
+        {
+            skipped.push(cmd_name);
+            continue;
+        }
+        if test.env_clear {
+            cmd.env_clear();
+        }
+        cmd.envs(test.env.iter().map(|(k, v)| (k, v)));
+        let prepared = match (&test.status, test.timeout.or(inner.test_timeout)) {
+            (Status::Timeout, None) => Err(format!(
+                "{}: status: timeout requires a timeout to be set with the timeout key or \
+                 LangTester::test_timeout.",
+                cmd_name
+            )),
+            _ => prepare_cmd(&mut cmd, test, &path, tempdir),
+        };
+        let prepared = prepared.and_then(|stdin| {
+            let normalize = inner.normalize_newlines;
+            let stderr_file =
+                read_pattern_file(&test.stderr_file, "stderr-file", &path, normalize)?;
+            let stdout_file =
+                read_pattern_file(&test.stdout_file, "stdout-file", &path, normalize)?;
+            Ok((stdin, stderr_file, stdout_file))
+        });
+        let (mut stdin, stderr_file, stdout_file) = match prepared {
+            Ok(x) => x,
+            Err(msg) => {
+                if inner.record_cmd_results() {
+                    cmd_results.push(CmdResult {
+                        name: cmd_name.clone(),
+                        passed: false,
+                        duration: Duration::from_secs(0),
+                        retries: 0,
+                        status: msg.clone(),
+                        expected_stderr: test.stderr.join("\n"),
+                        stderr: String::new(),
+                        expected_stdout: test.stdout.join("\n"),
+                        stdout: String::new(),
+                        combined: false,
+                        exit_status: None,
+                        raw_stderr: Vec::new(),
+                        raw_stdout: Vec::new(),
+                    });
+                }
+                failure.status = Some(msg);
+                setup_failed = cmd_name == SETUP_CMD;
+                break;
+            }
+        };
+        if let Some(ref cmd_wrapper) = inner.cmd_wrapper {
+            let mut wrapped = cmd_wrapper(&cmd_name, &cmd);
+            keep_cmd_env(&cmd, &mut wrapped, test.env_clear);
+            cmd = wrapped;
+        }
+        set_resource_limits(&mut cmd, inner.memory_limit, inner.cpu_limit);
+        if inner.verbose {
+            write_verbose_cmd(&inner, &test_fname, &cmd_name, &cmd, test.env_clear);
+        }
+        if let Some(ref from) = test.stdin_from {
+            stdin = cmd_stdouts.get(from).cloned();
+        }
+        if !captures.is_empty() && test.spans.contains_key("stdin") {
+            stdin = stdin.map(|b| match String::from_utf8(b) {
+                Ok(s) => fuzzy::substitute(&s, &captures).into_bytes(),
+                Err(e) => e.into_bytes(),
+            });
+        }
+        // With `stdout-equals-stdin`, stdout must consist exactly of the (fully resolved) stdin.
+        let stdin_str = match test.stdout_equals_stdin {
+            true => Some(String::from_utf8_lossy(stdin.as_deref().unwrap_or(&[])).into_owned()),
+            false => None,
+        };
+        // Patterns read from `stderr-file`/`stdout-file` are then matched exactly as if they had
+        // been specified with `stderr`/`stdout`.
+        let mut test = test.clone();
+        if let Some(ref s) = stderr_file {
+            test.stderr = pattern_lines(s);
+        }
+        if let Some(ref s) = stdout_file {
+            test.stdout = pattern_lines(s);
+        }
+        if !captures.is_empty() {
+            substitute_captures(&mut test, &captures, &mut substituted);
+        }
+        if let Some(ref s) = stdin_str {
+            test.stdout_exact = Some(fuzzy::exact_lines(s));
+        }
+        let test = &test;
+        let stream = can_stream(
+            &inner,
+            test,
+            tests
+                .values()
+                .any(|t| t.stdin_from.as_ref() == Some(&cmd_name)),
+        );
+        let timeout = test.timeout.or(inner.test_timeout);
+        // If a command which may fail does so, subsequent commands are not run.
+        let meant_to_error = match test.status {
+            Status::Error | Status::Any | Status::Timeout => true,
+            Status::Not(ref s) => **s != Status::Error,
+            _ => false,
+        };
+        // A command which fails is rerun until it passes or it has been retried `test.retries`
+        // times.
+        let mut retries = 0;
+        let attempt = loop {
+            if let Some(ref f) = inner.before_cmd {
+                f(&path, &cmd_name);
+            }
+            let attempt = run_attempt(
+                &inner,
+                &test_fname,
+                &cmd_name,
+                &mut cmd,
+                test,
+                &stdin,
+                stream,
+            );
+            if let Some(ref f) = inner.after_cmd {
+                f(&path, &cmd_name);
+            }
+            if attempt.passed() || retries == test.retries {
+                break attempt;
+            }
+            retries += 1;
+        };
+        cmd_timings.push((cmd_name.clone(), attempt.duration));
+        if retries > 0 && attempt.passed() {
+            inner
+                .flaky
+                .lock()
+                .unwrap()
+                .push((test_fname.clone(), cmd_name.clone(), retries));
+        }
+        let Attempt {
+            status,
+            stderr,
+            stdout,
+            stderr_bytes,
+            stdout_bytes,
+            timed_out,
+            duration: cmd_duration,
+            pass_status,
+            mut match_stderr,
+            mut match_stdout,
+            stderr_forbidden,
+            stdout_forbidden,
+            stderr_missing,
+            stdout_missing,
+            stderr_unordered,
+            stdout_unordered,
+            stderr_mismatch,
+            stdout_mismatch,
+            stderr_line_count,
+            stdout_line_count,
+            stderr_byte_count,
+            stdout_byte_count,
+            interact_failure,
+            max_rss_failure,
+            output_file_error,
+            output_file_mismatch,
+        } = attempt;
+        // Exceeding `max-rss` is reported in the same way as an `interact` failure, since the
+        // command's exit status is then irrelevant.
+        let interact_failure = interact_failure.or(max_rss_failure);
+        // If the only sub-tests which failed are ones whose expected output can be rewritten,
+        // bless them. `xfail` tests are expected to fail, so are never blessed.
+        if inner.bless
+            && xfail.is_none()
+            && pass_status
+            && stderr_forbidden.is_none()
+            && stdout_forbidden.is_none()
+            && stderr_missing.is_none()
+            && stdout_missing.is_none()
+            && stderr_unordered.is_none()
+            && stdout_unordered.is_none()
+            && stderr_mismatch.is_none()
+            && stdout_mismatch.is_none()
+            && stderr_line_count.is_none()
+            && stdout_line_count.is_none()
+            && stderr_byte_count.is_none()
+            && stdout_byte_count.is_none()
+            && output_file_error.is_none()
+            && output_file_mismatch.is_none()
+            && !(match_stderr && match_stdout)
+        {
+            let stderr_edit = match match_stderr {
+                true => None,
+                false => bless_edit(test, "stderr", &test.stderr_file, &stderr),
+            };
+            let stdout_edit = match (match_stdout, &test.output) {
+                (true, _) => None,
+                (false, Some(_)) => bless_edit(test, "output", &None, &stdout),
+                (false, None) => bless_edit(test, "stdout", &test.stdout_file, &stdout),
+            };
+            if (match_stderr || stderr_edit.is_some()) && (match_stdout || stdout_edit.is_some()) {
+                bless_edits.extend(stderr_edit);
+                bless_edits.extend(stdout_edit);
+                match_stderr = true;
+                match_stdout = true;
+            }
+        }
+        if tests
+            .values()
+            .any(|t| t.stdin_from.as_ref() == Some(&cmd_name))
+        {
+            cmd_stdouts.insert(cmd_name.clone(), stdout_bytes.clone());
+        }
+        let (stderr_pattern, stdout_pattern, stdout_raw) = match test.output {
+            Some(ref output) => (None, Some(output), false),
+            None => (
+                Some(&test.stderr).filter(|_| test.stderr_exact.is_none()),
+                Some(&test.stdout).filter(|_| test.stdout_exact.is_none()),
+                test.stdout_raw,
+            ),
+        };
+        let bound = [
+            (stderr_pattern, &stderr, test.stderr_raw, match_stderr),
+            (stdout_pattern, &stdout, stdout_raw, match_stdout),
+        ];
+        for (pattern, text, raw, matched) in bound {
+            if let (Some(pattern), true) = (pattern, matched) {
+                if let Some(caps) =
+                    fuzzy::match_captures(pattern, text, raw, inner.case_insensitive)
+                {
+                    captures.extend(caps);
                 }
-                if let Some(ref stdout) = test.stdout {
-                    eprintln!(
-                        "\n---- lang_tests::{} stdout ----\n{}\n",
-                        test_fname, stdout
-                    );
+            }
+        }
+        let pass_stderr = match_stderr
+            && stderr_forbidden.is_none()
+            && stderr_missing.is_none()
+            && stderr_unordered.is_none()
+            && stderr_mismatch.is_none()
+            && stderr_line_count.is_none()
+            && stderr_byte_count.is_none();
+        let pass_stdout = match_stdout
+            && stdout_forbidden.is_none()
+            && stdout_missing.is_none()
+            && stdout_unordered.is_none()
+            && stdout_mismatch.is_none()
+            && stdout_line_count.is_none()
+            && stdout_byte_count.is_none();
+        let pass_output_file = output_file_error.is_none() && output_file_mismatch.is_none();
+        if inner.record_cmd_results() {
+            let (expected_stderr, expected_stdout) = match test.output {
+                Some(ref output) => (String::new(), output.join("\n")),
+                None => (
+                    test.stderr_exact
+                        .as_ref()
+                        .or(test.stderr_json.as_ref())
+                        .unwrap_or(&test.stderr)
+                        .join("\n"),
+                    test.stdout_exact
+                        .as_ref()
+                        .or(test.stdout_json.as_ref())
+                        .unwrap_or(&test.stdout)
+                        .join("\n"),
+                ),
+            };
+            cmd_results.push(CmdResult {
+                name: cmd_name.clone(),
+                passed: pass_status && pass_stderr && pass_stdout && pass_output_file,
+                duration: cmd_duration,
+                retries,
+                status: interact_failure
+                    .clone()
+                    .unwrap_or_else(|| status_desc(status, timed_out, timeout, &inner)),
+                exit_status: Some(status),
+                expected_stderr,
+                stderr: stderr.clone(),
+                expected_stdout,
+                stdout: stdout.clone(),
+                raw_stderr: stderr_bytes,
+                raw_stdout: stdout_bytes,
+                combined: test.output.is_some(),
+            });
+        }
+
+        // Second, if a test failed, we want to print out everything which didn't match
+        // successfully (i.e. if the stderr test failed, print that out; but, equally, if
+        // stderr wasn't specified as a test, print it out, because the user can't
+        // otherwise know what it contains).
+        if !(pass_status && pass_stderr && pass_stdout && pass_output_file) {
+            if let Some(msg) = interact_failure {
+                failure.status = Some(msg);
+            } else if timed_out || !pass_status || failure.status.is_none() {
+                failure.status = Some(format!(
+                    "{}: {}",
+                    cmd_name,
+                    status_desc(status, timed_out, timeout, &inner)
+                ));
+            }
+
+            if let Some(ref output) = test.output {
+                if !pass_stdout {
+                    failure.output_pattern = Some(to_owned_lines(output));
+                }
+                failure.output = Some(stdout);
+            } else {
+                if !match_stderr {
+                    failure.stderr_pattern = Some(to_owned_lines(&test.stderr));
+                    failure.stderr_raw = test.stderr_raw;
+                }
+                failure.stderr_forbidden = stderr_forbidden;
+                failure.stderr_missing = stderr_missing;
+                failure.stderr_unordered = stderr_unordered;
+                failure.stderr_mismatch = stderr_mismatch;
+                failure.stderr_line_count = stderr_line_count;
+                failure.stderr_byte_count = stderr_byte_count;
+                if !pass_stderr || failure.stderr.is_none() {
+                    failure.stderr = Some(stderr);
+                }
+
+                if !match_stdout {
+                    failure.stdout_pattern = Some(to_owned_lines(&test.stdout));
+                    failure.stdout_raw = test.stdout_raw;
+                }
+                failure.stdout_forbidden = stdout_forbidden;
+                failure.stdout_missing = stdout_missing;
+                failure.stdout_unordered = stdout_unordered;
+                failure.stdout_mismatch = stdout_mismatch;
+                failure.stdout_line_count = stdout_line_count;
+                failure.stdout_byte_count = stdout_byte_count;
+                if !pass_stdout || failure.stdout.is_none() {
+                    failure.stdout = Some(stdout);
                 }
             }
-            eprintln!("\nfailures:");
-            for (test_fname, _) in failures {
-                eprint!("    lang_tests::{}", test_fname);
+            failure.output_file_error = output_file_error;
+            failure.output_file_mismatch = output_file_mismatch.map(|(path, contents)| {
+                let pattern = to_owned_lines(test.output_file_contents.as_ref().unwrap());
+                (path, pattern, contents)
+            });
+
+            // If a sub-test failed, bail out immediately, otherwise subsequent sub-tests
+            // will overwrite the failure output!
+            setup_failed = cmd_name == SETUP_CMD;
+            break;
+        }
+
+        // If a command failed, and we weren't expecting it to, bail out immediately.
+        if !succeeded(status, &inner.ignore_signals) && meant_to_error {
+            break;
+        }
+    }
+
+    // If the setup command failed, none of the remaining commands were run: make sure the user
+    // knows which.
+    if setup_failed {
+        let not_run = cmd_iter.map(|(cmd_name, _)| cmd_name).collect::<Vec<_>>();
+        if !not_run.is_empty() {
+            if inner.record_cmd_results() {
+                for cmd_name in &not_run {
+                    cmd_results.push(CmdResult {
+                        name: cmd_name.clone(),
+                        passed: false,
+                        duration: Duration::from_secs(0),
+                        retries: 0,
+                        status: "Not run: setup failed".to_owned(),
+                        expected_stderr: String::new(),
+                        stderr: String::new(),
+                        expected_stdout: String::new(),
+                        stdout: String::new(),
+                        combined: false,
+                        exit_status: None,
+                        raw_stderr: Vec::new(),
+                        raw_stdout: Vec::new(),
+                    });
+                }
             }
+            failure.status = Some(format!(
+                "Setup failed: {}\nNot run: {}",
+                failure.status.take().unwrap_or_default(),
+                not_run.join(", ")
+            ));
         }
+    }
 
-        eprint!("\n\ntest result: ");
-        if failures.is_empty() {
-            write_with_colour("ok", Color::Green);
+    if inner.record_cmd_results() {
+        for cmd_name in &skipped {
+            cmd_results.push(CmdResult {
+                name: cmd_name.clone(),
+                passed: true,
+                duration: Duration::from_secs(0),
+                retries: 0,
+                status: "Skipped".to_owned(),
+                expected_stderr: String::new(),
+                stderr: String::new(),
+                expected_stdout: String::new(),
+                stdout: String::new(),
+                combined: false,
+                exit_status: None,
+                raw_stderr: Vec::new(),
+                raw_stdout: Vec::new(),
+            });
+        }
+    }
+
+    if !bless_edits.is_empty() {
+        // Holding the lock means that tests in the same file can't bless it at the same time.
+        let mut blessed = inner.blessed.lock().unwrap();
+        match bless(&path, test_str, bless_edits) {
+            Ok(()) => blessed.push(test_fname.clone()),
+            Err(msg) => failure.status = Some(format!("Couldn't bless expected output: {}", msg)),
+        }
+    }
+
+    let test_duration = test_start.elapsed();
+    inner.timings.lock().unwrap().push(TestTiming {
+        name: test_fname.clone(),
+        duration: test_duration,
+        cmds: cmd_timings,
+    });
+
+    let mut passed = failure == TestFailure::default();
+    // An `xfail` test passes if any of its sub-tests failed, and fails if none did.
+    if let Some(reason) = xfail {
+        if passed {
+            failure.status = Some(
+                "Test is marked xfail but passed: if it has been fixed, remove the xfail marker."
+                    .to_owned(),
+            );
+            inner
+                .xpassed
+                .lock()
+                .unwrap()
+                .push((test_fname.clone(), reason));
         } else {
-            write_with_colour("FAILED", Color::Red);
+            failure = TestFailure::default();
+            inner
+                .xfailed
+                .lock()
+                .unwrap()
+                .push((test_fname.clone(), reason));
         }
-        eprintln!(
-            ". {} passed; {} failed; {} ignored; 0 measured; {} filtered out\n",
-            test_files_len - failures.len(),
-            failures.len(),
-            num_ignored,
-            num_filtered
+        passed = !passed;
+    }
+    if !passed && (inner.keep_artifacts || inner.keep_artifacts_on_failure) {
+        failure.artifacts = tempdir.map(|d| d.to_owned());
+    }
+    if let OutputFormat::JUnitXml { .. } = inner.output_format {
+        inner.junit_suites.lock().unwrap().push(output::junit_suite(
+            &test_fname,
+            test_duration,
+            &cmd_results,
+        ));
+    }
+    if let Some(ref f) = inner.on_test_result {
+        f(&TestResult {
+            name: test_fname.clone(),
+            passed,
+            duration: test_duration,
+            cmds: cmd_results.clone(),
+        });
+    }
+    if !inner.output_format.is_pretty() {
+        output::write_test(
+            &inner.output_format,
+            &inner.num_reported,
+            &test_fname,
+            &tags,
+            passed,
+            test_duration,
+            &cmd_results,
         );
+        if !passed {
+            failures.lock().unwrap().push((test_fname, failure));
+        }
+    } else {
+        // Grab a lock on stderr so that we can avoid the possibility of lines blurring
+        // together in confusing ways.
+        let style = &inner.reporter_style;
+        let stderr = StandardStream::stderr(style.colour_choice());
+        let mut handle = stderr.lock();
+        if inner.test_threads > 1 {
+            inner.hide_progress(&mut handle);
+            handle
+                .write_all(format!("\ntest lang_tests::{} ... ", test_fname).as_bytes())
+                .ok();
+        }
+        if !passed {
+            let mut failures = failures.lock().unwrap();
+            failures.push((test_fname, failure));
+            handle
+                .set_color(ColorSpec::new().set_fg(Some(Color::Red)))
+                .ok();
+            handle.write_all(style.fail.as_bytes()).ok();
+            handle.reset().ok();
+        } else {
+            handle
+                .set_color(ColorSpec::new().set_fg(Some(Color::Green)))
+                .ok();
+            handle.write_all(style.pass.as_bytes()).ok();
+            handle.reset().ok();
+        }
+        if !skipped.is_empty() {
+            handle
+                .write_all(format!(" (skipped: {})", skipped.join(", ")).as_bytes())
+                .ok();
+        }
+        inner.show_progress(&mut handle, true);
     }
+
+    false
 }
 
-/// The status of an executed command.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub(crate) enum Status {
-    /// The command exited successfully (by whatever definition of "successful" the running
-    /// platform uses).
-    Success,
-    /// The command did not execute successfully (by whatever definition of "not successful" the
-    /// running platform uses).
-    Error,
-    /// The command terminated due to a signal. This option may not be available on all
-    /// platforms.
-    Signal,
-    /// The command exited with a precise exit code. This option may not be available on all
-    /// platforms.
-    Int(i32),
+/// Can the program `program` be found in the same way that running `cmd` (after `test`'s
+/// alterations to it) would find it? As when spawning a process, a program whose name contains a
+/// `/` is a path (relative to the command's working directory); otherwise it is searched for in
+/// the directories of the command's `PATH` (or, if it has none, a default path). `path` is the
+/// test file.
+fn program_exists(cmd: &Command, test: &TestCmd, path: &Path, program: &str) -> bool {
+    let is_executable = |p: &Path| {
+        fs::metadata(p).is_ok_and(|md| md.is_file() && md.permissions().mode() & 0o111 != 0)
+    };
+    if program.contains('/') {
+        let cwd = match test.cwd {
+            Some(ref cwd) => path.parent().unwrap().join(cwd),
+            None => cmd
+                .get_current_dir()
+                .map(Path::to_owned)
+                .unwrap_or_default(),
+        };
+        return is_executable(&cwd.join(program));
+    }
+    // `env-var` overrides `cmd`'s environment, which `env-clear` removes.
+    let search_path = match test.env.iter().rev().find(|(k, _)| k == "PATH") {
+        Some((_, v)) => Some(OsString::from(v)),
+        None if test.env_clear => None,
+        None => match cmd.get_envs().find(|(k, _)| *k == "PATH") {
+            Some((_, v)) => v.map(|v| v.to_owned()),
+            None => env::var_os("PATH"),
+        },
+    };
+    // As with `execvp`, a command without a `PATH` searches a default path.
+    let search_path = search_path.unwrap_or_else(|| OsString::from("/bin:/usr/bin"));
+    env::split_paths(&search_path).any(|dir| is_executable(&dir.join(program)))
 }
 
-/// A user `TestCmd`.
-#[derive(Clone, Debug)]
-pub(crate) struct TestCmd<'a> {
-    pub status: Status,
-    pub stderr: Vec<&'a str>,
-    pub stdout: Vec<&'a str>,
-    /// A list of custom command line arguments which should be passed when
-    /// executing the test command.
-    pub args: Vec<String>,
+/// Append `test`'s extra arguments (with their placeholders expanded) to `cmd`, set `cmd`'s working
+/// directory if `test` specifies one, and return the data (if any) to be written to `cmd`'s stdin.
+/// `path` is the test file: relative paths in `test` are relative to its directory. `tempdir` is
+/// the test's scratch directory, if it has one. If something `cmd` needs doesn't exist, returns
+/// `Err` with a message suitable for showing to the user.
+fn prepare_cmd(
+    cmd: &mut Command,
+    test: &TestCmd,
+    path: &Path,
+    tempdir: Option<&Path>,
+) -> Result<Option<Vec<u8>>, String> {
+    let dir = path.parent().unwrap();
+    for arg in &test.args {
+        let arg = expand_placeholders(arg, |name| {
+            let p = match name {
+                "tempdir" => tempdir.map(|d| d.to_owned()).ok_or_else(|| {
+                    "{tempdir} can only be used in extra-args if the test_cmds_with_tempdir \
+                     function was used"
+                        .to_owned()
+                })?,
+                _ => {
+                    // Placeholders are absolute paths, so that they're unaffected by `cwd`.
+                    let path = fs::canonicalize(path).map_err(|e| {
+                        format!("Couldn't canonicalise '{}': {}", path.display(), e)
+                    })?;
+                    match name {
+                        "testdir" => path.parent().unwrap().to_owned(),
+                        _ => path,
+                    }
+                }
+            };
+            p.to_str()
+                .map(|s| s.to_owned())
+                .ok_or_else(|| format!("'{}' is not valid UTF-8", p.display()))
+        })?;
+        cmd.arg(arg);
+    }
+    if let Some(ref cwd) = test.cwd {
+        let cwd = dir.join(cwd);
+        if !cwd.is_dir() {
+            return Err(format!(
+                "Working directory '{}' does not exist",
+                cwd.display()
+            ));
+        }
+        cmd.current_dir(cwd);
+    }
+    match (&test.stdin, &test.stdin_file) {
+        (Some(stdin), _) => Ok(Some(stdin.clone())),
+        (None, Some(stdin_file)) => {
+            let stdin_file = dir.join(stdin_file);
+            fs::read(&stdin_file)
+                .map(Some)
+                .map_err(|e| format!("Couldn't read stdin-file '{}': {}", stdin_file.display(), e))
+        }
+        (None, None) => Ok(None),
+    }
 }
 
-impl<'a> TestCmd<'a> {
-    pub fn default() -> Self {
-        Self {
-            status: Status::Success,
-            stderr: vec!["..."],
-            stdout: vec!["..."],
-            args: Vec::new(),
+/// If `file` (the value of the key `key`) is `Some`, return the contents of the pattern file it
+/// names. `path` is the test file: relative paths are relative to its directory. If `normalize` is
+/// `true`, the file's line endings are normalised (see `LangTester::normalize_newlines`). If the
+/// file can't be read, or contains an invalid regular expression, returns `Err` with a message
+/// suitable for showing to the user.
+fn read_pattern_file(
+    file: &Option<PathBuf>,
+    key: &str,
+    path: &Path,
+    normalize: bool,
+) -> Result<Option<String>, String> {
+    let file = match file {
+        Some(file) => path.parent().unwrap().join(file),
+        None => return Ok(None),
+    };
+    let s = read_to_string(&file)
+        .map_err(|e| format!("Couldn't read {} '{}': {}", key, file.display(), e))?;
+    let s = match normalize {
+        true => fuzzy::normalize_newlines(&s).into_owned(),
+        false => s,
+    };
+    for l in pattern_lines(&s) {
+        if let Some(Err(e)) = fuzzy::line_regex(l, false) {
+            return Err(format!(
+                "Invalid regular expression in {} '{}':\n{}",
+                key,
+                file.display(),
+                e
+            ));
+        }
+    }
+    Ok(Some(s))
+}
+
+/// Split the contents of a pattern file into lines in the same way as a multi-line key's value:
+/// each line is trimmed, and leading and trailing blank lines are removed.
+fn pattern_lines(s: &str) -> Vec<&str> {
+    let lines = s.lines().map(|l| l.trim()).collect::<Vec<_>>();
+    let start = lines
+        .iter()
+        .position(|l| !l.is_empty())
+        .unwrap_or(lines.len());
+    let end = lines
+        .iter()
+        .rposition(|l| !l.is_empty())
+        .map_or(start, |i| i + 1);
+    lines[start..end].to_vec()
+}
+
+/// Substitute `captures` (see `fuzzy::match_captures`) into each pattern of `test`, storing the
+/// substituted lines in `store`.
+fn substitute_captures<'a>(
+    test: &mut TestCmd<'a>,
+    captures: &HashMap<String, String>,
+    store: &'a mut Vec<Vec<String>>,
+) {
+    // `-exact` patterns have no regular expression lines, so don't need escaping.
+    let mut patterns = vec![
+        (&mut test.stderr, true),
+        (&mut test.stdout, true),
+        (&mut test.stderr_not, true),
+        (&mut test.stdout_not, true),
+        (&mut test.stderr_contains, true),
+        (&mut test.stdout_contains, true),
+        (&mut test.stderr_ordered, true),
+        (&mut test.stdout_ordered, true),
+    ];
+    patterns.extend(test.output.as_mut().map(|p| (p, true)));
+    patterns.extend(test.stderr_exact.as_mut().map(|p| (p, false)));
+    patterns.extend(test.stdout_exact.as_mut().map(|p| (p, false)));
+    *store = patterns
+        .iter()
+        .map(|(plines, fuzzy)| {
+            plines
+                .iter()
+                .map(|p| match fuzzy {
+                    true => fuzzy::substitute_pattern(p, captures),
+                    false => fuzzy::substitute(p, captures),
+                })
+                .collect()
+        })
+        .collect();
+    let store: &'a Vec<Vec<String>> = store;
+    for ((plines, _), lines) in patterns.into_iter().zip(store) {
+        *plines = lines.iter().map(|l| l.as_str()).collect();
+    }
+}
+
+/// A change to a test's expected output made by `bless`.
+enum BlessEdit {
+    /// Replace the key `key` and its value, which occupy the lines `span` of the test data, with
+    /// `key` and the value `actual`.
+    Inline {
+        key: &'static str,
+        span: Range<usize>,
+        actual: String,
+    },
+    /// Overwrite the expected output file `path` (relative to the directory containing the test
+    /// file) with `actual`.
+    File { path: PathBuf, actual: String },
+}
+
+/// Return the `BlessEdit` which would make the `key` (`stderr`, `stdout`, or `output`) sub-test of
+/// `test` expect `actual`, where `file` is the value of the corresponding `-file` key. Returns
+/// `None` if the sub-test can't be blessed.
+fn bless_edit(
+    test: &TestCmd,
+    key: &'static str,
+    file: &Option<PathBuf>,
+    actual: &str,
+) -> Option<BlessEdit> {
+    if let Some(file) = file {
+        return Some(BlessEdit::File {
+            path: file.clone(),
+            actual: actual.to_owned(),
+        });
+    }
+    test.spans.get(key).map(|span| BlessEdit::Inline {
+        key,
+        span: span.clone(),
+        actual: actual.to_owned(),
+    })
+}
+
+/// Apply `edits` to the test file `path` (whose test data is `test_str`) and/or its expected
+/// output files. If the test data can't be found in the test file, or a file can't be read or
+/// written, returns `Err` with a message suitable for showing to the user.
+fn bless(path: &Path, test_str: &str, edits: Vec<BlessEdit>) -> Result<(), String> {
+    let mut inline = Vec::new();
+    for e in edits {
+        match e {
+            BlessEdit::Inline { key, span, actual } => inline.push((key, span, actual)),
+            BlessEdit::File { path: file, actual } => {
+                let file = path.parent().unwrap().join(file);
+                fs::write(&file, actual)
+                    .map_err(|e| format!("couldn't write '{}': {}", file.display(), e))?;
+            }
+        }
+    }
+    if inline.is_empty() {
+        return Ok(());
+    }
+    let file_str = read_to_string(path).map_err(|e| e.to_string())?;
+    let new_str = bless_inline(&file_str, test_str, inline)?;
+    fs::write(path, new_str).map_err(|e| e.to_string())
+}
+
+/// Return the contents of a test file `file_str` (whose test data is `test_str`) with each
+/// `(key, span, actual)` in `inline` applied as `BlessEdit::Inline { key, span, actual }` is.
+/// Lines which aren't replaced keep their line endings, and replacement lines take theirs from
+/// the lines they replace, so that files with `\r\n` line endings are left with them.
+fn bless_inline(
+    file_str: &str,
+    test_str: &str,
+    mut inline: Vec<(&'static str, Range<usize>, String)>,
+) -> Result<String, String> {
+    fn line_ending(l: &str) -> &str {
+        if l.ends_with("\r\n") {
+            "\r\n"
+        } else if l.ends_with('\n') {
+            "\n"
+        } else {
+            ""
+        }
+    }
+
+    let file_lines_eol = file_str.split_inclusive('\n').collect::<Vec<_>>();
+    let file_lines = file_lines_eol
+        .iter()
+        .map(|l| &l[..l.len() - line_ending(l).len()])
+        .collect::<Vec<_>>();
+    let data_lines = test_str.lines().collect::<Vec<_>>();
+    // Find where in the test file the test data is.
+    let off = (0..(file_lines.len() + 1).saturating_sub(data_lines.len()))
+        .find(|&j| {
+            data_lines
+                .iter()
+                .enumerate()
+                .all(|(i, l)| file_lines[j + i].ends_with(l))
+        })
+        .ok_or_else(|| "couldn't find the test data in the test file".to_owned())?;
+
+    // The line ending used between replacement lines if the line being replaced has none (i.e. it
+    // is the file's last line).
+    let default_eol = file_lines_eol
+        .first()
+        .map(|l| line_ending(l))
+        .filter(|e| !e.is_empty())
+        .unwrap_or("\n");
+    let mut new_lines = file_lines_eol
+        .iter()
+        .map(|l| (*l).to_owned())
+        .collect::<Vec<_>>();
+    // Apply the edits from the end of the file backwards, so that each edit's span remains valid.
+    inline.sort_by_key(|(_, span, _)| Reverse(span.start));
+    for (key, span, actual) in inline {
+        let key_line = data_lines[span.start];
+        let file_key_line = file_lines[off + span.start];
+        // Whatever precedes the test data on each line (e.g. a comment marker).
+        let prefix = &file_key_line[..file_key_line.len() - key_line.len()];
+        let indent = &key_line[..key_line.len() - key_line.trim_start().len()];
+        // If the value was on separate lines, keep its indentation; otherwise indent by 2 more
+        // spaces than the key.
+        let val_indent = data_lines[span.start + 1..span.end]
+            .iter()
+            .find(|l| !l.trim().is_empty())
+            .map(|l| l[..l.len() - l.trim_start().len()].to_owned())
+            .unwrap_or_else(|| format!("{}  ", indent));
+        let actual_lines = pattern_lines(&actual);
+        let mut replacement = Vec::new();
+        if actual_lines.len() == 1 && span.len() == 1 {
+            replacement.push(format!("{}{}{}: {}", prefix, indent, key, actual_lines[0]));
+        } else {
+            replacement.push(format!("{}{}{}:", prefix, indent, key));
+            for l in actual_lines {
+                if l.is_empty() {
+                    replacement.push(prefix.trim_end().to_owned());
+                } else {
+                    replacement.push(format!("{}{}{}", prefix, val_indent, l));
+                }
+            }
+        }
+        let eol = match line_ending(file_lines_eol[off + span.start]) {
+            "" => default_eol,
+            e => e,
+        };
+        let last_eol = line_ending(file_lines_eol[off + span.end - 1]);
+        let n = replacement.len();
+        for (i, l) in replacement.iter_mut().enumerate() {
+            l.push_str(if i + 1 < n { eol } else { last_eol });
+        }
+        new_lines.splice(off + span.start..off + span.end, replacement);
+    }
+    Ok(new_lines.concat())
+}
+
+/// Format `d` as a number of seconds, suitable for showing to the user.
+fn duration_str(d: Duration) -> String {
+    format!("{:.3}s", d.as_secs_f64())
+}
+
+/// The result of running a command once and checking it against its test.
+struct Attempt {
+    status: ExitStatus,
+    stderr: String,
+    stdout: String,
+    /// The bytes the command wrote to stderr/stdout, exactly as they were captured.
+    stderr_bytes: Vec<u8>,
+    stdout_bytes: Vec<u8>,
+    timed_out: bool,
+    /// How long the command took to run, from spawn to exit.
+    duration: Duration,
+    pass_status: bool,
+    /// Did stderr/stdout match the `stderr`/`stdout` (or `output`) patterns?
+    match_stderr: bool,
+    match_stdout: bool,
+    /// If a line of stderr/stdout matched a `stderr-not`/`stdout-not` pattern, a description of
+    /// it.
+    stderr_forbidden: Option<String>,
+    stdout_forbidden: Option<String>,
+    /// If a `stderr-contains`/`stdout-contains` pattern didn't match any line of stderr/stdout, a
+    /// description of it.
+    stderr_missing: Option<String>,
+    stdout_missing: Option<String>,
+    /// If the `stderr-contains-ordered`/`stdout-contains-ordered` patterns didn't match lines of
+    /// stderr/stdout in order, a description of the first pattern which didn't match.
+    stderr_unordered: Option<String>,
+    stdout_unordered: Option<String>,
+    /// If stderr/stdout didn't match `stderr-exact`/`stdout-exact` (or `stdout-equals-stdin`, or
+    /// a `-json` key), the key and a description of the first difference.
+    stderr_mismatch: Option<(&'static str, String)>,
+    stdout_mismatch: Option<(&'static str, String)>,
+    /// If the number of lines of stderr/stdout wasn't in the `stderr-lines`/`stdout-lines` range,
+    /// a description of the difference.
+    stderr_line_count: Option<String>,
+    stdout_line_count: Option<String>,
+    /// If the number of bytes of stderr/stdout wasn't in the `stderr-bytes`/`stdout-bytes`
+    /// range, a description of the difference.
+    stderr_byte_count: Option<String>,
+    stdout_byte_count: Option<String>,
+    /// If an `expect` step of an `interact` block was not satisfied, a description of why.
+    interact_failure: Option<String>,
+    /// If the command's peak resident set size exceeded `max-rss` (or couldn't be measured), a
+    /// description of why.
+    max_rss_failure: Option<String>,
+    /// If the `output-file` couldn't be read, a description of why.
+    output_file_error: Option<String>,
+    /// If the contents of the `output-file` didn't match its `contents` pattern, the file's path
+    /// and contents.
+    output_file_mismatch: Option<(PathBuf, String)>,
+}
+
+impl Attempt {
+    fn passed(&self) -> bool {
+        self.pass_status
+            && self.match_stderr
+            && self.match_stdout
+            && self.stderr_forbidden.is_none()
+            && self.stdout_forbidden.is_none()
+            && self.stderr_missing.is_none()
+            && self.stdout_missing.is_none()
+            && self.stderr_unordered.is_none()
+            && self.stdout_unordered.is_none()
+            && self.stderr_mismatch.is_none()
+            && self.stdout_mismatch.is_none()
+            && self.stderr_line_count.is_none()
+            && self.stdout_line_count.is_none()
+            && self.stderr_byte_count.is_none()
+            && self.stdout_byte_count.is_none()
+            && self.output_file_error.is_none()
+            && self.output_file_mismatch.is_none()
+    }
+}
+
+/// Can the stdout of the command `test` be checked as it is written (see
+/// `LangTester::stream_output`)? `stdin_source` says whether another command takes its stdout as
+/// `stdin-from`.
+fn can_stream(inner: &LangTesterPooler, test: &TestCmd, stdin_source: bool) -> bool {
+    let binds_captures = |p: &&str| {
+        matches!(fuzzy::line_regex(p, false), Some(Ok(re))
+            if re.capture_names().any(|n| n.is_some()))
+    };
+    inner.stream_output
+        && !inner.nocapture
+        && !inner.bless
+        && inner.output_transform.is_none()
+        && !stdin_source
+        && test.output.is_none()
+        && test.stdout_exact.is_none()
+        && test.stdout_json.is_none()
+        && test.stdout_contains.is_empty()
+        && test.stdout_ordered.is_empty()
+        && test.interact.is_empty()
+        && !test.stdout.iter().any(binds_captures)
+}
+
+/// Checks a command's stdout as it is read, keeping only its last `STREAM_TAIL` lines (see
+/// `LangTester::stream_output`).
+struct StdoutStream<'a> {
+    matcher: fuzzy::StreamMatcher<'a>,
+    not: &'a [&'a str],
+    strip_ansi: bool,
+    case_insensitive: bool,
+    /// If `true`, line endings are normalised (see `LangTester::normalize_newlines`).
+    normalize_newlines: bool,
+    /// The last, incomplete, line read so far. This is only converted to UTF-8 once it is
+    /// complete, as a chunk may end part way through a character.
+    partial: Vec<u8>,
+    /// The number of bytes read.
+    bytes: usize,
+    /// The number of lines read.
+    lines: usize,
+    /// The (1-based) numbers of the first and last non-blank lines read.
+    nonblank: Option<(usize, usize)>,
+    /// Have blank lines been read since the last non-blank line?
+    blank: bool,
+    /// If a line matched a `stdout-not` pattern, a description of it.
+    forbidden: Option<String>,
+    /// The last `STREAM_TAIL` lines read.
+    tail: VecDeque<String>,
+}
+
+/// The result of checking a command's stdout with a `StdoutStream`.
+struct Streamed {
+    /// If stdout didn't match the `stdout` pattern, a description of why.
+    mismatch: Option<String>,
+    forbidden: Option<String>,
+    /// The number of lines, ignoring leading and trailing blank lines, as `fuzzy::line_count`.
+    line_count: usize,
+    bytes: usize,
+    /// The last `STREAM_TAIL` lines, preceded by a note of how many lines were not kept.
+    tail: String,
+}
+
+impl<'a> StdoutStream<'a> {
+    fn new(inner: &LangTesterPooler, test: &'a TestCmd<'a>) -> Self {
+        StdoutStream {
+            matcher: fuzzy::StreamMatcher::new(
+                &test.stdout,
+                test.stdout_raw,
+                inner.case_insensitive,
+            ),
+            not: &test.stdout_not,
+            strip_ansi: inner.strip_ansi,
+            case_insensitive: inner.case_insensitive,
+            normalize_newlines: inner.normalize_newlines && !test.stdout_raw,
+            partial: Vec::new(),
+            bytes: 0,
+            lines: 0,
+            nonblank: None,
+            blank: false,
+            forbidden: None,
+            tail: VecDeque::new(),
+        }
+    }
+
+    /// Check the next chunk of stdout, `b`.
+    fn push_bytes(&mut self, b: &[u8]) {
+        self.bytes += b.len();
+        self.partial.extend_from_slice(b);
+        // A `\n` byte is never part of a multi-byte character, so everything up to the last one
+        // can be converted to UTF-8 on its own.
+        if let Some(i) = self.partial.iter().rposition(|&c| c == b'\n') {
+            let rest = self.partial.split_off(i + 1);
+            let complete = std::mem::replace(&mut self.partial, rest);
+            self.push_lines(&String::from_utf8_lossy(&complete));
+        }
+    }
+
+    fn push_lines(&mut self, s: &str) {
+        let s = match self.normalize_newlines {
+            true => fuzzy::normalize_newlines(s),
+            false => Cow::Borrowed(s),
+        };
+        for l in s.lines() {
+            self.push_line(l);
+        }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        self.lines += 1;
+        self.tail.push_back(line.to_owned());
+        if self.tail.len() > STREAM_TAIL {
+            self.tail.pop_front();
+        }
+        let stripped;
+        let line = if self.strip_ansi {
+            stripped = fuzzy::strip_ansi(line);
+            &stripped
+        } else {
+            line
+        };
+        self.matcher.push_line(line);
+        if line.trim().is_empty() {
+            self.blank = self.nonblank.is_some();
+            return;
+        }
+        self.nonblank = Some((
+            self.nonblank.map_or(self.lines, |(first, _)| first),
+            self.lines,
+        ));
+        if self.forbidden.is_none() {
+            // Blank lines between non-blank lines are checked too (as `fuzzy::find_line` does).
+            let blank = self.blank;
+            self.blank = false;
+            let mut check = vec![line];
+            if blank {
+                check.insert(0, "");
+            }
+            self.forbidden = check.into_iter().find_map(|l| {
+                fuzzy::find_line(self.not, l, self.case_insensitive)
+                    .map(|(p, l)| format!("Line '{}' matches forbidden pattern '{}'", l, p))
+            });
+        }
+    }
+
+    fn finish(mut self) -> Streamed {
+        if !self.partial.is_empty() {
+            let partial = std::mem::take(&mut self.partial);
+            self.push_lines(&String::from_utf8_lossy(&partial));
+        }
+        let not_kept = self.lines - self.tail.len();
+        let mut tail = Vec::from(self.tail).join("\n");
+        if not_kept > 0 {
+            tail = format!("[{} earlier lines not kept]\n{}", not_kept, tail);
+        }
+        Streamed {
+            mismatch: self.matcher.finish(),
+            forbidden: self.forbidden,
+            line_count: self.nonblank.map_or(0, |(first, last)| last - first + 1),
+            bytes: self.bytes,
+            tail,
         }
     }
 }
 
-/// A collection of tests.
-pub(crate) struct Tests<'a> {
-    pub ignore: bool,
-    pub tests: HashMap<String, TestCmd<'a>>,
-}
+/// Run `cmd` (the command `cmd_name` of the test `test_fname`) once, writing `stdin` (if any) to
+/// it, and check the result against `test`. If `stream` is `true`, stdout is checked as it is
+/// read (see `can_stream`).
+fn run_attempt(
+    inner: &Arc<LangTesterPooler>,
+    test_fname: &str,
+    cmd_name: &str,
+    cmd: &mut Command,
+    test: &TestCmd,
+    stdin: &Option<Vec<u8>>,
+    stream: bool,
+) -> Attempt {
+    let timeout = test.timeout.or(inner.test_timeout);
+    // If nothing is captured, `expect` steps can't be checked, so `send` steps are simply
+    // written to stdin up front.
+    let (stdin, interact) = if inner.nocapture && !test.interact.is_empty() {
+        let sends = test
+            .interact
+            .iter()
+            .filter_map(|step| match step {
+                InteractStep::Send(l) => Some(format!("{}\n", l)),
+                InteractStep::Expect(_) => None,
+            })
+            .collect::<String>();
+        (Some(sends.into_bytes()), &[][..])
+    } else {
+        (stdin.clone(), test.interact.as_slice())
+    };
+    let mut stdout_stream = match stream {
+        true => Some(StdoutStream::new(inner, test)),
+        false => None,
+    };
+    if let Some(delay) = test.delay {
+        sleep(delay);
+    }
+    let cmd_start = Instant::now();
+    let (status, stderr, stdout, timed_out, interact_failure, peak_rss) = run_cmd(
+        Arc::clone(inner),
+        test_fname,
+        cmd,
+        stdin,
+        interact,
+        timeout,
+        test.output.is_some(),
+        stdout_stream.as_mut(),
+    );
+    let duration = cmd_start.elapsed();
+    let streamed = stdout_stream.map(StdoutStream::finish);
+    // Captured output is an exact copy of what the command wrote, so these are the numbers of
+    // bytes it wrote.
+    let (stderr_len, stdout_len) = (
+        stderr.len(),
+        streamed.as_ref().map_or(stdout.len(), |s| s.bytes),
+    );
+    let stderr_bytes = stderr;
+    let stderr = String::from_utf8_lossy(&stderr_bytes).into_owned();
+    // Streamed stdout isn't kept, so there are no raw bytes: only its (already decoded) tail is
+    // available to report.
+    let (stdout, stdout_bytes) = match streamed {
+        Some(ref streamed) => (streamed.tail.clone(), Vec::new()),
+        None => (String::from_utf8_lossy(&stdout).into_owned(), stdout),
+    };
+    let (stderr, stdout) = if inner.strip_ansi {
+        (fuzzy::strip_ansi(&stderr), fuzzy::strip_ansi(&stdout))
+    } else {
+        (stderr, stdout)
+    };
+    let (stderr, stdout) = match inner.output_transform {
+        Some(ref f) if test.output.is_some() => (stderr, f(&stdout)),
+        Some(ref f) => (f(&stderr), f(&stdout)),
+        None => (stderr, stdout),
+    };
+    // Output checked by a raw or exact key is left as the command wrote it.
+    let normalize = |s: String, raw: bool| {
+        if !inner.normalize_newlines || raw {
+            return s;
+        }
+        match fuzzy::normalize_newlines(&s) {
+            Cow::Borrowed(_) => s,
+            Cow::Owned(n) => n,
+        }
+    };
+    let stderr = normalize(
+        stderr,
+        test.output.is_none() && (test.stderr_raw || test.stderr_exact.is_some()),
+    );
+    let stdout = normalize(
+        stdout,
+        test.output.is_none() && (test.stdout_raw || test.stdout_exact.is_some()),
+    );
 
-/// If one or more parts of a `TestCmd` fail, the parts that fail are set to `Some(...)` in an
-/// instance of this struct.
-#[derive(Debug, PartialEq)]
-struct TestFailure {
-    status: Option<String>,
-    stderr: Option<String>,
-    stdout: Option<String>,
+    let max_rss_failure = match (test.max_rss, peak_rss) {
+        (None, _) => None,
+        (Some(_), None) => Some(format!(
+            "{}: max-rss is not supported on this platform",
+            cmd_name
+        )),
+        (Some(max_rss), Some(peak)) if peak > max_rss * MB => Some(format!(
+            "{}: Peak RSS of {:.1} MB exceeded max-rss of {} MB",
+            cmd_name,
+            peak as f64 / MB as f64,
+            max_rss
+        )),
+        (Some(_), Some(_)) => None,
+    };
+    // A command whose `interact` block failed, or which exceeded `max-rss`, always fails, as does
+    // one which timed out unless it was expected to.
+    let pass_status = interact_failure.is_none()
+        && max_rss_failure.is_none()
+        && match timed_out {
+            true => test.status == Status::Timeout,
+            false => test.status.matches(status, &inner.ignore_signals),
+        };
+    // If the command has an `output` test, `stdout` contains the combined stderr and stdout (and
+    // `stderr` is empty).
+    let (match_stderr, match_stdout) = if inner.nocapture {
+        // We haven't captured anything, so we can't check stderr/stdout.
+        if test.output.is_some()
+            || test.stderr != ["..."]
+            || test.stdout != ["..."]
+            || test.stderr_exact.is_some()
+            || test.stdout_exact.is_some()
+            || test.stderr_json.is_some()
+            || test.stdout_json.is_some()
+            || !test.stderr_not.is_empty()
+            || !test.stdout_not.is_empty()
+            || !test.stderr_contains.is_empty()
+            || !test.stdout_contains.is_empty()
+            || !test.stderr_ordered.is_empty()
+            || !test.stdout_ordered.is_empty()
+            || test.stderr_lines.is_some()
+            || test.stdout_lines.is_some()
+            || test.stderr_bytes.is_some()
+            || test.stdout_bytes.is_some()
+            || test
+                .interact
+                .iter()
+                .any(|step| matches!(step, InteractStep::Expect(_)))
+        {
+            write_nocapture_warning(test_fname, cmd_name, &inner.reporter_style);
+        }
+        (true, true)
+    } else {
+        match test.output {
+            Some(ref output) => (
+                true,
+                fuzzy::match_vec(output, &stdout, inner.case_insensitive),
+            ),
+            None => {
+                let match_vec = |plines: &[&str], s: &str, raw: bool| {
+                    if raw {
+                        fuzzy::match_vec_raw(plines, s, inner.case_insensitive)
+                    } else {
+                        fuzzy::match_vec(plines, s, inner.case_insensitive)
+                    }
+                };
+                (
+                    test.stderr_exact.is_some()
+                        || test.stderr_json.is_some()
+                        || match_vec(&test.stderr, &stderr, test.stderr_raw),
+                    streamed.is_some()
+                        || test.stdout_exact.is_some()
+                        || test.stdout_json.is_some()
+                        || match_vec(&test.stdout, &stdout, test.stdout_raw),
+                )
+            }
+        }
+    };
+    let mismatch = |elines: &Option<Vec<&str>>,
+                    jlines: &Option<Vec<&str>>,
+                    subset: bool,
+                    s: &str,
+                    exact_key: &'static str,
+                    json_key: &'static str| {
+        if inner.nocapture {
+            return None;
+        }
+        if let Some(elines) = elines {
+            return fuzzy::exact_mismatch(elines, s).map(|m| (exact_key, m));
+        }
+        let jlines = jlines.as_ref()?;
+        // The expected JSON was checked when the test was parsed.
+        let expected = json::parse(&jlines.join("\n")).unwrap();
+        let msg = match json::parse(s) {
+            Ok(actual) => json::diff(&expected, &actual, subset)?,
+            Err(e) => format!("Output is not valid JSON: {}", e),
+        };
+        Some((json_key, msg))
+    };
+    let stderr_mismatch = mismatch(
+        &test.stderr_exact,
+        &test.stderr_json,
+        test.stderr_json_subset,
+        &stderr,
+        "stderr-exact",
+        if test.stderr_json_subset {
+            "stderr-json-subset"
+        } else {
+            "stderr-json"
+        },
+    );
+    let stdout_mismatch = match streamed {
+        Some(ref streamed) => streamed.mismatch.clone().map(|m| {
+            let key = match test.stdout_raw {
+                true => "stdout-raw (streamed)",
+                false => "stdout (streamed)",
+            };
+            (key, m)
+        }),
+        None => mismatch(
+            &test.stdout_exact,
+            &test.stdout_json,
+            test.stdout_json_subset,
+            &stdout,
+            if test.stdout_equals_stdin {
+                "stdout-equals-stdin"
+            } else {
+                "stdout-exact"
+            },
+            if test.stdout_json_subset {
+                "stdout-json-subset"
+            } else {
+                "stdout-json"
+            },
+        ),
+    };
+    let forbidden = |plines: &[&str], s: &str| {
+        if inner.nocapture {
+            return None;
+        }
+        fuzzy::find_line(plines, s, inner.case_insensitive)
+            .map(|(p, l)| format!("Line '{}' matches forbidden pattern '{}'", l, p))
+    };
+    let stderr_forbidden = forbidden(&test.stderr_not, &stderr);
+    let stdout_forbidden = match streamed {
+        Some(ref streamed) => streamed.forbidden.clone(),
+        None => forbidden(&test.stdout_not, &stdout),
+    };
+    let missing = |plines: &[&str], s: &str| {
+        if inner.nocapture {
+            return None;
+        }
+        fuzzy::find_missing_line(plines, s, inner.contains_substring, inner.case_insensitive).map(
+            |p| match inner.contains_substring {
+                true => format!("No line contains '{}'", p),
+                false => format!("No line matches pattern '{}'", p),
+            },
+        )
+    };
+    let stderr_missing = missing(&test.stderr_contains, &stderr);
+    let stdout_missing = missing(&test.stdout_contains, &stdout);
+    let unordered = |plines: &[&str], s: &str| {
+        if inner.nocapture {
+            return None;
+        }
+        fuzzy::find_unmatched_subsequence(plines, s, inner.case_insensitive).map(|(p, prev)| {
+            match prev {
+                Some(l) => format!("No line after '{}' matches pattern '{}'", l, p),
+                None => format!("No line matches pattern '{}'", p),
+            }
+        })
+    };
+    let stderr_unordered = unordered(&test.stderr_ordered, &stderr);
+    let stdout_unordered = unordered(&test.stdout_ordered, &stdout);
+    let count = |range: &Option<RangeInclusive<usize>>, n: usize, unit: &str| match range {
+        Some(range) if !inner.nocapture => {
+            if range.contains(&n) {
+                None
+            } else if range.start() == range.end() {
+                Some(format!("Expected {} {} but got {}", range.start(), unit, n))
+            } else {
+                Some(format!(
+                    "Expected {} to {} {} but got {}",
+                    range.start(),
+                    range.end(),
+                    unit,
+                    n
+                ))
+            }
+        }
+        _ => None,
+    };
+    let stderr_line_count = count(&test.stderr_lines, fuzzy::line_count(&stderr), "lines");
+    let stdout_lines = streamed
+        .as_ref()
+        .map_or_else(|| fuzzy::line_count(&stdout), |s| s.line_count);
+    let stdout_line_count = count(&test.stdout_lines, stdout_lines, "lines");
+    let stderr_byte_count = count(&test.stderr_bytes, stderr_len, "bytes");
+    let stdout_byte_count = count(&test.stdout_bytes, stdout_len, "bytes");
+    let (output_file_error, output_file_mismatch) = match check_output_file(cmd, test, inner) {
+        Ok(()) => (None, None),
+        Err(OutputFileFailure::Unreadable(msg)) => (Some(msg), None),
+        Err(OutputFileFailure::Mismatch(path, contents)) => (None, Some((path, contents))),
+    };
+    Attempt {
+        status,
+        stderr,
+        stdout,
+        stderr_bytes,
+        stdout_bytes,
+        timed_out,
+        duration,
+        pass_status,
+        match_stderr,
+        match_stdout,
+        stderr_forbidden,
+        stdout_forbidden,
+        stderr_missing,
+        stdout_missing,
+        stderr_unordered,
+        stdout_unordered,
+        stderr_mismatch,
+        stdout_mismatch,
+        stderr_line_count,
+        stdout_line_count,
+        stderr_byte_count,
+        stdout_byte_count,
+        interact_failure,
+        max_rss_failure,
+        output_file_error,
+        output_file_mismatch,
+    }
 }
 
-fn write_with_colour(s: &str, colour: Color) {
-    let mut stderr = StandardStream::stderr(ColorChoice::Always);
-    stderr.set_color(ColorSpec::new().set_fg(Some(colour))).ok();
-    io::stderr().write_all(s.as_bytes()).ok();
-    stderr.reset().ok();
+/// Why an `output-file` sub-test failed.
+enum OutputFileFailure {
+    /// The file doesn't exist, or couldn't be read: a description of why, suitable for showing to
+    /// the user.
+    Unreadable(String),
+    /// The file's contents (the second element) didn't match the `contents` pattern.
+    Mismatch(PathBuf, String),
 }
 
-fn write_ignored(test_name: &str, message: &str, inner: Arc<LangTesterPooler>) {
-    // Grab a lock on stderr so that we can avoid the possibility of lines blurring
-    // together in confusing ways.
-    let stderr = StandardStream::stderr(ColorChoice::Always);
-    let mut handle = stderr.lock();
-    if inner.test_threads > 1 {
-        handle
-            .write_all(&format!("\ntest lang_tests::{} ... ", test_name).as_bytes())
-            .ok();
-    }
-    handle
-        .set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))
-        .ok();
-    handle.write_all(b"ignored").ok();
-    handle.reset().ok();
-    if !message.is_empty() {
-        handle.write_all(format!(" ({})", message).as_bytes()).ok();
+/// Check that the `output-file` of `test`, if any, exists after `cmd` has run and, if it has a
+/// `contents` pattern, that its contents match.
+fn check_output_file(
+    cmd: &Command,
+    test: &TestCmd,
+    inner: &LangTesterPooler,
+) -> Result<(), OutputFileFailure> {
+    let path = match test.output_file {
+        Some(ref p) => match cmd.get_current_dir() {
+            Some(cwd) => cwd.join(p),
+            None => p.clone(),
+        },
+        None => return Ok(()),
+    };
+    let pattern = match test.output_file_contents {
+        Some(ref pattern) => pattern,
+        None if path.is_file() => return Ok(()),
+        None => {
+            return Err(OutputFileFailure::Unreadable(format!(
+                "Output file '{}' does not exist",
+                path.display()
+            )))
+        }
+    };
+    let contents = fs::read_to_string(&path).map_err(|e| {
+        OutputFileFailure::Unreadable(format!(
+            "Couldn't read output file '{}': {}",
+            path.display(),
+            e
+        ))
+    })?;
+    if fuzzy::match_vec(pattern, &contents, inner.case_insensitive) {
+        Ok(())
+    } else {
+        Err(OutputFileFailure::Mismatch(path, contents))
     }
 }
 
-fn usage() -> ! {
-    eprintln!("Usage: [--ignored] [--nocapture] [--test-threads=<n>] [<filter1>] [... <filtern>]");
-    process::exit(1);
+/// A description of how a command exited, suitable for showing to the user.
+fn status_desc(
+    status: ExitStatus,
+    timed_out: bool,
+    timeout: Option<Duration>,
+    inner: &LangTesterPooler,
+) -> String {
+    if timed_out {
+        format!("Timed out after {}s", timeout.unwrap().as_secs())
+    } else if let Some(code) = status.code() {
+        if status.success() {
+            "Success".to_owned()
+        } else {
+            format!("Exited with code {}", code)
+        }
+    } else {
+        let sig = status.signal().unwrap();
+        if let (Some(cpu_limit), true) = (inner.cpu_limit, sig == Signal::SIGXCPU as i32) {
+            return format!(
+                "Killed: exceeded CPU limit of {}s",
+                cpu_limit_secs(cpu_limit)
+            );
+        }
+        let mut desc = match Signal::try_from(sig) {
+            Ok(name) => format!("Exited due to signal: {} ({})", sig, name),
+            Err(_) => format!("Exited due to signal: {}", sig),
+        };
+        // A command whose allocations fail often aborts, but we can't know that's why it did.
+        if let Some(memory_limit) = inner.memory_limit {
+            desc.push_str(&format!(
+                " (possibly due to exceeding the memory limit of {} bytes)",
+                memory_limit
+            ));
+        }
+        desc
+    }
 }
 
-/// Check for the case where the user has a test called `X` but `test_cmds` doesn't have a command
-/// with a matching name. This is almost certainly a bug, in the sense that the test can never,
-/// ever fire.
-fn check_names<'a>(cmd_pairs: &[(String, Command)], tests: &HashMap<String, TestCmd<'a>>) {
-    let cmd_names = cmd_pairs.iter().map(|x| &x.0).collect::<HashSet<_>>();
-    let test_names = tests.keys().map(|x| x).collect::<HashSet<_>>();
-    let diff = test_names
-        .difference(&cmd_names)
-        .map(|x| x.as_str())
-        .collect::<Vec<_>>();
-    if !diff.is_empty() {
-        fatal(&format!(
-            "Command name(s) '{}' in tests are not found in the actual commands.",
-            diff.join(", ")
-        ));
+/// Return `cpu_limit` as the whole number of seconds `RLIMIT_CPU` is set to.
+fn cpu_limit_secs(cpu_limit: Duration) -> u64 {
+    let secs = cpu_limit.as_secs();
+    if cpu_limit.subsec_nanos() > 0 {
+        secs + 1
+    } else {
+        secs
     }
 }
 
-/// Run every test in `test_files`, returning a tuple `(failures, num_ignored)`.
-fn test_file(
-    test_files: Vec<PathBuf>,
-    inner: Arc<LangTesterPooler>,
-) -> (Vec<(String, TestFailure)>, usize) {
-    let failures = Arc::new(Mutex::new(Vec::new()));
-    let num_ignored = Arc::new(AtomicUsize::new(0));
-    let pool = ThreadPool::new(inner.test_threads);
-    for p in test_files {
-        let test_fname = p.file_stem().unwrap().to_str().unwrap().to_owned();
-
-        let num_ignored = num_ignored.clone();
-        let failures = failures.clone();
-        let inner = inner.clone();
-        pool.execute(move || {
-            if inner.test_threads == 1 {
-                eprint!("\ntest lang_test::{} ... ", test_fname);
-            }
-            let all_str = read_to_string(p.as_path())
-                .unwrap_or_else(|_| fatal(&format!("Couldn't read {}", test_fname)));
-            let test_str = inner.test_extract.as_ref().unwrap()(&all_str).unwrap_or_else(|| {
-                fatal(&format!("Couldn't extract test string from {}", test_fname))
-            });
-
-            if test_str.is_empty() {
-                write_ignored(test_fname.as_str(), "test string is empty", inner);
-                num_ignored.fetch_add(1, Ordering::Relaxed);
-                return;
-            }
-
-            let tests = parse_tests(&test_str);
-            if (inner.ignored && !tests.ignore) || (!inner.ignored && tests.ignore) {
-                write_ignored(test_fname.as_str(), "", inner);
-                num_ignored.fetch_add(1, Ordering::Relaxed);
-                return;
+/// Arrange for `cmd`'s address space to be limited to `memory_limit` bytes, and its CPU time to
+/// `cpu_limit` (if either is `Some`).
+fn set_resource_limits(cmd: &mut Command, memory_limit: Option<u64>, cpu_limit: Option<Duration>) {
+    if memory_limit.is_none() && cpu_limit.is_none() {
+        return;
+    }
+    let cpu_secs = cpu_limit.map(cpu_limit_secs);
+    // The closure runs in the child process after `fork`, so it must only call
+    // async-signal-safe functions (which `getrlimit` and `setrlimit` are), and must not allocate.
+    unsafe {
+        cmd.pre_exec(move || {
+            if let Some(bytes) = memory_limit {
+                set_rlimit(libc::RLIMIT_AS, bytes, bytes)?;
             }
-
-            if run_tests(Arc::clone(&inner), tests.tests, p, failures) {
-                num_ignored.fetch_add(1, Ordering::Relaxed);
+            if let Some(secs) = cpu_secs {
+                // Exceeding the soft limit sends `SIGXCPU`; if the command survives that, the
+                // hard limit kills it a second later.
+                set_rlimit(libc::RLIMIT_CPU, secs, secs + 1)?;
             }
+            Ok(())
         });
     }
-    pool.join();
-    let failures = Mutex::into_inner(Arc::try_unwrap(failures).unwrap()).unwrap();
-
-    (failures, Arc::try_unwrap(num_ignored).unwrap().into_inner())
 }
 
-/// Run the tests for `path`.
-fn run_tests<'a>(
-    inner: Arc<LangTesterPooler>,
-    tests: HashMap<String, TestCmd<'a>>,
-    path: PathBuf,
-    failures: Arc<Mutex<Vec<(String, TestFailure)>>>,
-) -> bool {
-    let test_fname = path.file_stem().unwrap().to_str().unwrap().to_owned();
+/// The type of the `resource` argument to `getrlimit` and `setrlimit`, which varies by platform.
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type RlimitResource = libc::c_int;
 
-    if !cfg!(unix) && tests.values().any(|t| t.status == Status::Signal) {
-        write_ignored(
-            test_fname.as_str(),
-            "signal termination not supported on this platform",
-            inner,
-        );
-        return true;
+/// Set the soft and hard limits of `resource` in the current process to (at most) `soft` and
+/// `hard`. Limits higher than the current hard limit are lowered to it, since an unprivileged
+/// process can't raise its hard limits.
+fn set_rlimit(resource: RlimitResource, soft: u64, hard: u64) -> io::Result<()> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(resource, &mut rlim) } != 0 {
+        return Err(io::Error::last_os_error());
     }
+    rlim.rlim_max = rlim.rlim_max.min(hard as libc::rlim_t);
+    rlim.rlim_cur = rlim.rlim_max.min(soft as libc::rlim_t);
+    if unsafe { libc::setrlimit(resource, &rlim) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
 
-    let cmd_pairs = inner.test_cmds.as_ref().unwrap()(path.as_path())
-        .into_iter()
-        .map(|(test_name, cmd)| (test_name.to_lowercase(), cmd))
-        .collect::<Vec<_>>();
-    check_names(&cmd_pairs, &tests);
-
-    let mut failure = TestFailure {
-        status: None,
-        stderr: None,
-        stdout: None,
-    };
-    for (cmd_name, mut cmd) in cmd_pairs {
-        let default_test = TestCmd::default();
-        let test = tests.get(&cmd_name).unwrap_or(&default_test);
-        cmd.args(&test.args);
-        let (status, stderr, stdout) = run_cmd(inner.clone(), &test_fname, cmd);
-
-        let mut meant_to_error = false;
-
-        // First, check whether the tests passed.
-        let pass_status = match test.status {
-            Status::Success => status.success(),
-            Status::Error => {
-                meant_to_error = true;
-                !status.success()
-            }
-            Status::Signal => status.signal().is_some(),
-            Status::Int(i) => status.code() == Some(i),
-        };
-        let pass_stderr = fuzzy::match_vec(&test.stderr, &stderr);
-        let pass_stdout = fuzzy::match_vec(&test.stdout, &stdout);
+/// The state of a running command's `interact` steps.
+struct Interaction<'a> {
+    steps: &'a [InteractStep<'a>],
+    /// The index in `steps` of the next step to be performed.
+    next: usize,
+    /// The offset in the captured stdout up to which lines have been matched by `expect` steps.
+    matched: usize,
+    /// If `Some`, the time by which the current `expect` step must be satisfied.
+    deadline: Option<Instant>,
+    /// The command's stdin, which is dropped (so that the command sees EOF) once every step has
+    /// been performed.
+    stdin: Option<ChildStdin>,
+}
 
-        // Second, if a test failed, we want to print out everything which didn't match
-        // successfully (i.e. if the stderr test failed, print that out; but, equally, if
-        // stderr wasn't specified as a test, print it out, because the user can't
-        // otherwise know what it contains).
-        if !(pass_status && pass_stderr && pass_stdout) {
-            if !pass_status || failure.status.is_none() {
-                match test.status {
-                    Status::Success | Status::Error => {
-                        if status.success() {
-                            failure.status = Some("Success".to_owned());
-                        } else if status.code().is_none() {
-                            failure.status = Some(format!(
-                                "Exited due to signal: {}",
-                                status.signal().unwrap()
-                            ));
-                        } else {
-                            failure.status = Some("Error".to_owned());
-                        }
+impl Interaction<'_> {
+    /// Perform as many steps as possible given the stdout `stdout` captured so far. Returns `Err`
+    /// with a description of the failure if an `expect` step can no longer be satisfied, either
+    /// because it has timed out or because stdout has been closed.
+    fn advance(
+        &mut self,
+        stdout: &[u8],
+        stdout_closed: bool,
+        case_insensitive: bool,
+    ) -> Result<(), String> {
+        while let Some(step) = self.steps.get(self.next) {
+            match *step {
+                InteractStep::Send(l) => {
+                    // As with `stdin`, if the command has exited we don't care that the write
+                    // fails.
+                    if let Some(ref mut stdin) = self.stdin {
+                        stdin.write_all(format!("{}\n", l).as_bytes()).ok();
                     }
-                    Status::Signal => {
-                        failure.status = Some("Exit was not due to signal".to_owned());
-                    }
-                    Status::Int(_) => {
-                        failure.status =
-                            Some(status.code().map(|x| x.to_string()).unwrap_or_else(|| {
-                                format!("Exited due to signal: {}", status.signal().unwrap())
-                            }))
+                }
+                InteractStep::Expect(p) => {
+                    let unmatched = &stdout[self.matched..];
+                    match fuzzy::find_line_end(p, unmatched, case_insensitive) {
+                        Some(end) => {
+                            self.matched += end;
+                            self.deadline = None;
+                        }
+                        None => {
+                            let deadline = *self.deadline.get_or_insert_with(|| {
+                                Instant::now() + Duration::from_secs(EXPECT_TIMEOUT)
+                            });
+                            let unmatched = String::from_utf8_lossy(unmatched);
+                            let got = match unmatched.trim() {
+                                "" => "<no output>",
+                                s => s,
+                            };
+                            if stdout_closed {
+                                return Err(format!(
+                                    "Expected a line matching '{}' but stdout was closed \
+                                     after:\n{}",
+                                    p, got
+                                ));
+                            } else if Instant::now() >= deadline {
+                                return Err(format!(
+                                    "Expected a line matching '{}' within {}s but got:\n{}",
+                                    p, EXPECT_TIMEOUT, got
+                                ));
+                            }
+                            return Ok(());
+                        }
                     }
                 }
             }
-
-            if !pass_stderr || failure.stderr.is_none() {
-                failure.stderr = Some(stderr);
-            }
-
-            if !pass_stdout || failure.stdout.is_none() {
-                failure.stdout = Some(stdout);
-            }
-
-            // If a sub-test failed, bail out immediately, otherwise subsequent sub-tests
-            // will overwrite the failure output!
-            break;
-        }
-
-        // If a command failed, and we weren't expecting it to, bail out immediately.
-        if !status.success() && meant_to_error {
-            break;
-        }
-    }
-
-    {
-        // Grab a lock on stderr so that we can avoid the possibility of lines blurring
-        // together in confusing ways.
-        let stderr = StandardStream::stderr(ColorChoice::Always);
-        let mut handle = stderr.lock();
-        if inner.test_threads > 1 {
-            handle
-                .write_all(&format!("\ntest lang_tests::{} ... ", test_fname).as_bytes())
-                .ok();
-        }
-        if failure
-            != (TestFailure {
-                status: None,
-                stderr: None,
-                stdout: None,
-            })
-        {
-            let mut failures = failures.lock().unwrap();
-            failures.push((test_fname, failure));
-            handle
-                .set_color(ColorSpec::new().set_fg(Some(Color::Red)))
-                .ok();
-            handle.write_all(b"FAILED").ok();
-            handle.reset().ok();
-        } else {
-            handle
-                .set_color(ColorSpec::new().set_fg(Some(Color::Green)))
-                .ok();
-            handle.write_all(b"ok").ok();
-            handle.reset().ok();
+            self.next += 1;
         }
+        self.stdin = None;
+        Ok(())
     }
-
-    false
 }
 
+#[allow(clippy::too_many_arguments)]
 fn run_cmd(
     inner: Arc<LangTesterPooler>,
     test_fname: &str,
-    mut cmd: Command,
-) -> (ExitStatus, String, String) {
+    cmd: &mut Command,
+    stdin: Option<Vec<u8>>,
+    interact: &[InteractStep],
+    timeout: Option<Duration>,
+    combined: bool,
+    mut stdout_stream: Option<&mut StdoutStream>,
+) -> (
+    ExitStatus,
+    Vec<u8>,
+    Vec<u8>,
+    bool,
+    Option<String>,
+    Option<u64>,
+) {
     // The basic sequence here is:
     //   1) Spawn the command
-    //   2) Read everything from stderr & stdout until they are both disconnected
-    //   3) wait() for the command to finish
+    //   2) Write any stdin data to the command (in a separate thread, so that a command which
+    //      produces lots of output before having read all of its input can't deadlock us)
+    //   3) Read everything from stderr & stdout until they are both disconnected
+    //   4) wait() for the command to finish
+    // If `interact` is non-empty, step 2 is instead performed incrementally during step 3, with
+    // each `send` line written only once the preceding `expect` steps have matched. If the command
+    // runs for longer than `timeout`, it is killed (and reaped) at whatever point in the sequence
+    // we have reached. If `combined` is true, stderr and stdout are connected to a
+    // single pipe, so that their output is captured, interleaved, in the order it was written:
+    // the result is returned as if it was all written to stdout. If `inner.nocapture` is true,
+    // stderr and stdout are inherited from the parent process and nothing is captured. If
+    // `stdout_stream` is `Some`, stdout is passed to it as it is read, and not captured. The
+    // captured stderr and stdout are returned as the raw bytes the command wrote, and the
+    // command's peak resident set size, in bytes, is returned if the platform can measure it.
 
+    let combined_pipe = if inner.nocapture {
+        cmd.stderr(process::Stdio::inherit())
+            .stdout(process::Stdio::inherit());
+        None
+    } else if combined {
+        let pipe = Pipe::new().unwrap_or_else(|_| fatal("Couldn't create pipe."));
+        let stdio = || {
+            pipe.write
+                .as_stdio()
+                .unwrap_or_else(|_| fatal("Couldn't duplicate pipe."))
+        };
+        cmd.stderr(stdio()).stdout(stdio());
+        Some(pipe)
+    } else {
+        cmd.stderr(process::Stdio::piped())
+            .stdout(process::Stdio::piped());
+        None
+    };
     let mut child = cmd
-        .stderr(process::Stdio::piped())
-        .stdout(process::Stdio::piped())
-        .stdin(process::Stdio::null())
+        .stdin(match stdin {
+            Some(_) => process::Stdio::piped(),
+            None if !interact.is_empty() => process::Stdio::piped(),
+            None => process::Stdio::null(),
+        })
         .spawn()
         .unwrap_or_else(|_| fatal(&format!("Couldn't run command {:?}.", cmd)));
 
-    let mut stderr = FileDescriptor::dup(child.stderr.as_ref().unwrap()).unwrap();
-    let mut stdout = FileDescriptor::dup(child.stdout.as_ref().unwrap()).unwrap();
+    let mut interaction = match interact {
+        [] => None,
+        steps => Some(Interaction {
+            steps,
+            next: 0,
+            matched: 0,
+            deadline: None,
+            stdin: child.stdin.take(),
+        }),
+    };
+    let mut interact_failure = None;
+
+    let stdin_thread = match (stdin, child.stdin.take()) {
+        (Some(stdin), Some(mut child_stdin)) => {
+            if stdin.is_empty() {
+                // Dropping `child_stdin` closes the pipe, so the child immediately sees EOF.
+                None
+            } else {
+                Some(thread::spawn(move || {
+                    // If the child exits before reading all of its input, the write will fail
+                    // with `EPIPE`: that isn't our concern, so we ignore any errors.
+                    child_stdin.write_all(&stdin).ok();
+                }))
+            }
+        }
+        _ => None,
+    };
 
-    non_blocking(&stderr, &stdout)
-        .unwrap_or_else(|_| fatal("Couldn't set stderr and stdout to be non-blocking."));
+    // Each stream we're reading from, and whether it's stderr (`true`) or stdout (`false`).
+    let mut streams = match combined_pipe {
+        _ if inner.nocapture => Vec::new(),
+        Some(pipe) => {
+            // We only see EOF on the pipe once every copy of its write end is closed: there is
+            // one in `pipe` and two in `cmd` (which keeps hold of the `Stdio`s we gave it).
+            drop(pipe.write);
+            cmd.stderr(process::Stdio::null())
+                .stdout(process::Stdio::null());
+            vec![(pipe.read, false)]
+        }
+        None => vec![
+            (
+                FileDescriptor::dup(child.stderr.as_ref().unwrap()).unwrap(),
+                true,
+            ),
+            (
+                FileDescriptor::dup(child.stdout.as_ref().unwrap()).unwrap(),
+                false,
+            ),
+        ],
+    };
+    for (fd, _) in &streams {
+        non_blocking(fd)
+            .unwrap_or_else(|_| fatal("Couldn't set stderr and stdout to be non-blocking."));
+    }
 
-    let mut cap_stderr = String::new();
-    let mut cap_stdout = String::new();
-    let mut pollfds = [
-        pollfd {
-            fd: FileDescriptor::dup(&stderr)
-                .unwrap()
-                .into_socket_descriptor(),
-            events: POLLERR | POLLIN | POLLHUP,
-            revents: 0,
-        },
-        pollfd {
-            fd: FileDescriptor::dup(&stdout)
-                .unwrap()
-                .into_socket_descriptor(),
-            events: POLLERR | POLLIN | POLLHUP,
-            revents: 0,
-        },
-    ];
+    let mut cap_stderr = Vec::new();
+    let mut cap_stdout = Vec::new();
     let mut buf = [0; READBUF];
     let start = Instant::now();
+    let deadline = timeout.map(|t| start + t);
+    let mut timed_out = false;
     let mut last_warning = Instant::now();
     let mut next_warning = last_warning
         .checked_add(Duration::from_secs(TIMEOUT))
         .unwrap();
-    loop {
-        let timeout = {
-            // FIXME: When Rust 1.39.0 is out we can replace this mess with duration_since_checked.
+    while !streams.is_empty() {
+        if let Some(ref mut interaction) = interaction {
+            let stdout_closed = streams.iter().all(|(_, is_stderr)| *is_stderr);
+            if let Err(msg) =
+                interaction.advance(&cap_stdout, stdout_closed, inner.case_insensitive)
+            {
+                child.kill().ok();
+                interact_failure = Some(msg);
+                break;
+            }
+        }
+        let poll_timeout = {
             let t = Instant::now();
-            if t > next_warning {
+            let mut poll_timeout = if t > next_warning {
                 Duration::from_secs(1)
             } else {
                 next_warning.duration_since(t)
+            };
+            if let Some(d) = deadline {
+                poll_timeout = poll_timeout.min(d.saturating_duration_since(t));
+            }
+            if let Some(d) = interaction.as_ref().and_then(|i| i.deadline) {
+                poll_timeout = poll_timeout.min(d.saturating_duration_since(t));
             }
+            poll_timeout
         };
-        if poll(&mut pollfds, Some(timeout)).is_ok() {
-            if pollfds[0].revents & POLLIN == POLLIN {
-                while let Ok(i) = stderr.read(&mut buf) {
-                    if i == 0 {
-                        break;
-                    }
-                    let utf8 = str::from_utf8(&buf[..i]).unwrap_or_else(|_| {
-                        fatal(&format!("Can't convert stderr from '{:?}' into UTF-8", cmd))
-                    });
-                    cap_stderr.push_str(&utf8);
-                    if inner.nocapture {
-                        eprint!("{}", utf8);
-                    }
+        let mut pollfds = streams
+            .iter()
+            .map(|(fd, _)| pollfd {
+                fd: fd.as_socket_descriptor(),
+                events: POLLERR | POLLIN | POLLHUP,
+                revents: 0,
+            })
+            .collect::<Vec<_>>();
+        if poll(&mut pollfds, Some(poll_timeout)).is_ok() {
+            // Streams which have been disconnected: we stop polling these, as they would otherwise
+            // cause `poll` to return immediately.
+            let mut closed = Vec::new();
+            for (i, pfd) in pollfds.iter().enumerate() {
+                if pfd.revents == 0 {
+                    continue;
                 }
-            }
-
-            if pollfds[1].revents & POLLIN == POLLIN {
-                while let Ok(i) = stdout.read(&mut buf) {
-                    if i == 0 {
-                        break;
-                    }
-                    let utf8 = str::from_utf8(&buf[..i]).unwrap_or_else(|_| {
-                        fatal(&format!("Can't convert stdout from '{:?}' into UTF-8", cmd))
-                    });
-                    cap_stdout.push_str(&utf8);
-                    if inner.nocapture {
-                        print!("{}", utf8);
+                let (ref mut fd, is_stderr) = streams[i];
+                let mut eof = pfd.revents & POLLERR == POLLERR;
+                loop {
+                    match fd.read(&mut buf) {
+                        Ok(0) => {
+                            eof = true;
+                            break;
+                        }
+                        Ok(i) => {
+                            if is_stderr {
+                                cap_stderr.extend_from_slice(&buf[..i]);
+                            } else if let Some(ref mut stream) = stdout_stream {
+                                stream.push_bytes(&buf[..i]);
+                            } else {
+                                cap_stdout.extend_from_slice(&buf[..i]);
+                            }
+                        }
+                        Err(_) => break,
                     }
                 }
+                if eof {
+                    closed.push(i);
+                }
             }
+            for i in closed.into_iter().rev() {
+                streams.remove(i);
+            }
+        }
 
-            if pollfds[0].revents & POLLHUP == POLLHUP && pollfds[1].revents & POLLHUP == POLLHUP {
+        if let Some(d) = deadline {
+            if Instant::now() >= d {
+                // We don't wait for stderr/stdout to be disconnected, as the child may have
+                // passed them on to its own children which we haven't killed.
+                child.kill().ok();
+                timed_out = true;
                 break;
             }
         }
@@ -745,10 +5146,15 @@ fn run_cmd(
             if inner.test_threads == 1 {
                 eprint!("running for over {} seconds... ", running_for);
             } else {
-                eprintln!(
+                let mut handle = io::stderr().lock();
+                inner.hide_progress(&mut handle);
+                writeln!(
+                    handle,
                     "\nlang_tests::{} ... has been running for over {} seconds",
                     test_fname, running_for
-                );
+                )
+                .ok();
+                inner.show_progress(&mut handle, false);
             }
             last_warning = next_warning;
             next_warning = last_warning
@@ -757,7 +5163,18 @@ fn run_cmd(
         }
     }
 
-    let status = {
+    // If stdout was closed before every `expect` step was satisfied, the remaining steps can never
+    // be performed.
+    if let Some(mut interaction) = interaction.take() {
+        if !timed_out && interact_failure.is_none() {
+            if let Err(msg) = interaction.advance(&cap_stdout, true, inner.case_insensitive) {
+                child.kill().ok();
+                interact_failure = Some(msg);
+            }
+        }
+    }
+
+    let (status, peak_rss) = {
         // We have no idea how long it will take the child process to exit. In practise, the mere
         // act of yielding (via sleep) for a ridiculously short period of time will often be enough
         // for the child process to exit. So we use an exponentially increasing timeout with a very
@@ -765,21 +5182,35 @@ fn run_cmd(
         // something that's almost certainly already occurred.
         let mut wait_timeout = INITIAL_WAIT_TIMEOUT;
         loop {
-            match child.try_wait() {
+            match try_wait_rss(&mut child) {
                 Ok(Some(s)) => break s,
                 Ok(None) => (),
                 Err(e) => fatal(&format!("{:?} did not exit correctly: {:?}", cmd, e)),
             }
 
+            if let Some(d) = deadline {
+                if !timed_out && Instant::now() >= d {
+                    // The next call to try_wait() will reap the killed child.
+                    child.kill().ok();
+                    timed_out = true;
+                    continue;
+                }
+            }
+
             if Instant::now() >= next_warning {
                 let running_for = ((Instant::now() - start).as_secs() / TIMEOUT) * TIMEOUT;
                 if inner.test_threads == 1 {
                     eprint!("running for over {} seconds... ", running_for);
                 } else {
-                    eprintln!(
+                    let mut handle = io::stderr().lock();
+                    inner.hide_progress(&mut handle);
+                    writeln!(
+                        handle,
                         "\nlang_tests::{} ... has been running for over {} seconds",
                         test_fname, running_for
-                    );
+                    )
+                    .ok();
+                    inner.show_progress(&mut handle, false);
                 }
                 last_warning = next_warning;
                 next_warning = last_warning
@@ -793,27 +5224,134 @@ fn run_cmd(
             }
         }
     };
-    (status, cap_stderr, cap_stdout)
+    if let Some(t) = stdin_thread {
+        t.join().ok();
+    }
+    (
+        status,
+        cap_stderr,
+        cap_stdout,
+        timed_out,
+        interact_failure,
+        peak_rss,
+    )
 }
 
-fn non_blocking(
-    stdout: &FileDescriptor,
-    stderr: &FileDescriptor,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let stdout_fd = stdout.as_socket_descriptor();
-    let stderr_fd = stderr.as_socket_descriptor();
+/// If `child` has exited, reap it, returning its exit status and peak resident set size in bytes.
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+fn try_wait_rss(child: &mut Child) -> io::Result<Option<(ExitStatus, Option<u64>)>> {
+    let mut status = 0;
+    let mut rusage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    let pid = child.id() as libc::pid_t;
+    match unsafe { libc::wait4(pid, &mut status, libc::WNOHANG, &mut rusage) } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(None),
+        _ => {
+            // `ru_maxrss` is in bytes on macOS, and in kilobytes elsewhere.
+            let maxrss = u64::try_from(rusage.ru_maxrss).unwrap_or(0);
+            let maxrss = if cfg!(target_os = "macos") {
+                maxrss
+            } else {
+                maxrss * 1024
+            };
+            Ok(Some((ExitStatus::from_raw(status), Some(maxrss))))
+        }
+    }
+}
 
-    let flags = fcntl(stdout_fd, F_GETFL)?;
-    fcntl(
-        stdout_fd,
-        F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK),
-    )?;
+/// If `child` has exited, reap it, returning its exit status. This platform has no `wait4`, so
+/// the child's peak resident set size is not known.
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+fn try_wait_rss(child: &mut Child) -> io::Result<Option<(ExitStatus, Option<u64>)>> {
+    child.try_wait().map(|s| s.map(|s| (s, None)))
+}
 
-    let flags = fcntl(stderr_fd, F_GETFL)?;
+fn non_blocking(fd: &FileDescriptor) -> Result<(), Box<dyn std::error::Error>> {
+    let fd = fd.as_socket_descriptor();
+    let flags = fcntl(fd, F_GETFL)?;
     fcntl(
-        stderr_fd,
+        fd,
         F_SETFL(OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK),
     )?;
-
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bless_inline() {
+        let test_str =
+            "Compiler:\n  status: success\n  stdout: x\n\nRun-time:\n  stdout:\n      a\n      b";
+        let file_str =
+            "// Compiler:\n//   status: success\n//   stdout: x\n//\n// Run-time:\n//   \
+                        stdout:\n//       a\n//       b\n\nfn main() {}\n";
+        let bless = |inline| bless_inline(file_str, test_str, inline).unwrap();
+
+        // A single line value stays on the key's line.
+        assert_eq!(
+            bless(vec![("stdout", 2..3, "y".to_owned())]),
+            file_str.replace("stdout: x", "stdout: y")
+        );
+        // A multi-line value is indented by 2 more than the key, with blank lines keeping the
+        // comment prefix.
+        assert_eq!(
+            bless(vec![("stdout", 2..3, "y\n\n  z\n".to_owned())]),
+            file_str.replace("//   stdout: x\n", "//   stdout:\n//     y\n//\n//     z\n")
+        );
+        // A value which was on separate lines keeps its indentation.
+        assert_eq!(
+            bless(vec![("stdout", 5..8, "c".to_owned())]),
+            file_str.replace("//       a\n//       b\n", "//       c\n")
+        );
+        // Edits are applied independently of the order they're given in.
+        assert_eq!(
+            bless(vec![
+                ("stdout", 2..3, "y".to_owned()),
+                ("stdout", 5..8, "c".to_owned())
+            ]),
+            file_str
+                .replace("stdout: x", "stdout: y")
+                .replace("//       a\n//       b\n", "//       c\n")
+        );
+
+        // `\r\n` line endings are kept, including on new lines.
+        let crlf = file_str.replace('\n', "\r\n");
+        assert_eq!(
+            bless_inline(&crlf, test_str, vec![("stdout", 2..3, "y\nz".to_owned())]).unwrap(),
+            crlf.replace(
+                "//   stdout: x\r\n",
+                "//   stdout:\r\n//     y\r\n//     z\r\n"
+            )
+        );
+        // As is the lack of a line ending on the last line.
+        assert_eq!(
+            bless_inline(
+                "# a:\r\n#   stdout: x",
+                "a:\n  stdout: x",
+                vec![("stdout", 1..2, "y\nz".to_owned())]
+            )
+            .unwrap(),
+            "# a:\r\n#   stdout:\r\n#     y\r\n#     z"
+        );
+
+        assert!(bless_inline("fn main() {}\n", test_str, Vec::new()).is_err());
+    }
+}